@@ -0,0 +1,54 @@
+//! Lists a small directory inside a large synthetic archive, comparing the binary-searched
+//! `list_files` against a scan over every entry in the archive.
+//!
+//! Run with `cargo bench --bench archive_listing`.
+
+use std::path::PathBuf;
+use std::time::Instant;
+use evfs::{ArchiveCreator, ArchiveFileSystem, EncUtils, FileSystem};
+
+const DIRECTORIES: usize = 200;
+const FILES_PER_DIRECTORY: usize = 100;
+const ITERATIONS: usize = 100;
+
+fn main() {
+    let source = std::env::temp_dir().join("evfs_bench_listing_source");
+    let output = std::env::temp_dir().join("evfs_bench_listing.arc");
+    for d in 0..DIRECTORIES {
+        let directory = source.join(format!("dir_{}", d));
+        std::fs::create_dir_all(&directory).expect("Failed to create source directory");
+        for f in 0..FILES_PER_DIRECTORY {
+            std::fs::write(directory.join(format!("f_{}.bin", f)), [d as u8, f as u8]).expect("Failed to write source file");
+        }
+    }
+    let key = EncUtils::generate_random_key();
+    let mut creator = ArchiveCreator::new(source.to_str().unwrap(), output.to_str().unwrap(), key.clone(), true)
+        .expect("Failed to create ArchiveCreator");
+    creator.create().expect("Failed to create archive");
+    let archive_fs = ArchiveFileSystem::open(PathBuf::from(&output), key).expect("Failed to open archive");
+
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        let files = archive_fs.list_files(&format!("dir_{}", i % DIRECTORIES)).expect("Failed to list files");
+        assert_eq!(files.len(), FILES_PER_DIRECTORY);
+    }
+    let indexed = start.elapsed();
+
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        let prefix = format!("dir_{}/", i % DIRECTORIES);
+        let files = archive_fs.walk("")
+            .filter_map(Result::ok)
+            .filter(|f| f.path.starts_with(&prefix))
+            .count();
+        assert_eq!(files, FILES_PER_DIRECTORY);
+    }
+    let scanned = start.elapsed();
+
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::remove_file(&output).ok();
+
+    println!("{} listings in an archive of {} entries", ITERATIONS, DIRECTORIES * FILES_PER_DIRECTORY);
+    println!("  binary search: {:?}", indexed);
+    println!("  full scan:     {:?}", scanned);
+}
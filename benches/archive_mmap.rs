@@ -0,0 +1,52 @@
+//! Reads random files from a large synthetic archive, comparing an archive opened with
+//! `open_mmap` against one opened with `open`, which opens and seeks the file per read.
+//!
+//! Run with `cargo bench --bench archive_mmap --features mmap`.
+
+use std::path::PathBuf;
+use std::time::Instant;
+use evfs::{ArchiveCreator, ArchiveFileSystem, EncUtils, FileSystem};
+
+const FILES: usize = 2000;
+const FILE_SIZE: usize = 16 * 1024;
+const READS: usize = 20_000;
+
+fn main() {
+    let source = std::env::temp_dir().join("evfs_bench_mmap_source");
+    let output = std::env::temp_dir().join("evfs_bench_mmap.arc");
+    std::fs::create_dir_all(&source).expect("Failed to create source directory");
+    for f in 0..FILES {
+        std::fs::write(source.join(format!("f_{}.bin", f)), vec![f as u8; FILE_SIZE]).expect("Failed to write source file");
+    }
+    let key = EncUtils::generate_random_key();
+    let mut creator = ArchiveCreator::new(source.to_str().unwrap(), output.to_str().unwrap(), key.clone(), true)
+        .expect("Failed to create ArchiveCreator");
+    creator.create().expect("Failed to create archive");
+    let opened = ArchiveFileSystem::open(PathBuf::from(&output), key.clone()).expect("Failed to open archive");
+    // SAFETY: nothing modifies the archive while the benchmark runs
+    let mapped = unsafe { ArchiveFileSystem::open_mmap(PathBuf::from(&output), key) }.expect("Failed to map archive");
+
+    // A fixed pseudo-random sequence, so both runs read the same files
+    let paths: Vec<String> = (0..READS).map(|i| format!("f_{}.bin", (i * 7919 + 13) % FILES)).collect();
+    let mut buf = Vec::new();
+    let start = Instant::now();
+    for path in &paths {
+        assert_eq!(opened.read_into(path, &mut buf).expect("Failed to read file"), FILE_SIZE);
+    }
+    let seeked = start.elapsed();
+
+    let start = Instant::now();
+    for path in &paths {
+        assert_eq!(mapped.read_into(path, &mut buf).expect("Failed to read file"), FILE_SIZE);
+    }
+    let mmapped = start.elapsed();
+
+    drop(mapped);
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::remove_file(&output).ok();
+
+    let megabytes = (READS * FILE_SIZE) as f64 / (1024.0 * 1024.0);
+    println!("{} random reads of {} byte files from an archive of {} entries", READS, FILE_SIZE, FILES);
+    println!("  open + seek: {:?} ({:.0} MB/s)", seeked, megabytes / seeked.as_secs_f64());
+    println!("  mmap:        {:?} ({:.0} MB/s)", mmapped, megabytes / mmapped.as_secs_f64());
+}
@@ -0,0 +1,80 @@
+//! Encrypts and decrypts many small payloads, as when packing an archive of many small
+//! files, comparing a reused buffer with `encrypt_in_place`/`decrypt_in_place` against
+//! allocating a fresh ciphertext and nonce vector on every call. A counting allocator
+//! reports how many allocations each approach makes.
+//!
+//! Run with `cargo bench --bench enc_in_place`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, KeyInit, OsRng, rand_core::RngCore};
+use evfs::EncUtils;
+
+const ITERATIONS: usize = 20_000;
+const PAYLOAD_SIZE: usize = 256;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let key = EncUtils::generate_random_key();
+    let mut enc_utils = EncUtils::new(key.clone()).expect("Failed to create EncUtils");
+    enc_utils.set_encryption_limit(None);
+    let payload = vec![0x5au8; PAYLOAD_SIZE];
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut buffer = Vec::with_capacity(PAYLOAD_SIZE + evfs::ENCRYPTION_OVERHEAD);
+    for _ in 0..ITERATIONS {
+        buffer.clear();
+        buffer.extend_from_slice(&payload);
+        enc_utils.encrypt_in_place(&mut buffer).expect("Encryption failed");
+        enc_utils.decrypt_in_place(&mut buffer).expect("Decryption failed");
+        assert_eq!(buffer.len(), PAYLOAD_SIZE);
+    }
+    let in_place = start.elapsed();
+    let in_place_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_bytes()));
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let mut nonce_bytes = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce = Nonce::from_slice(&nonce_bytes);
+        let ciphertext = cipher.encrypt(nonce, payload.as_ref()).expect("Encryption failed");
+        let mut encrypted = nonce_bytes.to_vec();
+        encrypted.extend_from_slice(&ciphertext);
+        let (nonce_bytes, ciphertext) = encrypted.split_at(12);
+        let decrypted = cipher.decrypt(Nonce::from_slice(nonce_bytes), ciphertext).expect("Decryption failed");
+        assert_eq!(decrypted.len(), PAYLOAD_SIZE);
+    }
+    let allocating = start.elapsed();
+    let allocating_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    println!("{} encrypt/decrypt pairs of {} bytes", ITERATIONS, PAYLOAD_SIZE);
+    println!("  in place:   {:?}, {} allocations", in_place, in_place_allocations);
+    println!("  allocating: {:?}, {} allocations", allocating, allocating_allocations);
+}
@@ -0,0 +1,44 @@
+//! Encrypts and decrypts many small payloads, comparing the reused cipher held by
+//! `EncUtils` against rebuilding the AES key schedule on every call.
+//!
+//! Run with `cargo bench --bench enc_utils`.
+
+use std::time::Instant;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, KeyInit, OsRng, rand_core::RngCore};
+use evfs::EncUtils;
+
+const ITERATIONS: usize = 20_000;
+const PAYLOAD_SIZE: usize = 256;
+
+fn main() {
+    let key = EncUtils::generate_random_key();
+    let enc_utils = EncUtils::new(key.clone()).expect("Failed to create EncUtils");
+    let payload = vec![0x5au8; PAYLOAD_SIZE];
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let encrypted = enc_utils.encrypt(payload.clone()).expect("Encryption failed");
+        let decrypted = enc_utils.decrypt(encrypted).expect("Decryption failed");
+        assert_eq!(decrypted.len(), PAYLOAD_SIZE);
+    }
+    let reused = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let content = payload.clone();
+        let mut nonce_bytes = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce = Nonce::from_slice(&nonce_bytes);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_bytes()));
+        let encrypted = cipher.encrypt(nonce, content.as_ref()).expect("Encryption failed");
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_bytes()));
+        let decrypted = cipher.decrypt(nonce, encrypted.as_ref()).expect("Decryption failed");
+        assert_eq!(decrypted.len(), PAYLOAD_SIZE);
+    }
+    let rebuilt = start.elapsed();
+
+    println!("{} encrypt/decrypt pairs of {} bytes", ITERATIONS, PAYLOAD_SIZE);
+    println!("  reused cipher:  {:?}", reused);
+    println!("  rebuilt cipher: {:?}", rebuilt);
+}
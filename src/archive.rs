@@ -2,15 +2,41 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::SeekFrom;
-use std::path;
-use std::path::{PathBuf};
-use crate::{FileContent, FileInfo, FileSystem, FileSystemError};
-use crate::enc_utils::{EncKey, EncUtils};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+use crate::{glob_match, normalize_path, Capabilities, Compressor, FileContent, FileInfo, FileSystem, FileSystemError, FsEvent, Observer, NO_COMPRESSION};
+use crate::enc_utils::{EncKey, EncUtils, ENCRYPTION_OVERHEAD, LEGACY_ENCRYPTION_OVERHEAD, MAX_MESSAGE_SIZE, PASSWORD_ITERATIONS, PASSWORD_SALT_SIZE};
+#[cfg(feature = "mmap")]
+use crate::mmap::Mmap;
 
-const HEADER_SIZE: usize = 1 + 4 + 8 + 8; // Version, number of files, total size
-const FILE_ENTRY_SIZE: usize = MAX_FILE_NAME_SIZE + MAX_PATH_SIZE + 8 + 8; // File name, path, size, offset
+const ARCHIVE_MAGIC: &[u8; 4] = b"EVFS"; // Identifies an archive file, always at offset 0
+const HEADER_SIZE: usize = 4 + 1 + 1 + 1 + 4 + 8 + 8 + 4 + PASSWORD_SALT_SIZE + 4; // Magic, version, cipher mode, flags, number of files, total size, data offset, reserved entries, password salt and iterations
+const LEGACY_HEADER_SIZE: usize = 1 + 4 + 8 + 8; // Version, number of files, total size, data offset
+const FILE_ENTRY_SIZE: usize = MAX_FILE_NAME_SIZE + MAX_PATH_SIZE + 8 + 8 + 8 + HASH_SIZE + 1 + 1 + 8 + 2; // File name, path, size, offset, modified, hash, key slot, codec, uncompressed size, volume
+const LEGACY_FILE_ENTRY_SIZE: usize = MAX_FILE_NAME_SIZE + MAX_PATH_SIZE + 8 + 8; // File name, path, size, offset
+const LEGACY_VERSION: u8 = 1; // Oldest version that can still be opened, the only one without the magic number
+const HASH_SIZE: usize = 32; // SHA-256 of the plaintext
 const MAX_FILE_NAME_SIZE: usize = 16; // Maximum size for file name in bytes
 const MAX_PATH_SIZE: usize = 255; // Maximum size for file path in bytes
+const FLAG_ENCRYPTED_INDEX: u8 = 1; // Header and entry table are encrypted with the default key
+const EMBED_MAGIC: &[u8; 8] = b"EVFSEMBD"; // Ends a file with an archive appended by `ArchiveCreator::append_to`
+const EMBED_TRAILER_SIZE: u64 = 8 + 8; // Offset of the embedded archive and magic
+
+/// Numbers temporary archive files, see `temp_path`.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Archive format version written and read by this library. Archives reporting a newer
+/// version through `ArchiveFileSystem::version_of` need a newer release of evfs.
+pub const ARCHIVE_VERSION: u8 = 2;
+
+/// Key slot used for files not assigned to another slot.
+pub const DEFAULT_KEY_SLOT: u8 = 0;
+
+/// Largest file `ArchiveFileSystem` reads unless `set_max_read_bytes` says otherwise: 1 GiB.
+pub const DEFAULT_MAX_READ_BYTES: u64 = 1 << 30;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct FileEntry {
@@ -18,18 +44,49 @@ pub(crate) struct FileEntry {
     pub path: [u8; MAX_PATH_SIZE],
     pub size: u64,
     pub offset: u64,
+    /// Modification time of the source file in nanoseconds since the Unix epoch, 0 if unknown
+    pub modified: u64,
+    /// SHA-256 of the plaintext content
+    pub hash: [u8; HASH_SIZE],
+    /// Keyring slot of the key the content is encrypted with
+    pub key_slot: u8,
+    /// Id of the `Compressor` the content is compressed with, `NO_COMPRESSION` if it is not
+    pub codec: u8,
+    /// Size of the plaintext before compression and encryption
+    pub uncompressed_size: u64,
+    /// Volume file the content is stored in, 0 for the archive file itself
+    pub volume: u16,
 }
 
 impl FileEntry {
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        if bytes.len() < FILE_ENTRY_SIZE {
-            panic!("File entry data is too short");
+    /// Parses an entry of the given archive format version. Version 1 entries only have a
+    /// name, path, size and offset: they have no modification time or hash, are in the
+    /// default key slot and the archive file itself, and are uncompressed, with their
+    /// uncompressed size left at their stored size.
+    pub fn from_bytes(bytes: &[u8], version: u8) -> Result<Self, FileSystemError> {
+        if bytes.len() < entry_size(version) {
+            return Err(FileSystemError::from("File entry data is too short"));
         }
-        let name = bytes[0..MAX_FILE_NAME_SIZE].try_into().unwrap_or([0; MAX_FILE_NAME_SIZE]);
-        let path = bytes[MAX_FILE_NAME_SIZE..MAX_FILE_NAME_SIZE + MAX_PATH_SIZE].try_into().unwrap_or([0; MAX_PATH_SIZE]);
-        let size = u64::from_le_bytes(bytes[MAX_FILE_NAME_SIZE + MAX_PATH_SIZE..MAX_FILE_NAME_SIZE + MAX_PATH_SIZE + 8].try_into().unwrap());
-        let offset = u64::from_le_bytes(bytes[MAX_FILE_NAME_SIZE + MAX_PATH_SIZE + 8..].try_into().unwrap());
-        FileEntry { name, path, size, offset }
+        let mut cursor = 0;
+        let mut take = |len: usize| {
+            let field = &bytes[cursor..cursor + len];
+            cursor += len;
+            field
+        };
+        let name = take(MAX_FILE_NAME_SIZE).try_into().unwrap();
+        let path = take(MAX_PATH_SIZE).try_into().unwrap();
+        let size = u64::from_le_bytes(take(8).try_into().unwrap());
+        let offset = u64::from_le_bytes(take(8).try_into().unwrap());
+        if version == LEGACY_VERSION {
+            return Ok(FileEntry { name, path, ..FileEntry::new("", "", size, offset) });
+        }
+        let modified = u64::from_le_bytes(take(8).try_into().unwrap());
+        let hash = take(HASH_SIZE).try_into().unwrap();
+        let key_slot = take(1)[0];
+        let codec = take(1)[0];
+        let uncompressed_size = u64::from_le_bytes(take(8).try_into().unwrap());
+        let volume = u16::from_le_bytes(take(2).try_into().unwrap());
+        Ok(FileEntry { name, path, size, offset, modified, hash, key_slot, codec, uncompressed_size, volume })
     }
 
     pub fn name(&self) -> String {
@@ -46,10 +103,18 @@ impl FileEntry {
         bytes.extend_from_slice(&self.path);
         bytes.extend_from_slice(&self.size.to_le_bytes());
         bytes.extend_from_slice(&self.offset.to_le_bytes());
+        bytes.extend_from_slice(&self.modified.to_le_bytes());
+        bytes.extend_from_slice(&self.hash);
+        bytes.push(self.key_slot);
+        bytes.push(self.codec);
+        bytes.extend_from_slice(&self.uncompressed_size.to_le_bytes());
+        bytes.extend_from_slice(&self.volume.to_le_bytes());
         bytes
     }
 
+    /// Creates an entry; `\` separators in `path` are stored as `/`.
     pub fn new(name: &str, path: &str, size: u64, offset: u64) -> Self {
+        let path = normalize_path(path);
         let mut name_bytes = [0u8; MAX_FILE_NAME_SIZE];
         let mut path_bytes = [0u8; MAX_PATH_SIZE];
         name_bytes[..name.len()].copy_from_slice(name.as_bytes());
@@ -59,6 +124,12 @@ impl FileEntry {
             path: path_bytes,
             size,
             offset,
+            modified: 0,
+            hash: [0; HASH_SIZE],
+            key_slot: DEFAULT_KEY_SLOT,
+            codec: NO_COMPRESSION,
+            uncompressed_size: size,
+            volume: 0,
         }
     }
 
@@ -70,267 +141,3874 @@ impl FileEntry {
         self.offset = offset;
     }
 
-    pub fn strip_prefix(&mut self, path: &PathBuf) -> Result<(), FileSystemError> {
-        let full_path = PathBuf::from(self.path());
-        let stripped = full_path.strip_prefix(path).unwrap_or(&full_path);
-        let stripped_path = stripped.to_str().ok_or(FileSystemError::from("Invalid UTF-8 in file path"))?;
-        let mut new_path_bytes = [0u8; MAX_PATH_SIZE];
-        new_path_bytes[..stripped_path.len()].copy_from_slice(stripped_path.as_bytes());
-        self.path.copy_from_slice(&new_path_bytes);
-        Ok(())
+    /// Moves the entry to another path, keeping its file name.
+    pub fn set_path(&mut self, path: &str) {
+        self.path = [0u8; MAX_PATH_SIZE];
+        self.path[..path.len()].copy_from_slice(path.as_bytes());
+    }
+
+    /// Returns the source modification time, if it was recorded.
+    pub fn modified_time(&self) -> Option<SystemTime> {
+        (self.modified != 0).then(|| UNIX_EPOCH + Duration::from_nanos(self.modified))
+    }
+}
+
+/// Returns the size of an entry in the entry table of the given archive format version.
+fn entry_size(version: u8) -> usize {
+    if version == LEGACY_VERSION { LEGACY_FILE_ENTRY_SIZE } else { FILE_ENTRY_SIZE }
+}
+
+/// Returns the size of the header of the given archive format version.
+fn header_size(version: u8) -> usize {
+    if version == LEGACY_VERSION { LEGACY_HEADER_SIZE } else { HEADER_SIZE }
+}
+
+/// How the file contents of an archive are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CipherMode {
+    /// Contents are stored as plain bytes, without encryption overhead.
+    None,
+    /// Contents are encrypted with AES-256-GCM.
+    #[default]
+    Aes256Gcm,
+}
+
+impl CipherMode {
+    fn to_byte(self) -> u8 {
+        match self {
+            CipherMode::None => 0,
+            CipherMode::Aes256Gcm => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, FileSystemError> {
+        match byte {
+            0 => Ok(CipherMode::None),
+            1 => Ok(CipherMode::Aes256Gcm),
+            _ => Err(FileSystemError::from(format!("Unknown archive cipher mode {}", byte))),
+        }
     }
 }
 
 pub struct Header {
     pub version: u8,
+    pub cipher: CipherMode,
+    pub flags: u8,
     pub number_of_files: u32,
     pub size: u64,
     pub data_offset: u64,
+    /// Free entry slots preallocated after the entry table, see `ArchiveCreator::set_reserved_entries`
+    pub reserved_entries: u32,
+    /// Salt the archive key was derived from, see `ArchiveCreator::set_password`
+    pub password_salt: [u8; PASSWORD_SALT_SIZE],
+    /// PBKDF2 iterations the archive key was derived with, 0 if it was not derived from a password
+    pub password_iterations: u32,
 }
 
 impl Header {
-    fn from_bytes(bytes: &[u8]) -> Self {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, FileSystemError> {
+        if !bytes.starts_with(ARCHIVE_MAGIC) {
+            return Self::from_legacy_bytes(bytes);
+        }
+        let version = bytes.get(ARCHIVE_MAGIC.len()).copied().unwrap_or_default();
+        if version <= LEGACY_VERSION {
+            return Err(FileSystemError::from(format!("Unknown archive format version {}, the archive may be corrupt", version)));
+        }
         if bytes.len() < HEADER_SIZE {
-            panic!("Header data is too short");
+            return Err(FileSystemError::from("Header data is too short"));
         }
-        let version = bytes[0];
-        let number_of_files = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
-        let size = u64::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8], bytes[9], bytes[10], bytes[11], bytes[12]]);
-        let data_offset = u64::from_le_bytes([bytes[13], bytes[14], bytes[15], bytes[16], bytes[17], bytes[18], bytes[19], bytes[20]]);
-        Header {
+        let mut cursor = ARCHIVE_MAGIC.len() + 1;
+        let mut take = |len: usize| {
+            let field = &bytes[cursor..cursor + len];
+            cursor += len;
+            field
+        };
+        let cipher = take(1)[0];
+        let flags = take(1)[0];
+        let number_of_files = u32::from_le_bytes(take(4).try_into().unwrap());
+        let size = u64::from_le_bytes(take(8).try_into().unwrap());
+        let data_offset = u64::from_le_bytes(take(8).try_into().unwrap());
+        // The cipher and flags bytes are only meaningful for the versions this library reads
+        let (cipher, flags) = if version <= ARCHIVE_VERSION { (CipherMode::from_byte(cipher)?, flags) } else { (CipherMode::default(), 0) };
+        let reserved_entries = u32::from_le_bytes(take(4).try_into().unwrap());
+        let password_salt = take(PASSWORD_SALT_SIZE).try_into().unwrap();
+        let password_iterations = u32::from_le_bytes(take(4).try_into().unwrap());
+        Ok(Header {
             version,
+            cipher,
+            flags,
             number_of_files,
             size,
             data_offset,
+            reserved_entries,
+            password_salt,
+            password_iterations,
+        })
+    }
+
+    /// Parses the header of a version 1 archive, which starts directly with the version
+    /// byte and has no cipher mode or flags: its contents are always encrypted.
+    fn from_legacy_bytes(bytes: &[u8]) -> Result<Self, FileSystemError> {
+        if bytes.first() != Some(&LEGACY_VERSION) {
+            return Err(FileSystemError::from("Not an EVFS archive"));
         }
+        if bytes.len() < LEGACY_HEADER_SIZE {
+            return Err(FileSystemError::from("Header data is too short"));
+        }
+        Ok(Header {
+            version: LEGACY_VERSION,
+            cipher: CipherMode::Aes256Gcm,
+            flags: 0,
+            number_of_files: u32::from_le_bytes(bytes[1..5].try_into().unwrap()),
+            size: u64::from_le_bytes(bytes[5..13].try_into().unwrap()),
+            data_offset: u64::from_le_bytes(bytes[13..21].try_into().unwrap()),
+            reserved_entries: 0,
+            password_salt: [0u8; PASSWORD_SALT_SIZE],
+            password_iterations: 0,
+        })
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = vec![self.version];
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        bytes.extend_from_slice(&[self.version, self.cipher.to_byte(), self.flags]);
         bytes.extend_from_slice(&self.number_of_files.to_le_bytes());
         bytes.extend_from_slice(&self.size.to_le_bytes());
         bytes.extend_from_slice(&self.data_offset.to_le_bytes());
+        bytes.extend_from_slice(&self.reserved_entries.to_le_bytes());
+        bytes.extend_from_slice(&self.password_salt);
+        bytes.extend_from_slice(&self.password_iterations.to_le_bytes());
         bytes
     }
 }
 
+/// A read-only file system backed by an archive file created with `ArchiveCreator`.
+///
+/// The `FileSystem` write methods fail, except `rename_dir` and `swap_files`, which only
+/// rewrite the entry table in place; files can only be added with `append_file`, to
+/// archives created with reserved entry slots.
+///
+/// Archives are encrypted unless they were created with `CipherMode::None`; use `open` for
+/// encrypted archives and `open_unencrypted` for plain ones. The header and entry table are
+/// loaded once by `open`; file contents are read on demand.
+///
+/// Files may be encrypted under different keys, one per key slot. `open_with_keyring`
+/// opens such an archive with any subset of its keys, and only the files whose key was
+/// supplied can be read.
+///
+/// `ArchiveFileSystem` is `Send + Sync`: every read opens its own handle to the archive file,
+/// so it can be shared across threads (e.g. in an `Arc`) and read from concurrently without
+/// any contention between readers. The entry table is behind a read-write lock, which only
+/// `rename_dir` and `swap_files` take exclusively, and the optional read cache (see
+/// `with_cache`) is behind a mutex, held only while looking up or storing an entry.
+///
+/// Those handles are closed before each read returns, so an open archive holds no file
+/// descriptors between reads; `close` releases the memory it does hold.
+///
+/// It is deliberately not `Clone`: a clone would either share the read cache and mappings
+/// or silently duplicate them, so share one instance through an `Arc` instead, or open the
+/// archive again for an independent one.
 pub struct ArchiveFileSystem {
     file_path: PathBuf,
     #[allow(dead_code)]
     header: Header,
+    /// The entries, behind a lock so `rename_dir` and `swap_files` can rewrite them
+    table: RwLock<EntryTable>,
+    /// Keys by slot, empty for unencrypted archives
+    keyring: HashMap<u8, EncUtils>,
+    /// Decrypted contents of recently read files, if enabled
+    cache: Option<Mutex<ReadCache>>,
+    /// Codecs by id, for reading compressed entries
+    compressors: HashMap<u8, Box<dyn Compressor>>,
+    /// Number of volume files, counting the archive file itself
+    volume_count: u32,
+    observer: Option<Observer>,
+    /// Largest stored or decompressed size a read may allocate for
+    max_read_bytes: u64,
+    /// Every volume mapped into memory, if opened with `open_mmap`
+    #[cfg(feature = "mmap")]
+    maps: Option<Vec<Mmap>>,
+    /// The reader all data is read from, if opened with `open_from`
+    source: Option<Mutex<Box<dyn ReadSeek>>>,
+}
+
+/// The entries of an archive, by path and sorted by path.
+struct EntryTable {
     entries: HashMap<String, FileEntry>,
-    enc_utils: EncUtils,
+    /// The same entries sorted by path, for prefix range lookups
+    sorted_entries: Vec<(String, FileEntry)>,
+}
+
+impl EntryTable {
+    fn new(sorted_entries: Vec<(String, FileEntry)>) -> Self {
+        EntryTable { entries: sorted_entries.iter().cloned().collect(), sorted_entries }
+    }
+
+    /// Returns the sorted entries whose path starts with `prefix`, found by binary search.
+    fn prefix_range(&self, prefix: &str) -> &[(String, FileEntry)] {
+        let start = self.sorted_entries.partition_point(|(path, _)| path.as_str() < prefix);
+        let rest = &self.sorted_entries[start..];
+        let len = rest.partition_point(|(path, _)| path.starts_with(prefix));
+        &rest[..len]
+    }
+}
+
+/// A source an archive can be read from with `ArchiveFileSystem::open_from`.
+trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// A reader whose start is moved to where it was positioned when wrapped, so an archive
+/// embedded in a larger file can be read with offsets relative to the archive.
+struct Section<R> {
+    inner: R,
+    start: u64,
+}
+
+impl<R: Seek> Section<R> {
+    fn new(mut inner: R) -> std::io::Result<Self> {
+        let start = inner.stream_position()?;
+        Ok(Section { inner, start })
+    }
+}
+
+impl<R: Read> Read for Section<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Section<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(self.start.checked_add(offset)
+                .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek position overflows"))?),
+            other => other,
+        };
+        let position = self.inner.seek(pos)?;
+        position.checked_sub(self.start)
+            .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek before the start of the archive"))
+    }
+}
+
+/// Bound on the size of an `ArchiveFileSystem` read cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheLimit {
+    /// At most this many files are cached.
+    Entries(usize),
+    /// At most this many bytes of decrypted content are cached. Larger files are not cached.
+    Bytes(usize),
+}
+
+/// Where an archive stores its entries, as reported by `ArchiveFileSystem::layout_report`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ArchiveLayout {
+    /// Every entry, ordered by volume and offset
+    pub entries: Vec<LayoutEntry>,
+    /// Size of the clear header at the start of the archive file
+    pub header_size: u64,
+    /// Size of the entry table including reserved slots, and of its encryption framing if
+    /// the index is encrypted
+    pub table_size: u64,
+    /// Total size of the stored blobs, counting blobs shared by deduplication once
+    pub data_size: u64,
+}
+
+/// Placement of one entry in an `ArchiveLayout`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutEntry {
+    pub path: String,
+    /// Volume file holding the blob, 0 for the archive file itself
+    pub volume: u16,
+    pub offset: u64,
+    /// Stored size, after compression and encryption
+    pub size: u64,
+    /// Bytes of the stored size taken by encryption framing (format version, nonce and tag)
+    pub overhead: u64,
+}
+
+/// An entry left out by `ArchiveFileSystem::open_best_effort`, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedEntry {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Least recently used cache of decrypted file contents, keyed by path.
+///
+/// Eviction scans every cached entry for the oldest one, which is cheap for the small
+/// caches this is meant for.
+struct ReadCache {
+    limit: CacheLimit,
+    entries: HashMap<String, (FileContent, u64)>,
+    bytes: usize,
+    /// Incremented on every access, so the entry with the lowest value is the least recently used
+    clock: u64,
+}
+
+impl ReadCache {
+    fn new(limit: CacheLimit) -> Self {
+        ReadCache { limit, entries: HashMap::new(), bytes: 0, clock: 0 }
+    }
+
+    fn get(&mut self, path: &str) -> Option<&FileContent> {
+        self.clock += 1;
+        let (content, last_used) = self.entries.get_mut(path)?;
+        *last_used = self.clock;
+        Some(content)
+    }
+
+    fn insert(&mut self, path: &str, content: &FileContent) {
+        if let CacheLimit::Bytes(max_bytes) = self.limit && content.len() > max_bytes {
+            return;
+        }
+        self.clock += 1;
+        if let Some((previous, _)) = self.entries.insert(path.to_string(), (content.clone(), self.clock)) {
+            self.bytes -= previous.len();
+        }
+        self.bytes += content.len();
+        while self.is_over_limit() {
+            let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(path, _)| path.clone()) else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+    }
+
+    fn is_over_limit(&self) -> bool {
+        match self.limit {
+            CacheLimit::Entries(max_entries) => self.entries.len() > max_entries,
+            CacheLimit::Bytes(max_bytes) => self.bytes > max_bytes,
+        }
+    }
+
+    fn remove(&mut self, path: &str) {
+        if let Some((removed, _)) = self.entries.remove(path) {
+            self.bytes -= removed.len();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
 }
 
 
 impl ArchiveFileSystem {
 
+    /// Opens an encrypted archive.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is invalid or was created without encryption.
     pub fn open(file_path: PathBuf, key: EncKey) -> Result<Self, FileSystemError> {
-        let mut file = File::open(&file_path).map_err(|e| FileSystemError::from(e.to_string()))?;
+        Self::open_with(file_path, Some(HashMap::from([(DEFAULT_KEY_SLOT, key)])))
+    }
+
+    /// Opens an encrypted archive and maps all its volumes into memory, so reads are
+    /// served from the page cache by copying out of the mapping instead of opening,
+    /// seeking and reading the file each time. Pays off for many random reads from a
+    /// large archive.
+    ///
+    /// A mapped archive is read-only, and `append_file` fails. Only 64-bit Unix platforms
+    /// support mapping.
+    ///
+    /// # Safety
+    /// The archive files must not be truncated or modified, by this or any other process,
+    /// until the returned value is dropped. Reads hand out the mapped bytes as a shared
+    /// slice, so a change under them is undefined behavior, and reading a part that was
+    /// truncated away kills the process with `SIGBUS`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is invalid, was created without encryption, or
+    /// cannot be mapped.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn open_mmap(file_path: PathBuf, key: EncKey) -> Result<Self, FileSystemError> {
+        let mut archive = Self::open(file_path, key)?;
+        let maps = (0..archive.volume_count)
+            // SAFETY: the caller keeps the volumes unmodified while the archive is open
+            .map(|volume| unsafe { Mmap::map(&archive.open_volume(volume as u16)?) })
+            .collect::<Result<Vec<_>, FileSystemError>>()?;
+        archive.maps = Some(maps);
+        Ok(archive)
+    }
+
+    /// Opens an archive encrypted with a password-derived key, see
+    /// `ArchiveCreator::set_password`.
+    ///
+    /// The salt and iteration count are read from the archive header and the key is derived
+    /// from them, which is deliberately slow. Use `open` with the derived key to skip the
+    /// derivation when opening the same archive repeatedly.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is invalid, its key was not derived from a
+    /// password, or, for an archive with an encrypted index, the password is wrong.
+    /// With a clear index, a wrong password only fails when files are read.
+    pub fn open_with_password(file_path: PathBuf, password: &str) -> Result<Self, FileSystemError> {
+        let header = Self::read_header(&file_path)?;
+        Self::check_version(header.version)?;
+        if header.password_iterations == 0 {
+            return Err(FileSystemError::from("Archive key was not derived from a password, open it with its key"));
+        }
+        let key = EncUtils::derive_key(password, &header.password_salt, header.password_iterations)?;
+        Self::open(file_path, key)
+    }
+
+    /// Checks whether `key` is the default-slot key of an encrypted archive, without
+    /// opening it for reading, e.g. to reject a wrong password early.
+    ///
+    /// An encrypted index is itself the sample: it is decrypted and nothing else is read.
+    /// Otherwise the smallest file in the default slot is decrypted.
+    ///
+    /// # Returns
+    /// True if the key decrypts the sample, false if it does not.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is invalid or unencrypted, or has no file in the
+    /// default slot to check against.
+    pub fn check_key(file_path: &Path, key: EncKey) -> Result<bool, FileSystemError> {
+        let header = Self::read_header(file_path)?;
+        Self::check_version(header.version)?;
+        if header.cipher == CipherMode::None {
+            return Err(FileSystemError::from("Archive is not encrypted, there is no key to check"));
+        }
+        let enc_utils = EncUtils::new(key.clone())?;
+        if header.flags & FLAG_ENCRYPTED_INDEX != 0 {
+            let mut file = File::open(file_path).map_err(FileSystemError::from)?;
+            let header_size = header_size(header.version) as u64;
+            let file_size = file.metadata().map_err(FileSystemError::from)?.len();
+            if header.data_offset < header_size || header.data_offset > file_size {
+                return Err(FileSystemError::from("Invalid data offset in archive"));
+            }
+            let mut encrypted = vec![0u8; (header.data_offset - header_size) as usize];
+            file.seek(SeekFrom::Start(header_size)).map_err(FileSystemError::from)?;
+            file.read_exact(&mut encrypted).map_err(FileSystemError::from)?;
+            return Ok(enc_utils.verify_against(&encrypted));
+        }
+        let archive = Self::open(file_path.to_path_buf(), key)?;
+        let sample = archive.table().sorted_entries.iter()
+            .filter(|(_, entry)| entry.key_slot == DEFAULT_KEY_SLOT)
+            .min_by_key(|(_, entry)| entry.size)
+            .map(|(_, entry)| entry.clone())
+            .ok_or(FileSystemError::from("Archive has no file encrypted with the default key to check against"))?;
+        Ok(enc_utils.verify_against(&archive.read_raw(&sample)?))
+    }
+
+    /// Reads the clear header at the start of an archive file.
+    fn read_header(file_path: &Path) -> Result<Header, FileSystemError> {
         let mut header_data = [0u8; HEADER_SIZE];
-        file.read_exact(&mut header_data).map_err(|e| FileSystemError::from(e.to_string()))?;
-        let header = Header::from_bytes(&header_data);
-        if header.version != 1 {
-            return Err(FileSystemError::from("Unsupported archive version"));
+        File::open(file_path)
+            .and_then(|mut file| file.read_exact(&mut header_data))
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => FileSystemError::from("Not an EVFS archive, the file is too short"),
+                _ => FileSystemError::from(e),
+            })?;
+        Header::from_bytes(&header_data)
+    }
+
+    /// Opens an encrypted archive with a key for each slot that should be readable.
+    ///
+    /// Slots without a key are not an error when opening; reading a file from such a
+    /// slot fails instead, so an archive can be partially unlocked.
+    ///
+    /// # Arguments
+    /// - _file_path:_ Path of the archive file.
+    /// - _keyring:_ Keys by the slot they were assigned to with `ArchiveCreator::add_key_slot`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is invalid, is not encrypted, the keyring is empty
+    /// or one of its keys is invalid.
+    pub fn open_with_keyring(file_path: PathBuf, keyring: HashMap<u8, EncKey>) -> Result<Self, FileSystemError> {
+        Self::open_with(file_path, Some(keyring))
+    }
+
+    /// Checks whether a file starts with the EVFS archive magic number.
+    ///
+    /// Only the first bytes are read, so this is a cheap way to tell archives apart from
+    /// other files; it does not check the version or validate the rest of the archive.
+    /// Version 1 archives have no magic number and are not recognized, although `open`
+    /// still reads them; `version_of` tells those apart.
+    pub fn is_archive(file_path: &Path) -> bool {
+        let mut magic = [0u8; ARCHIVE_MAGIC.len()];
+        File::open(file_path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == *ARCHIVE_MAGIC
+    }
+
+    /// Reads the format version of an archive without opening it.
+    ///
+    /// Only the first bytes are read and nothing else is validated, so this also works for
+    /// archives written by older or newer releases that `open` rejects. Compare the result
+    /// with `ARCHIVE_VERSION` to tell whether evfs needs updating.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the file cannot be read or is not an EVFS archive.
+    pub fn version_of(file_path: &Path) -> Result<u8, FileSystemError> {
+        let mut prefix = [0u8; ARCHIVE_MAGIC.len() + 1];
+        File::open(file_path)
+            .and_then(|mut file| file.read_exact(&mut prefix))
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => FileSystemError::from("Not an EVFS archive, the file is too short"),
+                _ => FileSystemError::from(e),
+            })?;
+        if prefix.starts_with(ARCHIVE_MAGIC) {
+            return Ok(prefix[ARCHIVE_MAGIC.len()]);
+        }
+        if prefix[0] == LEGACY_VERSION {
+            return Ok(LEGACY_VERSION);
+        }
+        Err(FileSystemError::from("Not an EVFS archive"))
+    }
+
+    /// Rejects versions this library cannot read, telling newer versions, which need a
+    /// library update, apart from versions that were never released, which mean corruption.
+    fn check_version(version: u8) -> Result<(), FileSystemError> {
+        match version {
+            LEGACY_VERSION..=ARCHIVE_VERSION => Ok(()),
+            version if version > ARCHIVE_VERSION => Err(FileSystemError::from(format!(
+                "Archive format version {} is newer than version {} supported by this release, update evfs to open it",
+                version, ARCHIVE_VERSION
+            ))),
+            version => Err(FileSystemError::from(format!(
+                "Unknown archive format version {}, the archive may be corrupt",
+                version
+            ))),
+        }
+    }
+
+    /// Opens an archive created with `CipherMode::None`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is invalid or is encrypted.
+    pub fn open_unencrypted(file_path: PathBuf) -> Result<Self, FileSystemError> {
+        Self::open_with(file_path, None)
+    }
+
+    /// Opens an encrypted archive from any reader, such as an archive embedded in a larger
+    /// bundle file or held in memory in a `Cursor`.
+    ///
+    /// The archive is read from the reader's current position on, so offsets in it are
+    /// relative to where it starts rather than to the start of the reader. Reads from the
+    /// archive take turns on the reader behind a lock. Only single-volume archives can be
+    /// opened this way, and the archive is read-only: `append_file`, `rename_entries` and
+    /// `swap_entries` fail, and `root` returns `None`.
+    ///
+    /// # Arguments
+    /// - _reader:_ The source, positioned at the start of the archive.
+    /// - _key:_ The key for the default slot.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is invalid, was created without encryption, spans
+    /// several volumes or cannot be read.
+    pub fn open_from<R: Read + Seek + Send + 'static>(reader: R, key: EncKey) -> Result<Self, FileSystemError> {
+        let mut reader = Section::new(reader).map_err(FileSystemError::from)?;
+        let file_size = reader.seek(SeekFrom::End(0)).map_err(FileSystemError::from)?;
+        reader.seek(SeekFrom::Start(0)).map_err(FileSystemError::from)?;
+        let mut archive = Self::read_index(PathBuf::new(), &mut reader, file_size, Some(HashMap::from([(DEFAULT_KEY_SLOT, key)])))?;
+        if archive.volume_count > 1 {
+            return Err(FileSystemError::from("Archive spans several volumes, which cannot be read from a single reader"));
+        }
+        archive.check_volume_sizes(&[file_size])?;
+        archive.source = Some(Mutex::new(Box::new(reader)));
+        Ok(archive)
+    }
+
+    /// Opens an encrypted archive appended to a file with `ArchiveCreator::append_to`, such
+    /// as the asset pack of a self-contained game executable.
+    ///
+    /// The file is only opened for reading, so this works on the running executable, e.g.
+    /// with the path from `std::env::current_exe`. The archive is opened as by `open_from`
+    /// and is read-only.
+    ///
+    /// # Arguments
+    /// - _binary_path:_ Path of the file the archive is appended to.
+    /// - _key:_ The key for the default slot.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the file has no archive appended, or the archive is invalid or
+    /// was created without encryption.
+    pub fn open_embedded(binary_path: PathBuf, key: EncKey) -> Result<Self, FileSystemError> {
+        let mut file = File::open(&binary_path).map_err(FileSystemError::from)?;
+        let start = embedded_start(&mut file)?
+            .ok_or_else(|| FileSystemError::from(format!("No archive is embedded in {}", binary_path.display())))?;
+        file.seek(SeekFrom::Start(start)).map_err(FileSystemError::from)?;
+        Self::open_from(file, key)
+    }
+
+    /// Opens an encrypted archive whose entry table or data is partly damaged, keeping only
+    /// the entries that can still be read, to salvage what is left of a corrupted pack.
+    ///
+    /// Every entry is read in full and checked: entries pointing past the end of their
+    /// volume or into a missing volume, entries that fail to decrypt or decompress, and
+    /// entries whose content does not match the SHA-256 recorded for it are dropped. This
+    /// reads the whole archive, so use `open` for archives that are not known to be
+    /// damaged; it fails on the first problem instead. The header and the bounds of the
+    /// entry table must still be intact.
+    ///
+    /// # Returns
+    /// The archive with the readable entries, and the entries that were dropped, by path.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the header is damaged or the archive was created without
+    /// encryption.
+    pub fn open_best_effort(file_path: PathBuf, key: EncKey) -> Result<(Self, Vec<DroppedEntry>), FileSystemError> {
+        let mut file = File::open(&file_path).map_err(FileSystemError::from)?;
+        let file_size = file.metadata().map_err(FileSystemError::from)?.len();
+        let mut archive = Self::read_index(file_path, &mut file, file_size, Some(HashMap::from([(DEFAULT_KEY_SLOT, key)])))?;
+        let mut volume_sizes = vec![Some(file_size)];
+        for volume in 1..archive.volume_count {
+            volume_sizes.push(std::fs::metadata(volume_path(&archive.file_path, volume as u16)).ok().map(|metadata| metadata.len()));
+        }
+        let mut dropped = Vec::new();
+        let mut content = Vec::new();
+        let mut volume = None;
+        for (path, entry) in &archive.table().sorted_entries {
+            let reason = match volume_sizes[entry.volume as usize] {
+                None => Some(format!("Volume {} is missing", entry.volume)),
+                Some(size) if entry.offset.checked_add(entry.size).is_none_or(|end| end > size) => {
+                    Some(String::from("Entry exceeds the size of its archive volume"))
+                }
+                Some(_) => match archive.read_entry(&mut volume, entry, &mut content) {
+                    Err(e) => Some(e.message),
+                    Ok(()) if entry.hash != [0; HASH_SIZE] && Sha256::digest(&content)[..] != entry.hash => {
+                        Some(String::from("Content does not match its checksum"))
+                    }
+                    Ok(()) => None,
+                },
+            };
+            if let Some(reason) = reason {
+                dropped.push(DroppedEntry { path: path.clone(), reason });
+            }
+        }
+        let table = archive.table.get_mut().unwrap_or_else(|e| e.into_inner());
+        for entry in &dropped {
+            table.entries.remove(&entry.path);
+        }
+        table.sorted_entries.retain(|(path, _)| table.entries.contains_key(path));
+        Ok((archive, dropped))
+    }
+
+    fn open_with(file_path: PathBuf, keyring: Option<HashMap<u8, EncKey>>) -> Result<Self, FileSystemError> {
+        let mut file = File::open(&file_path).map_err(FileSystemError::from)?;
+        let file_size = file.metadata().map_err(FileSystemError::from)?.len();
+        let archive = Self::read_index(file_path, &mut file, file_size, keyring)?;
+        // Catch sizes and offsets pointing past the end of a volume now, rather than
+        // allocating for them on every read
+        let mut volume_sizes = vec![file_size];
+        for volume in 1..archive.volume_count {
+            let size = std::fs::metadata(volume_path(&archive.file_path, volume as u16))
+                .map_err(|e| FileSystemError::from(format!("Failed to open archive volume {}: {}", volume, e)))?
+                .len();
+            volume_sizes.push(size);
+        }
+        archive.check_volume_sizes(&volume_sizes)?;
+        Ok(archive)
+    }
+
+    /// Checks that every entry lies within its volume.
+    fn check_volume_sizes(&self, volume_sizes: &[u64]) -> Result<(), FileSystemError> {
+        for entry in self.table().entries.values() {
+            let end = entry.offset.checked_add(entry.size);
+            if end.is_none_or(|end| end > volume_sizes[entry.volume as usize]) {
+                return Err(FileSystemError::from(format!("Entry {} exceeds the size of its archive volume", entry.path())));
+            }
         }
+        Ok(())
+    }
+
+    /// Parses the header and entry table from `file`, positioned at the start of the
+    /// archive, into an archive reading its data from `file_path`.
+    fn read_index<R: Read + Seek>(file_path: PathBuf, file: &mut R, file_size: u64, keyring: Option<HashMap<u8, EncKey>>) -> Result<Self, FileSystemError> {
+        let mut header_data = [0u8; HEADER_SIZE];
+        file.read_exact(&mut header_data).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => FileSystemError::from("Not an EVFS archive, the file is too short"),
+            _ => FileSystemError::from(e),
+        })?;
+        let header = Header::from_bytes(&header_data)?;
+        Self::check_version(header.version)?;
+        let keyring = match (header.cipher, keyring) {
+            (CipherMode::Aes256Gcm, Some(keyring)) if keyring.is_empty() => return Err(FileSystemError::from("Archive is encrypted, at least one key is required to open it")),
+            (CipherMode::Aes256Gcm, Some(keyring)) => keyring.into_iter()
+                .map(|(slot, key)| Ok((slot, EncUtils::new(key)?)))
+                .collect::<Result<HashMap<_, _>, FileSystemError>>()?,
+            (CipherMode::None, None) => HashMap::new(),
+            (CipherMode::Aes256Gcm, None) => return Err(FileSystemError::from("Archive is encrypted, a key is required to open it")),
+            (CipherMode::None, Some(_)) => return Err(FileSystemError::from("Archive is not encrypted, open it without a key")),
+        };
+        let header_size = header_size(header.version);
+        file.seek(SeekFrom::Start(header_size as u64)).map_err(FileSystemError::from)?;
+        // With an encrypted index, the clear header only tells where the data starts and
+        // the real header is the first part of the decrypted index
+        let (header, mut index) = if header.flags & FLAG_ENCRYPTED_INDEX != 0 {
+            let enc_utils = keyring.get(&DEFAULT_KEY_SLOT)
+                .ok_or(FileSystemError::from("Archive index is encrypted, the key for the default slot is required to open it"))?;
+            if header.data_offset < header_size as u64 || header.data_offset > file_size {
+                return Err(FileSystemError::from("Invalid data offset in archive"));
+            }
+            let mut encrypted = vec![0u8; (header.data_offset - header_size as u64) as usize];
+            file.read_exact(&mut encrypted).map_err(FileSystemError::from)?;
+            let index = enc_utils.decrypt(encrypted)
+                .map_err(|_| FileSystemError::from("Failed to decrypt archive index, the key may be wrong"))?;
+            if index.len() < header_size {
+                return Err(FileSystemError::from("Failed to decrypt archive index, the key may be wrong"));
+            }
+            (Header::from_bytes(&index[..header_size])?, index[header_size..].to_vec())
+        } else {
+            (header, Vec::new())
+        };
         if header.number_of_files == 0 {
             return Err(FileSystemError::from("Archive contains no files"));
         }
-        if header.size < HEADER_SIZE as u64 + header.number_of_files as u64 * FILE_ENTRY_SIZE as u64 {
+        let entry_size = entry_size(header.version);
+        // The table has room for the reserved entries too, but only the used ones are read
+        let table_size = header.number_of_files as u64 * entry_size as u64;
+        let slots_size = (header.number_of_files as u64 + header.reserved_entries as u64) * entry_size as u64;
+        if header.size < header_size as u64 + slots_size {
             return Err(FileSystemError::from("Invalid archive size"));
         }
-        if header.data_offset < HEADER_SIZE as u64 + header.number_of_files as u64 * FILE_ENTRY_SIZE as u64 {
+        if header.data_offset < header_size as u64 + slots_size {
             return Err(FileSystemError::from("Invalid data offset in archive"));
         }
-        let mut entries = HashMap::new();
-        for _ in 0..header.number_of_files {
-            let mut entry_data = vec![0u8; FILE_ENTRY_SIZE];
-            file.read_exact(&mut entry_data).map_err(|e| FileSystemError::from(e.to_string()))?;
-            let file_entry = FileEntry::from_bytes(&entry_data);
+        // Make sure the entry table actually fits in the file before allocating for it
+        if header_size as u64 + table_size > file_size {
+            return Err(FileSystemError::from("Archive entry table exceeds file size"));
+        }
+        if header.flags & FLAG_ENCRYPTED_INDEX == 0 {
+            index = vec![0u8; table_size as usize];
+            file.read_exact(&mut index).map_err(FileSystemError::from)?;
+        } else if index.len() as u64 != slots_size {
+            return Err(FileSystemError::from("Archive entry table does not match the number of files"));
+        }
+        let mut entries = HashMap::with_capacity(header.number_of_files as usize);
+        let overhead = if header.cipher == CipherMode::None { 0 } else { LEGACY_ENCRYPTION_OVERHEAD as u64 };
+        for entry_data in index[..table_size as usize].chunks_exact(entry_size) {
+            let mut file_entry = FileEntry::from_bytes(entry_data, header.version)?;
+            if header.version == LEGACY_VERSION {
+                file_entry.uncompressed_size = file_entry.size.saturating_sub(overhead);
+            }
             entries.insert(file_entry.path(), file_entry);
         }
-        let enc_utils = EncUtils::new(key)?;
+        let mut sorted_entries: Vec<(String, FileEntry)> = entries.iter()
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect();
+        sorted_entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let volume_count = entries.values().map(|entry| entry.volume as u32 + 1).max().unwrap_or(1);
 
         Ok(ArchiveFileSystem {
             file_path,
             header,
-            entries,
-            enc_utils,
+            table: RwLock::new(EntryTable { entries, sorted_entries }),
+            keyring,
+            cache: None,
+            compressors: Self::builtin_compressors(),
+            volume_count,
+            observer: None,
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            #[cfg(feature = "mmap")]
+            maps: None,
+            source: None,
         })
     }
-}
 
+    /// Returns the codecs shipped with evfs, by id.
+    fn builtin_compressors() -> HashMap<u8, Box<dyn Compressor>> {
+        #[allow(unused_mut)]
+        let mut compressors: HashMap<u8, Box<dyn Compressor>> = HashMap::new();
+        #[cfg(feature = "deflate")]
+        compressors.insert(crate::DEFLATE_CODEC_ID, Box::new(crate::DeflateCompressor));
+        compressors
+    }
 
-pub struct ArchiveCreator {
-    directory_path: PathBuf,
-    file_path: PathBuf,
-    enc_utils: EncUtils,
-    file_entries: Vec<FileEntry>,
-}
+    /// Registers a codec for reading entries compressed with it.
+    ///
+    /// The codecs shipped with evfs are registered already; this is for custom codecs
+    /// passed to `ArchiveCreator::set_compressor`. A codec with the id of one already
+    /// registered replaces it.
+    ///
+    /// # Arguments
+    /// - _compressor:_ The codec, matched to entries by its `id`.
+    pub fn with_compressor(mut self, compressor: Box<dyn Compressor>) -> Self {
+        self.compressors.insert(compressor.id(), compressor);
+        self
+    }
 
-impl ArchiveCreator {
-    pub fn new(directory_path: &str, file_path: &str, key: EncKey, overwrite: bool) -> Result<Self, FileSystemError> {
-        let directory_path = PathBuf::from(directory_path);
-        let file_path = PathBuf::from(file_path);
-        if !directory_path.is_dir() {
-            return Err(FileSystemError::from("Provided source directory path is not a directory"));
-        }
-        if file_path.exists() && !overwrite {
-            return Err(FileSystemError::from("Archive file already exists and overwrite is not allowed"));
-        }
-        let enc_utils = EncUtils::new(key)?;
-        Ok(ArchiveCreator {
-            directory_path,
-            file_path,
-            enc_utils,
-            file_entries: Vec::new(),
-        })
+    /// Enables a cache of decrypted file contents consulted by `read_file`.
+    ///
+    /// Useful when the same small files are read over and over, since each uncached read
+    /// goes to disk and decrypts again. The least recently used files are evicted once
+    /// `limit` is exceeded. Cached contents only go stale if another handle changes the
+    /// archive; `append_file` on this one evicts the file it replaces.
+    ///
+    /// # Arguments
+    /// - _limit:_ How many files or bytes the cache may hold.
+    pub fn with_cache(mut self, limit: CacheLimit) -> Self {
+        self.cache = Some(Mutex::new(ReadCache::new(limit)));
+        self
     }
 
-    fn scan_directory(&mut self, path: &PathBuf) -> Result<(), FileSystemError> {
-        if !path.is_dir() {
-            return Err(FileSystemError::from("Provided path is not a directory"));
+    /// Calls `observer` after every successful read and listing, including reads served
+    /// from the read cache, and every change made with `append_file`, `rename_dir` or
+    /// `swap_files`. Byte counts are decrypted sizes. See `LocalFileSystem::set_observer`.
+    ///
+    /// # Arguments
+    /// - _observer:_ The callback.
+    pub fn with_observer(mut self, observer: Observer) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    fn notify(&self, event: FsEvent) {
+        if let Some(observer) = &self.observer {
+            observer(event);
         }
-        for entry in std::fs::read_dir(path).map_err(|e| FileSystemError::from(e.to_string()))? {
-            let entry = entry.map_err(|e| FileSystemError::from(e.to_string()))?;
-            let entry_path = entry.path();
-            if entry_path.is_dir() {
-                self.scan_directory(&entry_path)?;
-            } else if entry_path.is_file() {
-                let file_name = entry.file_name().to_string_lossy().into_owned();
-                let file_size = entry.metadata().map_err(|e| FileSystemError::from(e.to_string()))?.len();
-                let entry = FileEntry::new(
-                    &file_name,
-                    entry_path.to_str().ok_or(FileSystemError::from("Invalid file path"))?,
-                    file_size, // An updated file size will be set later
-                    0, // Offset will be set later
-                );
-                self.file_entries.push(entry);
-            } else {
-                self.scan_directory(&entry_path)?;
-            }
+    }
+
+    /// Sets the largest file this instance will read, `DEFAULT_MAX_READ_BYTES` by default.
+    ///
+    /// Reads check the stored size of a file, and the decompressed size of a compressed
+    /// one, against the limit before allocating for it, so a corrupted or malicious entry
+    /// cannot make a read allocate gigabytes.
+    ///
+    /// # Arguments
+    /// - _max_read_bytes:_ The limit in bytes.
+    pub fn set_max_read_bytes(&mut self, max_read_bytes: u64) {
+        self.max_read_bytes = max_read_bytes;
+    }
+
+    /// Fails if reading `entry` would allocate more than `max_read_bytes`.
+    fn check_read_size(&self, entry: &FileEntry) -> Result<(), FileSystemError> {
+        let size = if entry.codec == NO_COMPRESSION { entry.size } else { entry.size.max(entry.uncompressed_size) };
+        if size > self.max_read_bytes {
+            return Err(FileSystemError::from(format!(
+                "{} is {} bytes, more than the read limit of {} bytes",
+                entry.path(), size, self.max_read_bytes
+            )));
         }
         Ok(())
     }
 
-    pub fn create(&mut self) -> Result<(), FileSystemError> {
-        let directory_path = self.directory_path.clone();
-        self.scan_directory(&directory_path)?;
-        if self.file_entries.is_empty() {
-            return Err(FileSystemError::from("No files found to archive"));
-        }
-        let mut file = File::create(&self.file_path).map_err(|e| FileSystemError::from(e.to_string()))?;
-        let mut header = Header {
-            version: 1,
-            number_of_files: self.file_entries.len() as u32,
-            size: 0, // Will be updated later
-            data_offset: HEADER_SIZE as u64 + self.file_entries.len() as u64 * FILE_ENTRY_SIZE as u64,
-        };
-        file.write_all(&header.to_bytes()).map_err(|e| FileSystemError::from(e.to_string()))?;
-        let mut new_entries: Vec<FileEntry> = Vec::new();
-        for entry in &self.file_entries {
-            let full_path = path::PathBuf::from(entry.path());
-            if !full_path.exists() || !full_path.is_file() {
-                return Err(FileSystemError::from(format!("File does not exist: {}", full_path.display())));
-            }
-            let content = std::fs::read(full_path).map_err(|e| FileSystemError::from(e.to_string()))?;
-            let encrypted_content = self.enc_utils.encrypt(content).map_err(|e| FileSystemError::from(e.to_string()))?;
-            let offset = file.stream_position().map_err(|e| FileSystemError::from(e.to_string()))?;
-            file.write_all(&encrypted_content).map_err(|e| FileSystemError::from(e.to_string()))?;
-            let size = encrypted_content.len() as u64;
-            let mut new_entry = entry.clone();
-            new_entry.set_size(size);
-            new_entry.set_offset(offset);
-            new_entry.strip_prefix(&self.directory_path)?;
-            new_entries.push(new_entry);
-        }
-        // Write file entries
-        file.seek(SeekFrom::Start(HEADER_SIZE as u64)).map_err(|e| FileSystemError::from(e.to_string()))?;
-        for entry in new_entries {
-            file.write_all(&entry.to_bytes()).map_err(|e| FileSystemError::from(e.to_string()))?;
-        }
-        header.size = file.stream_position().map_err(|e| FileSystemError::from(e.to_string()))?;
-        file.seek(SeekFrom::Start(0)).map_err(|e| FileSystemError::from(e.to_string()))?;
-        file.write_all(&header.to_bytes()).map_err(|e| FileSystemError::from(e.to_string()))?;
+    /// Closes the archive, releasing everything it holds.
+    ///
+    /// Reads open and close their own file handles, so no descriptor stays open between
+    /// them and dropping the archive releases the same resources. `close` makes the release
+    /// explicit and reports any error doing it, which matters if handles are ever kept open
+    /// across reads.
+    ///
+    /// # Errors
+    /// `FileSystemError` if a resource could not be released. Nothing can fail at present.
+    pub fn close(self) -> Result<(), FileSystemError> {
+        self.clear_cache();
         Ok(())
     }
-}
 
+    /// Drops everything in the read cache, if one is enabled.
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
 
-impl From<&FileEntry> for FileInfo {
-    fn from(entry: &FileEntry) -> Self {
-        FileInfo {
-            name: entry.name(),
-            path: entry.path(),
-            size: entry.size,
-            is_directory: false, // Archive entries are not directories
+    /// Returns the prefix every entry below `directory` starts with: empty for the
+    /// root, otherwise the directory path followed by a `/`.
+    fn directory_prefix(directory: &str) -> String {
+        let directory = normalize_path(directory);
+        let directory = directory.trim_end_matches('/');
+        if directory.is_empty() || directory == "." {
+            String::new()
+        } else {
+            format!("{}/", directory)
         }
     }
-}
 
-impl FileSystem for ArchiveFileSystem {
-    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
-        let entry = self.entries.get(path).ok_or(FileSystemError::from("File not found in archive"))?;
-        let mut file = File::open(&self.file_path).map_err(|e| FileSystemError::from(e.to_string()))?;
-        file.seek(SeekFrom::Start(entry.offset)).map_err(|e| FileSystemError::from(e.to_string()))?;
-        let mut content = vec![0u8; entry.size as usize];
-        file.read_exact(&mut content).map_err(|e| FileSystemError::from(e.to_string()))?;
-        self.enc_utils.decrypt(content).map_err(|e| FileSystemError::from(e.to_string()))
+    /// Locks the entry table for reading.
+    fn table(&self) -> RwLockReadGuard<'_, EntryTable> {
+        self.table.read().unwrap_or_else(|e| e.into_inner())
     }
 
-    fn write_file(&self, _path: &str, _content: FileContent) -> Result<(), FileSystemError> {
-        Err(FileSystemError::from("Archive is read-only, cannot write files"))
+    /// Returns how the contents of this archive are stored.
+    pub fn cipher_mode(&self) -> CipherMode {
+        self.header.cipher
     }
 
-    fn delete_file(&self, _path: &str) -> Result<(), FileSystemError> {
-        Err(FileSystemError::from("Archive is read-only, cannot delete files"))
+    /// Returns every file in the archive, sorted by path.
+    ///
+    /// Built from the in-memory index, so unlike `list_files` or `walk` nothing is read
+    /// from the archive file or filtered by directory. The entries are collected up front,
+    /// so a `rename_dir` or `swap_files` during iteration does not affect it. Directories
+    /// are implied by the paths and not yielded.
+    pub fn entries_iter(&self) -> impl Iterator<Item = FileInfo> {
+        let files: Vec<FileInfo> = self.table().sorted_entries.iter().map(|(_, entry)| FileInfo::from(entry)).collect();
+        files.into_iter()
     }
 
-    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
-        let mut file_infos: Vec<FileInfo> = Vec::new();
-        let keys = self.entries.keys().filter(|k| k.starts_with(directory)).cloned().collect::<Vec<_>>();
-        for key in keys {
-            if let Some(entry) = self.entries.get(&key) {
-                let file_info = FileInfo::from(entry);
-                file_infos.push(file_info);
+    /// Returns how many files the archive holds.
+    pub fn entry_count(&self) -> usize {
+        self.table().sorted_entries.len()
+    }
+
+    /// Returns the names of the immediate subdirectories of a directory, sorted, e.g. to
+    /// expand one node of a tree view over a large archive.
+    ///
+    /// Only the range of the sorted index below `directory` is visited, and the whole range
+    /// of each subdirectory is skipped by binary search instead of visiting its files, so
+    /// nothing is allocated for the files themselves. A trailing `/` is ignored.
+    ///
+    /// # Arguments
+    /// - _directory:_ The directory to look in, empty for the root.
+    pub fn child_dirs(&self, directory: &str) -> Vec<String> {
+        let prefix = Self::directory_prefix(directory);
+        let table = self.table();
+        let mut rest = table.prefix_range(&prefix);
+        let mut names = Vec::new();
+        while let Some((path, _)) = rest.first() {
+            match path[prefix.len()..].split_once('/') {
+                None => rest = &rest[1..],
+                Some((name, _)) => {
+                    let child_prefix = format!("{}{}/", prefix, name);
+                    rest = &rest[rest.partition_point(|(path, _)| path.starts_with(&child_prefix))..];
+                    names.push(name.to_string());
+                }
             }
         }
-        let directories: Vec<String> = self.entries.keys()
-            .filter(|k| k.starts_with(directory) && k != &directory)
-            .map(|k| k.split('/').next().unwrap_or("").to_string())
+        names
+    }
+
+    /// Describes where every entry is stored, to find what takes up space in an archive.
+    ///
+    /// Entries are ordered by position, so gaps between one entry's end and the next
+    /// one's offset show space no entry refers to any more, which `compact` reclaims.
+    /// Entries sharing a blob through deduplication have the same offset. Only the index
+    /// read by `open` is used; the archive file is not read, so the overhead is that of
+    /// blobs written in the archive's format version: blobs that `compact` carried over
+    /// from a version 1 archive have no format version byte.
+    pub fn layout_report(&self) -> ArchiveLayout {
+        let overhead = match self.header.cipher {
+            CipherMode::None => 0,
+            _ if self.header.version == LEGACY_VERSION => LEGACY_ENCRYPTION_OVERHEAD as u64,
+            _ => ENCRYPTION_OVERHEAD as u64,
+        };
+        let mut entries: Vec<LayoutEntry> = self.table().sorted_entries.iter()
+            .map(|(path, entry)| LayoutEntry { path: path.clone(), volume: entry.volume, offset: entry.offset, size: entry.size, overhead })
             .collect();
-        for dir in directories {
-            if !file_infos.iter().any(|f| f.path == dir) {
-                file_infos.push(FileInfo {
-                    path: dir.clone(),
-                    name: dir.split("/").last().unwrap_or("").to_string(),
-                    size: 0,
-                    is_directory: true,
-                });
+        // Sorting is stable, so entries sharing a blob stay ordered by path
+        entries.sort_by_key(|entry| (entry.volume, entry.offset));
+        let mut data_size = 0;
+        for (i, entry) in entries.iter().enumerate() {
+            if i == 0 || (entries[i - 1].volume, entries[i - 1].offset) != (entry.volume, entry.offset) {
+                data_size += entry.size;
             }
         }
-        Ok(file_infos)
+        let header_size = header_size(self.header.version) as u64;
+        ArchiveLayout {
+            entries,
+            header_size,
+            table_size: self.header.data_offset.saturating_sub(header_size),
+            data_size,
+        }
     }
-}
 
+    /// Returns how many more files `append_file` can add to the archive.
+    pub fn reserved_entries(&self) -> u32 {
+        self.header.reserved_entries
+    }
 
+    /// Adds a file to the archive, or replaces the file already at `path`.
+    ///
+    /// The content is appended after the existing data, encrypted with the key for the
+    /// default slot, and the entry table is rewritten in place. A new file takes one of the
+    /// slots reserved with `ArchiveCreator::set_reserved_entries`, so nothing already in the
+    /// archive moves; once they run out, the archive has to be created again to grow. A
+    /// replaced file keeps its slot, and its previous content stays in the file until
+    /// `compact` drops it. The update is not atomic: other handles reading the archive at
+    /// the same time, or a crash while the entry table is written, can see it half written.
+    ///
+    /// # Arguments
+    /// - _path:_ Path of the file in the archive.
+    /// - _content:_ Content of the file, stored uncompressed.
+    ///
+    /// # Errors
+    /// `FileSystemError` if no reserved slot is left, the archive has an older format
+    /// version, the path is too long, the default key was not supplied or writing fails.
+    pub fn append_file(&mut self, path: &str, content: &[u8]) -> Result<(), FileSystemError> {
+        let path = normalize_path(path);
+        let name = path.rsplit('/').next().unwrap_or_default();
+        if name.is_empty() {
+            return Err(FileSystemError::from(format!("Invalid file path: {}", path)));
+        }
+        if name.len() > MAX_FILE_NAME_SIZE || path.len() > MAX_PATH_SIZE {
+            return Err(FileSystemError::from(format!(
+                "File name or path too long for an archive entry, at most {} and {} bytes: {}",
+                MAX_FILE_NAME_SIZE, MAX_PATH_SIZE, path
+            )));
+        }
+        self.check_writable()?;
+        #[cfg(feature = "mmap")]
+        if self.maps.is_some() {
+            return Err(FileSystemError::from("Files cannot be appended to a memory-mapped archive, open it with open instead"));
+        }
+        if self.volume_count > 1 {
+            return Err(FileSystemError::from("Files cannot be appended to a multi-volume archive, compact it first"));
+        }
+        let table = self.table.get_mut().unwrap_or_else(|e| e.into_inner());
+        let replaced = table.entries.contains_key(&path);
+        if !replaced && self.header.reserved_entries == 0 {
+            return Err(FileSystemError::from("No reserved entry slots left in the archive, create it again with more"));
+        }
+        let enc_utils = self.keyring.get(&DEFAULT_KEY_SLOT);
+        let blob = match (self.header.cipher, enc_utils) {
+            (CipherMode::None, _) => content.to_vec(),
+            (CipherMode::Aes256Gcm, Some(enc_utils)) => enc_utils.encrypt_slice(content)?,
+            (CipherMode::Aes256Gcm, None) => return Err(FileSystemError::from("The key for the default slot is required to append files")),
+        };
+        let mut entry = FileEntry::new(name, &path, blob.len() as u64, self.header.size);
+        entry.modified = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        entry.hash = Sha256::digest(content).into();
+        entry.uncompressed_size = content.len() as u64;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::enc_utils::EncUtils;
+        let mut sorted_entries = table.sorted_entries.clone();
+        match sorted_entries.binary_search_by(|(entry_path, _)| entry_path.as_str().cmp(&path)) {
+            Ok(index) => sorted_entries[index].1 = entry.clone(),
+            Err(index) => sorted_entries.insert(index, (path.clone(), entry.clone())),
+        }
+        let header = Header {
+            number_of_files: sorted_entries.len() as u32,
+            size: self.header.size + blob.len() as u64,
+            reserved_entries: self.header.reserved_entries - u32::from(!replaced),
+            ..self.header
+        };
+        let mut file = std::fs::OpenOptions::new().write(true).open(&self.file_path).map_err(FileSystemError::from)?;
+        file.seek(SeekFrom::Start(entry.offset)).map_err(FileSystemError::from)?;
+        file.write_all(&blob).map_err(FileSystemError::from)?;
+        self.rewrite_index(&mut file, &header, &sorted_entries)?;
 
-    #[test]
-    fn test_archive() {
-        let key = EncUtils::generate_random_key();
-        let mut creator = ArchiveCreator::new("test_directory", "test_archive.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
-        creator.create().expect("Failed to create archive");
-        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_archive.arc"), key).expect("Failed to open archive");
-        assert!(!archive_fs.entries.is_empty(), "Archive should contain files");
-        let files = archive_fs.list_files("").expect("Failed to list files in archive");
-        assert!(!files.is_empty(), "Archive should list files");
-        for file in files {
-            println!("{}", file.path);
+        self.header = header;
+        let table = self.table.get_mut().unwrap_or_else(|e| e.into_inner());
+        table.sorted_entries = sorted_entries;
+        table.entries.insert(path.clone(), entry);
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap_or_else(|e| e.into_inner()).remove(&path);
+        }
+        self.notify(FsEvent::Write { path: &path, bytes: content.len() as u64 });
+        Ok(())
+    }
+
+    /// Renames a directory by rewriting the paths of the entries below it. This is what
+    /// `FileSystem::rename_dir` does on archives.
+    ///
+    /// Only the entry table is rewritten in place; no file content moves, so this is much
+    /// cheaper than copying the files and takes no reserved slots. Other handles on the
+    /// same archive see the change once they open it again. It is not atomic, like
+    /// `append_file`.
+    ///
+    /// # Arguments
+    /// - _from:_ The directory to rename.
+    /// - _to:_ Its new path, empty to move its contents to the root.
+    ///
+    /// # Errors
+    /// `FileSystemError` with `FileSystemErrorKind::NotFound` if no file is below `from`,
+    /// or a plain one if `to` already holds a file the renamed ones would replace, a new
+    /// path is too long, the archive has an older format version or writing fails.
+    pub fn rename_entries(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        let from_prefix = Self::directory_prefix(from);
+        let to_prefix = Self::directory_prefix(to);
+        if from_prefix.is_empty() {
+            return Err(FileSystemError::from("Cannot rename the archive root"));
+        }
+        if to_prefix.starts_with(&from_prefix) {
+            return Err(FileSystemError::from(format!("Cannot move {} into itself", from)));
+        }
+        self.check_writable()?;
+        let mut table = self.table.write().unwrap_or_else(|e| e.into_inner());
+        if table.prefix_range(&from_prefix).is_empty() {
+            return Err(FileSystemError::not_found(from));
         }
+        if table.entries.contains_key(to_prefix.trim_end_matches('/')) {
+            return Err(FileSystemError::from(format!("Destination is a file: {}", to)));
+        }
+        let mut sorted_entries: Vec<(String, FileEntry)> = Vec::with_capacity(table.sorted_entries.len());
+        for (path, entry) in &table.sorted_entries {
+            let Some(rest) = path.strip_prefix(&from_prefix) else {
+                sorted_entries.push((path.clone(), entry.clone()));
+                continue;
+            };
+            let new_path = format!("{}{}", to_prefix, rest);
+            if new_path.len() > MAX_PATH_SIZE {
+                return Err(FileSystemError::from(format!("Path too long for an archive entry, at most {} bytes: {}", MAX_PATH_SIZE, new_path)));
+            }
+            if table.entries.contains_key(&new_path) {
+                return Err(FileSystemError::from(format!("Destination already contains {}", new_path)));
+            }
+            let mut entry = entry.clone();
+            entry.set_path(&new_path);
+            sorted_entries.push((new_path, entry));
+        }
+        sorted_entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut file = std::fs::OpenOptions::new().write(true).open(&self.file_path).map_err(FileSystemError::from)?;
+        self.rewrite_index(&mut file, &self.header, &sorted_entries)?;
+
+        if let Some(cache) = &self.cache {
+            let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+            for (path, _) in table.prefix_range(&from_prefix) {
+                cache.remove(path);
+            }
+        }
+        *table = EntryTable::new(sorted_entries);
+        Ok(())
+    }
+
+    /// Swaps the contents of two files by exchanging where their entries point. This is
+    /// what `FileSystem::swap_files` does on archives.
+    ///
+    /// Only the entry table is rewritten in place, no file content moves. Like
+    /// `rename_entries`, it is not atomic.
+    ///
+    /// # Arguments
+    /// - _a:_ The first file.
+    /// - _b:_ The second file.
+    ///
+    /// # Errors
+    /// `FileSystemError` with `FileSystemErrorKind::NotFound` if either file is missing, or
+    /// a plain one if the archive has an older format version or writing fails.
+    pub fn swap_entries(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        let (a, b) = (normalize_path(a), normalize_path(b));
+        let mut table = self.table.write().unwrap_or_else(|e| e.into_inner());
+        let entry_a = table.entries.get(&a).ok_or_else(|| FileSystemError::not_found(&a))?.clone();
+        let entry_b = table.entries.get(&b).ok_or_else(|| FileSystemError::not_found(&b))?.clone();
+        self.check_writable()?;
+        if a == b {
+            return Ok(());
+        }
+        // Each path keeps its own name and path fields and takes the other's content
+        let swapped = |target: &FileEntry, source: &FileEntry| FileEntry { name: target.name, path: target.path, ..source.clone() };
+        let (new_a, new_b) = (swapped(&entry_a, &entry_b), swapped(&entry_b, &entry_a));
+        let mut sorted_entries = table.sorted_entries.clone();
+        for (path, entry) in sorted_entries.iter_mut() {
+            if *path == a {
+                *entry = new_a.clone();
+            } else if *path == b {
+                *entry = new_b.clone();
+            }
+        }
+        let mut file = std::fs::OpenOptions::new().write(true).open(&self.file_path).map_err(FileSystemError::from)?;
+        self.rewrite_index(&mut file, &self.header, &sorted_entries)?;
+
+        if let Some(cache) = &self.cache {
+            let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache.remove(&a);
+            cache.remove(&b);
+        }
+        table.entries.insert(a, new_a);
+        table.entries.insert(b, new_b);
+        table.sorted_entries = sorted_entries;
+        Ok(())
+    }
+
+    /// Rejects changes to archives whose entry table has an older layout, which
+    /// `write_index` cannot write back in place.
+    fn check_writable(&self) -> Result<(), FileSystemError> {
+        if self.source.is_some() {
+            return Err(FileSystemError::from("Archive was opened from a reader and cannot be changed"));
+        }
+        if self.header.version != ARCHIVE_VERSION {
+            return Err(FileSystemError::from(format!(
+                "Only archives of format version {} can be changed in place, compact this one first",
+                ARCHIVE_VERSION
+            )));
+        }
+        Ok(())
+    }
+
+    /// Writes the header and entry table over the current ones, encrypting them again if
+    /// the index is encrypted.
+    fn rewrite_index(&self, file: &mut File, header: &Header, sorted_entries: &[(String, FileEntry)]) -> Result<(), FileSystemError> {
+        let entries: Vec<FileEntry> = sorted_entries.iter().map(|(_, entry)| entry.clone()).collect();
+        write_index(file, header, &entries, self.keyring.get(&DEFAULT_KEY_SLOT))
+    }
+
+    /// Decrypts the stored blob of an entry in place with the key of its slot, or leaves
+    /// it as is for unencrypted archives.
+    fn decode(&self, entry: &FileEntry, content: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        if self.header.cipher == CipherMode::None {
+            return Ok(());
+        }
+        match self.keyring.get(&entry.key_slot) {
+            Some(enc_utils) => enc_utils.decrypt_in_place(content),
+            None => Err(FileSystemError::from(format!("Key for slot {} is not available to read {}", entry.key_slot, entry.path()))),
+        }
+    }
+
+    /// Reads, decodes and decompresses an entry into `buf`, from the mapped volume or else
+    /// through `file`, which holds the last volume opened and is replaced if the entry is
+    /// in another one.
+    fn read_entry(&self, file: &mut Option<(u16, File)>, entry: &FileEntry, buf: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        self.check_read_size(entry)?;
+        buf.clear();
+        if let Some(blob) = self.mapped(entry)? {
+            buf.extend_from_slice(blob);
+        } else {
+            buf.resize(entry.size as usize, 0);
+            self.read_at(file, entry.volume, entry.offset, buf)?;
+        }
+        self.decode(entry, buf)?;
+        if entry.codec == NO_COMPRESSION {
+            return Ok(());
+        }
+        let compressor = self.compressors.get(&entry.codec).ok_or(FileSystemError::from(format!(
+            "{} is compressed with codec {}, register a Compressor for it with with_compressor",
+            entry.path(), entry.codec
+        )))?;
+        *buf = compressor.decompress(buf, entry.uncompressed_size as usize)?;
+        if buf.len() as u64 != entry.uncompressed_size {
+            return Err(FileSystemError::from(format!("{} is corrupted, it decompresses to {} bytes instead of {}", entry.path(), buf.len(), entry.uncompressed_size)));
+        }
+        Ok(())
+    }
+
+    /// Returns how many files the archive is split into, counting the archive file itself.
+    pub fn volume_count(&self) -> u32 {
+        self.volume_count
+    }
+
+    /// Returns the stored blob of an entry from its mapped volume, or `None` if the
+    /// archive is not memory-mapped.
+    #[cfg(feature = "mmap")]
+    fn mapped(&self, entry: &FileEntry) -> Result<Option<&[u8]>, FileSystemError> {
+        let Some(maps) = &self.maps else {
+            return Ok(None);
+        };
+        let blob = maps.get(entry.volume as usize)
+            .and_then(|map| map.as_slice().get(entry.offset as usize..(entry.offset + entry.size) as usize))
+            .ok_or(FileSystemError::from(format!("Entry {} exceeds the size of its archive volume", entry.path())))?;
+        Ok(Some(blob))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn mapped(&self, _entry: &FileEntry) -> Result<Option<&[u8]>, FileSystemError> {
+        Ok(None)
+    }
+
+    /// Fills `buf` from `offset` in a volume, through the reader if opened with
+    /// `open_from`, or else through `file`, which holds the last volume opened and is
+    /// replaced if it is another one.
+    fn read_at(&self, file: &mut Option<(u16, File)>, volume: u16, offset: u64, buf: &mut [u8]) -> Result<(), FileSystemError> {
+        if let Some(source) = &self.source {
+            let mut source = source.lock().unwrap_or_else(|e| e.into_inner());
+            source.seek(SeekFrom::Start(offset)).map_err(FileSystemError::from)?;
+            return source.read_exact(buf).map_err(FileSystemError::from);
+        }
+        if file.as_ref().is_none_or(|(open, _)| *open != volume) {
+            *file = Some((volume, self.open_volume(volume)?));
+        }
+        let (_, file) = file.as_mut().expect("volume opened above");
+        file.seek(SeekFrom::Start(offset)).map_err(FileSystemError::from)?;
+        file.read_exact(buf).map_err(FileSystemError::from)
+    }
+
+    /// Opens the file holding the given volume of the archive.
+    fn open_volume(&self, volume: u16) -> Result<File, FileSystemError> {
+        File::open(volume_path(&self.file_path, volume)).map_err(FileSystemError::from)
+    }
+
+    /// Reads the stored (encrypted) blob of an entry without decrypting it.
+    fn read_raw(&self, entry: &FileEntry) -> Result<FileContent, FileSystemError> {
+        self.check_read_size(entry)?;
+        if let Some(blob) = self.mapped(entry)? {
+            return Ok(blob.to_vec());
+        }
+        let mut content = vec![0u8; entry.size as usize];
+        self.read_at(&mut None, entry.volume, entry.offset, &mut content)?;
+        Ok(content)
+    }
+
+    /// Rewrites the archive to `output` with only the data its entries still refer to.
+    ///
+    /// Stored blobs are copied as they are, without decrypting them, and laid out back to
+    /// back, so the cipher mode, key slots, hashes, modification times, flags and reserved
+    /// entry slots all carry over and files shared by deduplication stay shared. The volumes
+    /// of a multi-volume archive are merged into one file. Anything else in the file, such
+    /// as unreferenced blobs or trailing data, is dropped. An
+    /// encrypted index is encrypted again with the key for the default slot. `output` may
+    /// be this archive's own path: the new archive is moved into place once it is complete,
+    /// and this instance keeps reading the old one.
+    ///
+    /// # Arguments
+    /// - _output:_ Path of the compacted archive.
+    ///
+    /// # Returns
+    /// How many bytes smaller the compacted archive is than this one.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive cannot be read or the output cannot be written.
+    pub fn compact(&self, output: &str) -> Result<u64, FileSystemError> {
+        let original_size = match &self.source {
+            Some(source) => source.lock().unwrap_or_else(|e| e.into_inner()).seek(SeekFrom::End(0)).map_err(FileSystemError::from)?,
+            None => std::fs::metadata(&self.file_path).map_err(FileSystemError::from)?.len(),
+        };
+        let size = replace_with(Path::new(output), |path| {
+            self.rewrite_to(path, self.keyring.get(&DEFAULT_KEY_SLOT), true, |_, blob| Ok(blob))
+        })?;
+        Ok(original_size.saturating_sub(size))
+    }
+
+    /// Writes a copy of the archive to `output` with the files of the default slot
+    /// encrypted under a new key.
+    ///
+    /// Each of those files is decrypted with the current key and encrypted again, without
+    /// decompressing it, so rotating a key needs no extraction. Files in other key slots are
+    /// copied as they are and keep their keys, and an encrypted index is encrypted with the
+    /// new key. Otherwise the copy is laid out like `compact` would. Before the copy is
+    /// moved into place, the first two files it re-encrypted are decrypted with `new_key`
+    /// and compared with the originals. `output` may be this archive's own path. The copy
+    /// records no password salt, even if the current key came from a password, so it is
+    /// opened with `open` and `new_key`.
+    ///
+    /// # Arguments
+    /// - _output:_ Path of the re-encrypted archive.
+    /// - _new_key:_ The key for the default slot of the new archive.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is not encrypted, it was opened without the key for
+    /// the default slot, `new_key` is invalid, the copy does not read back or the output
+    /// cannot be written.
+    pub fn reencrypt(&self, output: &str, new_key: EncKey) -> Result<(), FileSystemError> {
+        if self.header.cipher == CipherMode::None {
+            return Err(FileSystemError::from("Archive is not encrypted, there is no key to change"));
+        }
+        if !self.keyring.contains_key(&DEFAULT_KEY_SLOT) {
+            return Err(FileSystemError::from("The key for the default slot is required to re-encrypt the archive"));
+        }
+        let new_enc_utils = EncUtils::new(new_key.clone())?;
+        replace_with(Path::new(output), |path| {
+            let size = self.rewrite_to(path, Some(&new_enc_utils), false, |entry, mut blob| {
+                if entry.key_slot != DEFAULT_KEY_SLOT {
+                    return Ok(blob);
+                }
+                self.decode(entry, &mut blob)?;
+                new_enc_utils.encrypt(blob)
+            })?;
+            let reencrypted = Self::open_with(path.to_path_buf(), Some(HashMap::from([(DEFAULT_KEY_SLOT, new_key)])))?;
+            let samples: Vec<(String, FileEntry)> = self.table().sorted_entries.iter()
+                .filter(|(_, entry)| entry.key_slot == DEFAULT_KEY_SLOT)
+                .take(2)
+                .cloned()
+                .collect();
+            for (path, entry) in &samples {
+                let new_entry = reencrypted.table().entries.get(path).cloned().ok_or(FileSystemError::not_found(path))?;
+                let (mut expected, mut actual) = (self.read_raw(entry)?, reencrypted.read_raw(&new_entry)?);
+                self.decode(entry, &mut expected)?;
+                reencrypted.decode(&new_entry, &mut actual)?;
+                if actual != expected {
+                    return Err(FileSystemError::from(format!("Re-encrypted archive does not read back {} correctly", path)));
+                }
+            }
+            Ok(size)
+        })?;
+        Ok(())
+    }
+
+    /// Writes the archive to `path` as a single file with its blobs laid out back to back,
+    /// passing each stored blob through `transform`, and returns the size of the new archive.
+    ///
+    /// # Arguments
+    /// - _index_key:_ The key an encrypted index is encrypted with.
+    /// - _keep_password:_ Whether the default key is unchanged, so the password salt still applies.
+    /// - _transform:_ Turns the stored blob of an entry into the one to write. Blobs shared
+    ///   by several entries are transformed once.
+    fn rewrite_to(&self, path: &Path, index_key: Option<&EncUtils>, keep_password: bool, transform: impl Fn(&FileEntry, Vec<u8>) -> Result<Vec<u8>, FileSystemError>) -> Result<u64, FileSystemError> {
+        let mut source: Option<(u16, File)> = None;
+        let mut file = File::create(path).map_err(FileSystemError::from)?;
+        let mut entries: Vec<FileEntry> = self.table().sorted_entries.iter().map(|(_, entry)| entry.clone()).collect();
+        let index_overhead = if self.header.flags & FLAG_ENCRYPTED_INDEX != 0 { (HEADER_SIZE + ENCRYPTION_OVERHEAD) as u64 } else { 0 };
+        let mut header = Header {
+            version: ARCHIVE_VERSION,
+            cipher: self.header.cipher,
+            flags: self.header.flags,
+            number_of_files: entries.len() as u32,
+            size: 0, // Will be updated later
+            data_offset: HEADER_SIZE as u64 + (entries.len() as u64 + self.header.reserved_entries as u64) * FILE_ENTRY_SIZE as u64 + index_overhead,
+            reserved_entries: self.header.reserved_entries,
+            password_salt: if keep_password { self.header.password_salt } else { [0u8; PASSWORD_SALT_SIZE] },
+            password_iterations: if keep_password { self.header.password_iterations } else { 0 },
+        };
+        file.seek(SeekFrom::Start(header.data_offset)).map_err(FileSystemError::from)?;
+        // Copy blobs in their current order, so reading the source is sequential
+        entries.sort_by_key(|entry| (entry.volume, entry.offset));
+        // Old volume and offset -> new offset and size of every blob copied so far
+        let mut moved: HashMap<(u16, u64), (u64, u64)> = HashMap::new();
+        for entry in entries.iter_mut() {
+            let volume = std::mem::take(&mut entry.volume);
+            if let Some(&(offset, size)) = moved.get(&(volume, entry.offset)) {
+                entry.set_offset(offset);
+                entry.set_size(size);
+                continue;
+            }
+            let mut content = vec![0u8; entry.size as usize];
+            self.read_at(&mut source, volume, entry.offset, &mut content)?;
+            let content = transform(entry, content)?;
+            let offset = file.stream_position().map_err(FileSystemError::from)?;
+            file.write_all(&content).map_err(FileSystemError::from)?;
+            moved.insert((volume, entry.offset), (offset, content.len() as u64));
+            entry.set_offset(offset);
+            entry.set_size(content.len() as u64);
+        }
+        header.size = file.stream_position().map_err(FileSystemError::from)?;
+        write_index(&mut file, &header, &entries, index_key)?;
+        Ok(header.size)
+    }
+}
+
+
+pub struct ArchiveCreator {
+    directory_path: PathBuf,
+    file_path: PathBuf,
+    /// Keys by slot, empty when creating an unencrypted archive
+    keys: HashMap<u8, EncUtils>,
+    /// Glob patterns and the key slot files matching them are encrypted with
+    key_assignments: Vec<(String, u8)>,
+    /// Source path of each scanned file and its entry, keyed by the path relative to the source directory
+    file_entries: Vec<(PathBuf, FileEntry)>,
+    deduplicate: bool,
+    encrypt_index: bool,
+    exclude: Vec<String>,
+    include: Vec<String>,
+    progress: Option<Box<dyn FnMut(usize, usize)>>,
+    threads: usize,
+    compressor: Option<Box<dyn Compressor>>,
+    reserved_entries: u32,
+    follow_symlinks: bool,
+    volume_size: Option<u64>,
+    /// Salt and iterations the default key was derived from a password with
+    password: Option<([u8; PASSWORD_SALT_SIZE], u32)>,
+}
+
+/// Encrypted content of a file to archive, along with its plaintext hash.
+struct PreparedFile {
+    hash: [u8; HASH_SIZE],
+    content: FileContent,
+    reused: bool,
+    /// Codec the content is compressed with
+    codec: u8,
+    uncompressed_size: u64,
+}
+
+/// What identifies a blob for deduplication: plaintext hash, key slot and codec.
+type BlobKey = ([u8; HASH_SIZE], u8, u8);
+
+/// Summary of an incremental archive update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IncrementalReport {
+    /// Files whose encrypted blob was copied from the existing archive
+    pub reused: usize,
+    /// Files that were read and encrypted again
+    pub encrypted: usize,
+}
+
+impl ArchiveCreator {
+    pub fn new(directory_path: &str, file_path: &str, key: EncKey, overwrite: bool) -> Result<Self, FileSystemError> {
+        Self::new_with(directory_path, file_path, Some(key), overwrite)
+    }
+
+    /// Creates an `ArchiveCreator` that stores files without encryption (`CipherMode::None`).
+    ///
+    /// Such archives carry no nonce or tag overhead and are opened with
+    /// `ArchiveFileSystem::open_unencrypted`.
+    pub fn new_unencrypted(directory_path: &str, file_path: &str, overwrite: bool) -> Result<Self, FileSystemError> {
+        Self::new_with(directory_path, file_path, None, overwrite)
+    }
+
+    fn new_with(directory_path: &str, file_path: &str, key: Option<EncKey>, overwrite: bool) -> Result<Self, FileSystemError> {
+        let directory_path = PathBuf::from(directory_path);
+        let file_path = PathBuf::from(file_path);
+        if !directory_path.is_dir() {
+            return Err(FileSystemError::from("Provided source directory path is not a directory"));
+        }
+        if file_path.exists() && !overwrite {
+            return Err(FileSystemError::from("Archive file already exists and overwrite is not allowed"));
+        }
+        let keys = match key {
+            Some(key) => HashMap::from([(DEFAULT_KEY_SLOT, EncUtils::new(key)?)]),
+            None => HashMap::new(),
+        };
+        Ok(ArchiveCreator {
+            directory_path,
+            file_path,
+            keys,
+            key_assignments: Vec::new(),
+            file_entries: Vec::new(),
+            deduplicate: false,
+            encrypt_index: false,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            progress: None,
+            threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            compressor: None,
+            reserved_entries: 0,
+            follow_symlinks: false,
+            volume_size: None,
+            password: None,
+        })
+    }
+
+    /// Sets how many threads `create` uses to read and encrypt files.
+    ///
+    /// Defaults to the available parallelism. Files are always written in scan order,
+    /// so the layout of the archive does not depend on the thread count.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Compresses files with `compressor` before encrypting them.
+    ///
+    /// Each entry records the codec id, so readers know how to decompress it; codecs
+    /// other than the built-in ones must be registered with
+    /// `ArchiveFileSystem::with_compressor`. Files that do not get smaller are stored
+    /// uncompressed. Files are stored uncompressed by default.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the codec uses the reserved id `NO_COMPRESSION`.
+    pub fn set_compressor(&mut self, compressor: Box<dyn Compressor>) -> Result<(), FileSystemError> {
+        if compressor.id() == NO_COMPRESSION {
+            return Err(FileSystemError::from(format!("Codec id {} is reserved for uncompressed entries", NO_COMPRESSION)));
+        }
+        self.compressor = Some(compressor);
+        Ok(())
+    }
+
+    /// Sets a callback invoked by `create` after each file is written.
+    ///
+    /// The callback receives `(files_done, files_total)`.
+    pub fn set_progress(&mut self, callback: impl FnMut(usize, usize) + 'static) {
+        self.progress = Some(Box::new(callback));
+    }
+
+    /// Adds a glob pattern for files and directories to leave out of the archive.
+    ///
+    /// A pattern matches an entry if it matches either its path relative to the source
+    /// directory (with `/` separators) or just its name, so `*.tmp`, `.DS_Store` and
+    /// `assets/raw` all work as expected. Excluded directories are not scanned at all.
+    /// See [`glob_match`] for the supported syntax.
+    pub fn add_exclude(&mut self, pattern: &str) {
+        self.exclude.push(pattern.to_string());
+        self.file_entries.clear();
+    }
+
+    /// Adds a glob pattern to the include allowlist.
+    ///
+    /// Once any include pattern is set, only files matching at least one of them are
+    /// packed. Directories are always scanned unless excluded. Patterns match the same
+    /// way as in `add_exclude`.
+    pub fn add_include(&mut self, pattern: &str) {
+        self.include.push(pattern.to_string());
+        self.file_entries.clear();
+    }
+
+    /// Encrypts files matching a glob pattern with a separate key.
+    ///
+    /// Files are assigned to the slot of the first pattern they match, in the order the
+    /// patterns were added, and to `DEFAULT_KEY_SLOT` (the creator's key) otherwise.
+    /// Patterns match the same way as in `add_exclude`. Adding the same slot again with
+    /// another pattern assigns more files to it; its key must then be the same.
+    ///
+    /// # Arguments
+    /// - _slot:_ The key slot, anything but `DEFAULT_KEY_SLOT`.
+    /// - _key:_ The key for that slot.
+    /// - _pattern:_ Glob pattern for the files to encrypt with it.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is unencrypted, the slot is the default one,
+    /// the key is invalid or differs from the key already registered for the slot.
+    pub fn add_key_slot(&mut self, slot: u8, key: EncKey, pattern: &str) -> Result<(), FileSystemError> {
+        if self.keys.is_empty() {
+            return Err(FileSystemError::from("Unencrypted archives do not take keys"));
+        }
+        if slot == DEFAULT_KEY_SLOT {
+            return Err(FileSystemError::from("The default key slot is reserved for the archive key"));
+        }
+        let enc_utils = EncUtils::new(key)?;
+        if self.keys.get(&slot).is_some_and(|existing| *existing != enc_utils) {
+            return Err(FileSystemError::from(format!("Key slot {} already has a different key", slot)));
+        }
+        self.keys.insert(slot, enc_utils);
+        self.key_assignments.push((pattern.to_string(), slot));
+        self.file_entries.clear();
+        Ok(())
+    }
+
+    fn matches_any(patterns: &[String], relative_path: &str, name: &str) -> bool {
+        patterns.iter().any(|p| glob_match(p, relative_path) || glob_match(p, name))
+    }
+
+    /// Enables or disables deduplication of identical files.
+    ///
+    /// When enabled, files are hashed before encryption and every file whose plaintext
+    /// matches an already written file points at that file's encrypted blob instead of
+    /// storing another copy. Only files in the same key slot and with the same codec share
+    /// a blob, since it is encrypted under one key. Since the blob is shared, anyone who can read the entry table
+    /// can tell which files have identical content, even without the key. Disabled by default.
+    ///
+    /// # Arguments
+    /// - _deduplicate:_ If true, identical files are stored only once.
+    pub fn set_deduplicate(&mut self, deduplicate: bool) {
+        self.deduplicate = deduplicate;
+    }
+
+    /// Makes the scan follow symbolic links in the source directory, or skip them.
+    ///
+    /// Archives have no entry kind for links, so when not following, which is the default,
+    /// links are left out entirely; this keeps a link in an untrusted tree from pulling in
+    /// files outside it. When following, linked files are packed with their target's
+    /// content and linked directories are scanned, which never ends on a cyclic link.
+    ///
+    /// # Arguments
+    /// - _follow:_ If true, symbolic links are followed.
+    pub fn set_follow_symlinks(&mut self, follow: bool) {
+        self.follow_symlinks = follow;
+    }
+
+    /// Splits the archive into volumes of at most `volume_size` bytes, e.g. to fit on
+    /// media with a size limit.
+    ///
+    /// The first volume is the archive file itself and holds the header, the entry table and
+    /// the first files; further volumes hold only file contents and are named after the
+    /// archive file with a three-digit number appended: `game.arc`, `game.arc.001`,
+    /// `game.arc.002` and so on. A file is never split across volumes: a volume ends early
+    /// when the next file does not fit, so creating fails if a single file, or the header
+    /// and entry table, is larger than a volume. `ArchiveFileSystem::open` takes the path
+    /// of the first volume and opens the others as files in them are read, so all volumes
+    /// must be in the same directory. Volumes left over from an earlier archive with more
+    /// of them are removed. By default archives are a single file.
+    ///
+    /// # Arguments
+    /// - _volume_size:_ The maximum size of each volume in bytes, `None` for a single file.
+    pub fn set_volume_size(&mut self, volume_size: Option<u64>) {
+        self.volume_size = volume_size;
+    }
+
+    /// Preallocates free slots in the entry table for `ArchiveFileSystem::append_file`.
+    ///
+    /// The entry table sits between the header and the file data, so without free slots an
+    /// archive can only gain files by being created again. Each reserved slot takes as much
+    /// space as an entry, about 340 bytes, whether it is used or not: reserve
+    /// about as many as you expect to append before the next full rebuild. No slots are
+    /// reserved by default.
+    ///
+    /// # Arguments
+    /// - _reserved_entries:_ How many files can be appended to the archive.
+    pub fn set_reserved_entries(&mut self, reserved_entries: u32) {
+        self.reserved_entries = reserved_entries;
+    }
+
+    /// Enables or disables encryption of the header and entry table.
+    ///
+    /// By default file names, paths, sizes and hashes are stored in the clear, so anyone
+    /// can list an encrypted archive without the key. When enabled, they are encrypted
+    /// with the archive key (`DEFAULT_KEY_SLOT`) and only the format version, cipher mode
+    /// and the offset where the file data starts remain readable. The archive can then only
+    /// be opened with the default key. Disabled by default.
+    ///
+    /// # Arguments
+    /// - _encrypt_index:_ If true, the header and entry table are encrypted.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is unencrypted.
+    pub fn set_encrypt_index(&mut self, encrypt_index: bool) -> Result<(), FileSystemError> {
+        if encrypt_index && self.keys.is_empty() {
+            return Err(FileSystemError::from("Unencrypted archives cannot encrypt their index"));
+        }
+        self.encrypt_index = encrypt_index;
+        Ok(())
+    }
+
+    /// Encrypts the archive with a key derived from a password instead of the key given
+    /// to `new`.
+    ///
+    /// A fresh random salt is generated and stored in the archive header along with the
+    /// iteration count, so `ArchiveFileSystem::open_with_password` needs only the password.
+    /// The salt is public; the archive is as strong as the password. Files assigned to
+    /// other key slots keep their keys.
+    ///
+    /// # Arguments
+    /// - _password:_ The password to derive the key from.
+    /// - _iterations:_ PBKDF2 iterations, at least `PASSWORD_ITERATIONS` is recommended.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the archive is unencrypted, the password is empty or
+    /// `iterations` is 0.
+    pub fn set_password(&mut self, password: &str, iterations: u32) -> Result<(), FileSystemError> {
+        if self.keys.is_empty() {
+            return Err(FileSystemError::from("Unencrypted archives cannot use a password"));
+        }
+        let salt = EncUtils::generate_salt();
+        let key = EncUtils::derive_key(password, &salt, iterations)?;
+        self.keys.insert(DEFAULT_KEY_SLOT, EncUtils::new(key)?);
+        self.password = Some((salt, iterations));
+        Ok(())
+    }
+
+    /// Creates an encrypted archive at `output` from files that `unpack` writes into a
+    /// staging directory next to it, `<output>.import`, which is removed afterwards. Used
+    /// by the importers for other archive formats.
+    ///
+    /// Fails rather than reuse a staging directory that already exists, since it may
+    /// belong to someone else.
+    #[cfg(any(feature = "tar", feature = "zip"))]
+    pub(crate) fn from_unpacked<R>(output: &str, key: EncKey, unpack: impl FnOnce(&Path) -> Result<R, FileSystemError>) -> Result<R, FileSystemError> {
+        if Path::new(output).exists() {
+            return Err(FileSystemError::from("Archive file already exists and overwrite is not allowed"));
+        }
+        let staging = PathBuf::from(format!("{}.import", output));
+        if let Some(parent) = staging.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(FileSystemError::from)?;
+        }
+        std::fs::create_dir(&staging).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => FileSystemError::from(format!(
+                "Staging directory {} already exists, remove it or choose another output", staging.display()
+            )),
+            _ => FileSystemError::from(e),
+        })?;
+        let result = unpack(&staging).and_then(|unpacked| {
+            ArchiveCreator::new(&staging.to_string_lossy(), output, key, false)?.create()?;
+            Ok(unpacked)
+        });
+        std::fs::remove_dir_all(&staging).ok();
+        result
+    }
+
+    /// Returns a builder for configuring an `ArchiveCreator` step by step.
+    pub fn builder() -> ArchiveCreatorBuilder {
+        ArchiveCreatorBuilder::default()
+    }
+
+    fn scan_directory(&mut self, path: &PathBuf) -> Result<(), FileSystemError> {
+        if !path.is_dir() {
+            return Err(FileSystemError::from("Provided path is not a directory"));
+        }
+        for entry in std::fs::read_dir(path).map_err(FileSystemError::from)? {
+            let entry = entry.map_err(FileSystemError::from)?;
+            let entry_path = entry.path();
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let relative_path = entry_path.strip_prefix(&self.directory_path).unwrap_or(&entry_path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if Self::matches_any(&self.exclude, &relative_path, &file_name) {
+                continue;
+            }
+            if !self.follow_symlinks && entry.file_type().map_err(FileSystemError::from)?.is_symlink() {
+                continue;
+            }
+            if entry_path.is_dir() {
+                self.scan_directory(&entry_path)?;
+            } else if entry_path.is_file() {
+                if !self.include.is_empty() && !Self::matches_any(&self.include, &relative_path, &file_name) {
+                    continue;
+                }
+                // Of the link target, for followed links
+                let metadata = std::fs::metadata(&entry_path).map_err(FileSystemError::from)?;
+                let mut entry = FileEntry::new(
+                    &file_name,
+                    &relative_path,
+                    metadata.len(), // An updated file size will be set later
+                    0, // Offset will be set later
+                );
+                entry.modified = metadata.modified().ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_nanos() as u64);
+                entry.key_slot = self.key_assignments.iter()
+                    .find(|(pattern, _)| glob_match(pattern, &relative_path) || glob_match(pattern, &file_name))
+                    .map_or(DEFAULT_KEY_SLOT, |&(_, slot)| slot);
+                self.file_entries.push((entry_path, entry));
+            } else {
+                self.scan_directory(&entry_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scans the source directory and returns the files `create` would pack, without
+    /// writing anything.
+    ///
+    /// The returned sizes are plaintext sizes. The next `create` or `create_incremental`
+    /// packs exactly these files instead of scanning again, unless an exclude, include or
+    /// key slot pattern is added in between. Files that change after planning are packed
+    /// with their new content, but incremental updates compare against the planned size
+    /// and modification time, so plan right before creating.
+    ///
+    /// # Returns
+    /// One `FileInfo` per file to pack, in the order they will be written.
+    pub fn plan(&mut self) -> Result<Vec<FileInfo>, FileSystemError> {
+        self.scan()?;
+        Ok(self.file_entries.iter().map(|(_, entry)| FileInfo::from(entry)).collect())
+    }
+
+    fn scan(&mut self) -> Result<(), FileSystemError> {
+        let directory_path = self.directory_path.clone();
+        self.file_entries.clear();
+        self.scan_directory(&directory_path)
+    }
+
+    pub fn create(&mut self) -> Result<(), FileSystemError> {
+        self.write_archive(None).map(|_| ())
+    }
+
+    /// Creates the archive, copying the encrypted blobs of unchanged files from an existing
+    /// archive instead of encrypting them again.
+    ///
+    /// A file is considered unchanged when the existing archive has an entry at the same path
+    /// with the same size and modification time, or failing that, the same content hash, and
+    /// its blob is encrypted with the same key as this creator would use for it. The existing
+    /// archive must use the same cipher mode. The output may be the existing archive itself,
+    /// since the new archive is only moved into place once it is complete.
+    ///
+    /// # Returns
+    /// How many files were reused versus encrypted again.
+    pub fn create_incremental(&mut self, existing: &ArchiveFileSystem) -> Result<IncrementalReport, FileSystemError> {
+        if existing.cipher_mode() != self.cipher_mode() {
+            return Err(FileSystemError::from("Existing archive uses a different cipher mode"));
+        }
+        self.write_archive(Some(existing))
+    }
+
+    /// Appends the archive to the end of an existing file, typically an executable, instead
+    /// of writing it to the output path, so a game can ship as a single file. Read it back
+    /// with `ArchiveFileSystem::open_embedded`.
+    ///
+    /// The archive is followed by a 16-byte trailer: the offset in the file where the
+    /// archive starts, as a little-endian u64, and the magic `EVFSEMBD`. The trailer is
+    /// what `open_embedded` looks for at the end of the file. If the file has an archive
+    /// appended already, it is replaced. Executables generally still run with data
+    /// appended, but code signatures have to be applied afterwards.
+    ///
+    /// # Arguments
+    /// - _existing_binary:_ Path of the file to append the archive to.
+    ///
+    /// # Errors
+    /// `FileSystemError` if volumes are set with `set_volume_size`, there are no files to
+    /// archive, or the archive or the file cannot be written. Writing to an executable
+    /// that is running fails on some platforms.
+    pub fn append_to(&mut self, existing_binary: &str) -> Result<(), FileSystemError> {
+        if self.volume_size.is_some() {
+            return Err(FileSystemError::from("An archive split into volumes cannot be appended to a file"));
+        }
+        if self.file_entries.is_empty() {
+            self.scan()?;
+        }
+        if self.file_entries.is_empty() {
+            return Err(FileSystemError::from("No files found to archive"));
+        }
+        let binary_path = Path::new(existing_binary);
+        let temp_path = temp_path(binary_path);
+        let result = self.write_archive_to(&temp_path, None)
+            .and_then(|_| {
+                let mut binary = std::fs::OpenOptions::new().read(true).write(true).open(binary_path).map_err(FileSystemError::from)?;
+                let start = match embedded_start(&mut binary)? {
+                    Some(start) => start,
+                    None => binary.seek(SeekFrom::End(0)).map_err(FileSystemError::from)?,
+                };
+                binary.set_len(start).map_err(FileSystemError::from)?;
+                binary.seek(SeekFrom::Start(start)).map_err(FileSystemError::from)?;
+                let mut archive = File::open(&temp_path).map_err(FileSystemError::from)?;
+                std::io::copy(&mut archive, &mut binary).map_err(FileSystemError::from)?;
+                binary.write_all(&start.to_le_bytes()).map_err(FileSystemError::from)?;
+                binary.write_all(EMBED_MAGIC).map_err(FileSystemError::from)
+            });
+        std::fs::remove_file(&temp_path).ok();
+        self.file_entries.clear();
+        result
+    }
+
+    fn cipher_mode(&self) -> CipherMode {
+        if self.keys.is_empty() { CipherMode::None } else { CipherMode::Aes256Gcm }
+    }
+
+    fn write_archive(&mut self, existing: Option<&ArchiveFileSystem>) -> Result<IncrementalReport, FileSystemError> {
+        // Reuse the scan from `plan`, if there is one
+        if self.file_entries.is_empty() {
+            self.scan()?;
+        }
+        if self.file_entries.is_empty() {
+            return Err(FileSystemError::from("No files found to archive"));
+        }
+        // Write next to the destination and move into place once complete
+        let temp_path = temp_path(&self.file_path);
+        let result = self.write_archive_to(&temp_path, existing)
+            .and_then(|(report, last_volume)| {
+                for volume in 1..=last_volume {
+                    std::fs::rename(volume_path(&temp_path, volume), volume_path(&self.file_path, volume)).map_err(FileSystemError::from)?;
+                }
+                std::fs::rename(&temp_path, &self.file_path).map_err(FileSystemError::from)?;
+                // Volumes left over from a previous archive that needed more of them
+                if let Some(next) = last_volume.checked_add(1) {
+                    remove_volumes(&self.file_path, next);
+                }
+                Ok(report)
+            });
+        if result.is_err() {
+            std::fs::remove_file(&temp_path).ok();
+            remove_volumes(&temp_path, 1);
+        }
+        // The next archive reflects the directory as it is then
+        self.file_entries.clear();
+        result
+    }
+
+    /// Writes the archive to `path`, and its further volumes next to it, and returns the
+    /// report along with the number of the last volume.
+    fn write_archive_to(&mut self, path: &Path, existing: Option<&ArchiveFileSystem>) -> Result<(IncrementalReport, u16), FileSystemError> {
+        let mut file = File::create(path).map_err(FileSystemError::from)?;
+        let mut report = IncrementalReport::default();
+        // An encrypted index also carries a copy of the header and the encryption overhead
+        let index_overhead = if self.encrypt_index { (HEADER_SIZE + ENCRYPTION_OVERHEAD) as u64 } else { 0 };
+        let mut header = Header {
+            version: ARCHIVE_VERSION,
+            cipher: self.cipher_mode(),
+            flags: if self.encrypt_index { FLAG_ENCRYPTED_INDEX } else { 0 },
+            number_of_files: self.file_entries.len() as u32,
+            size: 0, // Will be updated later
+            data_offset: HEADER_SIZE as u64 + (self.file_entries.len() as u64 + self.reserved_entries as u64) * FILE_ENTRY_SIZE as u64 + index_overhead,
+            reserved_entries: self.reserved_entries,
+            password_salt: self.password.map_or([0u8; PASSWORD_SALT_SIZE], |(salt, _)| salt),
+            password_iterations: self.password.map_or(0, |(_, iterations)| iterations),
+        };
+        if let Some(volume_size) = self.volume_size && header.data_offset >= volume_size {
+            return Err(FileSystemError::from(format!("The archive header and entry table do not fit in a volume of {} bytes", volume_size)));
+        }
+        file.write_all(&header.to_bytes()).map_err(FileSystemError::from)?;
+        // Leave room for the entry table, which is written once all offsets are known
+        file.seek(SeekFrom::Start(header.data_offset)).map_err(FileSystemError::from)?;
+        let mut new_entries: Vec<FileEntry> = Vec::new();
+        // (plaintext hash, key slot, codec) -> (volume, offset, size) of the blob already written for it
+        let mut written: HashMap<BlobKey, (u16, u64, u64)> = HashMap::new();
+        // The volume being written and the position in it; past volume 0, its file
+        let (mut volume, mut position) = (0u16, header.data_offset);
+        let mut volume_file: Option<File> = None;
+        let files_total = self.file_entries.len();
+        // Encrypt a bounded batch in parallel, then write it out in order
+        let batch_size = self.threads * 4;
+        let mut prepared: Vec<Result<PreparedFile, FileSystemError>> = Vec::new();
+        for (index, (_, entry)) in self.file_entries.iter().enumerate() {
+            if index % batch_size == 0 {
+                let batch = &self.file_entries[index..files_total.min(index + batch_size)];
+                prepared = Self::prepare_batch(&self.keys, self.compressor.as_deref(), existing, self.threads, batch);
+                prepared.reverse();
+            }
+            let prepared_file = prepared.pop().expect("prepared file for entry")?;
+            if prepared_file.reused {
+                report.reused += 1;
+            } else {
+                report.encrypted += 1;
+            }
+            let blob_key = (prepared_file.hash, entry.key_slot, prepared_file.codec);
+            let (volume, offset, size) = match self.deduplicate.then(|| written.get(&blob_key)).flatten() {
+                Some(&existing) => existing,
+                None => {
+                    let size = prepared_file.content.len() as u64;
+                    if let Some(volume_size) = self.volume_size && position + size > volume_size {
+                        // Files are never split, so start the next volume unless this one is empty
+                        let volume_start = if volume == 0 { header.data_offset } else { 0 };
+                        if position > volume_start {
+                            volume = volume.checked_add(1).ok_or(FileSystemError::from("Too many archive volumes"))?;
+                            volume_file = Some(File::create(volume_path(path, volume)).map_err(FileSystemError::from)?);
+                            position = 0;
+                        }
+                        if position + size > volume_size {
+                            return Err(FileSystemError::from(format!("{} does not fit in a volume of {} bytes", entry.path(), volume_size)));
+                        }
+                    }
+                    volume_file.as_mut().unwrap_or(&mut file).write_all(&prepared_file.content).map_err(FileSystemError::from)?;
+                    let offset = position;
+                    position += size;
+                    written.insert(blob_key, (volume, offset, size));
+                    (volume, offset, size)
+                }
+            };
+            let mut new_entry = entry.clone();
+            new_entry.set_size(size);
+            new_entry.set_offset(offset);
+            new_entry.volume = volume;
+            new_entry.hash = prepared_file.hash;
+            new_entry.codec = prepared_file.codec;
+            new_entry.uncompressed_size = prepared_file.uncompressed_size;
+            new_entries.push(new_entry);
+            if let Some(progress) = self.progress.as_mut() {
+                progress(index + 1, files_total);
+            }
+        }
+        header.size = file.stream_position().map_err(FileSystemError::from)?;
+        let index_key = if self.encrypt_index { self.keys.get(&DEFAULT_KEY_SLOT) } else { None };
+        write_index(&mut file, &header, &new_entries, index_key)?;
+        Ok((report, volume))
+    }
+
+    /// Reads, hashes, compresses and encrypts a batch of files, splitting the work across
+    /// threads. Unchanged files are copied from the existing archive, if one is given.
+    /// The results are in the same order as `batch`.
+    fn prepare_batch(keys: &HashMap<u8, EncUtils>, compressor: Option<&dyn Compressor>, existing: Option<&ArchiveFileSystem>, threads: usize, batch: &[(PathBuf, FileEntry)]) -> Vec<Result<PreparedFile, FileSystemError>> {
+        let codec = compressor.map_or(NO_COMPRESSION, |compressor| compressor.id());
+        let prepare = |(full_path, entry): &(PathBuf, FileEntry)| -> Result<PreparedFile, FileSystemError> {
+            let enc_utils = keys.get(&entry.key_slot);
+            // A previous blob can only be reused if it is encrypted with the key this file now
+            // gets, and stored uncompressed or with the codec it would now be compressed with
+            let previous = existing
+                .and_then(|archive| archive.table().entries.get(&entry.path()).cloned().map(|e| (archive, e)))
+                .filter(|(archive, previous)| previous.key_slot == entry.key_slot && archive.keyring.get(&entry.key_slot) == enc_utils)
+                .filter(|(_, previous)| previous.codec == NO_COMPRESSION || previous.codec == codec);
+            let reuse = |archive: &ArchiveFileSystem, previous: &FileEntry| -> Result<PreparedFile, FileSystemError> {
+                let content = archive.read_raw(previous)?;
+                Ok(PreparedFile { hash: previous.hash, content, reused: true, codec: previous.codec, uncompressed_size: previous.uncompressed_size })
+            };
+            if let Some((archive, previous)) = &previous
+                && entry.modified != 0 && entry.modified == previous.modified
+                && entry.size == previous.uncompressed_size {
+                return reuse(archive, previous);
+            }
+            if !full_path.is_file() {
+                return Err(FileSystemError::not_found(&full_path.to_string_lossy()));
+            }
+            // Checked before reading, so an oversized file is not loaded into memory first
+            if enc_utils.is_some() && entry.size > MAX_MESSAGE_SIZE {
+                return Err(FileSystemError::from(format!(
+                    "{} is larger than the AES-GCM limit of {} bytes for an encrypted archive entry",
+                    entry.path(), MAX_MESSAGE_SIZE
+                )));
+            }
+            let content = std::fs::read(full_path).map_err(FileSystemError::from)?;
+            let hash: [u8; HASH_SIZE] = Sha256::digest(&content).into();
+            if let Some((archive, previous)) = &previous
+                && hash == previous.hash {
+                return reuse(archive, previous);
+            }
+            let uncompressed_size = content.len() as u64;
+            let (codec, content) = match compressor {
+                Some(compressor) => {
+                    let compressed = compressor.compress(&content)?;
+                    if compressed.len() < content.len() { (codec, compressed) } else { (NO_COMPRESSION, content) }
+                }
+                None => (NO_COMPRESSION, content),
+            };
+            let content = match enc_utils {
+                Some(enc_utils) => enc_utils.encrypt(content)?,
+                None => content,
+            };
+            Ok(PreparedFile { hash, content, reused: false, codec, uncompressed_size })
+        };
+        if threads <= 1 || batch.len() <= 1 {
+            return batch.iter().map(prepare).collect();
+        }
+        let chunk_size = batch.len().div_ceil(threads);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = batch.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(prepare).collect::<Vec<_>>()))
+                .collect();
+            workers.into_iter()
+                .flat_map(|worker| worker.join().expect("Archive worker thread panicked"))
+                .collect()
+        })
+    }
+}
+
+
+/// Writes the header and entry table at the start of an archive file, encrypting both
+/// with `index_key` when the header has `FLAG_ENCRYPTED_INDEX` set. The reserved entry
+/// slots are written as zeros after the entries.
+fn write_index(file: &mut File, header: &Header, entries: &[FileEntry], index_key: Option<&EncUtils>) -> Result<(), FileSystemError> {
+    let mut index = Vec::with_capacity((entries.len() + header.reserved_entries as usize) * FILE_ENTRY_SIZE);
+    for entry in entries {
+        index.extend_from_slice(&entry.to_bytes());
+    }
+    index.resize(index.len() + header.reserved_entries as usize * FILE_ENTRY_SIZE, 0);
+    file.seek(SeekFrom::Start(0)).map_err(FileSystemError::from)?;
+    if header.flags & FLAG_ENCRYPTED_INDEX != 0 {
+        let index_key = index_key.ok_or(FileSystemError::from("The key for the default slot is required to encrypt the archive index"))?;
+        let clear_header = Header {
+            version: header.version,
+            cipher: header.cipher,
+            flags: header.flags,
+            number_of_files: 0,
+            size: 0,
+            data_offset: header.data_offset,
+            reserved_entries: 0,
+            // Needed to derive the key before the index can be decrypted
+            password_salt: header.password_salt,
+            password_iterations: header.password_iterations,
+        };
+        let mut plain = header.to_bytes();
+        plain.extend_from_slice(&index);
+        let encrypted = index_key.encrypt(plain)?;
+        file.write_all(&clear_header.to_bytes()).map_err(FileSystemError::from)?;
+        file.write_all(&encrypted).map_err(FileSystemError::from)?;
+    } else {
+        file.write_all(&header.to_bytes()).map_err(FileSystemError::from)?;
+        file.write_all(&index).map_err(FileSystemError::from)?;
+    }
+    Ok(())
+}
+
+/// Returns the path of a volume of the archive at `path`: the archive file itself for
+/// volume 0, otherwise the archive file name followed by the volume number, as in
+/// `game.arc.001`.
+fn volume_path(path: &Path, volume: u16) -> PathBuf {
+    if volume == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{:03}", volume));
+    path.with_file_name(name)
+}
+
+/// Removes the volumes of the archive at `path` from `first` on, up to the first one
+/// that does not exist.
+fn remove_volumes(path: &Path, first: u16) {
+    for volume in first..=u16::MAX {
+        if std::fs::remove_file(volume_path(path, volume)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Reads the trailer written by `ArchiveCreator::append_to` at the end of `file`.
+///
+/// # Returns
+/// The offset the embedded archive starts at, or `None` if the file has no trailer.
+fn embedded_start<R: Read + Seek>(file: &mut R) -> Result<Option<u64>, FileSystemError> {
+    let len = file.seek(SeekFrom::End(0)).map_err(FileSystemError::from)?;
+    if len < EMBED_TRAILER_SIZE {
+        return Ok(None);
+    }
+    let mut trailer = [0u8; EMBED_TRAILER_SIZE as usize];
+    file.seek(SeekFrom::Start(len - EMBED_TRAILER_SIZE)).map_err(FileSystemError::from)?;
+    file.read_exact(&mut trailer).map_err(FileSystemError::from)?;
+    if trailer[8..] != EMBED_MAGIC[..] {
+        return Ok(None);
+    }
+    let start = u64::from_le_bytes(trailer[..8].try_into().expect("8 bytes"));
+    if start > len - EMBED_TRAILER_SIZE {
+        return Err(FileSystemError::from("Invalid offset in embedded archive trailer"));
+    }
+    Ok(Some(start))
+}
+
+/// Returns a path that does not exist yet to write an archive to before moving it to
+/// `path`, named `.<name>.<pid>.<n>.tmp` with `n` unique within the process, so runs
+/// writing the same output never share one.
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    loop {
+        let temp_path = path.with_file_name(format!(
+            ".{}.{}.{}.tmp",
+            file_name,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        // Left behind by an earlier process with the same id
+        if !temp_path.exists() {
+            return temp_path;
+        }
+    }
+}
+
+/// Runs `write` on the temporary path for `output` and moves the result into place once
+/// it succeeds, or removes it if it fails.
+fn replace_with(output: &Path, write: impl FnOnce(&Path) -> Result<u64, FileSystemError>) -> Result<u64, FileSystemError> {
+    let temp_path = temp_path(output);
+    let result = write(&temp_path)
+        .and_then(|size| {
+            std::fs::rename(&temp_path, output).map_err(FileSystemError::from)?;
+            Ok(size)
+        });
+    if result.is_err() {
+        std::fs::remove_file(&temp_path).ok();
+    }
+    result
+}
+
+/// Turns a path from another archive format into a relative path, or `None` if it would
+/// leave the root, e.g. `../escape.txt`. Leading `/` and `.` components are dropped.
+#[cfg(any(feature = "tar", feature = "zip"))]
+pub(crate) fn safe_relative_path(path: &str) -> Option<PathBuf> {
+    use std::path::Component;
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!relative.as_os_str().is_empty()).then_some(relative)
+}
+
+/// Builder for `ArchiveCreator`.
+///
+/// The source directory, output path and key are required; every other option
+/// has the same default as `ArchiveCreator::new`.
+#[derive(Default)]
+pub struct ArchiveCreatorBuilder {
+    source_dir: Option<String>,
+    output: Option<String>,
+    key: Option<EncKey>,
+    cipher: CipherMode,
+    overwrite: bool,
+    deduplicate: bool,
+    encrypt_index: bool,
+    exclude: Vec<String>,
+    include: Vec<String>,
+    key_slots: Vec<(u8, EncKey, String)>,
+    compressor: Option<Box<dyn Compressor>>,
+    reserved_entries: u32,
+    follow_symlinks: bool,
+    volume_size: Option<u64>,
+    password: Option<String>,
+    password_iterations: Option<u32>,
+}
+
+impl ArchiveCreatorBuilder {
+    /// Sets the directory whose files will be packed.
+    pub fn source_dir(mut self, source_dir: &str) -> Self {
+        self.source_dir = Some(source_dir.to_string());
+        self
+    }
+
+    /// Sets the path of the archive file to create.
+    pub fn output(mut self, output: &str) -> Self {
+        self.output = Some(output.to_string());
+        self
+    }
+
+    /// Sets the encryption key for the archive.
+    pub fn key(mut self, key: EncKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Derives the encryption key from a password, in place of `key`. See
+    /// `ArchiveCreator::set_password`.
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Sets the PBKDF2 iterations for `password`. Defaults to `PASSWORD_ITERATIONS`.
+    pub fn password_iterations(mut self, iterations: u32) -> Self {
+        self.password_iterations = Some(iterations);
+        self
+    }
+
+    /// Sets how file contents are stored. Defaults to `CipherMode::Aes256Gcm`, which
+    /// requires a key or password; `CipherMode::None` must not be given either.
+    pub fn cipher(mut self, cipher: CipherMode) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Allows overwriting an existing archive file.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Stores identical files only once. See `ArchiveCreator::set_deduplicate`.
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    /// Encrypts the header and entry table. See `ArchiveCreator::set_encrypt_index`.
+    pub fn encrypt_index(mut self, encrypt_index: bool) -> Self {
+        self.encrypt_index = encrypt_index;
+        self
+    }
+
+    /// Adds an exclude pattern. See `ArchiveCreator::add_exclude`.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    /// Adds an include pattern. See `ArchiveCreator::add_include`.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_string());
+        self
+    }
+
+    /// Encrypts files matching `pattern` with a separate key. See `ArchiveCreator::add_key_slot`.
+    pub fn key_slot(mut self, slot: u8, key: EncKey, pattern: &str) -> Self {
+        self.key_slots.push((slot, key, pattern.to_string()));
+        self
+    }
+
+    /// Compresses files before encrypting them. See `ArchiveCreator::set_compressor`.
+    pub fn compressor(mut self, compressor: Box<dyn Compressor>) -> Self {
+        self.compressor = Some(compressor);
+        self
+    }
+
+    /// Preallocates entry slots for appending files. See `ArchiveCreator::set_reserved_entries`.
+    pub fn reserved_entries(mut self, reserved_entries: u32) -> Self {
+        self.reserved_entries = reserved_entries;
+        self
+    }
+
+    /// Follows symbolic links in the source directory. See `ArchiveCreator::set_follow_symlinks`.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Splits the archive into volumes. See `ArchiveCreator::set_volume_size`.
+    pub fn volume_size(mut self, volume_size: u64) -> Self {
+        self.volume_size = Some(volume_size);
+        self
+    }
+
+    /// Builds the `ArchiveCreator`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if a required option is missing or `ArchiveCreator::new` rejects it.
+    pub fn build(self) -> Result<ArchiveCreator, FileSystemError> {
+        let source_dir = self.source_dir.ok_or(FileSystemError::from("Archive source directory not set"))?;
+        let output = self.output.ok_or(FileSystemError::from("Archive output path not set"))?;
+        let key = match (self.cipher, self.key, self.password.is_some()) {
+            (CipherMode::Aes256Gcm, Some(_), true) => return Err(FileSystemError::from("Set either an encryption key or a password, not both")),
+            // Replaced by the password-derived key below
+            (CipherMode::Aes256Gcm, None, true) => Some(EncUtils::generate_random_key()),
+            (CipherMode::Aes256Gcm, None, false) => return Err(FileSystemError::from("Archive encryption key not set")),
+            (CipherMode::None, Some(_), _) => return Err(FileSystemError::from("Unencrypted archives do not take a key")),
+            (CipherMode::None, None, true) => return Err(FileSystemError::from("Unencrypted archives do not take a password")),
+            (_, key, _) => key,
+        };
+        let mut creator = ArchiveCreator::new_with(&source_dir, &output, key, self.overwrite)?;
+        if let Some(password) = &self.password {
+            creator.set_password(password, self.password_iterations.unwrap_or(PASSWORD_ITERATIONS))?;
+        }
+        creator.set_deduplicate(self.deduplicate);
+        creator.set_encrypt_index(self.encrypt_index)?;
+        creator.set_reserved_entries(self.reserved_entries);
+        creator.set_follow_symlinks(self.follow_symlinks);
+        creator.set_volume_size(self.volume_size);
+        creator.exclude = self.exclude;
+        creator.include = self.include;
+        for (slot, key, pattern) in self.key_slots {
+            creator.add_key_slot(slot, key, &pattern)?;
+        }
+        if let Some(compressor) = self.compressor {
+            creator.set_compressor(compressor)?;
+        }
+        Ok(creator)
+    }
+}
+
+
+impl From<&FileEntry> for FileInfo {
+    fn from(entry: &FileEntry) -> Self {
+        FileInfo {
+            name: entry.name(),
+            path: entry.path(),
+            size: entry.size,
+            is_directory: false, // Archive entries are not directories
+            modified: entry.modified_time(),
+            created: None,
+            is_symlink: false,
+        }
+    }
+}
+
+impl FileSystem for ArchiveFileSystem {
+    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        let mut content = Vec::new();
+        self.read_into(path, &mut content)?;
+        Ok(content)
+    }
+
+    /// Reads the stored blob into `buf` and decrypts it there.
+    fn read_into(&self, path: &str, buf: &mut Vec<u8>) -> Result<usize, FileSystemError> {
+        let path = normalize_path(path);
+        let entry = self.table().entries.get(&path).cloned().ok_or_else(|| FileSystemError::not_found(&path))?;
+        if let Some(cache) = &self.cache
+            && let Some(content) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(&path) {
+            buf.clear();
+            buf.extend_from_slice(content);
+            self.notify(FsEvent::Read { path: &path, bytes: buf.len() as u64 });
+            return Ok(buf.len());
+        }
+        self.read_entry(&mut None, &entry, buf)?;
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap_or_else(|e| e.into_inner()).insert(&path, buf);
+        }
+        self.notify(FsEvent::Read { path: &path, bytes: buf.len() as u64 });
+        Ok(buf.len())
+    }
+
+    /// Reads only the first `n` bytes of files stored unencrypted and uncompressed. Other
+    /// files are read whole: AES-GCM authenticates the entire ciphertext before any of it
+    /// can be trusted, and compressed data decompresses from the start.
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        let normalized = normalize_path(path);
+        let entry = self.table().entries.get(&normalized).cloned().ok_or_else(|| FileSystemError::not_found(&normalized))?;
+        if self.header.cipher != CipherMode::None || entry.codec != NO_COMPRESSION {
+            let mut content = self.read_file(path)?;
+            content.truncate(n);
+            return Ok(content);
+        }
+        let len = (n as u64).min(entry.size) as usize;
+        let content = if let Some(blob) = self.mapped(&entry)? {
+            blob[..len].to_vec()
+        } else {
+            let mut content = vec![0u8; len];
+            self.read_at(&mut None, entry.volume, entry.offset, &mut content)?;
+            content
+        };
+        self.notify(FsEvent::Read { path: &normalized, bytes: content.len() as u64 });
+        Ok(content)
+    }
+
+    /// Opens each volume once and reads the requested entries in offset order.
+    fn read_files(&self, paths: &[&str]) -> Result<HashMap<String, FileContent>, FileSystemError> {
+        let mut requested = Vec::with_capacity(paths.len());
+        let table = self.table();
+        for path in paths {
+            let entry = table.entries.get(&normalize_path(path)).cloned().ok_or_else(|| FileSystemError::not_found(path))?;
+            requested.push((*path, entry));
+        }
+        drop(table);
+        requested.sort_by_key(|(_, entry)| (entry.volume, entry.offset));
+        let mut file: Option<(u16, File)> = None;
+        let mut contents = HashMap::with_capacity(requested.len());
+        for (path, entry) in requested {
+            let mut content = Vec::new();
+            self.read_entry(&mut file, &entry, &mut content)?;
+            self.notify(FsEvent::Read { path, bytes: content.len() as u64 });
+            contents.insert(path.to_string(), content);
+        }
+        Ok(contents)
+    }
+
+    fn write_file(&self, _path: &str, _content: FileContent) -> Result<u64, FileSystemError> {
+        Err(FileSystemError::from("Archive is read-only, cannot write files"))
+    }
+
+    fn delete_file(&self, _path: &str) -> Result<(), FileSystemError> {
+        Err(FileSystemError::from("Archive is read-only, cannot delete files"))
+    }
+
+    fn touch(&self, _path: &str) -> Result<(), FileSystemError> {
+        Err(FileSystemError::from("Archive is read-only, cannot create files"))
+    }
+
+    fn truncate_file(&self, _path: &str, _len: u64) -> Result<(), FileSystemError> {
+        Err(FileSystemError::from("Archive is read-only, cannot truncate files"))
+    }
+
+    fn delete_dir_recursive(&self, _path: &str) -> Result<(), FileSystemError> {
+        Err(FileSystemError::from("Archive is read-only, cannot delete directories"))
+    }
+
+    fn empty_dir(&self, _directory: &str) -> Result<usize, FileSystemError> {
+        Err(FileSystemError::from("Archive is read-only, cannot delete files"))
+    }
+
+    /// Rewrites the paths in the entry table, see `ArchiveFileSystem::rename_entries`.
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        self.rename_entries(from, to)?;
+        self.notify(FsEvent::RenameDir { from, to });
+        Ok(())
+    }
+
+    /// Exchanges the entries in the entry table, see `ArchiveFileSystem::swap_entries`.
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.swap_entries(a, b)?;
+        self.notify(FsEvent::Swap { a, b });
+        Ok(())
+    }
+
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        Ok(self.table().entries.contains_key(&normalize_path(path)))
+    }
+
+    /// Archives store no directory records, so a directory exists if any entry is below
+    /// it. The root always exists, even in an empty archive.
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        let prefix = Self::directory_prefix(path);
+        Ok(prefix.is_empty() || !self.table().prefix_range(&prefix).is_empty())
+    }
+
+    /// Lists the files directly inside `directory`, plus its immediate subdirectories,
+    /// which are synthesized from the entry paths since archives store no directory records.
+    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let prefix = Self::directory_prefix(directory);
+        let mut file_infos: Vec<FileInfo> = Vec::new();
+        let mut last_directory: Option<&str> = None;
+        let table = self.table();
+        for (path, entry) in table.prefix_range(&prefix) {
+            match path[prefix.len()..].split_once('/') {
+                None => file_infos.push(FileInfo::from(entry)),
+                // Entries are sorted, so all entries of a subdirectory are adjacent
+                Some((name, _)) if last_directory != Some(name) => {
+                    last_directory = Some(name);
+                    file_infos.push(FileInfo {
+                        path: format!("{}{}", prefix, name),
+                        name: name.to_string(),
+                        size: 0,
+                        is_directory: true,
+                        modified: None,
+                        created: None,
+                        is_symlink: false,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        self.notify(FsEvent::List { directory, entries: file_infos.len() });
+        Ok(file_infos)
+    }
+
+    /// Finds the immediate subdirectories with `child_dirs`, without visiting their files.
+    fn list_dirs(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let prefix = Self::directory_prefix(directory);
+        Ok(self.child_dirs(directory).into_iter()
+            .map(|name| FileInfo {
+                path: format!("{}{}", prefix, name),
+                name,
+                size: 0,
+                is_directory: true,
+                modified: None,
+                created: None,
+                is_symlink: false,
+            })
+            .collect())
+    }
+
+    /// Yields the stored file entries under the directory prefix; archives have no
+    /// explicit directory records.
+    fn walk(&self, directory: &str) -> Box<dyn Iterator<Item = Result<FileInfo, FileSystemError>> + '_> {
+        let prefix = Self::directory_prefix(directory);
+        let files: Vec<Result<FileInfo, FileSystemError>> = self.table().prefix_range(&prefix).iter()
+            .map(|(_, entry)| Ok(FileInfo::from(entry)))
+            .collect();
+        Box::new(files.into_iter())
+    }
+
+    /// Always empty: the contents of an open archive never change. The modification times
+    /// of its entries are those of the source files when the archive was created.
+    fn list_modified_since(&self, _directory: &str, _since: SystemTime) -> Result<Vec<FileInfo>, FileSystemError> {
+        Ok(Vec::new())
+    }
+
+    /// Returns the SHA-256 of the plaintext recorded in the index, without any IO. Entries
+    /// written without a hash are identified by where their blob is stored instead, as
+    /// `volume:offset:size`.
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        let path = normalize_path(path);
+        let table = self.table();
+        let entry = table.entries.get(&path).ok_or_else(|| FileSystemError::not_found(&path))?;
+        if entry.hash == [0; HASH_SIZE] {
+            return Ok(format!("{}:{}:{}", entry.volume, entry.offset, entry.size));
+        }
+        Ok(entry.hash.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Sums the uncompressed sizes in the index directly, without any IO.
+    fn total_size(&self, directory: &str) -> Result<u64, FileSystemError> {
+        let prefix = Self::directory_prefix(directory);
+        Ok(self.table().prefix_range(&prefix).iter()
+            .map(|(_, entry)| entry.uncompressed_size)
+            .sum())
+    }
+
+    /// Matches the pattern against the stored entries directly, without synthesizing
+    /// directories. Patterns containing a `/` are matched against the full path,
+    /// otherwise against the file name.
+    fn list_files_glob(&self, directory: &str, pattern: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let match_path = pattern.contains('/');
+        let prefix = Self::directory_prefix(directory);
+        Ok(self.table().prefix_range(&prefix).iter()
+            .filter(|(path, entry)| {
+                if match_path { glob_match(pattern, path) } else { glob_match(pattern, &entry.name()) }
+            })
+            .map(|(_, entry)| FileInfo::from(entry))
+            .collect())
+    }
+    /// Archives are read-only and encrypted unless created with `CipherMode::None`.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            encrypted: self.cipher_mode() != CipherMode::None,
+            ..Capabilities::default()
+        }
+    }
+
+    /// Returns the path of the archive file, or `None` if it is not valid UTF-8 or the
+    /// archive was opened from a reader.
+    fn root(&self) -> Option<&str> {
+        if self.source.is_some() {
+            return None;
+        }
+        self.file_path.to_str()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enc_utils::EncUtils;
+
+    #[test]
+    fn test_archive() {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new("test_directory", "test_archive.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_archive.arc"), key).expect("Failed to open archive");
+        assert!(!archive_fs.table().entries.is_empty(), "Archive should contain files");
+        let files = archive_fs.list_files("").expect("Failed to list files in archive");
+        assert!(!files.is_empty(), "Archive should list files");
+        for file in files {
+            println!("{}", file.path);
+        }
+    }
+
+    /// Creates `output` from `test_directory` with a random key and opens it. The caller
+    /// removes `output` once done.
+    fn open_test_archive(output: &str) -> ArchiveFileSystem {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new("test_directory", output, key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        ArchiveFileSystem::open(PathBuf::from(output), key).expect("Failed to open archive")
+    }
+
+    #[test]
+    fn test_archive_properties() {
+        let archive_fs = open_test_archive("test_archive_properties.arc");
+        let start = std::fs::read("test_archive_properties.arc").map(|bytes| bytes[..ARCHIVE_MAGIC.len()].to_vec());
+        let detected = ArchiveFileSystem::is_archive(Path::new("test_archive_properties.arc"));
+        std::fs::remove_file("test_archive_properties.arc").ok();
+
+        assert_eq!(archive_fs.root(), Some("test_archive_properties.arc"));
+        assert_eq!(archive_fs.capabilities(), Capabilities { encrypted: true, ..Capabilities::default() });
+        assert_eq!(archive_fs.real_path("test_file.txt"), None, "Archive entries have no path on disk");
+        assert_eq!(start.unwrap(), ARCHIVE_MAGIC);
+        assert!(detected);
+    }
+
+    #[test]
+    fn test_archive_delete_dir_recursive() {
+        let archive_fs = open_test_archive("test_archive_delete_dir.arc");
+        let deleted = archive_fs.delete_dir_recursive("");
+        let listed = archive_fs.list_files("");
+        std::fs::remove_file("test_archive_delete_dir.arc").ok();
+
+        assert!(deleted.is_err(), "Archives are read-only");
+        assert!(!listed.unwrap().is_empty(), "A failed delete should leave every entry in place");
+    }
+
+    #[test]
+    fn test_archive_not_found() {
+        let archive_fs = open_test_archive("test_archive_not_found.arc");
+        let read = archive_fs.read_file("missing.txt").unwrap_err();
+        let peek = archive_fs.peek("missing.txt", 4).unwrap_err();
+        let hash = archive_fs.hash_file("missing.txt").unwrap_err();
+        std::fs::remove_file("test_archive_not_found.arc").ok();
+
+        for error in [&read, &peek, &hash] {
+            assert_eq!(error.kind, crate::FileSystemErrorKind::NotFound, "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_archive_builder() {
+        let key = EncUtils::generate_random_key();
+        let missing_key = ArchiveCreator::builder()
+            .source_dir("test_directory")
+            .output("test_archive_builder.arc")
+            .build();
+        assert!(missing_key.is_err(), "Expected error when the key is not set");
+
+        let mut creator = ArchiveCreator::builder()
+            .source_dir("test_directory")
+            .output("test_archive_builder.arc")
+            .key(key.clone())
+            .overwrite(true)
+            .build()
+            .expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_archive_builder.arc"), key).expect("Failed to open archive");
+        std::fs::remove_file("test_archive_builder.arc").ok();
+        assert!(!archive_fs.table().entries.is_empty(), "Archive should contain files");
+    }
+
+    #[test]
+    fn test_archive_parallel_layout() {
+        let source = "test_parallel_source";
+        std::fs::create_dir_all(source).unwrap();
+        for i in 0..20 {
+            std::fs::write(format!("{}/file_{}.bin", source, i), vec![i as u8; i * 10]).unwrap();
+        }
+        let key = EncUtils::generate_random_key();
+        let mut layouts = Vec::new();
+        for threads in [1, 4] {
+            let output = format!("test_parallel_{}.arc", threads);
+            let mut creator = ArchiveCreator::new(source, &output, key.clone(), true).expect("Failed to create ArchiveCreator");
+            creator.set_threads(threads);
+            creator.create().expect("Failed to create archive");
+            let archive_fs = ArchiveFileSystem::open(PathBuf::from(&output), key.clone()).expect("Failed to open archive");
+            let mut layout: Vec<(String, u64, u64)> = archive_fs.table().entries.values().map(|e| (e.path(), e.offset, e.size)).collect();
+            layout.sort();
+            assert_eq!(archive_fs.read_file("file_7.bin").unwrap(), vec![7u8; 70]);
+            std::fs::remove_file(&output).ok();
+            layouts.push(layout);
+        }
+        std::fs::remove_dir_all(source).ok();
+        assert_eq!(layouts[0], layouts[1], "Layout should not depend on the thread count");
+    }
+
+    #[test]
+    fn test_archive_incremental() {
+        let source = "test_incremental_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"unchanged").unwrap();
+        std::fs::write(format!("{}/b.txt", source), b"original").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_incremental.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+
+        std::fs::write(format!("{}/b.txt", source), b"modified content").unwrap();
+        std::fs::write(format!("{}/c.txt", source), b"new").unwrap();
+        let existing = ArchiveFileSystem::open(PathBuf::from("test_incremental.arc"), key.clone()).expect("Failed to open archive");
+        let report = creator.create_incremental(&existing).expect("Failed to update archive");
+        let updated = ArchiveFileSystem::open(PathBuf::from("test_incremental.arc"), key).expect("Failed to open archive");
+        let a = updated.read_file("a.txt");
+        let b = updated.read_file("b.txt");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_incremental.arc").ok();
+        assert_eq!(report, IncrementalReport { reused: 1, encrypted: 2 });
+        assert_eq!(a.unwrap(), b"unchanged");
+        assert_eq!(b.unwrap(), b"modified content");
+    }
+
+    #[test]
+    fn test_archive_exclude() {
+        let source = "test_exclude_source";
+        std::fs::create_dir_all(format!("{}/skipped", source)).unwrap();
+        std::fs::create_dir_all(format!("{}/kept", source)).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"a").unwrap();
+        std::fs::write(format!("{}/scratch.tmp", source), b"tmp").unwrap();
+        std::fs::write(format!("{}/kept/b.txt", source), b"b").unwrap();
+        std::fs::write(format!("{}/kept/c.tmp", source), b"tmp").unwrap();
+        std::fs::write(format!("{}/skipped/d.txt", source), b"d").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::builder()
+            .source_dir(source)
+            .output("test_exclude.arc")
+            .key(key.clone())
+            .overwrite(true)
+            .exclude("*.tmp")
+            .exclude("skipped")
+            .build()
+            .expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_exclude.arc"), key).expect("Failed to open archive");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_exclude.arc").ok();
+        let mut paths: Vec<String> = archive_fs.table().entries.keys().cloned().collect();
+        paths.sort();
+        assert_eq!(paths, vec!["a.txt", "kept/b.txt"]);
+    }
+
+    #[test]
+    fn test_archive_plan() {
+        let source = "test_plan_source";
+        std::fs::create_dir_all(format!("{}/sub", source)).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"aaa").unwrap();
+        std::fs::write(format!("{}/sub/b.txt", source), b"bb").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_plan.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        let mut plan = creator.plan().expect("Failed to plan archive");
+        plan.sort_by(|a, b| a.path.cmp(&b.path));
+        let planned_only = std::path::Path::new("test_plan.arc").exists();
+        // Files added after planning are not packed, since create reuses the plan
+        std::fs::write(format!("{}/late.txt", source), b"late").unwrap();
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_plan.arc"), key).expect("Failed to open archive");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_plan.arc").ok();
+
+        let summary: Vec<(String, u64)> = plan.into_iter().map(|f| (f.path, f.size)).collect();
+        assert_eq!(summary, vec![("a.txt".to_string(), 3), ("sub/b.txt".to_string(), 2)]);
+        assert!(!planned_only, "Planning should not write the archive");
+        assert!(archive_fs.table().entries.contains_key("sub/b.txt"));
+        assert!(!archive_fs.table().entries.contains_key("late.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_archive_symlinks() {
+        let source = "test_symlink_source";
+        std::fs::create_dir_all(format!("{}/real", source)).unwrap();
+        std::fs::write(format!("{}/real/a.txt", source), b"aaa").unwrap();
+        std::os::unix::fs::symlink("real", format!("{}/linked_dir", source)).unwrap();
+        std::os::unix::fs::symlink("real/a.txt", format!("{}/linked.txt", source)).unwrap();
+        let summarize = |follow: bool| {
+            let mut creator = ArchiveCreator::builder().source_dir(source).output("test_symlink.arc").cipher(CipherMode::None)
+                .follow_symlinks(follow).build().expect("Failed to create ArchiveCreator");
+            let mut plan: Vec<(String, u64)> = creator.plan().expect("Failed to plan archive").into_iter().map(|f| (f.path, f.size)).collect();
+            plan.sort();
+            plan
+        };
+        let skipped = summarize(false);
+        let followed = summarize(true);
+        std::fs::remove_dir_all(source).ok();
+
+        assert_eq!(skipped, vec![("real/a.txt".to_string(), 3)]);
+        assert_eq!(followed, vec![
+            ("linked.txt".to_string(), 3),
+            ("linked_dir/a.txt".to_string(), 3),
+            ("real/a.txt".to_string(), 3),
+        ]);
+    }
+
+    #[test]
+    fn test_archive_empty_file() {
+        let source = "test_empty_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/empty.txt", source), b"").unwrap();
+        std::fs::write(format!("{}/full.txt", source), b"full").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_empty.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let mut creator = ArchiveCreator::new_unencrypted(source, "test_empty_plain.arc", true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let encrypted = ArchiveFileSystem::open(PathBuf::from("test_empty.arc"), key).expect("Failed to open archive");
+        let plain = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_empty_plain.arc")).expect("Failed to open archive");
+        let contents = [encrypted.read_file("empty.txt"), plain.read_file("empty.txt"), encrypted.read_file("full.txt")];
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_empty.arc").ok();
+        std::fs::remove_file("test_empty_plain.arc").ok();
+
+        assert_eq!(encrypted.table().entries["empty.txt"].size, ENCRYPTION_OVERHEAD as u64);
+        assert_eq!(encrypted.total_size("").unwrap(), 4, "Empty files should not count the encryption overhead");
+        let [empty, plain_empty, full] = contents;
+        assert_eq!(empty.unwrap(), b"");
+        assert_eq!(plain_empty.unwrap(), b"");
+        assert_eq!(full.unwrap(), b"full");
+    }
+
+    #[test]
+    fn test_archive_volumes() {
+        let source = "test_volumes_source";
+        std::fs::create_dir_all(source).unwrap();
+        for i in 0..6u8 {
+            std::fs::write(format!("{}/{}.bin", source, i), vec![i; 1000]).unwrap();
+        }
+        let mut creator = ArchiveCreator::builder()
+            .source_dir(source)
+            .output("test_volumes.arc")
+            .cipher(CipherMode::None)
+            .volume_size(4000)
+            .overwrite(true)
+            .build()
+            .expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_volumes.arc")).expect("Failed to open archive");
+        let paths: Vec<String> = (0..6).map(|i| format!("{}.bin", i)).collect();
+        let path_refs: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let single = archive_fs.read_file("5.bin");
+        let contents = archive_fs.read_files(&path_refs);
+        let volume_sizes: Vec<u64> = (0..archive_fs.volume_count() as u16)
+            .map(|volume| std::fs::metadata(volume_path(Path::new("test_volumes.arc"), volume)).unwrap().len())
+            .collect();
+        archive_fs.compact("test_volumes_out.arc").expect("Failed to compact archive");
+        let compacted = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_volumes_out.arc")).expect("Failed to open compacted archive");
+        let compacted_contents = compacted.read_files(&path_refs);
+        std::fs::write(format!("{}/big.bin", source), vec![9u8; 5000]).unwrap();
+        let oversized = ArchiveCreator::builder().source_dir(source).output("test_volumes_big.arc").cipher(CipherMode::None).volume_size(4000).build().unwrap().create();
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_volumes.arc").ok();
+        remove_volumes(Path::new("test_volumes.arc"), 1);
+        std::fs::remove_file("test_volumes_out.arc").ok();
+        std::fs::remove_file("test_volumes_big.arc").ok();
+
+        assert!(archive_fs.volume_count() > 1, "The files should not fit in one volume");
+        assert!(volume_sizes.iter().all(|&size| size <= 4000), "Volumes should respect the size limit");
+        assert_eq!(single.unwrap(), vec![5u8; 1000]);
+        let contents = contents.expect("Failed to read files");
+        let compacted_contents = compacted_contents.expect("Failed to read compacted files");
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(contents[path.as_str()], vec![i as u8; 1000]);
+            assert_eq!(compacted_contents[path.as_str()], vec![i as u8; 1000]);
+        }
+        assert_eq!(compacted.volume_count(), 1, "Compacting should merge the volumes");
+        assert!(oversized.is_err(), "A file larger than a volume should fail");
+        assert!(!Path::new("test_volumes_big.arc").exists());
+    }
+
+    #[test]
+    fn test_archive_list_files_sorted() {
+        let source = "test_sorted_source";
+        std::fs::create_dir_all(format!("{}/Zones", source)).unwrap();
+        std::fs::create_dir_all(format!("{}/audio", source)).unwrap();
+        for name in ["b.txt", "A.txt", "c.txt", "Zones/z.txt", "audio/a.ogg"] {
+            std::fs::write(format!("{}/{}", source, name), name).unwrap();
+        }
+        let listings: Vec<Vec<String>> = (0..2).map(|_| {
+            let mut creator = ArchiveCreator::new_unencrypted(source, "test_sorted.arc", true).expect("Failed to create ArchiveCreator");
+            creator.create().expect("Failed to create archive");
+            let archive_fs = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_sorted.arc")).expect("Failed to open archive");
+            archive_fs.list_files_sorted("").unwrap().into_iter().map(|f| f.name).collect()
+        }).collect();
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_sorted.arc").ok();
+
+        assert_eq!(listings[0], ["audio", "Zones", "A.txt", "b.txt", "c.txt"]);
+        assert_eq!(listings[0], listings[1], "Listings should not depend on the run");
+    }
+
+    #[test]
+    fn test_archive_peek() {
+        let source = "test_peek_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/model.glb", source), b"glTF\x02\x00\x00\x00 and the rest").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_peek.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let mut creator = ArchiveCreator::new_unencrypted(source, "test_peek_plain.arc", true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let encrypted = ArchiveFileSystem::open(PathBuf::from("test_peek.arc"), key).expect("Failed to open archive");
+        let plain = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_peek_plain.arc")).expect("Failed to open archive");
+        let peeked = [encrypted.peek("model.glb", 4), plain.peek("model.glb", 4), plain.peek("model.glb", 1000)];
+        let missing = plain.peek("missing.glb", 4);
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_peek.arc").ok();
+        std::fs::remove_file("test_peek_plain.arc").ok();
+
+        let [encrypted_magic, plain_magic, whole] = peeked;
+        assert_eq!(encrypted_magic.unwrap(), b"glTF");
+        assert_eq!(plain_magic.unwrap(), b"glTF");
+        assert_eq!(whole.unwrap().len(), 21);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_archive_layout_report() {
+        let source = "test_layout_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/b.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/c.txt", source), b"other").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::builder()
+            .source_dir(source)
+            .output("test_layout.arc")
+            .key(key.clone())
+            .deduplicate(true)
+            .overwrite(true)
+            .build()
+            .expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_layout.arc"), key).expect("Failed to open archive");
+        let file_size = std::fs::metadata("test_layout.arc").unwrap().len();
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_layout.arc").ok();
+
+        let layout = archive_fs.layout_report();
+        let paths: Vec<&str> = layout.entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths.len(), 3);
+        assert!(layout.entries.windows(2).all(|pair| pair[0].offset <= pair[1].offset), "Entries should be ordered by offset");
+        let a = layout.entries.iter().find(|entry| entry.path == "a.txt").unwrap();
+        let b = layout.entries.iter().find(|entry| entry.path == "b.txt").unwrap();
+        assert_eq!(a.offset, b.offset);
+        assert_eq!(a.size, 12 + ENCRYPTION_OVERHEAD as u64);
+        assert_eq!(a.overhead, ENCRYPTION_OVERHEAD as u64);
+        assert_eq!(layout.header_size, HEADER_SIZE as u64);
+        assert_eq!(layout.header_size + layout.table_size, archive_fs.header.data_offset);
+        assert_eq!(layout.header_size + layout.table_size + layout.data_size, file_size, "Shared blobs should be counted once");
+    }
+
+    #[test]
+    fn test_archive_password() {
+        let build = |output: &str, encrypt_index: bool| ArchiveCreator::builder()
+            .source_dir("test_directory")
+            .output(output)
+            .password("correct horse")
+            .password_iterations(10)
+            .encrypt_index(encrypt_index)
+            .overwrite(true)
+            .build();
+        build("test_password.arc", true).expect("Failed to build ArchiveCreator").create().expect("Failed to create archive");
+        build("test_password_clear.arc", false).expect("Failed to build ArchiveCreator").create().expect("Failed to create archive");
+        let opened = ArchiveFileSystem::open_with_password(PathBuf::from("test_password.arc"), "correct horse");
+        let wrong = ArchiveFileSystem::open_with_password(PathBuf::from("test_password.arc"), "battery staple");
+        let clear = ArchiveFileSystem::open_with_password(PathBuf::from("test_password_clear.arc"), "correct horse").expect("Failed to open archive");
+        let header = ArchiveFileSystem::read_header(Path::new("test_password_clear.arc")).unwrap();
+        let derived = EncUtils::derive_key("correct horse", &header.password_salt, header.password_iterations).unwrap();
+        let with_key = ArchiveFileSystem::open(PathBuf::from("test_password_clear.arc"), derived);
+        let mut creator = ArchiveCreator::new("test_directory", "test_password_raw.arc", EncUtils::generate_random_key(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let not_password = ArchiveFileSystem::open_with_password(PathBuf::from("test_password_raw.arc"), "correct horse");
+        let read = |archive: ArchiveFileSystem| archive.read_file("test_file.txt");
+        let contents = [opened.map(read), Ok(read(clear)), with_key.map(read)];
+        for file in ["test_password.arc", "test_password_clear.arc", "test_password_raw.arc"] {
+            std::fs::remove_file(file).ok();
+        }
+
+        let expected = std::fs::read("test_directory/test_file.txt").unwrap();
+        for content in contents {
+            assert_eq!(content.unwrap().unwrap(), expected);
+        }
+        assert_eq!(header.password_iterations, 10);
+        assert!(wrong.is_err(), "A wrong password should not open an archive with an encrypted index");
+        assert!(not_password.err().unwrap().message.contains("not derived from a password"));
+        assert!(ArchiveCreator::builder().source_dir("test_directory").output("test_password_both.arc").key(EncUtils::generate_random_key()).password("pw").build().is_err());
+    }
+
+    #[test]
+    fn test_archive_max_read_bytes() {
+        let mut creator = ArchiveCreator::new_unencrypted("test_directory", "test_max_read.arc", true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let mut archive_fs = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_max_read.arc")).expect("Failed to open archive");
+        let unlimited = archive_fs.read_file("test_file.txt");
+        archive_fs.set_max_read_bytes(1);
+        let limited = archive_fs.read_file("test_file.txt");
+        // Cut off the end of the last blob
+        let file = std::fs::OpenOptions::new().write(true).open("test_max_read.arc").unwrap();
+        file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+        drop(file);
+        let truncated = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_max_read.arc"));
+        std::fs::remove_file("test_max_read.arc").ok();
+
+        assert!(unlimited.is_ok());
+        assert!(limited.is_err(), "Files over the limit should not be read");
+        assert!(truncated.err().unwrap().to_string().contains("exceeds"), "Entries past the end of the file should be rejected at open");
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_archive_open_mmap() {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new("test_directory", "test_mmap.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let expected = ArchiveFileSystem::open(PathBuf::from("test_mmap.arc"), key.clone()).unwrap().read_file("test_file.txt").unwrap();
+        // SAFETY: the archive is only removed after the mapping is dropped
+        let mut archive_fs = unsafe { ArchiveFileSystem::open_mmap(PathBuf::from("test_mmap.arc"), key) }.expect("Failed to map archive");
+        let content = archive_fs.read_file("test_file.txt");
+        let batch = archive_fs.read_files(&["test_file.txt"]);
+        let appended = archive_fs.append_file("new.txt", b"new");
+        drop(archive_fs);
+        std::fs::remove_file("test_mmap.arc").ok();
+
+        assert_eq!(content.unwrap(), expected);
+        assert_eq!(batch.unwrap()["test_file.txt"], expected);
+        assert!(appended.is_err(), "Mapped archives should be read-only");
+    }
+
+    #[test]
+    fn test_archive_close() {
+        let mut creator = ArchiveCreator::new_unencrypted("test_directory", "test_close.arc", true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_close.arc")).expect("Failed to open archive")
+            .with_cache(CacheLimit::Entries(4));
+        let content = archive_fs.read_file("test_file.txt");
+        let closed = archive_fs.close();
+        std::fs::remove_file("test_close.arc").ok();
+
+        assert!(content.is_ok());
+        assert!(closed.is_ok());
+    }
+
+    #[test]
+    fn test_archive_open_from() {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new("test_directory", "test_open_from.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        // An archive embedded as a section of a larger bundle, after some other data
+        let mut bundle = b"bundle header".to_vec();
+        bundle.extend(std::fs::read("test_open_from.arc").unwrap());
+        std::fs::remove_file("test_open_from.arc").ok();
+        let mut reader = std::io::Cursor::new(bundle);
+        reader.set_position(b"bundle header".len() as u64);
+        let mut archive_fs = ArchiveFileSystem::open_from(reader, key.clone()).expect("Failed to open archive from reader");
+        let content = archive_fs.read_file("test_file.txt");
+        let appended = archive_fs.append_file("new.txt", b"new");
+        let not_archive = ArchiveFileSystem::open_from(std::io::Cursor::new(b"bundle header".to_vec()), key);
+
+        assert_eq!(content.unwrap(), std::fs::read("test_directory/test_file.txt").unwrap());
+        assert!(appended.is_err(), "Archives opened from a reader are read-only");
+        assert!(archive_fs.root().is_none());
+        assert!(not_archive.err().is_some());
+    }
+
+    #[test]
+    fn test_archive_append_to() {
+        let key = EncUtils::generate_random_key();
+        std::fs::write("test_embed.bin", b"\x7fELF program code").unwrap();
+        let mut creator = ArchiveCreator::new("test_directory", "test_embed_unused.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.append_to("test_embed.bin").expect("Failed to append archive");
+        let size_once = std::fs::metadata("test_embed.bin").unwrap().len();
+        // Appending again replaces the archive instead of stacking a second one
+        creator.append_to("test_embed.bin").expect("Failed to append archive again");
+        let binary = std::fs::read("test_embed.bin").unwrap();
+        let content = ArchiveFileSystem::open_embedded(PathBuf::from("test_embed.bin"), key.clone())
+            .and_then(|archive_fs| archive_fs.read_file("test_file.txt"));
+        std::fs::write("test_embed_plain.bin", b"program code").unwrap();
+        let not_embedded = ArchiveFileSystem::open_embedded(PathBuf::from("test_embed_plain.bin"), key);
+        let output_written = Path::new("test_embed_unused.arc").exists();
+        std::fs::remove_file("test_embed.bin").ok();
+        std::fs::remove_file("test_embed_plain.bin").ok();
+
+        assert!(binary.starts_with(b"\x7fELF program code"), "The original content should be kept");
+        assert!(binary.ends_with(EMBED_MAGIC));
+        assert_eq!(size_once, binary.len() as u64);
+        assert_eq!(content.unwrap(), std::fs::read("test_directory/test_file.txt").unwrap());
+        assert!(not_embedded.err().is_some());
+        assert!(!output_written, "Only the existing file should be written");
+    }
+
+    #[test]
+    fn test_archive_open_best_effort() {
+        let source = "test_best_effort_source";
+        std::fs::create_dir_all(source).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(format!("{}/{}", source, name), format!("content of {}", name)).unwrap();
+        }
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_best_effort.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let layout = ArchiveFileSystem::open(PathBuf::from("test_best_effort.arc"), key.clone()).expect("Failed to open archive").layout_report();
+        let (flipped, truncated) = (&layout.entries[0], &layout.entries[2]);
+        // Flip a byte in the first blob and cut the last one short
+        let mut data = std::fs::read("test_best_effort.arc").unwrap();
+        data[flipped.offset as usize + 20] ^= 0xff;
+        data.truncate(truncated.offset as usize + 4);
+        std::fs::write("test_best_effort.arc", data).unwrap();
+        let strict = ArchiveFileSystem::open(PathBuf::from("test_best_effort.arc"), key.clone());
+        let (archive_fs, dropped) = ArchiveFileSystem::open_best_effort(PathBuf::from("test_best_effort.arc"), key).expect("Failed to open damaged archive");
+        let remaining: Vec<String> = archive_fs.list_files("").unwrap().into_iter().map(|f| f.path).collect();
+        let dropped: Vec<&str> = dropped.iter().map(|entry| entry.path.as_str()).collect();
+        let mut damaged = [flipped.path.as_str(), truncated.path.as_str()];
+        damaged.sort();
+        let salvaged = archive_fs.read_file(&layout.entries[1].path);
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_best_effort.arc").ok();
+
+        assert!(strict.err().is_some(), "open should stay strict");
+        assert_eq!(remaining, [layout.entries[1].path.clone()]);
+        assert_eq!(dropped, damaged, "Dropped entries should be listed by path");
+        assert_eq!(salvaged.unwrap(), format!("content of {}", layout.entries[1].path).into_bytes());
+    }
+
+    #[test]
+    fn test_archive_check_key() {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new("test_directory", "test_check_key.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let mut creator = ArchiveCreator::new("test_directory", "test_check_key_index.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.set_encrypt_index(true).unwrap();
+        creator.create().expect("Failed to create archive");
+        let mut creator = ArchiveCreator::new_unencrypted("test_directory", "test_check_key_plain.arc", true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let wrong_key = EncUtils::generate_random_key();
+        let results = [
+            ArchiveFileSystem::check_key(Path::new("test_check_key.arc"), key.clone()),
+            ArchiveFileSystem::check_key(Path::new("test_check_key.arc"), wrong_key.clone()),
+            ArchiveFileSystem::check_key(Path::new("test_check_key_index.arc"), key.clone()),
+            ArchiveFileSystem::check_key(Path::new("test_check_key_index.arc"), wrong_key),
+        ];
+        let plain = ArchiveFileSystem::check_key(Path::new("test_check_key_plain.arc"), key);
+        for file in ["test_check_key.arc", "test_check_key_index.arc", "test_check_key_plain.arc"] {
+            std::fs::remove_file(file).ok();
+        }
+
+        assert_eq!(results.map(Result::unwrap), [true, false, true, false]);
+        assert!(plain.is_err(), "Unencrypted archives have no key to check");
+    }
+
+    #[test]
+    fn test_archive_list_files() {
+        let source = "test_list_source";
+        std::fs::create_dir_all(format!("{}/textures/ui", source)).unwrap();
+        std::fs::create_dir_all(format!("{}/tex", source)).unwrap();
+        std::fs::write(format!("{}/root.txt", source), b"root").unwrap();
+        std::fs::write(format!("{}/textures/a.png", source), b"a").unwrap();
+        std::fs::write(format!("{}/textures/b.png", source), b"b").unwrap();
+        std::fs::write(format!("{}/textures/ui/button.png", source), b"button").unwrap();
+        std::fs::write(format!("{}/tex/other.png", source), b"other").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_list.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_list.arc"), key).expect("Failed to open archive");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_list.arc").ok();
+
+        let summarize = |files: Vec<FileInfo>| {
+            let mut summary: Vec<(String, bool)> = files.into_iter().map(|f| (f.path, f.is_directory)).collect();
+            summary.sort();
+            summary
+        };
+        let root = summarize(archive_fs.list_files("").unwrap());
+        assert_eq!(root, vec![
+            ("root.txt".to_string(), false),
+            ("tex".to_string(), true),
+            ("textures".to_string(), true),
+        ]);
+        let textures = summarize(archive_fs.list_files("textures/").unwrap());
+        assert_eq!(textures, vec![
+            ("textures/a.png".to_string(), false),
+            ("textures/b.png".to_string(), false),
+            ("textures/ui".to_string(), true),
+        ]);
+        assert_eq!(archive_fs.walk("textures").count(), 3);
+        assert_eq!(archive_fs.total_size("textures").unwrap(), 8, "Sizes should not include the encryption overhead");
+        assert_eq!(summarize(archive_fs.list_dirs("").unwrap()), vec![
+            ("tex".to_string(), true),
+            ("textures".to_string(), true),
+        ]);
+        assert_eq!(summarize(archive_fs.list_dirs("textures").unwrap()), vec![("textures/ui".to_string(), true)]);
+        assert_eq!(archive_fs.child_dirs(""), ["tex", "textures"]);
+        assert_eq!(archive_fs.child_dirs("textures/"), ["ui"]);
+        assert!(archive_fs.child_dirs("textures/ui").is_empty());
+        assert!(archive_fs.child_dirs("missing").is_empty());
+        assert_eq!(summarize(archive_fs.list_regular_files("textures").unwrap()), vec![
+            ("textures/a.png".to_string(), false),
+            ("textures/b.png".to_string(), false),
+        ]);
+        assert_eq!(archive_fs.entry_count(), 5);
+        assert!(archive_fs.is_dir("textures/ui").unwrap() && archive_fs.is_dir("").unwrap());
+        assert!(!archive_fs.is_dir("textures/a.png").unwrap());
+        assert!(!archive_fs.is_dir("text").unwrap(), "A partial directory name should not count");
+        assert!(archive_fs.is_file("textures/a.png").unwrap());
+        assert!(!archive_fs.is_file("textures").unwrap());
+        let paths: Vec<String> = archive_fs.entries_iter().map(|f| f.path).collect();
+        assert_eq!(paths, ["root.txt", "tex/other.png", "textures/a.png", "textures/b.png", "textures/ui/button.png"]);
+    }
+
+    #[test]
+    fn test_archive_path_separators() {
+        let entry = FileEntry::new("b.png", "textures\\ui\\b.png", 0, 0);
+        assert_eq!(entry.path(), "textures/ui/b.png");
+
+        let source = "test_separators_source";
+        std::fs::create_dir_all(format!("{}/textures/ui", source)).unwrap();
+        std::fs::write(format!("{}/textures/ui/b.png", source), b"b").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_separators.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_separators.arc"), key).expect("Failed to open archive");
+        let forward = archive_fs.read_file("textures/ui/b.png");
+        let backward = archive_fs.read_file("textures\\ui\\b.png");
+        let listed = archive_fs.list_files("textures\\ui");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_separators.arc").ok();
+        assert_eq!(forward.unwrap(), b"b");
+        assert_eq!(backward.unwrap(), b"b");
+        assert_eq!(listed.unwrap()[0].path, "textures/ui/b.png");
+    }
+
+    #[test]
+    fn test_archive_unencrypted() {
+        let mut creator = ArchiveCreator::builder()
+            .source_dir("test_directory")
+            .output("test_archive_plain.arc")
+            .cipher(CipherMode::None)
+            .overwrite(true)
+            .build()
+            .expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_archive_plain.arc")).expect("Failed to open archive");
+        let with_key = ArchiveFileSystem::open(PathBuf::from("test_archive_plain.arc"), EncUtils::generate_random_key());
+        let raw = std::fs::read("test_archive_plain.arc").unwrap();
+        let content = archive_fs.read_file("test_file.txt");
+        std::fs::remove_file("test_archive_plain.arc").ok();
+
+        let expected = std::fs::read("test_directory/test_file.txt").unwrap();
+        assert_eq!(archive_fs.cipher_mode(), CipherMode::None);
+        assert_eq!(content.unwrap(), expected);
+        assert!(raw.windows(expected.len()).any(|w| w == expected.as_slice()), "Contents should be stored as plain bytes");
+        assert!(with_key.is_err(), "Opening a plain archive with a key should fail");
+
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new("test_directory", "test_archive_encrypted.arc", key, true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let without_key = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_archive_encrypted.arc"));
+        std::fs::remove_file("test_archive_encrypted.arc").ok();
+        assert!(without_key.is_err(), "Opening an encrypted archive without a key should fail");
+    }
+
+    #[test]
+    fn test_archive_key_slots() {
+        let source = "test_key_slots_source";
+        std::fs::create_dir_all(format!("{}/dlc", source)).unwrap();
+        std::fs::write(format!("{}/base.txt", source), b"base").unwrap();
+        std::fs::write(format!("{}/dlc/extra.txt", source), b"extra").unwrap();
+        let key = EncUtils::generate_random_key();
+        let dlc_key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_key_slots.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.add_key_slot(1, dlc_key.clone(), "dlc/*").expect("Failed to add key slot");
+        assert!(creator.add_key_slot(DEFAULT_KEY_SLOT, dlc_key.clone(), "*").is_err(), "The default slot should be reserved");
+        creator.create().expect("Failed to create archive");
+        let partial = ArchiveFileSystem::open(PathBuf::from("test_key_slots.arc"), key.clone()).expect("Failed to open archive");
+        let full = ArchiveFileSystem::open_with_keyring(PathBuf::from("test_key_slots.arc"), HashMap::from([(DEFAULT_KEY_SLOT, key), (1, dlc_key)]))
+            .expect("Failed to open archive");
+        let empty = ArchiveFileSystem::open_with_keyring(PathBuf::from("test_key_slots.arc"), HashMap::new());
+
+        assert_eq!(partial.read_file("base.txt").unwrap(), b"base");
+        let locked = partial.read_file("dlc/extra.txt").expect_err("Reading without the slot key should fail");
+        assert!(locked.message.contains("slot 1"), "Unexpected error: {}", locked.message);
+        assert_eq!(full.read_file("base.txt").unwrap(), b"base");
+        assert_eq!(full.read_file("dlc/extra.txt").unwrap(), b"extra");
+        assert!(empty.is_err(), "An encrypted archive should need at least one key");
+
+        let unencrypted = ArchiveCreator::new_unencrypted(source, "test_key_slots_plain.arc", true)
+            .and_then(|mut creator| creator.add_key_slot(1, EncUtils::generate_random_key(), "*"));
+        std::fs::remove_file("test_key_slots.arc").ok();
+        std::fs::remove_dir_all(source).ok();
+        assert!(unencrypted.is_err(), "Unencrypted archives should not take key slots");
+    }
+
+    #[test]
+    fn test_archive_encrypted_index() {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::builder()
+            .source_dir("test_directory")
+            .output("test_archive_index.arc")
+            .key(key.clone())
+            .encrypt_index(true)
+            .overwrite(true)
+            .build()
+            .expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let raw = std::fs::read("test_archive_index.arc").unwrap();
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_archive_index.arc"), key).expect("Failed to open archive");
+        let wrong_key = ArchiveFileSystem::open(PathBuf::from("test_archive_index.arc"), EncUtils::generate_random_key());
+        let content = archive_fs.read_file("test_file.txt");
+        std::fs::remove_file("test_archive_index.arc").ok();
+
+        assert!(!raw.windows(b"test_file.txt".len()).any(|w| w == b"test_file.txt"), "File names should not be stored in the clear");
+        assert_eq!(content.unwrap(), std::fs::read("test_directory/test_file.txt").unwrap());
+        assert_eq!(archive_fs.list_files("").unwrap().len(), 1);
+        assert!(wrong_key.is_err(), "Opening with the wrong key should fail");
+
+        let mut plain = ArchiveCreator::new_unencrypted("test_directory", "test_archive_index_plain.arc", true).expect("Failed to create ArchiveCreator");
+        assert!(plain.set_encrypt_index(true).is_err(), "Unencrypted archives cannot encrypt their index");
+    }
+
+    #[test]
+    fn test_archive_read_files() {
+        let archive_fs = open_test_archive("test_archive_batch.arc");
+        let batch = archive_fs.read_files(&["test_file.txt"]);
+        let missing = archive_fs.read_files(&["test_file.txt", "missing.txt"]);
+        let mut buf = b"stale content from an earlier read".to_vec();
+        let read = archive_fs.read_into("test_file.txt", &mut buf);
+        std::fs::remove_file("test_archive_batch.arc").ok();
+        let expected = std::fs::read("test_directory/test_file.txt").unwrap();
+        assert_eq!(batch.expect("Failed to read files")["test_file.txt"], expected);
+        assert_eq!(read.unwrap(), expected.len());
+        assert_eq!(buf, expected);
+        assert!(missing.is_err(), "A missing path should fail the whole batch");
+    }
+
+    #[test]
+    fn test_archive_glob() {
+        let archive_fs = open_test_archive("test_archive_glob.arc");
+        let files = archive_fs.list_files_glob("", "*.txt").expect("Failed to list files in archive");
+        let missing = archive_fs.list_files_glob("", "*.png").expect("Failed to list files in archive");
+        std::fs::remove_file("test_archive_glob.arc").ok();
+        assert!(files.iter().any(|f| f.name == "test_file.txt"));
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_archive_deduplicate() {
+        let source = "test_dedup_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/b.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/c.txt", source), b"other content").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_dedup.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.set_deduplicate(true);
+        let calls = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        creator.set_progress(move |done, total| recorded.borrow_mut().push((done, total)));
+        creator.create().expect("Failed to create archive");
+        assert_eq!(*calls.borrow(), vec![(1, 3), (2, 3), (3, 3)]);
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_dedup.arc"), key).expect("Failed to open archive");
+        let a = archive_fs.table().entries["a.txt"].clone();
+        let b = archive_fs.table().entries["b.txt"].clone();
+        let c = archive_fs.table().entries["c.txt"].clone();
+        let content = archive_fs.read_file("b.txt");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_dedup.arc").ok();
+        assert_eq!((a.offset, a.size), (b.offset, b.size), "Identical files should share a blob");
+        assert_ne!(a.offset, c.offset);
+        assert_eq!(content.unwrap(), b"same content");
+    }
+
+    #[test]
+    fn test_archive_deduplicate_key_slots() {
+        let source = "test_dedup_slots_source";
+        std::fs::create_dir_all(format!("{}/secret", source)).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/b.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/secret/c.txt", source), b"same content").unwrap();
+        let (key, secret_key) = (EncUtils::generate_random_key(), EncUtils::generate_random_key());
+        let mut creator = ArchiveCreator::builder().source_dir(source).output("test_dedup_slots.arc").key(key.clone())
+            .key_slot(1, secret_key.clone(), "secret/*").deduplicate(true).build().expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let keyring = HashMap::from([(DEFAULT_KEY_SLOT, key.clone()), (1, secret_key)]);
+        let archive_fs = ArchiveFileSystem::open_with_keyring(PathBuf::from("test_dedup_slots.arc"), keyring).expect("Failed to open archive");
+        let contents = archive_fs.read_files(&["a.txt", "b.txt", "secret/c.txt"]);
+        let offsets = ["a.txt", "b.txt", "secret/c.txt"].map(|path| archive_fs.table().entries[path].offset);
+        let partial = ArchiveFileSystem::open(PathBuf::from("test_dedup_slots.arc"), key).expect("Failed to open archive").read_file("a.txt");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_dedup_slots.arc").ok();
+
+        let contents = contents.unwrap();
+        assert_eq!(contents["a.txt"], b"same content");
+        assert_eq!(contents["b.txt"], b"same content");
+        assert_eq!(contents["secret/c.txt"], b"same content");
+        assert_eq!(offsets[0], offsets[1], "Identical files in one slot should share a blob");
+        assert_ne!(offsets[0], offsets[2], "Files in different slots should not share a blob");
+        assert_eq!(partial.unwrap(), b"same content");
+    }
+
+    /// Run-length encoding as `(count, byte)` pairs, standing in for a user-supplied codec
+    struct RunLength;
+
+    impl Compressor for RunLength {
+        fn id(&self) -> u8 {
+            200
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>, FileSystemError> {
+            let mut compressed = Vec::new();
+            for run in data.chunk_by(|a, b| a == b) {
+                for part in run.chunks(255) {
+                    compressed.extend_from_slice(&[part.len() as u8, part[0]]);
+                }
+            }
+            Ok(compressed)
+        }
+
+        fn decompress(&self, data: &[u8], size: usize) -> Result<Vec<u8>, FileSystemError> {
+            let mut content = Vec::with_capacity(size);
+            for pair in data.chunks_exact(2) {
+                content.extend(std::iter::repeat_n(pair[1], pair[0] as usize));
+            }
+            Ok(content)
+        }
+    }
+
+    /// A codec claiming the id reserved for uncompressed entries
+    struct Reserved;
+
+    impl Compressor for Reserved {
+        fn id(&self) -> u8 {
+            NO_COMPRESSION
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>, FileSystemError> {
+            Ok(data.to_vec())
+        }
+
+        fn decompress(&self, data: &[u8], _size: usize) -> Result<Vec<u8>, FileSystemError> {
+            Ok(data.to_vec())
+        }
+    }
+
+    #[test]
+    fn test_archive_compression() {
+        let source = "test_compression_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/runs.dat", source), vec![7u8; 4000]).unwrap();
+        std::fs::write(format!("{}/mixed.txt", source), b"no runs here").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_compression.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        let reserved = creator.set_compressor(Box::new(Reserved));
+        creator.set_compressor(Box::new(RunLength)).unwrap();
+        creator.create().expect("Failed to create archive");
+        let archive_size = std::fs::metadata("test_compression.arc").unwrap().len();
+        let without_codec = ArchiveFileSystem::open(PathBuf::from("test_compression.arc"), key.clone()).expect("Failed to open archive");
+        let missing = without_codec.read_file("runs.dat");
+        let uncompressed = without_codec.read_file("mixed.txt");
+        let archive_fs = without_codec.with_compressor(Box::new(RunLength));
+        let runs = archive_fs.read_file("runs.dat");
+        let total = archive_fs.total_size("");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_compression.arc").ok();
+
+        assert!(reserved.is_err(), "Codec id 0 is reserved");
+        assert!(archive_size < 4000, "Compressible files should be stored compressed");
+        assert_eq!(archive_fs.table().entries["mixed.txt"].codec, NO_COMPRESSION, "Files that do not shrink are stored as they are");
+        assert!(missing.unwrap_err().message.contains("codec 200"));
+        assert_eq!(uncompressed.unwrap(), b"no runs here");
+        assert_eq!(runs.unwrap(), vec![7u8; 4000]);
+        assert_eq!(total.unwrap(), 4000 + 12);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_archive_deflate() {
+        let source = "test_deflate_source";
+        std::fs::create_dir_all(source).unwrap();
+        let text: String = (0..300).map(|i| format!("entry {} of a repetitive save file\n", i % 10)).collect();
+        std::fs::write(format!("{}/save.txt", source), &text).unwrap();
+        let key = EncUtils::generate_random_key();
+        ArchiveCreator::builder().source_dir(source).output("test_deflate.arc").key(key.clone()).overwrite(true)
+            .compressor(Box::new(crate::DeflateCompressor))
+            .build().unwrap()
+            .create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_deflate.arc"), key).expect("Failed to open archive");
+        let content = archive_fs.read_file("save.txt");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_deflate.arc").ok();
+
+        assert_eq!(archive_fs.table().entries["save.txt"].codec, crate::DEFLATE_CODEC_ID);
+        assert_eq!(content.unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn test_archive_compact() {
+        let source = "test_compact_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/b.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/c.txt", source), b"other content").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::builder()
+            .source_dir(source)
+            .output("test_compact.arc")
+            .key(key.clone())
+            .deduplicate(true)
+            .encrypt_index(true)
+            .overwrite(true)
+            .build()
+            .expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        // Stand-in for data no entry refers to any more
+        let mut file = std::fs::OpenOptions::new().append(true).open("test_compact.arc").unwrap();
+        file.write_all(&[0u8; 100]).unwrap();
+        drop(file);
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_compact.arc"), key.clone()).expect("Failed to open archive");
+        let reclaimed = archive_fs.compact("test_compact_out.arc");
+        let compacted = ArchiveFileSystem::open(PathBuf::from("test_compact_out.arc"), key).expect("Failed to open compacted archive");
+        let contents = compacted.read_files(&["a.txt", "b.txt", "c.txt"]);
+        let shared = compacted.table().entries["a.txt"].offset == compacted.table().entries["b.txt"].offset;
+        let flags = compacted.header.flags;
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_compact.arc").ok();
+        std::fs::remove_file("test_compact_out.arc").ok();
+
+        assert_eq!(reclaimed.unwrap(), 100);
+        let contents = contents.unwrap();
+        assert_eq!(contents["a.txt"], b"same content");
+        assert_eq!(contents["b.txt"], b"same content");
+        assert_eq!(contents["c.txt"], b"other content");
+        assert!(shared, "Deduplicated files should still share a blob");
+        assert_eq!(flags, FLAG_ENCRYPTED_INDEX);
+    }
+
+    #[test]
+    fn test_archive_reencrypt() {
+        let source = "test_reencrypt_source";
+        std::fs::create_dir_all(format!("{}/secret", source)).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/b.txt", source), b"same content").unwrap();
+        std::fs::write(format!("{}/secret/c.txt", source), b"secret content").unwrap();
+        let (key, secret_key, new_key) = (EncUtils::generate_random_key(), EncUtils::generate_random_key(), EncUtils::generate_random_key());
+        let mut creator = ArchiveCreator::builder().source_dir(source).output("test_reencrypt.arc").key(key.clone())
+            .key_slot(1, secret_key.clone(), "secret/*").deduplicate(true).encrypt_index(true).build().expect("Failed to build ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let keyring = HashMap::from([(DEFAULT_KEY_SLOT, key.clone()), (1, secret_key.clone())]);
+        let archive_fs = ArchiveFileSystem::open_with_keyring(PathBuf::from("test_reencrypt.arc"), keyring).expect("Failed to open archive");
+        let result = archive_fs.reencrypt("test_reencrypt_out.arc", new_key.clone());
+        let with_old_key = ArchiveFileSystem::open(PathBuf::from("test_reencrypt_out.arc"), key);
+        let new_keyring = HashMap::from([(DEFAULT_KEY_SLOT, new_key), (1, secret_key)]);
+        let reencrypted = ArchiveFileSystem::open_with_keyring(PathBuf::from("test_reencrypt_out.arc"), new_keyring).expect("Failed to open re-encrypted archive");
+        let contents = reencrypted.read_files(&["a.txt", "b.txt", "secret/c.txt"]);
+        let shared = reencrypted.table().entries["a.txt"].offset == reencrypted.table().entries["b.txt"].offset;
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_reencrypt.arc").ok();
+        std::fs::remove_file("test_reencrypt_out.arc").ok();
+
+        result.expect("Failed to re-encrypt archive");
+        assert!(with_old_key.is_err(), "The old key should no longer open the index");
+        let contents = contents.unwrap();
+        assert_eq!(contents["a.txt"], b"same content");
+        assert_eq!(contents["b.txt"], b"same content");
+        assert_eq!(contents["secret/c.txt"], b"secret content", "Other slots should keep their key");
+        assert!(shared, "Deduplicated files should still share a blob");
+    }
+
+    #[test]
+    fn test_archive_temp_path() {
+        let output = Path::new("out/game.arc");
+        let (first, second) = (temp_path(output), temp_path(output));
+
+        assert_ne!(first, second, "Every run should get its own temporary file");
+        assert_eq!(first.parent(), output.parent());
+        assert!(first.file_name().unwrap().to_string_lossy().starts_with(".game.arc."));
+    }
+
+    #[test]
+    fn test_archive_append() {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::builder().source_dir("test_directory").output("test_append.arc").key(key.clone())
+            .encrypt_index(true).reserved_entries(1).build().expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let mut archive_fs = ArchiveFileSystem::open(PathBuf::from("test_append.arc"), key.clone()).expect("Failed to open archive");
+        let data_offset = archive_fs.header.data_offset;
+        let appended = archive_fs.append_file("added/new.txt", b"appended");
+        let full = archive_fs.append_file("added/other.txt", b"no room");
+        let replaced = archive_fs.append_file("added/new.txt", b"replaced");
+        let in_memory = archive_fs.read_file("added/new.txt");
+        let reopened = ArchiveFileSystem::open(PathBuf::from("test_append.arc"), key).expect("Failed to reopen archive");
+        let reread = (reopened.read_file("added/new.txt"), reopened.read_file("test_file.txt"));
+        std::fs::remove_file("test_append.arc").ok();
+
+        appended.expect("Failed to append file");
+        assert!(full.err().unwrap().message.contains("No reserved entry slots left"));
+        replaced.expect("Replacing a file should not need a slot");
+        assert_eq!(in_memory.unwrap(), b"replaced");
+        assert_eq!(reopened.header.data_offset, data_offset, "Appending should not move the data");
+        assert_eq!(reopened.reserved_entries(), 0);
+        assert_eq!(reopened.entry_count(), 2);
+        assert_eq!(reread.0.unwrap(), b"replaced");
+        assert!(reread.1.is_ok());
+    }
+
+    #[test]
+    fn test_archive_rename_dir() {
+        let source = "test_rename_source";
+        std::fs::create_dir_all(format!("{}/mods/old/ui", source)).unwrap();
+        std::fs::create_dir_all(format!("{}/mods/taken", source)).unwrap();
+        std::fs::write(format!("{}/mods/old/a.png", source), b"a").unwrap();
+        std::fs::write(format!("{}/mods/old/ui/b.png", source), b"b").unwrap();
+        std::fs::write(format!("{}/mods/taken/a.png", source), b"taken").unwrap();
+        std::fs::write(format!("{}/mods/older.png", source), b"older").unwrap();
+        let mut creator = ArchiveCreator::new_unencrypted(source, "test_rename.arc", true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_rename.arc")).expect("Failed to open archive");
+        let collision = archive_fs.rename_entries("mods/old", "mods/taken");
+        let into_itself = archive_fs.rename_entries("mods", "mods/nested");
+        let missing = archive_fs.rename_entries("mods/missing", "mods/other");
+        let fs: &dyn FileSystem = &archive_fs;
+        let renamed = fs.rename_dir("mods/old", "mods/new");
+        let listed: Vec<String> = fs.list_files("mods").unwrap().into_iter().map(|f| f.path).collect();
+        let reopened = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_rename.arc")).expect("Failed to reopen archive");
+        let paths: Vec<String> = reopened.entries_iter().map(|f| f.path).collect();
+        let moved = reopened.read_file("mods/new/ui/b.png");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_rename.arc").ok();
+
+        assert!(collision.err().unwrap().message.contains("mods/taken/a.png"));
+        assert!(into_itself.is_err());
+        assert_eq!(missing.unwrap_err().kind, crate::FileSystemErrorKind::NotFound);
+        renamed.expect("Failed to rename directory");
+        assert_eq!(listed, ["mods/new", "mods/older.png", "mods/taken"], "The open archive should see the rename");
+        assert_eq!(paths, ["mods/new/a.png", "mods/new/ui/b.png", "mods/older.png", "mods/taken/a.png"]);
+        assert_eq!(moved.unwrap(), b"b");
+    }
+
+    #[test]
+    fn test_archive_swap_entries() {
+        let source = "test_swap_source";
+        std::fs::create_dir_all(format!("{}/saves", source)).unwrap();
+        std::fs::write(format!("{}/saves/a.sav", source), b"slot a").unwrap();
+        std::fs::write(format!("{}/saves/b.sav", source), b"slot b, longer").unwrap();
+        let mut creator = ArchiveCreator::new_unencrypted(source, "test_swap.arc", true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_swap.arc")).expect("Failed to open archive");
+        let missing = archive_fs.swap_entries("saves/a.sav", "saves/c.sav");
+        let id_before = archive_fs.content_id("saves/b.sav").unwrap();
+        let fs: &dyn FileSystem = &archive_fs;
+        fs.swap_files("saves/a.sav", "saves/b.sav").expect("Failed to swap entries");
+        let swapped = (archive_fs.read_file("saves/a.sav"), archive_fs.read_file("saves/b.sav"));
+        let id_after = archive_fs.content_id("saves/a.sav").unwrap();
+        let full_hash = archive_fs.hash_file_hex("saves/a.sav").unwrap();
+        let reopened = ArchiveFileSystem::open_unencrypted(PathBuf::from("test_swap.arc")).expect("Failed to reopen archive");
+        let persisted = reopened.read_file("saves/a.sav");
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_swap.arc").ok();
+
+        assert_eq!(missing.unwrap_err().kind, crate::FileSystemErrorKind::NotFound);
+        assert_eq!(swapped.0.unwrap(), b"slot b, longer");
+        assert_eq!(swapped.1.unwrap(), b"slot a");
+        assert_eq!(persisted.unwrap(), b"slot b, longer");
+        assert_eq!(id_before, id_after, "The content identifier should move with the content");
+        assert_eq!(id_after, full_hash, "Entries with a stored hash should be identified by it");
+    }
+
+    #[test]
+    fn test_archive_concurrent_reads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ArchiveFileSystem>();
+
+        let source = "test_concurrent_source";
+        std::fs::create_dir_all(source).unwrap();
+        for i in 0..8 {
+            std::fs::write(format!("{}/file_{}.bin", source, i), vec![i as u8; 1000 + i]).unwrap();
+        }
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_concurrent.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let archive_fs = std::sync::Arc::new(ArchiveFileSystem::open(PathBuf::from("test_concurrent.arc"), key).expect("Failed to open archive"));
+        let workers: Vec<_> = (0..8).map(|i| {
+            let archive_fs = archive_fs.clone();
+            std::thread::spawn(move || {
+                (0..20).all(|_| archive_fs.read_file(&format!("file_{}.bin", i)).unwrap() == vec![i as u8; 1000 + i])
+            })
+        }).collect();
+        let results: Vec<bool> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_concurrent.arc").ok();
+        assert!(results.into_iter().all(|ok| ok), "Every thread should read its own entry correctly");
+    }
+
+    #[test]
+    fn test_archive_read_cache() {
+        let source = "test_cache_source";
+        std::fs::create_dir_all(source).unwrap();
+        std::fs::write(format!("{}/a.txt", source), b"aaaa").unwrap();
+        std::fs::write(format!("{}/b.txt", source), b"bbbb").unwrap();
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new(source, "test_cache.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let reads = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = reads.clone();
+        let archive_fs = ArchiveFileSystem::open(PathBuf::from("test_cache.arc"), key.clone()).expect("Failed to open archive")
+            .with_cache(CacheLimit::Entries(1))
+            .with_observer(std::sync::Arc::new(move |event| if let FsEvent::Read { .. } = event {
+                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }));
+        let by_bytes = ArchiveFileSystem::open(PathBuf::from("test_cache.arc"), key).expect("Failed to open archive")
+            .with_cache(CacheLimit::Bytes(3));
+        archive_fs.read_file("a.txt").unwrap();
+        archive_fs.read_file("b.txt").unwrap();
+        by_bytes.read_file("a.txt").unwrap();
+        // Cached reads no longer need the archive file
+        std::fs::remove_dir_all(source).ok();
+        std::fs::remove_file("test_cache.arc").ok();
+
+        assert_eq!(archive_fs.read_file("b.txt").unwrap(), b"bbbb");
+        assert!(archive_fs.read_file("a.txt").is_err(), "The least recently used file should have been evicted");
+        assert!(by_bytes.read_file("a.txt").is_err(), "Files larger than the byte limit should not be cached");
+        archive_fs.clear_cache();
+        assert!(archive_fs.read_file("b.txt").is_err(), "Clearing the cache should drop every file");
+        assert_eq!(reads.load(std::sync::atomic::Ordering::Relaxed), 3, "Cached reads should be reported, failed reads not");
+    }
+
+    #[test]
+    fn test_archive_version() {
+        let key = EncUtils::generate_random_key();
+        let mut creator = ArchiveCreator::new("test_directory", "test_archive_version.arc", key.clone(), true).expect("Failed to create ArchiveCreator");
+        creator.create().expect("Failed to create archive");
+        let current = ArchiveFileSystem::version_of(Path::new("test_archive_version.arc"));
+        let header = |version: u8| Header { version, cipher: CipherMode::Aes256Gcm, flags: 0, number_of_files: 1, size: 0, data_offset: 0, reserved_entries: 0, password_salt: [0; PASSWORD_SALT_SIZE], password_iterations: 0 }.to_bytes();
+        std::fs::write("test_archive_version_newer.arc", header(ARCHIVE_VERSION + 1)).unwrap();
+        std::fs::write("test_archive_version_unknown.arc", header(0)).unwrap();
+        std::fs::write("test_archive_version_legacy.arc", [LEGACY_VERSION; HEADER_SIZE]).unwrap();
+        let newer_version = ArchiveFileSystem::version_of(Path::new("test_archive_version_newer.arc"));
+        let newer = ArchiveFileSystem::open(PathBuf::from("test_archive_version_newer.arc"), key.clone());
+        let unknown = ArchiveFileSystem::open(PathBuf::from("test_archive_version_unknown.arc"), key);
+        let legacy = ArchiveFileSystem::version_of(Path::new("test_archive_version_legacy.arc"));
+        let missing = ArchiveFileSystem::version_of(Path::new("test_archive_version_missing.arc"));
+        for file in ["", "_newer", "_unknown", "_legacy"] {
+            std::fs::remove_file(format!("test_archive_version{}.arc", file)).ok();
+        }
+
+        assert_eq!(current.unwrap(), ARCHIVE_VERSION);
+        assert_eq!(newer_version.unwrap(), ARCHIVE_VERSION + 1);
+        let newer = newer.err().unwrap().message;
+        assert!(newer.contains(&format!("version {}", ARCHIVE_VERSION + 1)) && newer.contains("update evfs"), "Unexpected error: {}", newer);
+        let unknown = unknown.err().unwrap().message;
+        assert!(unknown.contains("Unknown archive format version 0"), "Unexpected error: {}", unknown);
+        assert_eq!(legacy.unwrap(), LEGACY_VERSION);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_archive_legacy_format() {
+        // A version 1 archive, laid out by hand: no magic number, cipher mode or flags,
+        // entries without the fields added since, and blobs without a format version byte
+        let key = EncUtils::generate_random_key();
+        let enc_utils = EncUtils::new(key.clone()).unwrap();
+        let files = [("a.txt", "a.txt", b"first file".to_vec()), ("b.txt", "dir/b.txt", b"second".to_vec())];
+        let blobs: Vec<Vec<u8>> = files.iter().map(|(_, _, content)| enc_utils.encrypt(content.clone()).unwrap()[1..].to_vec()).collect();
+        let data_offset = header_size(LEGACY_VERSION) + files.len() * entry_size(LEGACY_VERSION);
+        let size = data_offset + blobs.iter().map(Vec::len).sum::<usize>();
+        let mut bytes = vec![LEGACY_VERSION];
+        bytes.extend_from_slice(&(files.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(size as u64).to_le_bytes());
+        bytes.extend_from_slice(&(data_offset as u64).to_le_bytes());
+        let mut offset = data_offset as u64;
+        for ((name, path, _), blob) in files.iter().zip(&blobs) {
+            bytes.extend_from_slice(&FileEntry::new(name, path, blob.len() as u64, offset).to_bytes()[..entry_size(LEGACY_VERSION)]);
+            offset += blob.len() as u64;
+        }
+        bytes.extend(blobs.concat());
+        std::fs::write("test_archive_legacy_v1.arc", bytes).unwrap();
+        let version = ArchiveFileSystem::version_of(Path::new("test_archive_legacy_v1.arc"));
+        let v1 = ArchiveFileSystem::open(PathBuf::from("test_archive_legacy_v1.arc"), key.clone()).expect("Failed to open version 1 archive");
+        let contents = v1.read_files(&["a.txt", "dir/b.txt"]);
+        let renamed = v1.rename_dir("dir", "other");
+        let compacted = v1.compact("test_archive_legacy_out.arc")
+            .and_then(|_| ArchiveFileSystem::open(PathBuf::from("test_archive_legacy_out.arc"), key));
+        let upgraded = compacted.map(|compacted| (compacted.header.version, compacted.read_file("dir/b.txt")));
+        for file in ["v1", "out"] {
+            std::fs::remove_file(format!("test_archive_legacy_{}.arc", file)).ok();
+        }
+
+        assert_eq!(version.unwrap(), LEGACY_VERSION);
+        let contents = contents.expect("Failed to read legacy archive");
+        assert_eq!(contents["a.txt"], b"first file");
+        assert_eq!(contents["dir/b.txt"], b"second");
+        assert_eq!(v1.list_files("dir").unwrap().len(), 1);
+        assert_eq!(v1.table().entries["a.txt"].uncompressed_size, 10);
+        assert!(v1.content_id("a.txt").unwrap().contains(':'), "Version 1 entries have no hash");
+        assert!(renamed.err().unwrap().message.contains("compact this one first"));
+        let (version, content) = upgraded.expect("Failed to upgrade the archive");
+        assert_eq!(version, ARCHIVE_VERSION);
+        assert_eq!(content.unwrap(), b"second");
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        assert!(Header::from_bytes(&[LEGACY_VERSION; LEGACY_HEADER_SIZE - 1]).is_err());
+        let unreleased = Header::from_bytes(&[ARCHIVE_VERSION; HEADER_SIZE]).err().unwrap();
+        assert!(unreleased.message.contains("Not an EVFS archive"), "Unexpected error: {}", unreleased.message);
+        let mut magic_legacy = ARCHIVE_MAGIC.to_vec();
+        magic_legacy.resize(HEADER_SIZE, LEGACY_VERSION);
+        let magic_legacy = Header::from_bytes(&magic_legacy).err().unwrap();
+        assert!(magic_legacy.message.contains("Unknown archive format version"), "Unexpected error: {}", magic_legacy.message);
+
+        // Files that are not archives are told apart by the missing magic number
+        std::fs::write("test_not_archive.arc", b"This is just some text, not an archive at all").unwrap();
+        std::fs::write("test_tiny.arc", b"EV").unwrap();
+        let not_archive = ArchiveFileSystem::open(PathBuf::from("test_not_archive.arc"), EncUtils::generate_random_key());
+        let tiny = ArchiveFileSystem::open(PathBuf::from("test_tiny.arc"), EncUtils::generate_random_key());
+        let detected = ArchiveFileSystem::is_archive(Path::new("test_not_archive.arc"));
+        std::fs::remove_file("test_not_archive.arc").ok();
+        std::fs::remove_file("test_tiny.arc").ok();
+        assert!(not_archive.err().unwrap().message.contains("Not an EVFS archive"));
+        assert!(tiny.err().unwrap().message.contains("Not an EVFS archive"));
+        assert!(!detected);
+        assert!(FileEntry::from_bytes(&[0u8; FILE_ENTRY_SIZE - 1], ARCHIVE_VERSION).is_err());
+        assert!(FileEntry::from_bytes(&[0u8; LEGACY_FILE_ENTRY_SIZE], LEGACY_VERSION).is_ok(), "Version 1 entries have no hash, key slot, codec or volume");
+
+        // A header claiming far more entries than the file can hold must be rejected
+        let header = Header {
+            version: ARCHIVE_VERSION,
+            cipher: CipherMode::Aes256Gcm,
+            flags: 0,
+            number_of_files: u32::MAX,
+            size: u64::MAX,
+            data_offset: u64::MAX,
+            reserved_entries: 0,
+            password_salt: [0; PASSWORD_SALT_SIZE],
+            password_iterations: 0,
+        };
+        std::fs::write("test_truncated.arc", header.to_bytes()).unwrap();
+        let result = ArchiveFileSystem::open(PathBuf::from("test_truncated.arc"), EncUtils::generate_random_key());
+        std::fs::remove_file("test_truncated.arc").ok();
+        assert!(result.is_err(), "Expected error for oversized entry table");
     }
 }
\ No newline at end of file
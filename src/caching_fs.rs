@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
+use crate::{join_path, normalize_path, Capabilities, FileContent, FileInfo, FileSystem, FileSystemError, LocalFileSystem};
+
+/// Size of the version stamp stored in front of every cached file
+const STAMP_SIZE: usize = 32;
+
+/// How long a file's version stamp is reused before the source is asked again, by default
+pub const DEFAULT_STAMP_TTL: Duration = Duration::from_secs(1);
+
+/// A file system that keeps a local disk copy of every file read from a slow source.
+///
+/// `read_file` looks in the cache first and only reads the source on a miss, storing what
+/// it read for next time. Each cached copy is stamped with the version of the source file
+/// it came from, taken from the source file's size and modification time. A copy whose
+/// stamp no longer matches the source is treated as a miss, so changes to the source are
+/// picked up. Stamps are remembered for `DEFAULT_STAMP_TTL`, so repeated hits do not list
+/// the source each time, and a change made behind the cache's back can go unnoticed for
+/// that long. When the source does not report modification times, only a change of size
+/// is noticed, and a copy otherwise stays valid until it is invalidated.
+///
+/// Writes and deletes go to the source and drop the cached copy. Cached files are stored
+/// under the SHA-256 of their path, so the cache directory does not mirror the source layout.
+pub struct CachingFileSystem<S: FileSystem> {
+    source: S,
+    cache: LocalFileSystem,
+    /// Version stamps computed recently, with when they were computed, by path
+    stamps: Mutex<HashMap<String, (Instant, [u8; STAMP_SIZE])>>,
+    stamp_ttl: Duration,
+}
+
+impl<S: FileSystem> CachingFileSystem<S> {
+    /// Creates a `CachingFileSystem` reading from `source` and caching into `cache`.
+    ///
+    /// # Arguments
+    /// - _source:_ The file system to cache.
+    /// - _cache:_ The local file system to store cached copies in. Atomic writes are
+    ///   enabled on it so a crash never leaves a partial copy behind.
+    ///
+    /// # Errors
+    /// `FileSystemError` if `cache` is not writable.
+    pub fn new(source: S, mut cache: LocalFileSystem) -> Result<Self, FileSystemError> {
+        if !cache.is_writable() {
+            return Err(FileSystemError::from("The cache file system must be writable"));
+        }
+        cache.set_atomic_writes(true);
+        Ok(CachingFileSystem { source, cache, stamps: Mutex::new(HashMap::new()), stamp_ttl: DEFAULT_STAMP_TTL })
+    }
+
+    /// Sets how long a file's version stamp is reused before the source is listed again,
+    /// `DEFAULT_STAMP_TTL` by default. Zero checks the source on every read.
+    ///
+    /// # Arguments
+    /// - _stamp_ttl:_ The time to reuse a stamp for.
+    pub fn set_stamp_ttl(&mut self, stamp_ttl: Duration) {
+        self.stamp_ttl = stamp_ttl;
+    }
+
+    /// Drops the cached copy of `path`, if there is one.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the cached copy exists but cannot be deleted.
+    pub fn invalidate(&self, path: &str) -> Result<(), FileSystemError> {
+        self.stamps.lock().unwrap_or_else(|e| e.into_inner()).remove(&normalize_path(path));
+        let cache_path = Self::cache_path(path);
+        if !Path::new(self.cache.root().unwrap_or("")).join(&cache_path).is_file() {
+            return Ok(());
+        }
+        self.cache.delete_file(&cache_path)
+    }
+
+    /// Drops every cached copy.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the cache directory cannot be emptied.
+    pub fn clear(&self) -> Result<(), FileSystemError> {
+        self.stamps.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.cache.delete_dir_recursive("")
+    }
+
+    /// Returns the cached file system.
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    fn cache_path(path: &str) -> String {
+        let path = normalize_path(path);
+        let digest: String = Sha256::digest(path.trim_start_matches('/').as_bytes())
+            .iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}/{}", &digest[..2], &digest[2..])
+    }
+
+    fn collect_files(&self, directory: &str, files: &mut Vec<String>) -> Result<(), FileSystemError> {
+        for info in self.source.list_files(directory)? {
+            let path = join_path(directory, &info.name);
+            if info.is_directory {
+                self.collect_files(&path, files)?;
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the version stamp of `path` in the source, reusing one computed less than
+    /// `stamp_ttl` ago.
+    fn stamp(&self, path: &str) -> Result<[u8; STAMP_SIZE], FileSystemError> {
+        let path = normalize_path(path);
+        if let Some((computed, stamp)) = self.stamps.lock().unwrap_or_else(|e| e.into_inner()).get(&path)
+            && computed.elapsed() < self.stamp_ttl {
+            return Ok(*stamp);
+        }
+        let (directory, name) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+        let info = self.source.list_files(directory)?.into_iter()
+            .find(|f| f.name == name && !f.is_directory)
+            .ok_or_else(|| FileSystemError::not_found(&path))?;
+        let nanos = info.modified
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let stamp: [u8; STAMP_SIZE] = Sha256::digest(format!("{}:{}:{}", path, info.size, nanos)).into();
+        self.stamps.lock().unwrap_or_else(|e| e.into_inner()).insert(path, (Instant::now(), stamp));
+        Ok(stamp)
+    }
+}
+
+impl<S: FileSystem> FileSystem for CachingFileSystem<S> {
+    /// Reads from the cache if its copy is current, otherwise from the source.
+    ///
+    /// Failing to store a copy in the cache does not fail the read.
+    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        let stamp = self.stamp(path)?;
+        let cache_path = Self::cache_path(path);
+        if let Ok(mut cached) = self.cache.read_file(&cache_path)
+            && cached.len() >= STAMP_SIZE && cached[..STAMP_SIZE] == stamp {
+            cached.drain(..STAMP_SIZE);
+            return Ok(cached);
+        }
+        let content = self.source.read_file(path)?;
+        let mut cached = Vec::with_capacity(STAMP_SIZE + content.len());
+        cached.extend_from_slice(&stamp);
+        cached.extend_from_slice(&content);
+        self.cache.write_file(&cache_path, cached).ok();
+        Ok(content)
+    }
+
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        let stored = self.source.write_file(path, content)?;
+        self.invalidate(path)?;
+        Ok(stored)
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
+        self.source.delete_file(path)?;
+        self.invalidate(path)
+    }
+
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.source.swap_files(a, b)?;
+        self.invalidate(a)?;
+        self.invalidate(b)
+    }
+
+    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        self.source.list_files(directory)
+    }
+
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.source.is_file(path)
+    }
+
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.source.is_dir(path)
+    }
+
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        self.source.touch(path)
+    }
+
+    fn truncate_file(&self, path: &str, len: u64) -> Result<(), FileSystemError> {
+        self.source.truncate_file(path, len)?;
+        self.invalidate(path)
+    }
+
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        let mut files = Vec::new();
+        self.collect_files(path, &mut files)?;
+        self.source.delete_dir_recursive(path)?;
+        for file in files {
+            self.invalidate(&file)?;
+        }
+        Ok(())
+    }
+
+    /// Appends change the source file's size and modification time, so a copy cached
+    /// before them no longer matches its stamp; it is dropped right away all the same.
+    fn open_append(&self, path: &str) -> Result<Box<dyn std::io::Write + Send>, FileSystemError> {
+        let writer = self.source.open_append(path)?;
+        self.invalidate(path)?;
+        Ok(writer)
+    }
+
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        let mut files = Vec::new();
+        self.collect_files(from, &mut files)?;
+        self.source.rename_dir(from, to)?;
+        for file in files {
+            self.invalidate(&file)?;
+        }
+        Ok(())
+    }
+
+    fn hash_file(&self, path: &str) -> Result<[u8; 32], FileSystemError> {
+        self.source.hash_file(path)
+    }
+
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        self.source.content_id(path)
+    }
+
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        self.source.sync(path)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.source.capabilities()
+    }
+
+    fn root(&self) -> Option<&str> {
+        self.source.root()
+    }
+
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        self.source.available_space()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached_files() -> usize {
+        let cache = LocalFileSystem::new("test_caching_cache", true).unwrap();
+        cache.walk("").filter(|f| f.as_ref().is_ok_and(|f| !f.is_directory)).count()
+    }
+
+    #[test]
+    fn test_caching_file_system() {
+        let source = LocalFileSystem::new("test_caching_source", true).unwrap();
+        source.write_file("data/a.txt", b"original".to_vec()).unwrap();
+        let cache = LocalFileSystem::new("test_caching_cache", true).unwrap();
+        let mut caching = CachingFileSystem::new(source, cache).unwrap();
+        caching.set_stamp_ttl(Duration::ZERO);
+
+        let first = caching.read_file("data/a.txt").unwrap();
+        let populated = cached_files();
+        let second = caching.read_file("data/a.txt").unwrap();
+        // A change made behind the cache's back must be detected through the stamp
+        std::fs::write("test_caching_source/data/a.txt", b"changed underneath").unwrap();
+        let stale = caching.read_file("data/a.txt").unwrap();
+        caching.invalidate("data/a.txt").unwrap();
+        let after_invalidate = cached_files();
+        caching.read_file("data/a.txt").unwrap();
+        caching.write_file("data/a.txt", b"written".to_vec()).unwrap();
+        let after_write = cached_files();
+        caching.read_file("data/a.txt").unwrap();
+        caching.clear().unwrap();
+        let after_clear = cached_files();
+        std::fs::remove_dir_all("test_caching_source").ok();
+        std::fs::remove_dir_all("test_caching_cache").ok();
+
+        assert_eq!(first, b"original");
+        assert_eq!(populated, 1, "A read should populate the cache");
+        assert_eq!(second, b"original");
+        assert_eq!(stale, b"changed underneath");
+        assert_eq!(after_invalidate, 0);
+        assert_eq!(after_write, 0, "Writing through the cache should drop the cached copy");
+        assert_eq!(after_clear, 0);
+    }
+
+    #[test]
+    fn test_caching_file_system_stamp_ttl() {
+        let source = LocalFileSystem::new("test_caching_ttl_source", true).unwrap();
+        source.write_file("a.txt", b"original".to_vec()).unwrap();
+        let cache = LocalFileSystem::new("test_caching_ttl_cache", true).unwrap();
+        let caching = CachingFileSystem::new(source, cache).unwrap();
+
+        caching.read_file("a.txt").unwrap();
+        std::fs::write("test_caching_ttl_source/a.txt", b"changed underneath").unwrap();
+        let within_ttl = caching.read_file("a.txt").unwrap();
+        caching.invalidate("a.txt").unwrap();
+        let after_invalidate = caching.read_file("a.txt").unwrap();
+        let missing = caching.read_file("missing.txt");
+        std::fs::remove_dir_all("test_caching_ttl_source").ok();
+        std::fs::remove_dir_all("test_caching_ttl_cache").ok();
+
+        assert_eq!(within_ttl, b"original", "A recent stamp should be reused without listing the source");
+        assert_eq!(after_invalidate, b"changed underneath");
+        assert!(missing.is_err_and(|e| e.kind == crate::FileSystemErrorKind::NotFound));
+    }
+}
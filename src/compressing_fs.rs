@@ -0,0 +1,253 @@
+use crate::{Capabilities, Compressor, DeflateCompressor, FileContent, FileInfo, FileSystem, FileSystemError, NO_COMPRESSION};
+
+/// Size of the header in front of a compressed file: codec id and original size
+const COMPRESSED_HEADER_SIZE: usize = 1 + 8;
+
+/// Extensions of formats that are compressed already, stored as they are by
+/// `CompressingFileSystem`.
+pub const INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "ogg", "mp3", "mp4", "webm", "zip", "gz", "7z", "arc",
+];
+
+/// A file system that compresses files on `write_file` and decompresses them on
+/// `read_file`, e.g. to save space on text assets such as configs and JSON.
+///
+/// Every file starts with a one-byte header recording how it was stored: `NO_COMPRESSION`
+/// followed by the content as is, or the id of the codec followed by the original size
+/// and the compressed content. Files shorter than `min_size`, files with an extension in
+/// `INCOMPRESSIBLE_EXTENSIONS`, and files that would not get smaller are stored as is,
+/// so nothing is lost on binary assets. Because of the header, files written through
+/// the wrapper can only be read back through it, and `list_files` reports stored sizes.
+///
+/// Encrypted data does not compress, so to combine compression with encryption, wrap the
+/// encrypted backend: `CompressingFileSystem<LocalEncryptedFileSystem>` compresses first
+/// and encrypts the result.
+pub struct CompressingFileSystem<T: FileSystem> {
+    inner: T,
+    compressor: Box<dyn Compressor>,
+    min_size: usize,
+}
+
+impl<T: FileSystem> CompressingFileSystem<T> {
+    /// Wraps `inner`, compressing with `DeflateCompressor` files of at least 256 bytes.
+    ///
+    /// # Arguments
+    /// - _inner:_ The file system to store the files in.
+    pub fn new(inner: T) -> Self {
+        CompressingFileSystem { inner, compressor: Box::new(DeflateCompressor), min_size: 256 }
+    }
+
+    /// Sets the size below which files are stored without compression, since the header
+    /// and codec framing outweigh any savings on tiny files.
+    ///
+    /// # Arguments
+    /// - _min_size:_ The threshold in bytes.
+    pub fn set_min_size(&mut self, min_size: usize) {
+        self.min_size = min_size;
+    }
+
+    /// Sets the codec new files are compressed with. Files are read back with the codec
+    /// they were written with, so this only reads files whose header holds its id or
+    /// `NO_COMPRESSION`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the codec uses the id `NO_COMPRESSION`.
+    pub fn set_compressor(&mut self, compressor: Box<dyn Compressor>) -> Result<(), FileSystemError> {
+        if compressor.id() == NO_COMPRESSION {
+            return Err(FileSystemError::from("A compressor cannot use the id reserved for uncompressed files"));
+        }
+        self.compressor = compressor;
+        Ok(())
+    }
+
+    /// Returns the wrapped file system.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn should_compress(&self, path: &str, content: &[u8]) -> bool {
+        if content.len() < self.min_size {
+            return false;
+        }
+        let extension = path.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+        !extension.is_some_and(|extension| INCOMPRESSIBLE_EXTENSIONS.contains(&extension.as_str()))
+    }
+}
+
+impl<T: FileSystem> FileSystem for CompressingFileSystem<T> {
+    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        let mut stored = self.inner.read_file(path)?;
+        let Some(&codec) = stored.first() else {
+            return Ok(stored);
+        };
+        if codec == NO_COMPRESSION {
+            stored.remove(0);
+            return Ok(stored);
+        }
+        if codec != self.compressor.id() {
+            return Err(FileSystemError::from(format!(
+                "{} is compressed with codec {}, set a Compressor for it with set_compressor",
+                path, codec
+            )));
+        }
+        if stored.len() < COMPRESSED_HEADER_SIZE {
+            return Err(FileSystemError::from(format!("Compressed file {} is truncated", path)));
+        }
+        let size = u64::from_le_bytes(stored[1..COMPRESSED_HEADER_SIZE].try_into().expect("8 bytes"));
+        let size = usize::try_from(size).map_err(|_| FileSystemError::from(format!("Compressed file {} is too large", path)))?;
+        let content = self.compressor.decompress(&stored[COMPRESSED_HEADER_SIZE..], size)?;
+        if content.len() != size {
+            return Err(FileSystemError::from(format!("Compressed file {} is corrupted, it decompresses to {} bytes instead of {}", path, content.len(), size)));
+        }
+        Ok(content)
+    }
+
+    /// Returns the number of bytes the inner file system stored, header included.
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        if self.should_compress(path, &content) {
+            let compressed = self.compressor.compress(&content)?;
+            if compressed.len() + COMPRESSED_HEADER_SIZE < content.len() + 1 {
+                let mut stored = Vec::with_capacity(COMPRESSED_HEADER_SIZE + compressed.len());
+                stored.push(self.compressor.id());
+                stored.extend_from_slice(&(content.len() as u64).to_le_bytes());
+                stored.extend_from_slice(&compressed);
+                return self.inner.write_file(path, stored);
+            }
+        }
+        let mut stored = Vec::with_capacity(1 + content.len());
+        stored.push(NO_COMPRESSION);
+        stored.extend_from_slice(&content);
+        self.inner.write_file(path, stored)
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_file(path)
+    }
+
+    /// Swaps the stored files as they are, headers included.
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.inner.swap_files(a, b)
+    }
+
+    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        self.inner.list_files(directory)
+    }
+
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_file(path)
+    }
+
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_dir(path)
+    }
+
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_dir_recursive(path)
+    }
+
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        self.inner.rename_dir(from, to)
+    }
+
+    /// The identifier of the stored file, which changes whenever the content does.
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        self.inner.content_id(path)
+    }
+
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.sync(path)
+    }
+
+    /// Streaming, random access and appending would bypass the compression header, so
+    /// none of them is supported whatever the inner file system can do.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            supports_streaming: false,
+            supports_random_access: false,
+            supports_append: false,
+            ..self.inner.capabilities()
+        }
+    }
+
+    fn root(&self) -> Option<&str> {
+        self.inner.root()
+    }
+
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        self.inner.available_space()
+    }
+}
+
+#[cfg(all(test, feature = "local"))]
+mod tests {
+    use super::*;
+    use crate::LocalFileSystem;
+
+    #[test]
+    fn test_compressing_filesystem() {
+        let fs = CompressingFileSystem::new(LocalFileSystem::new("test_dir_compressing", true).unwrap());
+        let config = "{\"volume\": 10, \"fullscreen\": true}\n".repeat(40).into_bytes();
+        let image = config.clone();
+        let compressed_size = fs.write_file("config.json", config.clone()).unwrap();
+        let image_size = fs.write_file("icon.png", image.clone()).unwrap();
+        let small_size = fs.write_file("small.txt", b"tiny".to_vec()).unwrap();
+        let read = (fs.read_file("config.json"), fs.read_file("icon.png"), fs.read_file("small.txt"));
+        std::fs::remove_dir_all("test_dir_compressing").ok();
+
+        assert!(compressed_size < config.len() as u64 / 4, "Repetitive text should compress");
+        assert_eq!(image_size, image.len() as u64 + 1, "Compressed formats should be stored as they are");
+        assert_eq!(small_size, 5, "Files under min_size should be stored as they are");
+        assert_eq!(read.0.unwrap(), config);
+        assert_eq!(read.1.unwrap(), image);
+        assert_eq!(read.2.unwrap(), b"tiny");
+    }
+
+    #[test]
+    fn test_compressing_filesystem_wrong_size() {
+        let fs = CompressingFileSystem::new(LocalFileSystem::new("test_dir_compressing_size", true).unwrap());
+        let content = b"repeat ".repeat(100);
+        fs.write_file("data.txt", content.clone()).unwrap();
+        let mut stored = fs.inner().read_file("data.txt").unwrap();
+        let declared = (content.len() as u64 + 1).to_le_bytes();
+        stored[1..COMPRESSED_HEADER_SIZE].copy_from_slice(&declared);
+        fs.inner().write_file("longer.txt", stored.clone()).unwrap();
+        stored[1..COMPRESSED_HEADER_SIZE].copy_from_slice(&(content.len() as u64 - 1).to_le_bytes());
+        fs.inner().write_file("shorter.txt", stored).unwrap();
+        let read = (fs.read_file("data.txt"), fs.read_file("longer.txt"), fs.read_file("shorter.txt"));
+        std::fs::remove_dir_all("test_dir_compressing_size").ok();
+
+        assert_eq!(read.0.unwrap(), content);
+        assert!(read.1.is_err(), "A file shorter than its stored size should be rejected");
+        assert!(read.2.is_err(), "A file longer than its stored size should be rejected");
+    }
+
+    #[test]
+    fn test_compressing_filesystem_empty_dir() {
+        let fs = CompressingFileSystem::new(LocalFileSystem::new("test_dir_compressing_empty", true).unwrap());
+        for path in ["cache/a.txt", "cache/sub/b.txt", "cache/sub/deeper/c.txt", "kept.txt"] {
+            fs.write_file(path, b"content".to_vec()).unwrap();
+        }
+        let removed = fs.empty_dir("cache");
+        let left = (fs.list_files("cache").map(|files| files.len()), fs.is_file("kept.txt"));
+        std::fs::remove_dir_all("test_dir_compressing_empty").ok();
+
+        assert_eq!(removed.unwrap(), 3, "Files in subdirectories should be counted once");
+        assert_eq!(left.0.unwrap(), 0);
+        assert!(left.1.unwrap());
+    }
+
+    #[test]
+    fn test_compressing_filesystem_touch_unknown_codec() {
+        let fs = CompressingFileSystem::new(LocalFileSystem::new("test_dir_compressing_touch", true).unwrap());
+        fs.inner().write_file("data.bin", vec![200, 1, 2, 3]).unwrap();
+        let unreadable = fs.read_file("data.bin").is_err();
+        fs.touch("data.bin").unwrap();
+        fs.touch("new.txt").unwrap();
+        let stored = (fs.inner().read_file("data.bin").unwrap(), fs.read_file("new.txt").unwrap());
+        std::fs::remove_dir_all("test_dir_compressing_touch").ok();
+
+        assert!(unreadable);
+        assert_eq!(stored.0, vec![200, 1, 2, 3], "A file with an unknown codec should not be overwritten");
+        assert!(stored.1.is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::FileSystemError;
+
+/// Codec id of archive entries stored without compression.
+pub const NO_COMPRESSION: u8 = 0;
+
+/// Codec id of `DeflateCompressor`.
+pub const DEFLATE_CODEC_ID: u8 = 1;
+
+/// A compression codec for archive entries.
+///
+/// `ArchiveCreator::set_compressor` compresses files with it before encryption, and
+/// `ArchiveFileSystem::with_compressor` registers it for reading. Every entry records the
+/// id of the codec it was compressed with, so archives are self-describing: a reader only
+/// needs a compressor with the same id. Ids below 128 are reserved for codecs shipped with
+/// evfs, so custom codecs should use 128 to 255. `NO_COMPRESSION` cannot be used.
+pub trait Compressor: Send + Sync {
+    /// Returns the id stored in the entries this codec compressed.
+    fn id(&self) -> u8;
+
+    /// Compresses a file's content.
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, FileSystemError>;
+
+    /// Decompresses what `compress` produced.
+    ///
+    /// # Arguments
+    /// - _data:_ The compressed content.
+    /// - _size:_ The size of the original content, e.g. to allocate the output up front.
+    ///   It comes from the stored data, so implementations should fail rather than produce
+    ///   more than `size` bytes; callers reject output of any other length.
+    fn decompress(&self, data: &[u8], size: usize) -> Result<Vec<u8>, FileSystemError>;
+}
+
+/// Raw DEFLATE compression, without zlib or gzip framing.
+///
+/// Uses fixed Huffman codes, which compress a little less than what zlib produces but
+/// keep the encoder small. Any DEFLATE stream can be decompressed.
+#[cfg(feature = "deflate")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeflateCompressor;
+
+#[cfg(feature = "deflate")]
+impl Compressor for DeflateCompressor {
+    fn id(&self) -> u8 {
+        DEFLATE_CODEC_ID
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, FileSystemError> {
+        Ok(crate::deflate::deflate(data))
+    }
+
+    fn decompress(&self, data: &[u8], size: usize) -> Result<Vec<u8>, FileSystemError> {
+        crate::inflate::inflate(data, size)
+    }
+}
@@ -1,29 +1,102 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use sha2::{Digest, Sha256};
+use crate::glob::glob_match;
+use crate::scoped_fs::ScopedFileSystem;
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Category of a `FileSystemError`, for callers that need to react to specific failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileSystemErrorKind {
+    /// Any error without a more specific kind.
+    #[default]
+    Other,
+    /// A write was rejected because it would exceed a storage quota.
+    QuotaExceeded,
+    /// The file, directory or mount point does not exist.
+    NotFound,
+}
+
+/// Errors are compared by message and kind only, since the IO error they may wrap is not
+/// comparable. The wrapped IO error is not serialized either.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileSystemError {
     pub message: String,
+    pub kind: FileSystemErrorKind,
+    /// The IO error this error was converted from, returned by `Error::source`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    io_error: Option<Arc<std::io::Error>>,
 }
 
+impl FileSystemError {
+    /// Creates an error of a specific kind.
+    pub fn new(kind: FileSystemErrorKind, message: &str) -> Self {
+        FileSystemError {
+            message: message.to_string(),
+            kind,
+            io_error: None,
+        }
+    }
+
+    /// Creates a `FileSystemErrorKind::NotFound` error for a path, so every backend reports
+    /// missing files the same way.
+    pub fn not_found(path: &str) -> Self {
+        Self::new(FileSystemErrorKind::NotFound, &format!("File not found: {}", path))
+    }
+
+    /// Returns the IO error this error was converted from, if any.
+    pub fn io_error(&self) -> Option<&std::io::Error> {
+        self.io_error.as_deref()
+    }
+}
+
+impl PartialEq for FileSystemError {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message && self.kind == other.kind
+    }
+}
+
+impl Eq for FileSystemError {}
+
 impl std::fmt::Display for FileSystemError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "FileSystemError: {}", self.message)
     }
 }
 
-impl Error for FileSystemError {}
+/// The source is the IO error the error was converted from, so error reporters that walk
+/// the chain, such as `anyhow`, show it.
+impl Error for FileSystemError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.io_error.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
 
+/// Keeps the IO error as the source, with its message as the message.
+/// `std::io::ErrorKind::NotFound` maps to `FileSystemErrorKind::NotFound`.
 impl From<std::io::Error> for FileSystemError {
     fn from(err: std::io::Error) -> Self {
+        let kind = match err.kind() {
+            std::io::ErrorKind::NotFound => FileSystemErrorKind::NotFound,
+            _ => FileSystemErrorKind::Other,
+        };
         FileSystemError {
             message: err.to_string(),
+            kind,
+            io_error: Some(Arc::new(err)),
         }
     }
 }
 
 impl From<String> for FileSystemError {
     fn from(message: String) -> Self {
-        FileSystemError { message }
+        FileSystemError { message, kind: FileSystemErrorKind::Other, io_error: None }
     }
 }
 
@@ -31,45 +104,760 @@ impl From<&str> for FileSystemError {
     fn from(message: &str) -> Self {
         FileSystemError {
             message: message.to_string(),
+            kind: FileSystemErrorKind::Other,
+            io_error: None,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileInfo {
     pub name: String,
     pub path: String,
     pub is_directory: bool,
     pub size: u64,
+    /// Last modification time, if the backend records one.
+    pub modified: Option<SystemTime>,
+    /// Creation time, if the backend and platform record one.
+    pub created: Option<SystemTime>,
+    /// The entry is a symbolic link that was not followed, so the other fields describe
+    /// the link itself rather than what it points to.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub is_symlink: bool,
 }
 
-impl From<std::fs::DirEntry> for FileInfo {
-    fn from(entry: std::fs::DirEntry) -> Self {
-        let metadata = entry.metadata().expect("metadata");
-        FileInfo {
+impl FileInfo {
+    /// Returns the extension of the file name, without the dot.
+    ///
+    /// Only the part after the last dot counts, so `archive.tar.gz` has the extension
+    /// `gz`. Names without a dot and dotfiles such as `.gitignore` have no extension.
+    pub fn extension(&self) -> Option<&str> {
+        match self.name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => Some(extension),
+            _ => None,
+        }
+    }
+
+    /// Returns the file name without its extension, e.g. `archive.tar` for `archive.tar.gz`.
+    pub fn stem(&self) -> &str {
+        match self.name.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem,
+            _ => &self.name,
+        }
+    }
+
+    /// Returns true if the name starts with a dot.
+    pub fn is_hidden(&self) -> bool {
+        self.name.starts_with('.')
+    }
+}
+
+/// Orders directories before files, then by case-insensitive name, as a file browser
+/// would list them.
+///
+/// Names differing only in case fall back to a case-sensitive comparison and then to the
+/// remaining fields, so the order stays consistent with `Eq`.
+impl Ord for FileInfo {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.is_directory.cmp(&self.is_directory)
+            .then_with(|| self.name.chars().flat_map(char::to_lowercase).cmp(other.name.chars().flat_map(char::to_lowercase)))
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.path.cmp(&other.path))
+            .then_with(|| (self.size, self.modified, self.created, self.is_symlink).cmp(&(other.size, other.modified, other.created, other.is_symlink)))
+    }
+}
+
+impl PartialOrd for FileInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Describes the entry itself: symlinks are not followed, so a link is reported with
+/// `is_symlink` set and is never a directory.
+impl TryFrom<std::fs::DirEntry> for FileInfo {
+    type Error = FileSystemError;
+
+    fn try_from(entry: std::fs::DirEntry) -> Result<Self, Self::Error> {
+        let metadata = entry.metadata()?;
+        Ok(FileInfo {
             name: entry.file_name().to_string_lossy().into_owned(),
             path: entry.path().to_string_lossy().into_owned(),
             is_directory: metadata.is_dir(),
             size: metadata.len(),
-        }
+            modified: metadata.modified().ok(),
+            created: metadata.created().ok(),
+            is_symlink: metadata.file_type().is_symlink(),
+        })
     }
 }
 
+/// Contents of a file, owned by the caller.
+///
+/// Cloning copies the bytes. To hand one loaded asset to many consumers, convert it once
+/// into a reference-counted `Arc<[u8]>` (`content.into()`) and clone that instead.
 pub type FileContent = Vec<u8>;
 
+/// A completed file system operation, as reported to an `Observer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsEvent<'a> {
+    /// A file was read; `bytes` is how much of it was read.
+    Read { path: &'a str, bytes: u64 },
+    /// A file was written with `bytes` of content.
+    Write { path: &'a str, bytes: u64 },
+    /// A file was deleted.
+    Delete { path: &'a str },
+    /// A directory was listed and had `entries` entries.
+    List { directory: &'a str, entries: usize },
+    /// A file was created empty or had its modification time updated.
+    Touch { path: &'a str },
+    /// A file was cut or extended to `len` bytes.
+    Truncate { path: &'a str, len: u64 },
+    /// A file was opened for appending. What is then written through it is not reported.
+    Append { path: &'a str },
+    /// A directory was deleted with everything below it.
+    DeleteDir { path: &'a str },
+    /// A directory was emptied; `files` is how many files were deleted.
+    EmptyDir { directory: &'a str, files: usize },
+    /// A directory was renamed, moving everything below it.
+    RenameDir { from: &'a str, to: &'a str },
+    /// Two files exchanged their contents.
+    Swap { a: &'a str, b: &'a str },
+}
+
+/// Callback receiving the operations of a file system, e.g. to trace which assets are
+/// loaded and how often. See `LocalFileSystem::set_observer`. Clones of a file system
+/// share its observer.
+pub type Observer = Arc<dyn Fn(FsEvent) + Send + Sync>;
+
+/// What a file system supports, as reported by `FileSystem::capabilities`.
+///
+/// Every flag defaults to `false`, so a backend that does not report its capabilities is
+/// treated as read-only and unencrypted with whole-file access only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    /// Files can be written and deleted.
+    pub writable: bool,
+    /// File contents can be read incrementally instead of loaded whole.
+    pub supports_streaming: bool,
+    /// Parts of a file can be read at an offset without reading what comes before.
+    pub supports_random_access: bool,
+    /// File contents are encrypted at rest.
+    pub encrypted: bool,
+    /// Data can be appended to an existing file without rewriting it.
+    pub supports_append: bool,
+}
+
 
 pub trait FileSystem {
     fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError>;
-    fn write_file(&self, path: &str, content: FileContent) -> Result<(), FileSystemError>;
+    /// Writes a file, replacing it if it exists.
+    ///
+    /// # Arguments
+    /// - _path:_ The file to write.
+    /// - _content:_ The new file content.
+    ///
+    /// # Returns
+    /// The number of bytes stored: the content length for plain backends, and the
+    /// ciphertext length, including nonces and tags, for encrypted ones.
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError>;
     fn delete_file(&self, path: &str) -> Result<(), FileSystemError>;
     fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError>;
 
+    /// Reads a file into a caller-supplied buffer, replacing its contents.
+    ///
+    /// Reusing one buffer across many reads avoids allocating for each file once the
+    /// buffer has grown large enough. The default reads the file with `read_file` and
+    /// copies it; backends that can read straight into the buffer override this.
+    ///
+    /// # Arguments
+    /// - _path:_ The file to read.
+    /// - _buf:_ Cleared, then filled with the file content.
+    ///
+    /// # Returns
+    /// The number of bytes read, i.e. the new length of `buf`.
+    fn read_into(&self, path: &str, buf: &mut Vec<u8>) -> Result<usize, FileSystemError> {
+        let content = self.read_file(path)?;
+        buf.clear();
+        buf.extend_from_slice(&content);
+        Ok(buf.len())
+    }
+
+    /// Reads at most the first `n` bytes of a file, e.g. to check a magic number before
+    /// deciding how to parse it.
+    ///
+    /// The default reads the whole file with `read_file` and truncates it; backends that
+    /// can stop reading early override this.
+    ///
+    /// # Arguments
+    /// - _path:_ The file to read.
+    /// - _n:_ How many bytes to read at most.
+    ///
+    /// # Returns
+    /// The first `n` bytes, or the whole file if it is shorter.
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        let mut content = self.read_file(path)?;
+        content.truncate(n);
+        Ok(content)
+    }
+
     fn read_file_as_string(&self, path: &str) -> Result<String, FileSystemError> {
         let content = self.read_file(path)?;
-        String::from_utf8(content).map_err(|e| FileSystemError::from(e.to_string()))
+        String::from_utf8(content).map_err(|e| FileSystemError::from(format!("File {} is not valid UTF-8: {}", path, e)))
+    }
+    /// Reads a file as text, replacing invalid UTF-8 sequences with `U+FFFD`.
+    ///
+    /// Useful for logs and debugging output where a best-effort result is acceptable.
+    fn read_file_as_string_lossy(&self, path: &str) -> Result<String, FileSystemError> {
+        let content = self.read_file(path)?;
+        Ok(String::from_utf8_lossy(&content).into_owned())
     }
     fn write_file_from_string(&self, path: &str, content: &str) -> Result<(), FileSystemError> {
         let bytes = content.as_bytes().to_vec();
-        self.write_file(path, bytes)
+        self.write_file(path, bytes)?;
+        Ok(())
+    }
+
+    /// Reads several files at once, returning their contents keyed by path.
+    ///
+    /// The batch is all-or-nothing: if any file cannot be read, the whole call fails
+    /// with that file's error.
+    fn read_files(&self, paths: &[&str]) -> Result<HashMap<String, FileContent>, FileSystemError> {
+        let mut contents = HashMap::with_capacity(paths.len());
+        for path in paths {
+            contents.insert(path.to_string(), self.read_file(path)?);
+        }
+        Ok(contents)
+    }
+
+    /// Creates an empty file if it does not exist yet. Existing files are left untouched,
+    /// even ones that cannot be read.
+    ///
+    /// The default checks for the file with `is_file` and writes an empty one through
+    /// `write_file` if it is missing.
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        if self.is_file(path)? {
+            return Ok(());
+        }
+        self.write_file(path, Vec::new())?;
+        Ok(())
+    }
+
+    /// Sets the length of a file, zero-filling when growing and discarding data when shrinking.
+    ///
+    /// Not every backend can support this; the default returns an error.
+    fn truncate_file(&self, _path: &str, _len: u64) -> Result<(), FileSystemError> {
+        Err(FileSystemError::from("Truncation is not supported by this file system"))
+    }
+
+    /// Opens a file for appending, creating it if it does not exist.
+    ///
+    /// Everything written goes to the end of the file as it is written, so incremental
+    /// writers such as logs do not need to buffer the whole file. Backends that report
+    /// `Capabilities::supports_append` override this; the default returns an error.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the file cannot be opened or the backend cannot append.
+    fn open_append(&self, _path: &str) -> Result<Box<dyn Write + Send>, FileSystemError> {
+        Err(FileSystemError::from("Appending is not supported by this file system"))
+    }
+
+    /// Deletes every file below a directory, recursively.
+    ///
+    /// The default lists the directory and deletes each file through `delete_file`, so
+    /// backends that have no directory records are left empty; directories that exist on
+    /// their own are only removed by backends that override this.
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        for info in self.list_files(path)? {
+            let child = join_path(path, &info.name);
+            if info.is_directory {
+                self.delete_dir_recursive(&child)?;
+            } else {
+                self.delete_file(&child)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes everything below a directory but keeps the directory itself, e.g. to clear
+    /// a cache folder.
+    ///
+    /// The default lists the directory, deletes each file through `delete_file`, and
+    /// deletes each subdirectory through `delete_dir_recursive` after counting the files
+    /// below it with `walk`. Read-only backends fail.
+    ///
+    /// # Arguments
+    /// - _directory:_ The directory to empty, empty for the root.
+    ///
+    /// # Returns
+    /// The number of files deleted, in subdirectories too.
+    ///
+    /// # Errors
+    /// `FileSystemError` if `directory` cannot be listed or something below it cannot be
+    /// deleted; what was deleted before stays deleted.
+    fn empty_dir(&self, directory: &str) -> Result<usize, FileSystemError> {
+        let mut removed = 0;
+        for info in self.list_files(directory)? {
+            let child = join_path(directory, &info.name);
+            if info.is_directory {
+                let mut files = 0;
+                for info in self.walk(&child) {
+                    files += usize::from(!info?.is_directory);
+                }
+                self.delete_dir_recursive(&child)?;
+                removed += files;
+            } else {
+                self.delete_file(&child)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Renames a directory, moving everything below it.
+    ///
+    /// Meant for backends that can move a directory without copying its files, which
+    /// override this; the default returns an error. `to` must not exist yet, nothing is
+    /// merged into an existing directory.
+    ///
+    /// # Arguments
+    /// - _from:_ The directory to rename.
+    /// - _to:_ Its new path.
+    ///
+    /// # Errors
+    /// `FileSystemError` if `from` is not a directory, `to` already exists or the backend
+    /// cannot rename directories.
+    fn rename_dir(&self, _from: &str, _to: &str) -> Result<(), FileSystemError> {
+        Err(FileSystemError::from("Renaming directories is not supported by this file system"))
+    }
+
+    /// Exchanges the contents of two files, e.g. to promote a freshly written save slot
+    /// over the current one.
+    ///
+    /// The default reads both files and writes each one's content to the other, writing
+    /// `a` back if writing `b` fails. It is not atomic: a crash between the two writes
+    /// leaves both files with the same content. Backends that can swap files in place
+    /// override this and document what they guarantee.
+    ///
+    /// # Arguments
+    /// - _a:_ The first file.
+    /// - _b:_ The second file.
+    ///
+    /// # Errors
+    /// `FileSystemError` if either file cannot be read or written.
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        swap_by_copy(self, a, b)
+    }
+
+    /// Returns a view of one directory of this file system that cannot reach outside it,
+    /// e.g. to hand a subsystem only its assets. See `ScopedFileSystem`.
+    ///
+    /// # Arguments
+    /// - _prefix:_ The directory the view is rooted at.
+    ///
+    /// # Errors
+    /// `FileSystemError` if `prefix` has a `..` component.
+    fn scoped(&self, prefix: &str) -> Result<ScopedFileSystem<'_>, FileSystemError> where Self: Sized {
+        ScopedFileSystem::new(self, prefix)
+    }
+
+    /// Copies every file below a directory into another file system, recursively.
+    ///
+    /// Works across any pair of backends, e.g. from an archive to a local directory, since
+    /// files are only read with `read_file` and written with `write_file` on `to_fs`. Paths
+    /// relative to `from` are kept below `to`; intermediate directories are created by the
+    /// destination's `write_file`, as they would be for any other write.
+    ///
+    /// # Arguments
+    /// - _from:_ The directory to copy, empty for the root.
+    /// - _to_fs:_ The file system to copy into.
+    /// - _to:_ The directory in `to_fs` to copy into, empty for its root.
+    ///
+    /// # Returns
+    /// The number of files copied.
+    ///
+    /// # Errors
+    /// `FileSystemError` from the first listing, read or write that fails; files copied
+    /// before it are left in place.
+    fn copy_dir(&self, from: &str, to_fs: &dyn FileSystem, to: &str) -> Result<usize, FileSystemError> {
+        let mut copied = 0;
+        for info in self.list_files(from)? {
+            let source = join_path(from, &info.name);
+            let destination = join_path(to, &info.name);
+            if info.is_directory {
+                copied += self.copy_dir(&source, to_fs, &destination)?;
+            } else {
+                to_fs.write_file(&destination, self.read_file(&source)?)?;
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Moves a file into another file system, e.g. to promote a staged local file into an
+    /// encrypted store.
+    ///
+    /// Works across any pair of backends. The file is read, written to `dest` and synced
+    /// there, and the written file is hashed with `hash_file` and compared with what was
+    /// read. Only if all of that succeeds is the source deleted, so a failed or corrupted
+    /// write never loses the file.
+    ///
+    /// # Arguments
+    /// - _from:_ The file to move.
+    /// - _dest:_ The file system to move it into.
+    /// - _to:_ The path in `dest` to move it to.
+    ///
+    /// # Errors
+    /// `FileSystemError` if reading, writing, syncing or verifying fails, in which case the
+    /// source is left in place, or if deleting the source fails, in which case the file
+    /// exists in both.
+    fn move_file_to(&self, from: &str, dest: &dyn FileSystem, to: &str) -> Result<(), FileSystemError> {
+        let content = self.read_file(from)?;
+        let hash: [u8; 32] = Sha256::digest(&content).into();
+        dest.write_file(to, content)?;
+        dest.sync(to)?;
+        if dest.hash_file(to)? != hash {
+            return Err(FileSystemError::from(format!("{} does not match {} after writing it, the source was kept", to, from)));
+        }
+        self.delete_file(from)
+    }
+
+    /// Lists the entries of a directory whose name matches a glob pattern.
+    ///
+    /// See [`glob_match`] for the supported syntax. Matching is case-sensitive.
+    fn list_files_glob(&self, directory: &str, pattern: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let files = self.list_files(directory)?;
+        Ok(files.into_iter().filter(|f| glob_match(pattern, &f.name)).collect())
+    }
+
+    /// Lists the entries of a directory with directories first, then by case-insensitive
+    /// name. See the `Ord` implementation of `FileInfo`.
+    ///
+    /// `list_files` makes no promise about order and stays the cheaper call when the
+    /// order does not matter.
+    fn list_files_sorted(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let mut files = self.list_files(directory)?;
+        files.sort();
+        Ok(files)
+    }
+
+    /// Lists only the subdirectories of a directory.
+    ///
+    /// The default filters `list_files`; backends that can find directories without
+    /// listing every file override this.
+    fn list_dirs(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let files = self.list_files(directory)?;
+        Ok(files.into_iter().filter(|f| f.is_directory).collect())
+    }
+
+    /// Lists only the files of a directory, leaving out its subdirectories.
+    fn list_regular_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let files = self.list_files(directory)?;
+        Ok(files.into_iter().filter(|f| !f.is_directory).collect())
+    }
+
+    /// Returns whether `path` is an existing file.
+    ///
+    /// The default looks the path up in a listing of its parent directory; backends that
+    /// can check a single path override this.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the parent directory exists but cannot be listed.
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        Ok(find_in_parent(self, path)?.is_some_and(|info| !info.is_directory))
+    }
+
+    /// Returns whether `path` is an existing directory. The root, an empty path or `.`,
+    /// always is.
+    ///
+    /// The default looks the path up in a listing of its parent directory; backends that
+    /// can check a single path override this.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the parent directory exists but cannot be listed.
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        if is_root(path) {
+            return Ok(true);
+        }
+        Ok(find_in_parent(self, path)?.is_some_and(|info| info.is_directory))
+    }
+
+    /// Lazily walks a directory tree depth-first, yielding every file and directory below it.
+    ///
+    /// Only one directory listing is held in memory at a time, and callers can stop early
+    /// by dropping the iterator. Errors listing a directory are yielded in place and the
+    /// walk continues with the next directory.
+    fn walk(&self, directory: &str) -> Box<dyn Iterator<Item = Result<FileInfo, FileSystemError>> + '_> {
+        Box::new(Walk {
+            fs: self,
+            pending: vec![directory.to_string()],
+            current: Vec::new().into_iter(),
+            current_dir: String::new(),
+        })
+    }
+
+    /// Computes the total size of every file below a directory, recursively.
+    ///
+    /// Sizes are plaintext sizes, so encrypted backends do not count their overhead. The
+    /// default sums the sizes reported by `walk`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if any directory below `directory` cannot be listed.
+    fn total_size(&self, directory: &str) -> Result<u64, FileSystemError> {
+        let mut total = 0;
+        for info in self.walk(directory) {
+            let info = info?;
+            if !info.is_directory {
+                total += info.size;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Lists the files below a directory, recursively, modified after `since`, e.g. to
+    /// poll for assets to hot-reload.
+    ///
+    /// The default filters `walk` on `FileInfo::modified`. Files whose modification time
+    /// is unknown are skipped.
+    ///
+    /// # Arguments
+    /// - _directory:_ The directory to search, empty for the root.
+    /// - _since:_ Only files modified strictly after this are listed.
+    ///
+    /// # Errors
+    /// `FileSystemError` from the first listing that fails.
+    fn list_modified_since(&self, directory: &str, since: SystemTime) -> Result<Vec<FileInfo>, FileSystemError> {
+        let mut modified = Vec::new();
+        for info in self.walk(directory) {
+            let info = info?;
+            if !info.is_directory && info.modified.is_some_and(|time| time > since) {
+                modified.push(info);
+            }
+        }
+        Ok(modified)
+    }
+
+    /// Computes the SHA-256 hash of a file's content.
+    ///
+    /// Encrypted backends hash the decrypted plaintext, so the hash of a file is the
+    /// same regardless of where or how it is stored.
+    fn hash_file(&self, path: &str) -> Result<[u8; 32], FileSystemError> {
+        let content = self.read_file(path)?;
+        Ok(Sha256::digest(&content).into())
+    }
+
+    /// Computes the SHA-256 hash of a file's content as a lowercase hex string.
+    fn hash_file_hex(&self, path: &str) -> Result<String, FileSystemError> {
+        let hash = self.hash_file(path)?;
+        Ok(hash.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Returns a cheap identifier of a file's content, e.g. as an asset cache key.
+    ///
+    /// The identifier changes when the content changes in all but unusual cases, but it is
+    /// a heuristic, not a cryptographic hash: backends derive it from metadata where they
+    /// can, so a rewrite that keeps the size and modification time keeps the identifier.
+    /// Use `hash_file` where a change must never be missed. The default is the hex SHA-256
+    /// of the content, which reads the whole file.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the file does not exist or its metadata cannot be read.
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        self.hash_file_hex(path)
+    }
+
+    /// Forces a written file to durable storage.
+    ///
+    /// Call it after writing data that must survive a crash or power loss. Backends
+    /// without durability semantics return `Ok(())`, which is also the default.
+    fn sync(&self, _path: &str) -> Result<(), FileSystemError> {
+        Ok(())
+    }
+
+    /// Describes what this file system supports, so generic code can check before calling
+    /// an operation instead of handling its error.
+    ///
+    /// The default reports nothing as supported.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Returns where this file system stores its data, for logging and tooling.
+    ///
+    /// # Returns
+    /// The backing location, e.g. a base directory or archive file, or `None` if the
+    /// file system has no meaningful one. The default implementation returns `None`.
+    fn root(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the absolute on-disk path of a file, for handing it to external programs.
+    ///
+    /// Only backends that store each file as a plain file on disk return a path; for the
+    /// others, read the file and write it to a temporary file instead. The path is returned
+    /// whether or not the file exists.
+    ///
+    /// # Returns
+    /// The absolute path, or `None` if files have no on-disk path of their own, which is
+    /// also the default.
+    fn real_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Returns how many bytes can still be written, so callers can check for room before
+    /// writing a large file or extracting an archive instead of failing partway.
+    ///
+    /// The figure is a snapshot: other writers on the same disk can use the space up
+    /// before it is written to.
+    ///
+    /// # Returns
+    /// The free space in bytes, or `None` if the backend has no such limit or cannot tell,
+    /// which is also the default.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the backend has a limit but querying it failed.
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        Ok(None)
+    }
+}
+
+
+/// Returns true for the paths that name the root of a file system.
+fn is_root(path: &str) -> bool {
+    matches!(path.trim_matches(|c| c == '/' || c == '\\'), "" | ".")
+}
+
+/// Swaps two files by reading both and writing them back crosswise, restoring `a` if
+/// writing `b` fails. Backs the default `swap_files`.
+pub(crate) fn swap_by_copy<F: FileSystem + ?Sized>(file_system: &F, a: &str, b: &str) -> Result<(), FileSystemError> {
+    let content_a = file_system.read_file(a)?;
+    let content_b = file_system.read_file(b)?;
+    file_system.write_file(a, content_b)?;
+    if let Err(e) = file_system.write_file(b, content_a.clone()) {
+        file_system.write_file(a, content_a).ok();
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Finds a path in the listing of its parent directory, for the default `is_file` and
+/// `is_dir`. A parent that does not exist means the path does not either.
+fn find_in_parent<F: FileSystem + ?Sized>(file_system: &F, path: &str) -> Result<Option<FileInfo>, FileSystemError> {
+    if is_root(path) {
+        return Ok(None);
+    }
+    let path = normalize_path(path);
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    match file_system.list_files(parent) {
+        Ok(files) => Ok(files.into_iter().find(|info| info.name == name)),
+        Err(e) if e.kind == FileSystemErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Joins a file name onto a virtual directory path using `/` as the separator.
+pub(crate) fn join_path(directory: &str, name: &str) -> String {
+    let directory = directory.trim_end_matches('/');
+    if directory.is_empty() || directory == "." {
+        name.to_string()
+    } else {
+        format!("{}/{}", directory, name)
+    }
+}
+
+/// Converts `\` separators to `/`, so paths built on Windows match the `/` paths
+/// used everywhere else.
+pub(crate) fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// Depth-first iterator backing the default `FileSystem::walk`.
+struct Walk<'a, F: FileSystem + ?Sized> {
+    fs: &'a F,
+    pending: Vec<String>,
+    current: std::vec::IntoIter<FileInfo>,
+    current_dir: String,
+}
+
+impl<F: FileSystem + ?Sized> Iterator for Walk<'_, F> {
+    type Item = Result<FileInfo, FileSystemError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(info) = self.current.next() {
+                if info.is_directory {
+                    self.pending.push(join_path(&self.current_dir, &info.name));
+                }
+                return Some(Ok(info));
+            }
+            let directory = self.pending.pop()?;
+            match self.fs.list_files(&directory) {
+                Ok(files) => {
+                    self.current = files.into_iter();
+                    self.current_dir = directory;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_info(name: &str) -> FileInfo {
+        FileInfo { name: name.to_string(), path: name.to_string(), ..FileInfo::default() }
+    }
+
+    #[test]
+    fn test_file_info_name_helpers() {
+        let png = file_info("hero.png");
+        assert_eq!(png.extension(), Some("png"));
+        assert_eq!(png.stem(), "hero");
+        assert!(!png.is_hidden());
+
+        let tarball = file_info("archive.tar.gz");
+        assert_eq!(tarball.extension(), Some("gz"));
+        assert_eq!(tarball.stem(), "archive.tar");
+
+        let plain = file_info("README");
+        assert_eq!(plain.extension(), None);
+        assert_eq!(plain.stem(), "README");
+
+        let dotfile = file_info(".gitignore");
+        assert_eq!(dotfile.extension(), None);
+        assert_eq!(dotfile.stem(), ".gitignore");
+        assert!(dotfile.is_hidden());
+
+        let hidden_config = file_info(".config.toml");
+        assert_eq!(hidden_config.extension(), Some("toml"));
+        assert_eq!(hidden_config.stem(), ".config");
+    }
+
+    #[test]
+    fn test_file_info_ordering() {
+        let directory = FileInfo { is_directory: true, ..file_info("zeta") };
+        let mut files = [file_info("beta.txt"), file_info("Alpha.txt"), directory.clone(), file_info("alpha.txt")];
+        files.sort();
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["zeta", "Alpha.txt", "alpha.txt", "beta.txt"]);
+        assert_eq!(directory.cmp(&directory.clone()), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_file_system_error_source() {
+        let io_error = FileSystemError::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "access denied"));
+        let source = io_error.source().and_then(|e| e.downcast_ref::<std::io::Error>());
+        assert_eq!(source.map(|e| e.kind()), Some(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(io_error.message, "access denied");
+        assert_eq!(io_error.clone().io_error().map(|e| e.kind()), Some(std::io::ErrorKind::PermissionDenied));
+
+        let plain = FileSystemError::from("access denied");
+        assert!(plain.source().is_none());
+        assert_eq!(plain, io_error, "Errors compare by message and kind");
     }
 }
@@ -0,0 +1,152 @@
+use crate::inflate::{DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash are tried before settling for the best match
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+/// Writes bits least significant first, as DEFLATE packs them.
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which DEFLATE stores most significant bit first.
+    fn code(&mut self, code: u32, length: u32) {
+        self.bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+
+    /// Writes a literal/length symbol with the fixed Huffman code.
+    fn literal(&mut self, symbol: u16) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol as u32, 8),
+            144..=255 => self.code(0x190 + (symbol as u32 - 144), 9),
+            256..=279 => self.code(symbol as u32 - 256, 7),
+            _ => self.code(0xc0 + (symbol as u32 - 280), 8),
+        }
+    }
+
+    fn back_reference(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+        self.literal(257 + index as u16);
+        self.bits((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+        let index = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+        self.code(index as u32, 5);
+        self.bits((distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index] as u32);
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Records `position` as the most recent occurrence of the three bytes starting there.
+fn insert(data: &[u8], position: usize, head: &mut [usize], previous: &mut [usize]) {
+    if position + MIN_MATCH <= data.len() {
+        let hash = hash(&data[position..]);
+        previous[position % WINDOW_SIZE] = head[hash];
+        head[hash] = position;
+    }
+}
+
+/// Compresses data into a raw DEFLATE stream, as a single block with fixed Huffman codes.
+///
+/// Matches are found greedily through hash chains over the last 32 KiB.
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { output: Vec::with_capacity(data.len() / 2 + 16), buffer: 0, count: 0 };
+    // Final block, fixed Huffman codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+    // Most recent position of each hash, and the previous position with the same hash
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+    let mut position = 0;
+    while position < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if position + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - position);
+            let mut candidate = head[hash(&data[position..])];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || position - candidate > WINDOW_SIZE {
+                    break;
+                }
+                let length = data[candidate..].iter().zip(&data[position..position + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    (best_length, best_distance) = (length, position - candidate);
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = previous[candidate % WINDOW_SIZE];
+                // Slots are reused once the window moves on, so chains must go backwards
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+            }
+        }
+        if best_length >= MIN_MATCH {
+            writer.back_reference(best_length, best_distance);
+            for skipped in position..position + best_length {
+                insert(data, skipped, &mut head, &mut previous);
+            }
+            position += best_length;
+        } else {
+            writer.literal(data[position] as u16);
+            insert(data, position, &mut head, &mut previous);
+            position += 1;
+        }
+    }
+    writer.literal(256);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inflate::inflate;
+
+    #[test]
+    fn test_deflate() {
+        let text: Vec<u8> = (0..200).flat_map(|i| format!("line {}: the quick brown fox\n", i % 7).into_bytes()).collect();
+        let binary: Vec<u8> = (0..70_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        for data in [&b""[..], b"a", b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", &text, &binary] {
+            let compressed = deflate(data);
+            assert_eq!(inflate(&compressed, data.len()).unwrap(), data);
+        }
+        assert!(deflate(&text).len() < text.len() / 4, "Repetitive text should compress well");
+    }
+
+    #[test]
+    fn test_inflate_bomb() {
+        let bomb = deflate(&vec![0u8; 1 << 20]);
+
+        assert!(bomb.len() < 1 << 14, "A megabyte of zeros should compress to a few kilobytes");
+        assert!(inflate(&bomb, 1000).is_err(), "Decompression should stop at the limit");
+        assert_eq!(inflate(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+    }
+}
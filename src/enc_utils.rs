@@ -1,23 +1,181 @@
 use std::fmt::{Debug, Display};
-use aes_gcm::{Aes256Gcm, Key, Nonce};
-use aes_gcm::aead::{Aead, KeyInit, OsRng, rand_core::RngCore};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use aes_gcm::{Aes256Gcm, Key, Nonce, Tag};
+use aes_gcm::aead::{AeadInPlace, KeyInit, OsRng, rand_core::{CryptoRng, RngCore}};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
 use crate::{FileContent, FileSystemError};
 
 /// Constants for encryption key size
 /// AES-256-GCM requires a 32-byte key
 pub const MAX_ENC_KEY_SIZE: usize = 32; // Maximum size for encryption key
 
-/// Type alias for encryption key
-pub type EncKey = Vec<u8>;
+/// Size of the random nonce in every encrypted blob
+pub const NONCE_SIZE: usize = 12;
+
+/// Size of the GCM authentication tag appended to every encrypted blob
+pub const TAG_SIZE: usize = 16;
+
+/// Format version of blobs made by `encrypt` and its siblings: one AES-256-GCM message,
+/// laid out as `version || nonce || ciphertext || tag`
+pub const BLOB_FORMAT_AES_GCM: u8 = 1;
+
+/// Format version reserved for blobs encrypted as a sequence of AES-256-GCM chunks
+pub const BLOB_FORMAT_AES_GCM_CHUNKED: u8 = 2;
+
+/// Format version reserved for blobs encrypted with ChaCha20-Poly1305
+pub const BLOB_FORMAT_CHACHA20_POLY1305: u8 = 3;
+
+/// Format version reserved for blobs encrypted with XChaCha20-Poly1305
+pub const BLOB_FORMAT_XCHACHA20_POLY1305: u8 = 4;
+
+/// Number of bytes an encrypted blob is larger than its plaintext: format version, nonce
+/// and tag
+pub const ENCRYPTION_OVERHEAD: usize = 1 + NONCE_SIZE + TAG_SIZE;
+
+/// Number of bytes blobs from before format versions were added are larger than their
+/// plaintext: nonce and tag
+pub(crate) const LEGACY_ENCRYPTION_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Default number of encryptions allowed under one key. With random 96-bit nonces, NIST
+/// SP 800-38D caps this at 2^32 to keep the chance of a nonce collision negligible.
+pub const DEFAULT_ENCRYPTION_LIMIT: u64 = 1 << 32;
+
+/// Largest plaintext AES-GCM can encrypt as a single message, 2^36 - 32 bytes (about
+/// 64 GiB), per NIST SP 800-38D. `encrypt` and its siblings reject anything larger;
+/// `encrypt_stream` has no such limit since it encrypts each chunk separately.
+pub const MAX_MESSAGE_SIZE: u64 = (1 << 36) - 32;
+
+/// Default plaintext size of a chunk in `encrypt_stream`
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Smallest chunk size accepted by `set_chunk_size`
+pub const MIN_CHUNK_SIZE: usize = 1024;
+
+/// Largest chunk size accepted by `set_chunk_size`
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Size of the random salt `EncUtils::generate_salt` makes for password-derived keys
+pub const PASSWORD_SALT_SIZE: usize = 16;
+
+/// PBKDF2 iterations recommended for new password-derived keys, following the OWASP
+/// guidance for PBKDF2-HMAC-SHA256
+pub const PASSWORD_ITERATIONS: u32 = 600_000;
+
+/// Magic bytes at the start of every encrypted stream
+const STREAM_MAGIC: &[u8; 4] = b"EVST";
+
+/// Version of the encrypted stream format
+const STREAM_VERSION: u8 = 1;
+
+/// Size of the random salt the per-stream key is derived from
+const STREAM_SALT_SIZE: usize = 16;
+
+/// Size of the header in front of the chunks of an encrypted stream: magic, version,
+/// log2 of the chunk size and salt
+pub const STREAM_HEADER_SIZE: usize = STREAM_MAGIC.len() + 2 + STREAM_SALT_SIZE;
+
+/// A 256-bit AES key.
+///
+/// The length is enforced when the key is built, so a wrong-length key is caught at
+/// construction instead of deep inside an encryption call. `Debug` never prints the
+/// bytes and keys compare in constant time. Build one from a `[u8; 32]`, or with
+/// `EncKey::try_from` from a slice.
+#[derive(Clone)]
+pub struct EncKey([u8; MAX_ENC_KEY_SIZE]);
+
+impl EncKey {
+    /// Returns the raw key bytes.
+    pub fn as_bytes(&self) -> &[u8; MAX_ENC_KEY_SIZE] {
+        &self.0
+    }
+}
+
+impl From<[u8; MAX_ENC_KEY_SIZE]> for EncKey {
+    fn from(bytes: [u8; MAX_ENC_KEY_SIZE]) -> Self {
+        EncKey(bytes)
+    }
+}
+
+impl TryFrom<&[u8]> for EncKey {
+    type Error = FileSystemError;
+
+    /// Fails unless `bytes` is exactly `MAX_ENC_KEY_SIZE` long, see `EncUtils::is_valid_key`.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        EncUtils::is_valid_key(bytes)?;
+        let mut key = [0u8; MAX_ENC_KEY_SIZE];
+        key.copy_from_slice(bytes);
+        Ok(EncKey(key))
+    }
+}
+
+/// Deprecated: use `EncKey::try_from(bytes.as_slice())`, which returns an error instead.
+///
+/// Kept so code written when keys were plain `Vec<u8>` still compiles. It cannot sit next
+/// to a `TryFrom<Vec<u8>>`, and `#[deprecated]` has no effect on trait impls.
+///
+/// # Panics
+/// If `bytes` is not exactly `MAX_ENC_KEY_SIZE` long, with the message of
+/// `EncUtils::is_valid_key`, which `EncUtils::new` used to return as an error.
+impl From<Vec<u8>> for EncKey {
+    fn from(bytes: Vec<u8>) -> Self {
+        match EncKey::try_from(bytes.as_slice()) {
+            Ok(key) => key,
+            Err(e) => panic!("{}", e.message),
+        }
+    }
+}
+
+/// Compares in constant time, see `EncUtils::key_eq_ct`.
+impl PartialEq for EncKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for EncKey {}
+
+impl Debug for EncKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncKey([REDACTED])")
+    }
+}
 
 /// Utility struct for encryption and decryption operations
 /// using AES-256-GCM. It provides methods to encrypt and decrypt file content,
 /// manage the encryption key, and validate key sizes.
-#[derive(Clone, PartialEq, Eq)]
+///
+/// The cipher is built once from the key and reused for every operation.
+///
+/// Every encryption is counted, and once `encryption_limit` is reached further
+/// encryptions fail until the key is rotated, since a repeated nonce breaks GCM. Clones
+/// share the count, as they encrypt under the same key.
+///
+/// Nonces come from `OsRng` unless the instance was built with `new_with_rng`.
+#[derive(Clone)]
 pub struct EncUtils {
-    pub key: EncKey,
+    key: EncKey,
+    cipher: Aes256Gcm,
+    encryptions: Arc<AtomicU64>,
+    encryption_limit: Option<u64>,
+    /// Caller-supplied nonce source, shared by clones; `None` uses `OsRng`
+    rng: Option<Arc<Mutex<dyn RngCore + Send>>>,
+    /// Plaintext size of the chunks written by `encrypt_stream`
+    chunk_size: usize,
 }
 
+/// Compares keys in constant time, see `EncUtils::key_eq_ct`.
+impl PartialEq for EncUtils {
+    fn eq(&self, other: &Self) -> bool {
+        self.key_eq_ct(&other.key)
+    }
+}
+
+impl Eq for EncUtils {}
+
 impl Debug for EncUtils {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "EncUtils {{ key: [REDACTED] }}") // Avoid displaying the key directly
@@ -27,8 +185,7 @@ impl Debug for EncUtils {
 impl Default for EncUtils {
     fn default() -> Self {
         // Generate a random key by default
-        let key = EncUtils::generate_random_key();
-        EncUtils { key }
+        EncUtils::with_key(EncUtils::generate_random_key())
     }
 }
 impl Display for EncUtils {
@@ -46,13 +203,118 @@ impl EncUtils {
     ///  - _key:_ The encryption key to use for encryption and decryption.
     ///
     /// # Errors
-    /// Returns an error if the key is invalid (empty or exceeds maximum size).
+    /// None at the moment: `EncKey` already guarantees a valid key length.
     ///
     /// # Returns
-    /// Result containing the `EncUtils` instance or an error if the key is invalid.
+    /// Result containing the `EncUtils` instance.
     pub fn new(key: EncKey) -> Result<Self, FileSystemError> {
-        Self::is_valid_key(&key)?;
-        Ok(EncUtils { key })
+        Ok(EncUtils::with_key(key))
+    }
+
+    /// Creates an `EncUtils` that draws its nonces from `rng` instead of `OsRng`.
+    ///
+    /// Meant for tests and reproducible-build tooling that need the same ciphertext on
+    /// every run, by passing a seeded RNG. A predictable RNG makes nonces predictable and,
+    /// if two instances share a seed and a key, repeated, which breaks GCM; never use one
+    /// for data that has to stay confidential. Clones draw from the same RNG. `new` and
+    /// `Default` always use `OsRng`.
+    ///
+    /// # Arguments
+    /// - _key:_ The encryption key to use for encryption and decryption.
+    /// - _rng:_ The source of the random nonces used by `encrypt` and `encrypt_in_place`.
+    ///
+    /// # Returns
+    /// Result containing the `EncUtils` instance.
+    pub fn new_with_rng(key: EncKey, rng: impl RngCore + CryptoRng + Send + 'static) -> Result<Self, FileSystemError> {
+        let mut enc_utils = EncUtils::with_key(key);
+        enc_utils.rng = Some(Arc::new(Mutex::new(rng)));
+        Ok(enc_utils)
+    }
+
+    fn with_key(key: EncKey) -> Self {
+        let cipher = Self::build_cipher(&key);
+        EncUtils {
+            key,
+            cipher,
+            encryptions: Arc::new(AtomicU64::new(0)),
+            encryption_limit: Some(DEFAULT_ENCRYPTION_LIMIT),
+            rng: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Fills `bytes` from the configured RNG, or `OsRng` if there is none.
+    fn fill_random(&self, bytes: &mut [u8]) {
+        match &self.rng {
+            Some(rng) => rng.lock().unwrap_or_else(|e| e.into_inner()).fill_bytes(bytes),
+            None => OsRng.fill_bytes(bytes),
+        }
+    }
+
+    /// Returns how many times content has been encrypted under the current key, by this
+    /// instance and its clones.
+    pub fn encryption_count(&self) -> u64 {
+        self.encryptions.load(Ordering::Relaxed)
+    }
+
+    /// Sets how many encryptions are allowed under the current key.
+    ///
+    /// Defaults to `DEFAULT_ENCRYPTION_LIMIT`. Lowering it makes key rotation kick in
+    /// earlier; `None` disables the guard, which is only safe if the key is rotated by
+    /// other means well before 2^32 encryptions.
+    ///
+    /// # Arguments
+    /// - _limit:_ The maximum number of encryptions, or `None` for no limit.
+    pub fn set_encryption_limit(&mut self, limit: Option<u64>) {
+        self.encryption_limit = limit;
+    }
+
+    /// Returns the plaintext size of the chunks written by `encrypt_stream`.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Sets the plaintext size of the chunks written by `encrypt_stream`.
+    ///
+    /// Small chunks let readers start on, or seek to, any part of a stream with less
+    /// data to decrypt first; large chunks spend fewer bytes on tags. Defaults to
+    /// `DEFAULT_CHUNK_SIZE`. The size is stored in the stream header, so `decrypt_stream`
+    /// reads streams of any chunk size regardless of this setting.
+    ///
+    /// # Arguments
+    /// - _chunk_size:_ A power of two between `MIN_CHUNK_SIZE` and `MAX_CHUNK_SIZE`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the size is not a power of two or out of bounds.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) -> Result<(), FileSystemError> {
+        if !chunk_size.is_power_of_two() || !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+            return Err(FileSystemError::from(format!(
+                "Chunk size must be a power of two between {} and {} bytes, got {}",
+                MIN_CHUNK_SIZE, MAX_CHUNK_SIZE, chunk_size
+            )));
+        }
+        self.chunk_size = chunk_size;
+        Ok(())
+    }
+
+    /// Fails if `len` bytes are too many to encrypt as a single message.
+    fn check_message_size(len: usize) -> Result<(), FileSystemError> {
+        if len as u64 > MAX_MESSAGE_SIZE {
+            return Err(FileSystemError::from(format!(
+                "Content of {} bytes exceeds the AES-GCM limit of {} bytes for a single message, use encrypt_stream instead",
+                len, MAX_MESSAGE_SIZE
+            )));
+        }
+        Ok(())
+    }
+
+    /// Counts an encryption, failing if the limit has been reached.
+    fn reserve_encryption(&self) -> Result<(), FileSystemError> {
+        let limit = self.encryption_limit.unwrap_or(u64::MAX);
+        self.encryptions
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| (count < limit).then_some(count + 1))
+            .map(|_| ())
+            .map_err(|_| FileSystemError::from("Encryption limit for this key reached, rotate the key to avoid nonce reuse"))
     }
 
     /// Returns the current encryption key.
@@ -63,57 +325,402 @@ impl EncUtils {
         &self.key
     }
 
+    /// Checks whether `other` is the current encryption key, in constant time.
+    ///
+    /// A plain `==` on the key bytes returns as soon as a byte differs, so the time it
+    /// takes reveals how long the matching prefix is. When checking a user-supplied key
+    /// against a stored one, an attacker could use that to recover the key byte by byte.
+    /// This comparison always inspects every byte. `==` on `EncKey` and `EncUtils` uses it too.
+    ///
+    /// # Arguments
+    /// - _other:_ The key to compare against.
+    ///
+    /// # Returns
+    /// True if both keys are identical.
+    pub fn key_eq_ct(&self, other: &EncKey) -> bool {
+        self.key == *other
+    }
+
     /// Sets a new encryption key.
     ///
     /// # Arguments
     /// - _key:_ The new encryption key to set.
     ///
     /// # Returns
-    /// Result indicating success or an error if the key is invalid.
+    /// Result indicating success; `EncKey` already guarantees a valid key length.
     pub fn set_key(&mut self, key: EncKey) -> Result<(), FileSystemError> {
-        Self::is_valid_key(&key)?;
+        self.cipher = Self::build_cipher(&key);
         self.key = key;
+        // A fresh key starts a fresh nonce space
+        self.encryptions = Arc::new(AtomicU64::new(0));
         Ok(())
     }
 
+    /// Runs the AES key schedule for the key.
+    fn build_cipher(key: &EncKey) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_bytes()))
+    }
+
     /// Encrypts the provided file content using AES-256-GCM.
     ///
     /// # Arguments
     /// - _content:_ The file content to encrypt.
     ///
     /// # Returns
-    /// Result containing the encrypted content or an error if encryption fails.
+    /// Result containing the encrypted content or an error if encryption fails, the
+    /// content is larger than `MAX_MESSAGE_SIZE` or the encryption limit for the key has
+    /// been reached.
     pub fn encrypt(&self, content: FileContent) -> Result<FileContent, FileSystemError> {
-        // AES-256-GCM expects a 32-byte key and 12-byte nonce
-        let key = Key::<Aes256Gcm>::from_slice(&self.key);
-        let cipher = Aes256Gcm::new(key);
-        let mut nonce_bytes = [0u8; 12];
-        OsRng.fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
-        let ciphertext = cipher.encrypt(nonce, content.as_ref()).map_err(|_| FileSystemError::from("Encryption failed"))?;
-        // Prepend nonce to ciphertext
-        let mut result = nonce_bytes.to_vec();
-        result.extend_from_slice(&ciphertext);
+        let mut buffer = content;
+        self.encrypt_in_place(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Encrypts borrowed content into a new buffer, e.g. a sub-slice of a larger buffer,
+    /// without copying it into an owned `Vec` first. Only the output is allocated.
+    ///
+    /// # Arguments
+    /// - _content:_ The content to encrypt.
+    ///
+    /// # Returns
+    /// `version || nonce || ciphertext || tag`, as produced by `encrypt`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if encryption fails, the content is larger than
+    /// `MAX_MESSAGE_SIZE` or the encryption limit for the key has been reached.
+    pub fn encrypt_slice(&self, content: &[u8]) -> Result<FileContent, FileSystemError> {
+        Self::check_message_size(content.len())?;
+        self.reserve_encryption()?;
+        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        self.fill_random(&mut nonce_bytes);
+        let mut result = Vec::with_capacity(content.len() + ENCRYPTION_OVERHEAD);
+        result.push(BLOB_FORMAT_AES_GCM);
+        result.extend_from_slice(&nonce_bytes);
+        result.extend_from_slice(content);
+        let tag = self.cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce_bytes), b"", &mut result[1 + NONCE_SIZE..])
+            .map_err(|_| FileSystemError::from("Encryption failed"))?;
+        result.extend_from_slice(&tag);
+        Ok(result)
+    }
+
+    /// Encrypts a buffer in place, turning the plaintext into
+    /// `version || nonce || ciphertext || tag`.
+    ///
+    /// Only grows the buffer by `ENCRYPTION_OVERHEAD` bytes, so a buffer reused across
+    /// calls stops allocating once it has enough capacity.
+    ///
+    /// # Arguments
+    /// - _buffer:_ The plaintext, replaced by the encrypted content.
+    ///
+    /// # Errors
+    /// `FileSystemError` if encryption fails, the buffer is larger than `MAX_MESSAGE_SIZE`
+    /// or the encryption limit for the key has been reached; the buffer then still holds
+    /// the plaintext.
+    pub fn encrypt_in_place(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        Self::check_message_size(buffer.len())?;
+        self.reserve_encryption()?;
+        // AES-256-GCM expects a 12-byte nonce
+        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        self.fill_random(&mut nonce_bytes);
+        buffer.reserve(ENCRYPTION_OVERHEAD);
+        let tag = self.cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce_bytes), b"", buffer)
+            .map_err(|_| FileSystemError::from("Encryption failed"))?;
+        buffer.extend_from_slice(&tag);
+        // Prepend format version and nonce to ciphertext
+        buffer.splice(0..0, std::iter::once(BLOB_FORMAT_AES_GCM).chain(nonce_bytes));
+        Ok(())
+    }
+
+    /// Encrypts the provided file content using AES-256-GCM with a nonce derived from the content.
+    ///
+    /// The nonce is the first 12 bytes of HMAC-SHA256(key, content), so the same plaintext
+    /// encrypted under the same key always produces the same ciphertext. This makes encrypted
+    /// blobs deduplicable, but it also reveals to anyone holding the ciphertexts which files are
+    /// identical. Only use it where that leak is acceptable; `encrypt` remains the default.
+    ///
+    /// The output is decrypted with the regular `decrypt`, since the nonce is prepended as usual.
+    ///
+    /// # Arguments
+    /// - _content:_ The file content to encrypt.
+    ///
+    /// # Returns
+    /// Result containing the encrypted content or an error if encryption fails or the
+    /// content is larger than `MAX_MESSAGE_SIZE`.
+    pub fn encrypt_deterministic(&self, content: FileContent) -> Result<FileContent, FileSystemError> {
+        Self::check_message_size(content.len())?;
+        self.reserve_encryption()?;
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.key.as_bytes())
+            .map_err(|_| FileSystemError::from("Invalid key for nonce derivation"))?;
+        mac.update(&content);
+        let digest = mac.finalize().into_bytes();
+        let nonce = Nonce::from_slice(&digest[..NONCE_SIZE]);
+        let mut result = content;
+        result.reserve(ENCRYPTION_OVERHEAD);
+        let tag = self.cipher.encrypt_in_place_detached(nonce, b"", &mut result)
+            .map_err(|_| FileSystemError::from("Encryption failed"))?;
+        result.extend_from_slice(&tag);
+        result.splice(0..0, std::iter::once(BLOB_FORMAT_AES_GCM).chain(nonce.iter().copied()));
         Ok(result)
     }
 
     /// Decrypts the provided file content using AES-256-GCM.
     ///
+    /// Blobs start with a format version, `BLOB_FORMAT_AES_GCM` for everything this
+    /// release writes. Blobs written before the version byte was added start directly with
+    /// the nonce and are still read: a blob that does not authenticate as a versioned one
+    /// is tried as an unversioned one.
+    ///
     /// # Arguments
     /// - _content:_ The encrypted file content to decrypt.
     ///
     /// # Returns
-    /// Result containing the decrypted content or an error if decryption fails.
+    /// Result containing the decrypted content.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the content is too short or fails authentication, e.g. because
+    /// of a wrong key, corrupted content or a blob format this version cannot read.
     pub fn decrypt(&self, content: FileContent) -> Result<FileContent, FileSystemError> {
-        // The first 12 bytes are the nonce
-        if content.len() < 12 {
+        let mut buffer = content;
+        self.decrypt_in_place(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Decrypts a buffer holding a blob made by `encrypt` in place, leaving only the
+    /// plaintext. Nothing is allocated.
+    ///
+    /// # Arguments
+    /// - _buffer:_ The encrypted content, replaced by the plaintext.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the buffer is too short or fails authentication, e.g. because
+    /// of a wrong key or corrupted content. The buffer contents are unspecified afterwards.
+    pub fn decrypt_in_place(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        let plaintext = self.decrypt_blob(buffer)?;
+        buffer.truncate(plaintext.end);
+        buffer.drain(..plaintext.start);
+        Ok(())
+    }
+
+    /// Decrypts a borrowed blob made by `encrypt` into a new buffer holding only the
+    /// plaintext. Only the output is allocated.
+    ///
+    /// # Arguments
+    /// - _content:_ The encrypted content.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the content is too short or fails authentication, e.g. because
+    /// of a wrong key or corrupted content.
+    pub fn decrypt_slice(&self, content: &[u8]) -> Result<FileContent, FileSystemError> {
+        let mut result = content.to_vec();
+        self.decrypt_in_place(&mut result)?;
+        Ok(result)
+    }
+
+    /// Decrypts a blob in place, without moving the plaintext to the start.
+    ///
+    /// # Returns
+    /// Where the plaintext is in `blob`.
+    fn decrypt_blob(&self, blob: &mut [u8]) -> Result<std::ops::Range<usize>, FileSystemError> {
+        let version = blob.first().copied();
+        if version == Some(BLOB_FORMAT_AES_GCM) && blob.len() >= ENCRYPTION_OVERHEAD
+            && let Ok(plaintext) = self.decrypt_message(&mut blob[1..]) {
+            return Ok(plaintext.start + 1..plaintext.end + 1);
+        }
+        // A blob without a format version, or an unversioned blob whose nonce happens to
+        // start with one
+        if blob.len() < LEGACY_ENCRYPTION_OVERHEAD {
             return Err(FileSystemError::from("Content too short for decryption"));
         }
-        let (nonce_bytes, ciphertext) = content.split_at(12);
-        let key = Key::<Aes256Gcm>::from_slice(&self.key);
-        let cipher = Aes256Gcm::new(key);
-        let nonce = Nonce::from_slice(nonce_bytes);
-        Ok(cipher.decrypt(nonce, ciphertext).unwrap_or_else(|_| vec![]))
+        self.decrypt_message(blob).map_err(|e| match version {
+            Some(version @ (BLOB_FORMAT_AES_GCM_CHUNKED | BLOB_FORMAT_CHACHA20_POLY1305 | BLOB_FORMAT_XCHACHA20_POLY1305)) => FileSystemError::from(format!(
+                "Decryption failed, the key is wrong, the content is corrupted or it uses blob format {}, which this version of evfs cannot read",
+                version
+            )),
+            _ => e,
+        })
+    }
+
+    /// Decrypts `nonce || ciphertext || tag` in place. The buffer is left untouched if it
+    /// fails authentication.
+    ///
+    /// # Returns
+    /// Where the plaintext is in `message`.
+    fn decrypt_message(&self, message: &mut [u8]) -> Result<std::ops::Range<usize>, FileSystemError> {
+        let tag_start = message.len() - TAG_SIZE;
+        let tag = Tag::clone_from_slice(&message[tag_start..]);
+        let nonce = *Nonce::from_slice(&message[..NONCE_SIZE]);
+        self.cipher.decrypt_in_place_detached(&nonce, b"", &mut message[NONCE_SIZE..tag_start], &tag)
+            .map_err(|_| FileSystemError::from("Decryption failed, the key is wrong or the content is corrupted"))?;
+        Ok(NONCE_SIZE..tag_start)
+    }
+
+    /// Checks whether a sample was encrypted with this key, e.g. to report a wrong key or
+    /// password before doing any real work.
+    ///
+    /// The sample is decrypted with `decrypt` into a scratch copy, so a small one is best;
+    /// any blob made by `encrypt` works.
+    ///
+    /// # Returns
+    /// True if the sample decrypts under this key. False if the key is wrong or the sample
+    /// is corrupted or too short, which cannot be told apart.
+    pub fn verify_against(&self, sample_ciphertext: &[u8]) -> bool {
+        self.decrypt(sample_ciphertext.to_vec()).is_ok()
+    }
+
+    /// Encrypts everything read from `reader` into `writer`, one chunk at a time.
+    ///
+    /// Only two chunks are held in memory, so inputs of any size can be encrypted. The
+    /// output is a header followed by chunks of `chunk_size` plaintext bytes plus a tag,
+    /// the last one possibly shorter. Each stream is encrypted under its own key, derived
+    /// from the main key and a random salt in the header, and chunk nonces are the chunk
+    /// index, with the last chunk marked so a stream cut at a chunk boundary fails to
+    /// decrypt. Counts as one encryption towards the encryption limit.
+    ///
+    /// # Arguments
+    /// - _reader:_ The plaintext.
+    /// - _writer:_ Receives the encrypted stream.
+    ///
+    /// # Returns
+    /// The number of plaintext bytes encrypted.
+    ///
+    /// # Errors
+    /// `FileSystemError` if reading or writing fails, the stream has more than 2^32
+    /// chunks or the encryption limit for the key has been reached.
+    pub fn encrypt_stream(&self, mut reader: impl Read, mut writer: impl Write) -> Result<u64, FileSystemError> {
+        self.reserve_encryption()?;
+        let mut header = [0u8; STREAM_HEADER_SIZE];
+        header[..STREAM_MAGIC.len()].copy_from_slice(STREAM_MAGIC);
+        header[STREAM_MAGIC.len()] = STREAM_VERSION;
+        header[STREAM_MAGIC.len() + 1] = self.chunk_size.trailing_zeros() as u8;
+        self.fill_random(&mut header[STREAM_MAGIC.len() + 2..]);
+        writer.write_all(&header).map_err(FileSystemError::from)?;
+        let cipher = self.stream_cipher(&header)?;
+
+        let mut current = Vec::with_capacity(self.chunk_size + TAG_SIZE);
+        let mut next = Vec::with_capacity(self.chunk_size + TAG_SIZE);
+        read_chunk(&mut reader, &mut current, self.chunk_size)?;
+        let mut total = 0u64;
+        for index in 0..=u32::MAX {
+            // A full chunk is only the last one if nothing follows it
+            let last = current.len() < self.chunk_size || {
+                read_chunk(&mut reader, &mut next, self.chunk_size)?;
+                next.is_empty()
+            };
+            total += current.len() as u64;
+            let tag = cipher.encrypt_in_place_detached(&stream_nonce(index, last), &header, &mut current)
+                .map_err(|_| FileSystemError::from("Encryption failed"))?;
+            current.extend_from_slice(&tag);
+            writer.write_all(&current).map_err(FileSystemError::from)?;
+            if last {
+                writer.flush().map_err(FileSystemError::from)?;
+                return Ok(total);
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        Err(FileSystemError::from("Stream too long, it has more than 2^32 chunks"))
+    }
+
+    /// Decrypts a stream written by `encrypt_stream` from `reader` into `writer`.
+    ///
+    /// The chunk size is taken from the stream header. Every chunk is authenticated
+    /// before it is written, so on error `writer` holds only authentic plaintext, but
+    /// possibly not all of it.
+    ///
+    /// # Arguments
+    /// - _reader:_ The encrypted stream.
+    /// - _writer:_ Receives the plaintext.
+    ///
+    /// # Returns
+    /// The number of plaintext bytes written.
+    ///
+    /// # Errors
+    /// `FileSystemError` if reading or writing fails, the header is invalid, or a chunk
+    /// fails authentication, e.g. because of a wrong key, corruption or truncation.
+    pub fn decrypt_stream(&self, mut reader: impl Read, mut writer: impl Write) -> Result<u64, FileSystemError> {
+        let mut header = [0u8; STREAM_HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(|_| FileSystemError::from("Stream too short for its header"))?;
+        let chunk_size = stream_chunk_size(&header)?;
+        let cipher = self.stream_cipher(&header)?;
+
+        let mut current = Vec::with_capacity(chunk_size + TAG_SIZE);
+        let mut next = Vec::with_capacity(chunk_size + TAG_SIZE);
+        read_chunk(&mut reader, &mut current, chunk_size + TAG_SIZE)?;
+        let mut total = 0u64;
+        for index in 0..=u32::MAX {
+            let last = current.len() < chunk_size + TAG_SIZE || {
+                read_chunk(&mut reader, &mut next, chunk_size + TAG_SIZE)?;
+                next.is_empty()
+            };
+            decrypt_chunk(&cipher, &header, index, last, &mut current)?;
+            total += current.len() as u64;
+            writer.write_all(&current).map_err(FileSystemError::from)?;
+            if last {
+                writer.flush().map_err(FileSystemError::from)?;
+                return Ok(total);
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        Err(FileSystemError::from("Stream too long, it has more than 2^32 chunks"))
+    }
+
+    /// Decrypts part of a stream written by `encrypt_stream`, without reading the rest.
+    ///
+    /// Chunks have a fixed size, so only the chunks overlapping the range are read and
+    /// decrypted, found from their index. The range is clamped to the end of the stream.
+    ///
+    /// # Arguments
+    /// - _reader:_ The encrypted stream, positioned anywhere.
+    /// - _offset:_ Where the range starts in the plaintext.
+    /// - _len:_ How many plaintext bytes to return at most.
+    ///
+    /// # Returns
+    /// The plaintext in `[offset, offset + len)`, shorter if the stream ends first and
+    /// empty if `offset` is past its end.
+    ///
+    /// # Errors
+    /// `FileSystemError` if reading fails, the header or stream length is invalid, or a
+    /// chunk in the range fails authentication.
+    pub fn decrypt_stream_range(&self, mut reader: impl Read + Seek, offset: u64, len: u64) -> Result<Vec<u8>, FileSystemError> {
+        let mut header = [0u8; STREAM_HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))
+            .and_then(|_| reader.read_exact(&mut header))
+            .map_err(|_| FileSystemError::from("Stream too short for its header"))?;
+        let chunk_size = stream_chunk_size(&header)?;
+        let cipher = self.stream_cipher(&header)?;
+        let stream_len = reader.seek(SeekFrom::End(0)).map_err(FileSystemError::from)?;
+        let plaintext_len = stream_plaintext_len(stream_len, chunk_size)
+            .ok_or(FileSystemError::from("Encrypted stream is truncated"))?;
+        let end = offset.saturating_add(len).min(plaintext_len);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+
+        let stored_chunk = (chunk_size + TAG_SIZE) as u64;
+        let last_index = plaintext_len.saturating_sub(1) / chunk_size as u64;
+        let first = offset / chunk_size as u64;
+        reader.seek(SeekFrom::Start(STREAM_HEADER_SIZE as u64 + first * stored_chunk))
+            .map_err(FileSystemError::from)?;
+        let mut range = Vec::with_capacity((end - offset) as usize);
+        let mut chunk = Vec::with_capacity(chunk_size + TAG_SIZE);
+        for index in first..=(end - 1) / chunk_size as u64 {
+            let index32 = u32::try_from(index).map_err(|_| FileSystemError::from("Stream too long, it has more than 2^32 chunks"))?;
+            read_chunk(&mut reader, &mut chunk, chunk_size + TAG_SIZE)?;
+            decrypt_chunk(&cipher, &header, index32, index == last_index, &mut chunk)?;
+            let chunk_start = index * chunk_size as u64;
+            let from = offset.saturating_sub(chunk_start) as usize;
+            let to = ((end - chunk_start) as usize).min(chunk.len());
+            range.extend_from_slice(&chunk[from..to]);
+        }
+        Ok(range)
+    }
+
+    /// Derives the key of one stream from the main key and the salt in its header.
+    fn stream_cipher(&self, header: &[u8; STREAM_HEADER_SIZE]) -> Result<Aes256Gcm, FileSystemError> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.key.as_bytes())
+            .map_err(|_| FileSystemError::from("Invalid key for stream key derivation"))?;
+        mac.update(b"evfs stream key");
+        mac.update(&header[STREAM_MAGIC.len() + 2..]);
+        Ok(Aes256Gcm::new(&mac.finalize().into_bytes()))
     }
 
     /// Static method to validate the key size.
@@ -123,30 +730,134 @@ impl EncUtils {
     ///
     /// # Returns
     /// Result indicating success or an error if the key is invalid.
-    pub fn is_valid_key(key: &EncKey) -> Result<(), FileSystemError> {
-        if key.is_empty() {
-            return Err(FileSystemError::from("Encryption key cannot be empty"));
-        }
-        if key.len() > MAX_ENC_KEY_SIZE {
+    pub fn is_valid_key(key: &[u8]) -> Result<(), FileSystemError> {
+        if key.len() != MAX_ENC_KEY_SIZE {
             return Err(FileSystemError::from(format!(
-                "Encryption key exceeds maximum size of {} bytes",
+                "Encryption key must be exactly {} bytes",
                 MAX_ENC_KEY_SIZE
             )));
         }
         Ok(())
     }
 
+    /// Derives a key from a password with PBKDF2-HMAC-SHA256.
+    ///
+    /// The same password, salt and iteration count always give the same key, so all three
+    /// have to be stored or known to derive it again. Use a fresh salt from
+    /// `generate_salt` for every new key and at least `PASSWORD_ITERATIONS` iterations;
+    /// derivation is deliberately slow to make guessing passwords expensive.
+    ///
+    /// # Arguments
+    /// - _password:_ The password, used as UTF-8 bytes.
+    /// - _salt:_ Random bytes that make the key unique to this use of the password.
+    /// - _iterations:_ How many times the hash is applied.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the password is empty or `iterations` is 0.
+    pub fn derive_key(password: &str, salt: &[u8], iterations: u32) -> Result<EncKey, FileSystemError> {
+        if password.is_empty() {
+            return Err(FileSystemError::from("Password cannot be empty"));
+        }
+        if iterations == 0 {
+            return Err(FileSystemError::from("Password key derivation needs at least one iteration"));
+        }
+        let mac = <Hmac<Sha256> as Mac>::new_from_slice(password.as_bytes())
+            .map_err(|_| FileSystemError::from("Invalid password for key derivation"))?;
+        // The key is exactly one SHA-256 output long, so only the first PBKDF2 block is needed
+        let mut block = mac.clone();
+        block.update(salt);
+        block.update(&1u32.to_be_bytes());
+        let mut previous = block.finalize().into_bytes();
+        let mut key: [u8; MAX_ENC_KEY_SIZE] = previous.into();
+        for _ in 1..iterations {
+            let mut round = mac.clone();
+            round.update(&previous);
+            previous = round.finalize().into_bytes();
+            key.iter_mut().zip(previous.iter()).for_each(|(k, p)| *k ^= p);
+        }
+        Ok(EncKey(key))
+    }
+
+    /// Creates an `EncUtils` with a key derived from a password, see `derive_key`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the password is empty or `iterations` is 0.
+    pub fn from_password(password: &str, salt: &[u8], iterations: u32) -> Result<Self, FileSystemError> {
+        Self::new(Self::derive_key(password, salt, iterations)?)
+    }
+
+    /// Generates a random salt for `derive_key`.
+    pub fn generate_salt() -> [u8; PASSWORD_SALT_SIZE] {
+        let mut salt = [0u8; PASSWORD_SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        salt
+    }
+
     /// Static method to generate a random key.
     ///
     /// # Returns
     /// A random encryption key of size `MAX_ENC_KEY_SIZE`.
     pub fn generate_random_key() -> EncKey {
-        let mut key = vec![0u8; MAX_ENC_KEY_SIZE];
+        let mut key = [0u8; MAX_ENC_KEY_SIZE];
         OsRng.fill_bytes(&mut key);
-        key
+        EncKey(key)
     }
 }
 
+/// Reads up to `len` bytes into `buf`, stopping early only at the end of the input.
+fn read_chunk(reader: &mut impl Read, buf: &mut Vec<u8>, len: usize) -> Result<(), FileSystemError> {
+    buf.clear();
+    reader.take(len as u64).read_to_end(buf).map_err(FileSystemError::from)?;
+    Ok(())
+}
+
+/// Builds the nonce of a stream chunk: zeros, the chunk index and a last-chunk flag.
+fn stream_nonce(index: u32, last: bool) -> Nonce<aes_gcm::aead::consts::U12> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[NONCE_SIZE - 5..NONCE_SIZE - 1].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_SIZE - 1] = last as u8;
+    *Nonce::from_slice(&nonce)
+}
+
+/// Computes the plaintext length of an encrypted stream from its total length, or `None`
+/// if no stream with that chunk size has that length.
+pub(crate) fn stream_plaintext_len(stream_len: u64, chunk_size: usize) -> Option<u64> {
+    let stored = stream_len.checked_sub(STREAM_HEADER_SIZE as u64)?;
+    let chunks = stored.div_ceil((chunk_size + TAG_SIZE) as u64).max(1);
+    let plaintext_len = stored.checked_sub(chunks * TAG_SIZE as u64)?;
+    let last_chunk_len = plaintext_len.checked_sub((chunks - 1) * chunk_size as u64)?;
+    // Only the stream of an empty input ends with an empty chunk
+    (last_chunk_len > 0 || chunks == 1).then_some(plaintext_len)
+}
+
+/// Checks the header of an encrypted stream and returns its chunk size.
+fn stream_chunk_size(header: &[u8; STREAM_HEADER_SIZE]) -> Result<usize, FileSystemError> {
+    if &header[..STREAM_MAGIC.len()] != STREAM_MAGIC {
+        return Err(FileSystemError::from("Not an encrypted stream"));
+    }
+    if header[STREAM_MAGIC.len()] != STREAM_VERSION {
+        return Err(FileSystemError::from(format!("Unsupported encrypted stream version {}", header[STREAM_MAGIC.len()])));
+    }
+    let chunk_size = 1usize.checked_shl(header[STREAM_MAGIC.len() + 1] as u32).unwrap_or(0);
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(FileSystemError::from("Invalid chunk size in encrypted stream header"));
+    }
+    Ok(chunk_size)
+}
+
+/// Decrypts one chunk of a stream in place, leaving only its plaintext.
+fn decrypt_chunk(cipher: &Aes256Gcm, header: &[u8], index: u32, last: bool, chunk: &mut Vec<u8>) -> Result<(), FileSystemError> {
+    if chunk.len() < TAG_SIZE {
+        return Err(FileSystemError::from("Encrypted stream is truncated"));
+    }
+    let tag_start = chunk.len() - TAG_SIZE;
+    let tag = Tag::clone_from_slice(&chunk[tag_start..]);
+    cipher.decrypt_in_place_detached(&stream_nonce(index, last), header, &mut chunk[..tag_start], &tag)
+        .map_err(|_| FileSystemError::from(format!("Chunk {} of the stream failed to decrypt, the key is wrong or the stream is corrupted or truncated", index)))?;
+    chunk.truncate(tag_start);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,21 +870,252 @@ mod tests {
         let encrypted = enc_utils.encrypt(content.clone()).expect("Encryption failed");
         let decrypted = enc_utils.decrypt(encrypted).expect("Decryption failed");
         assert_eq!(content, decrypted);
+
+        // Empty content is just the nonce and tag
+        let encrypted = enc_utils.encrypt(Vec::new()).expect("Encryption failed");
+        assert_eq!(encrypted.len(), ENCRYPTION_OVERHEAD);
+        assert_eq!(enc_utils.decrypt(encrypted).expect("Decryption failed"), b"");
+    }
+
+    #[test]
+    fn test_encrypt_deterministic() {
+        let enc_utils = EncUtils::default();
+        let content = b"Hello, World!".to_vec();
+        let first = enc_utils.encrypt_deterministic(content.clone()).expect("Encryption failed");
+        let second = enc_utils.encrypt_deterministic(content.clone()).expect("Encryption failed");
+        let other = enc_utils.encrypt_deterministic(b"Goodbye".to_vec()).expect("Encryption failed");
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_eq!(enc_utils.decrypt(first).expect("Decryption failed"), content);
+    }
+
+    /// Seeded xorshift generator, so nonces repeat from run to run
+    struct SeededRng(u64);
+
+    impl RngCore for SeededRng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for chunk in dest.chunks_mut(8) {
+                let bytes = self.next_u64().to_le_bytes();
+                chunk.copy_from_slice(&bytes[..chunk.len()]);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), aes_gcm::aead::rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for SeededRng {}
+
+    #[test]
+    fn test_new_with_rng() {
+        let key = EncUtils::generate_random_key();
+        let first = EncUtils::new_with_rng(key.clone(), SeededRng(42)).expect("Failed to create EncUtils");
+        let second = EncUtils::new_with_rng(key.clone(), SeededRng(42)).expect("Failed to create EncUtils");
+        let content = b"Hello, World!".to_vec();
+        let a = first.encrypt(content.clone()).expect("Encryption failed");
+        let b = second.encrypt(content.clone()).expect("Encryption failed");
+        let next = first.encrypt(content.clone()).expect("Encryption failed");
+        let random = EncUtils::new(key).expect("Failed to create EncUtils").encrypt(content.clone()).expect("Encryption failed");
+        assert_eq!(a, b, "The same seed should produce the same ciphertext");
+        assert_ne!(a, next, "Nonces should advance with the RNG");
+        assert_ne!(a, random);
+        assert_eq!(second.decrypt(a).expect("Decryption failed"), content);
+    }
+
+    #[test]
+    fn test_in_place() {
+        let enc_utils = EncUtils::default();
+        let content = b"Hello, World!".to_vec();
+        let mut buffer = content.clone();
+        enc_utils.encrypt_in_place(&mut buffer).expect("Encryption failed");
+        assert_eq!(buffer.len(), content.len() + ENCRYPTION_OVERHEAD);
+        assert_eq!(enc_utils.decrypt(buffer.clone()).expect("Decryption failed"), content);
+        enc_utils.decrypt_in_place(&mut buffer).expect("Decryption failed");
+        assert_eq!(buffer, content);
+
+        let mut tampered = enc_utils.encrypt(content.clone()).expect("Encryption failed");
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(enc_utils.decrypt_in_place(&mut tampered).is_err(), "Tampered content should fail authentication");
+        assert!(enc_utils.decrypt_in_place(&mut vec![0u8; ENCRYPTION_OVERHEAD - 1]).is_err());
+    }
+
+    #[test]
+    fn test_blob_format_version() {
+        let enc_utils = EncUtils::default();
+        let content = b"Hello, World!".to_vec();
+        let encrypted = enc_utils.encrypt(content.clone()).expect("Encryption failed");
+        assert_eq!(encrypted[0], BLOB_FORMAT_AES_GCM);
+        assert_eq!(enc_utils.encrypt_deterministic(content.clone()).unwrap()[0], BLOB_FORMAT_AES_GCM);
+        // Blobs from before the version byte are `nonce || ciphertext || tag`
+        assert_eq!(enc_utils.decrypt_slice(&encrypted[1..]).expect("Legacy blobs should still decrypt"), content);
+        // Also when their random nonce happens to start with a valid version
+        let mut nonce = [7u8; NONCE_SIZE];
+        nonce[0] = BLOB_FORMAT_AES_GCM;
+        let mut legacy = content.clone();
+        let tag = enc_utils.cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce), b"", &mut legacy).unwrap();
+        legacy.extend_from_slice(&tag);
+        legacy.splice(0..0, nonce);
+        assert_eq!(enc_utils.decrypt_slice(&legacy).expect("Legacy blobs should still decrypt"), content);
+
+        let mut future = encrypted.clone();
+        future[0] = BLOB_FORMAT_CHACHA20_POLY1305;
+        let error = enc_utils.decrypt_slice(&future).unwrap_err();
+        assert!(error.message.contains("blob format 3"), "Unexpected error: {}", error.message);
+    }
+
+    #[test]
+    fn test_decrypt_errors() {
+        let enc_utils = EncUtils::default();
+        let encrypted = enc_utils.encrypt(b"save data".to_vec()).expect("Encryption failed");
+        let wrong_key = EncUtils::default().decrypt(encrypted.clone());
+        let mut unknown = encrypted.clone();
+        unknown[0] = 0xEE;
+        let unknown_version = enc_utils.decrypt(unknown);
+        let mut reserved = encrypted;
+        reserved[0] = BLOB_FORMAT_AES_GCM_CHUNKED;
+        let reserved_version = enc_utils.decrypt(reserved);
+
+        assert!(wrong_key.is_err(), "A wrong key should not decrypt to an empty file");
+        assert!(unknown_version.is_err(), "An unknown blob format should be an error");
+        assert!(reserved_version.unwrap_err().message.contains("blob format 2"));
+    }
+
+    #[test]
+    fn test_message_size_limit() {
+        assert!(EncUtils::check_message_size(MAX_MESSAGE_SIZE as usize).is_ok());
+        let oversized = EncUtils::check_message_size(MAX_MESSAGE_SIZE as usize + 1);
+        assert!(oversized.unwrap_err().message.contains("encrypt_stream"), "The error should point to the streaming API");
+    }
+
+    #[test]
+    fn test_slices() {
+        let enc_utils = EncUtils::default();
+        let buffer = b"header|Hello, World!|trailer".to_vec();
+        let encrypted = enc_utils.encrypt_slice(&buffer[7..20]).expect("Encryption failed");
+        assert_eq!(encrypted.len(), 13 + ENCRYPTION_OVERHEAD);
+        assert_eq!(enc_utils.decrypt(encrypted.clone()).expect("Decryption failed"), b"Hello, World!");
+        let owned = enc_utils.encrypt(b"Hello, World!".to_vec()).expect("Encryption failed");
+        assert_eq!(enc_utils.decrypt_slice(&owned).expect("Decryption failed"), b"Hello, World!");
+        assert!(EncUtils::default().decrypt_slice(&owned).is_err(), "Another key should fail authentication");
+        assert!(enc_utils.decrypt_slice(&owned[..ENCRYPTION_OVERHEAD - 1]).is_err());
+    }
+
+    #[test]
+    fn test_derive_key() {
+        let hex = |key: EncKey| key.as_bytes().iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        // Published PBKDF2-HMAC-SHA256 test vectors
+        assert_eq!(hex(EncUtils::derive_key("password", b"salt", 1).unwrap()), "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b");
+        assert_eq!(hex(EncUtils::derive_key("password", b"salt", 4096).unwrap()), "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+        assert!(EncUtils::derive_key("", b"salt", 1).is_err());
+        assert!(EncUtils::derive_key("password", b"salt", 0).is_err());
+        assert_ne!(EncUtils::generate_salt(), EncUtils::generate_salt());
+
+        let salt = EncUtils::generate_salt();
+        let encrypted = EncUtils::from_password("hunter2", &salt, 10).unwrap().encrypt(b"secret".to_vec()).unwrap();
+        assert_eq!(EncUtils::from_password("hunter2", &salt, 10).unwrap().decrypt(encrypted).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_verify_against() {
+        let enc_utils = EncUtils::default();
+        let sample = enc_utils.encrypt(b"known".to_vec()).unwrap();
+        assert!(enc_utils.verify_against(&sample));
+        assert!(!EncUtils::default().verify_against(&sample), "Another key should not verify");
+        assert!(!enc_utils.verify_against(&sample[..ENCRYPTION_OVERHEAD - 1]));
+        // The empty plaintext is a valid sample too, not a sign of failure
+        assert!(enc_utils.verify_against(&enc_utils.encrypt(Vec::new()).unwrap()));
+        assert!(!EncUtils::default().verify_against(&enc_utils.encrypt(Vec::new()).unwrap()));
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut enc_utils = EncUtils::default();
+        assert_eq!(enc_utils.chunk_size(), DEFAULT_CHUNK_SIZE);
+        assert!(enc_utils.set_chunk_size(3000).is_err(), "Chunk sizes must be powers of two");
+        assert!(enc_utils.set_chunk_size(MIN_CHUNK_SIZE / 2).is_err());
+        assert!(enc_utils.set_chunk_size(MAX_CHUNK_SIZE * 2).is_err());
+        enc_utils.set_chunk_size(MIN_CHUNK_SIZE).unwrap();
+
+        for len in [0, 100, MIN_CHUNK_SIZE, 3 * MIN_CHUNK_SIZE + 7] {
+            let content: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let mut encrypted = Vec::new();
+            assert_eq!(enc_utils.encrypt_stream(content.as_slice(), &mut encrypted).unwrap(), len as u64);
+            assert_eq!(encrypted.len(), STREAM_HEADER_SIZE + len + len.div_ceil(MIN_CHUNK_SIZE).max(1) * TAG_SIZE);
+            // The chunk size comes from the header, not from the decrypting instance
+            let mut decrypted = Vec::new();
+            EncUtils::new(enc_utils.get_key().clone()).unwrap().decrypt_stream(encrypted.as_slice(), &mut decrypted).unwrap();
+            assert_eq!(decrypted, content);
+        }
+
+        let content = vec![7u8; 2 * MIN_CHUNK_SIZE];
+        let mut encrypted = Vec::new();
+        enc_utils.encrypt_stream(content.as_slice(), &mut encrypted).unwrap();
+        let range = enc_utils.decrypt_stream_range(std::io::Cursor::new(&encrypted), MIN_CHUNK_SIZE as u64 - 2, 4).unwrap();
+        assert_eq!(range, &content[MIN_CHUNK_SIZE - 2..MIN_CHUNK_SIZE + 2]);
+        let truncated = &encrypted[..STREAM_HEADER_SIZE + MIN_CHUNK_SIZE + TAG_SIZE];
+        assert!(enc_utils.decrypt_stream_range(std::io::Cursor::new(truncated), 0, 10).is_err(), "The first chunk was not the last one");
+        assert!(enc_utils.decrypt_stream(truncated, &mut Vec::new()).is_err(), "Dropping whole chunks should be detected");
+        let mut tampered = encrypted.clone();
+        tampered[STREAM_MAGIC.len() + 1] += 1;
+        assert!(enc_utils.decrypt_stream(tampered.as_slice(), &mut Vec::new()).is_err(), "The header is authenticated");
+        assert!(EncUtils::default().decrypt_stream(encrypted.as_slice(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_encryption_limit() {
+        let mut enc_utils = EncUtils::default();
+        enc_utils.set_encryption_limit(Some(2));
+        let clone = enc_utils.clone();
+        assert!(enc_utils.encrypt(b"one".to_vec()).is_ok());
+        assert!(clone.encrypt_deterministic(b"two".to_vec()).is_ok());
+        assert_eq!(enc_utils.encryption_count(), 2, "Clones should share the count");
+        assert!(enc_utils.encrypt(b"three".to_vec()).is_err(), "Encrypting past the limit should fail");
+        assert_eq!(enc_utils.encryption_count(), 2);
+
+        enc_utils.set_key(EncUtils::generate_random_key()).unwrap();
+        assert_eq!(enc_utils.encryption_count(), 0, "A new key should reset the count");
+        assert!(enc_utils.encrypt(b"four".to_vec()).is_ok());
     }
 
     #[test]
     fn test_invalid_key() {
         let invalid_key = vec![0u8; MAX_ENC_KEY_SIZE + 1];
-        let result = EncUtils::new(invalid_key);
+        let result = EncKey::try_from(invalid_key.as_slice());
         assert!(result.is_err(), "Expected error for invalid key size");
 
-        let empty_key = vec![];
-        let result = EncUtils::new(empty_key);
+        let short_key = vec![0u8; MAX_ENC_KEY_SIZE - 1];
+        let result = EncKey::try_from(short_key.as_slice());
+        assert!(result.is_err(), "Expected error for short key");
+
+        let empty_key: Vec<u8> = vec![];
+        let result = EncKey::try_from(empty_key.as_slice());
         assert!(result.is_err(), "Expected error for empty key");
 
         let valid_key = vec![0u8; MAX_ENC_KEY_SIZE];
-        let result = EncUtils::new(valid_key);
+        let result = EncKey::try_from(valid_key.as_slice()).and_then(EncUtils::new);
         assert!(result.is_ok(), "Expected success for valid key size");
+        assert_eq!(EncKey::from(valid_key).as_bytes(), &[0u8; MAX_ENC_KEY_SIZE]);
+        assert_eq!(EncKey::from([7u8; MAX_ENC_KEY_SIZE]).as_bytes(), &[7u8; MAX_ENC_KEY_SIZE]);
+        assert_eq!(format!("{:?}", EncUtils::generate_random_key()), "EncKey([REDACTED])");
+    }
+
+    #[test]
+    #[should_panic(expected = "Encryption key must be exactly 32 bytes")]
+    fn test_key_from_short_vec() {
+        let _ = EncKey::from(vec![0u8; MAX_ENC_KEY_SIZE - 1]);
     }
 
     #[test]
@@ -189,9 +1131,9 @@ mod tests {
         enc_utils.set_key(new_key.clone()).expect("Failed to set new key");
         assert_eq!(enc_utils.get_key(), &new_key);
 
-        // Test set_key with invalid key
-        let invalid_key = vec![0u8; MAX_ENC_KEY_SIZE + 1];
-        let result = enc_utils.set_key(invalid_key);
-        assert!(result.is_err(), "Expected error for invalid key size");
+        // Test constant-time comparison
+        assert!(enc_utils.key_eq_ct(&new_key));
+        assert!(!enc_utils.key_eq_ct(&key));
+        assert_eq!(enc_utils, EncUtils::new(new_key.clone()).unwrap());
     }
 }
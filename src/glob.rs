@@ -0,0 +1,124 @@
+/// Matches `text` against a simple glob `pattern`.
+///
+/// Supported syntax:
+/// - `*` matches any sequence of characters (including none, and including `/`).
+/// - `?` matches exactly one character.
+/// - `[abc]` matches one of the listed characters, `[a-z]` matches a range,
+///   and `[!abc]` or `[^abc]` matches any character not listed.
+///
+/// Every other character matches itself. Matching is case-sensitive.
+///
+/// # Arguments
+/// - _pattern:_ The glob pattern.
+/// - _text:_ The text to match against the pattern.
+///
+/// # Returns
+/// True if the whole `text` matches the pattern.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen in the pattern and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_class(&pattern, p, text[t]) {
+                        if matched {
+                            p = next;
+                            t += 1;
+                            continue;
+                        }
+                    } else if text[t] == '[' {
+                        // Unterminated class, treat `[` as a literal
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+                c if c == text[t] => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        // Mismatch: let the last `*` swallow one more character, if there is one
+        match backtrack {
+            Some((star, start)) => {
+                p = star + 1;
+                t = start + 1;
+                backtrack = Some((star, start + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches a single character against the character class starting at `pattern[start]`.
+///
+/// # Returns
+/// `None` if the class is not terminated, otherwise whether `c` matched and the
+/// pattern position just past the closing `]`.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        if pattern[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            if pattern[i] <= c && c <= pattern[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if pattern[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.png", "hero.png"));
+        assert!(!glob_match("*.png", "hero.png.bak"));
+        assert!(glob_match("level_?.dat", "level_1.dat"));
+        assert!(!glob_match("level_?.dat", "level_10.dat"));
+        assert!(glob_match("level_[0-9][0-9].dat", "level_10.dat"));
+        assert!(glob_match("[!.]*", "visible"));
+        assert!(!glob_match("[!.]*", ".hidden"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("*.PNG", "hero.png"), "Matching should be case-sensitive");
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[a", "[a"));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::FileSystemError;
+
+/// Base lengths and extra bits of length symbols 257..=285
+pub(crate) const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+/// Base distances and extra bits of distance symbols 0..=29
+pub(crate) const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+pub(crate) const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order in which code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const MAX_BITS: usize = 15;
+
+/// Reads bits least significant first, as DEFLATE packs them.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u8) -> Result<u32, FileSystemError> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.position / 8).ok_or(FileSystemError::from("Deflate stream ends unexpectedly"))?;
+            value |= (((byte >> (self.position % 8)) & 1) as u32) << i;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+}
+
+/// Canonical Huffman code, stored as the number of codes of each length and the symbols
+/// ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, FileSystemError> {
+        // First code and symbol index of the current length
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(FileSystemError::from("Invalid Huffman code in deflate stream"))
+    }
+}
+
+/// Decompresses a raw DEFLATE stream.
+///
+/// # Arguments
+/// - _data:_ The compressed stream, without zlib or gzip framing.
+/// - _max_size:_ The most bytes the stream may decompress to, also used to size the output
+///   up front. Decompression stops as soon as it is exceeded, so a small stream that
+///   expands enormously cannot exhaust memory.
+///
+/// # Errors
+/// `FileSystemError` if the stream is truncated or malformed, or decompresses to more
+/// than `max_size` bytes.
+pub(crate) fn inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>, FileSystemError> {
+    let mut reader = BitReader { data, position: 0 };
+    // DEFLATE never expands data by more than a factor of 1032
+    let mut output = Vec::with_capacity(max_size.min(data.len().saturating_mul(1032)));
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let length = reader.bits(16)?;
+                let complement = reader.bits(16)?;
+                if length != !complement & 0xffff {
+                    return Err(FileSystemError::from("Invalid stored block length in deflate stream"));
+                }
+                let start = reader.position / 8;
+                let block = data.get(start..start + length as usize).ok_or(FileSystemError::from("Deflate stream ends unexpectedly"))?;
+                check_output_size(output.len() + block.len(), max_size)?;
+                output.extend_from_slice(block);
+                reader.position += length as usize * 8;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                inflate_block(&mut reader, &mut output, max_size, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut output, max_size, &literals, &distances)?;
+            }
+            _ => return Err(FileSystemError::from("Invalid block type in deflate stream")),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// Reads the literal/length and distance codes from a dynamic block header.
+fn read_dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), FileSystemError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or(FileSystemError::from("Invalid code lengths in deflate stream"))?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err(FileSystemError::from("Invalid code lengths in deflate stream"));
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+/// Fails if the output would grow to `size`, past `max_size`.
+fn check_output_size(size: usize, max_size: usize) -> Result<(), FileSystemError> {
+    if size > max_size {
+        return Err(FileSystemError::from(format!("Deflate stream decompresses to more than {} bytes", max_size)));
+    }
+    Ok(())
+}
+
+/// Decodes the symbols of a compressed block until its end-of-block symbol, without
+/// growing `output` past `max_size`.
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, max_size: usize, literals: &Huffman, distances: &Huffman) -> Result<(), FileSystemError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => {
+                check_output_size(output.len() + 1, max_size)?;
+                output.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index])? as usize;
+                let index = distances.decode(reader)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err(FileSystemError::from("Invalid distance in deflate stream"));
+                }
+                let distance = DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index])? as usize;
+                if distance > output.len() {
+                    return Err(FileSystemError::from("Distance too far back in deflate stream"));
+                }
+                check_output_size(output.len() + length, max_size)?;
+                // The copy may overlap what it produces, so go byte by byte
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+            _ => return Err(FileSystemError::from("Invalid literal/length symbol in deflate stream")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_inflate() {
+        // Produced with zlib at level 9, as raw deflate streams
+        let fixed = from_hex("cb48cdc9c957c8402253cbd28a01");
+        let dynamic = from_hex("85d0c90980400c40d1565282715fba51228ac141712b5ff03edff3bb3db7cb5c92568ec9643be761917e0ff72a63783af14f153545cd5073d402b544ad506bd486377eb2784bb94bf94b63612f");
+        let expected: String = (0..15).map(|i| format!("level {}: the quick brown fox; ", i)).collect();
+        // A final stored block holding "abc"
+        let stored = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+
+        assert_eq!(inflate(&fixed, 22).unwrap(), b"hello hello hello evfs");
+        assert_eq!(inflate(&dynamic, expected.len()).unwrap(), expected.as_bytes());
+        assert_eq!(inflate(&stored, 3).unwrap(), b"abc");
+        assert!(inflate(&fixed[..fixed.len() - 2], 22).is_err(), "A truncated stream should fail");
+    }
+
+    #[test]
+    fn test_inflate_max_size() {
+        let fixed = from_hex("cb48cdc9c957c8402253cbd28a01");
+        let stored = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+
+        assert!(inflate(&fixed, 21).is_err_and(|e| e.message.contains("more than 21 bytes")), "Output past the limit should fail");
+        assert!(inflate(&stored, 2).is_err(), "Stored blocks should respect the limit");
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crate::{EncKey, EncUtils, FileSystemError, MAX_ENC_KEY_SIZE};
+
+/// A source of encryption keys, so applications don't have to embed raw keys in code.
+///
+/// Every provider validates the key it returns with `EncUtils::is_valid_key`, through
+/// `EncKey::try_from`.
+pub trait KeyProvider {
+    /// Loads the key from its source.
+    ///
+    /// # Errors
+    /// `FileSystemError` describing the source if it is missing, unreadable or does not
+    /// hold a valid key.
+    fn key(&self) -> Result<EncKey, FileSystemError>;
+}
+
+/// Reads a key from an environment variable holding it as hex or base64.
+///
+/// A value of `2 * MAX_ENC_KEY_SIZE` hex digits is decoded as hex, anything else as
+/// standard base64. Surrounding whitespace is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvKeyProvider {
+    var: String,
+}
+
+impl EnvKeyProvider {
+    /// Creates a provider for the environment variable `var`.
+    pub fn new(var: &str) -> Self {
+        EnvKeyProvider { var: var.to_string() }
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn key(&self) -> Result<EncKey, FileSystemError> {
+        let value = std::env::var(&self.var)
+            .map_err(|e| FileSystemError::from(format!("Cannot read key from environment variable {}: {}", self.var, e)))?;
+        parse_env_key(&self.var, &value)
+    }
+}
+
+/// Decodes `value`, read from the environment variable `var`, as described on
+/// `EnvKeyProvider`. `var` only appears in error messages.
+fn parse_env_key(var: &str, value: &str) -> Result<EncKey, FileSystemError> {
+    let value = value.trim();
+    let key = if value.len() == 2 * MAX_ENC_KEY_SIZE && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        decode_hex(value)
+    } else {
+        BASE64.decode(value).ok()
+    };
+    let key = key.ok_or(FileSystemError::from(format!("Environment variable {} does not hold a hex or base64 key", var)))?;
+    EncKey::try_from(key.as_slice())
+        .map_err(|e| FileSystemError::from(format!("Invalid key in environment variable {}: {}", var, e.message)))
+}
+
+/// Reads the raw bytes of a key file.
+///
+/// The file must contain exactly the key bytes, not a hex or base64 encoding of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileKeyProvider {
+    path: PathBuf,
+}
+
+impl FileKeyProvider {
+    /// Creates a provider for the key file at `path`.
+    pub fn new(path: &str) -> Self {
+        FileKeyProvider { path: PathBuf::from(path) }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn key(&self) -> Result<EncKey, FileSystemError> {
+        let key = std::fs::read(&self.path)
+            .map_err(|e| FileSystemError::from(format!("Cannot read key file {}: {}", self.path.display(), e)))?;
+        EncKey::try_from(key.as_slice())
+            .map_err(|e| FileSystemError::from(format!("Invalid key in {}: {}", self.path.display(), e.message)))
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl EncUtils {
+    /// Creates an `EncUtils` with the key loaded from `provider`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the provider fails or the key is rejected by `EncUtils::new`.
+    pub fn from_provider(provider: &impl KeyProvider) -> Result<Self, FileSystemError> {
+        EncUtils::new(provider.key()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_key_provider() {
+        let key = EncUtils::generate_random_key();
+        let hex: String = key.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        let base64 = format!("{}\n", BASE64.encode(key.as_bytes()));
+        assert_eq!(parse_env_key("EVFS_TEST_KEY_HEX", &hex).unwrap(), key);
+        assert_eq!(parse_env_key("EVFS_TEST_KEY_BASE64", &base64).unwrap(), key);
+
+        let malformed = parse_env_key("EVFS_TEST_KEY_BAD", "not a key!").unwrap_err();
+        assert!(malformed.message.contains("EVFS_TEST_KEY_BAD"));
+        let missing = EnvKeyProvider::new("EVFS_TEST_KEY_MISSING").key().unwrap_err();
+        assert!(missing.message.contains("EVFS_TEST_KEY_MISSING"));
+    }
+
+    #[test]
+    fn test_file_key_provider() {
+        let key = EncUtils::generate_random_key();
+        std::fs::write("test_key_provider.key", key.as_bytes()).unwrap();
+        std::fs::write("test_key_provider_empty.key", b"").unwrap();
+        let loaded = FileKeyProvider::new("test_key_provider.key").key();
+        let enc_utils = EncUtils::from_provider(&FileKeyProvider::new("test_key_provider.key"));
+        let empty = FileKeyProvider::new("test_key_provider_empty.key").key();
+        std::fs::remove_file("test_key_provider.key").ok();
+        std::fs::remove_file("test_key_provider_empty.key").ok();
+        assert_eq!(loaded.unwrap(), key);
+        assert!(enc_utils.unwrap().key_eq_ct(&key));
+        assert!(empty.is_err(), "An empty key file should be rejected");
+        assert!(FileKeyProvider::new("test_key_provider_missing.key").key().is_err());
+    }
+}
@@ -10,21 +10,87 @@ A simple file system abstraction for rust game engines and applications.
 */
 
 mod core;
+mod glob;
+mod quota;
+mod retry;
+mod scoped_fs;
+mod virtual_fs;
 
 #[cfg(feature = "local")]
 mod local;
 
+#[cfg(feature = "local")]
+mod caching_fs;
+
+#[cfg(feature = "watch")]
+mod watch;
+
 #[cfg(feature = "local_enc")]
 mod local_encrypted;
 
 #[cfg(feature = "enc")]
 mod enc_utils;
+
+#[cfg(feature = "enc")]
+mod key_provider;
+
+#[cfg(feature = "archive")]
 mod archive;
 
+#[cfg(feature = "archive")]
+mod compression;
+
+#[cfg(feature = "tar")]
+mod tar_io;
+
+#[cfg(any(feature = "zip", feature = "deflate"))]
+mod inflate;
+
+#[cfg(feature = "deflate")]
+mod deflate;
+
+#[cfg(feature = "deflate")]
+mod compressing_fs;
+
+#[cfg(feature = "zip")]
+mod zip_io;
+
+#[cfg(feature = "mmap")]
+mod mmap;
+
 pub use core::*;
+pub use glob::*;
+pub use quota::*;
+pub use retry::*;
+pub use scoped_fs::*;
+pub use virtual_fs::*;
 
 #[cfg(feature = "local")]
 pub use local::*;
 
+#[cfg(feature = "local")]
+pub use caching_fs::*;
+
+#[cfg(feature = "watch")]
+pub use watch::*;
+
 #[cfg(feature = "local_enc")]
-pub use local_encrypted::*;
\ No newline at end of file
+pub use local_encrypted::*;
+
+#[cfg(feature = "enc")]
+pub use enc_utils::*;
+
+#[cfg(feature = "enc")]
+pub use key_provider::*;
+
+#[cfg(feature = "archive")]
+pub use archive::*;
+
+#[cfg(feature = "archive")]
+pub use compression::*;
+
+#[cfg(feature = "deflate")]
+pub use compressing_fs::*;
+
+#[cfg(feature = "tar")]
+pub use tar_io::*;
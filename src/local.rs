@@ -1,11 +1,22 @@
-use std::path::PathBuf;
-use crate::{FileInfo, FileSystem, FileSystemError, FileContent};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use sha2::{Digest, Sha256};
+use crate::{normalize_path, Capabilities, FileInfo, FileSystem, FileSystemError, FileContent, FsEvent, Observer};
+
+/// Makes temporary file names unique between the threads of a process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 /// A local file system implementation that reads and writes files to the local disk.
 /// It can be configured to be writable or read-only.
+#[derive(Clone)]
 pub struct LocalFileSystem {
     base_path: PathBuf,
     writable: bool,
+    atomic_writes: bool,
+    follow_symlinks: bool,
+    observer: Option<Observer>,
 }
 
 impl LocalFileSystem {
@@ -22,10 +33,44 @@ impl LocalFileSystem {
     /// `FileSystemError` if the base path is not valid for the specified mode.
     pub fn new(base_path: &str, writable: bool) -> Result<Self, FileSystemError> {
         let base_path = PathBuf::from(base_path);
+        Self::check_base_path(&base_path, writable)?;
+        Ok(LocalFileSystem {
+            base_path,
+            writable,
+            atomic_writes: false,
+            follow_symlinks: false,
+            observer: None,
+        })
+    }
+
+    /// Makes the file system writable or read-only.
+    ///
+    /// Switching to writable performs the same checks as `new`, creating the base path
+    /// if it does not exist. Switching to read-only always succeeds.
+    ///
+    /// # Arguments
+    /// - _writable:_ If true, the file system allows writing files; otherwise, it is read-only.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the base path cannot be created; the mode is left unchanged.
+    pub fn set_writable(&mut self, writable: bool) -> Result<(), FileSystemError> {
+        if writable && !self.writable {
+            Self::check_base_path(&self.base_path, true)?;
+        }
+        self.writable = writable;
+        Ok(())
+    }
+
+    /// Returns whether the file system allows writing files.
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn check_base_path(base_path: &Path, writable: bool) -> Result<(), FileSystemError> {
         if writable {
             if !base_path.exists() {
-                std::fs::create_dir_all(&base_path)
-                    .expect("Failed to create base path for writable local file system");
+                std::fs::create_dir_all(base_path)
+                    .map_err(|e| FileSystemError::from(format!("Failed to create base path for writable local file system: {}", e)))?;
             }
         } else {
             if !base_path.is_dir() {
@@ -35,14 +80,66 @@ impl LocalFileSystem {
                 return Err(FileSystemError::from("Base path does not exist for non-writable local file system"));
             }
         }
-        Ok(LocalFileSystem {
-            base_path,
-            writable,
-        })
+        Ok(())
+    }
+
+    /// Enables or disables atomic writes.
+    ///
+    /// When enabled, `write_file` writes to a temporary file in the same directory,
+    /// syncs it to disk and then renames it over the destination, so a crash never
+    /// leaves a half-written file behind. Disabled by default.
+    ///
+    /// # Arguments
+    /// - _atomic:_ If true, subsequent writes are atomic.
+    pub fn set_atomic_writes(&mut self, atomic: bool) {
+        self.atomic_writes = atomic;
+    }
+
+    /// Returns whether atomic writes are enabled.
+    pub fn atomic_writes(&self) -> bool {
+        self.atomic_writes
+    }
+
+    /// Makes listing and walking follow symbolic links, or report them as links.
+    ///
+    /// When not following, which is the default, a link is listed with
+    /// `FileInfo::is_symlink` set and `walk` never descends through it, so a link cannot
+    /// lead outside the base path or into a cycle. When following, a link is listed as what
+    /// it points to and `walk` descends into linked directories; a cyclic link then makes
+    /// the walk endless. Reading and writing through a link always reach its target.
+    ///
+    /// # Arguments
+    /// - _follow:_ If true, symbolic links are followed.
+    pub fn set_follow_symlinks(&mut self, follow: bool) {
+        self.follow_symlinks = follow;
+    }
+
+    /// Returns whether symbolic links are followed when listing and walking.
+    pub fn follow_symlinks(&self) -> bool {
+        self.follow_symlinks
+    }
+
+    /// Calls `observer` after every successful read and listing, and every successful
+    /// operation that changes files or directories.
+    ///
+    /// Paths are reported as given by the caller. The observer runs on the calling thread
+    /// before the operation returns, so it should be quick. Without one, which is the
+    /// default, nothing is reported.
+    ///
+    /// # Arguments
+    /// - _observer:_ The callback, or `None` to stop reporting.
+    pub fn set_observer(&mut self, observer: Option<Observer>) {
+        self.observer = observer;
     }
 
-    fn full_path(&self, path: &str) -> PathBuf {
-        self.base_path.join(path)
+    fn notify(&self, event: FsEvent) {
+        if let Some(observer) = &self.observer {
+            observer(event);
+        }
+    }
+
+    pub(crate) fn full_path(&self, path: &str) -> PathBuf {
+        self.base_path.join(normalize_path(path))
     }
 
     fn ensure_writable(&self) -> Result<(), FileSystemError> {
@@ -51,66 +148,511 @@ impl LocalFileSystem {
         }
         Ok(())
     }
+
+    /// Resolves a directory whose contents are about to be deleted, failing if the file
+    /// system is not writable or the directory resolves outside the base path.
+    ///
+    /// # Returns
+    /// The canonical path of the directory, and whether it is the base path itself.
+    fn directory_to_delete(&self, path: &str) -> Result<(PathBuf, bool), FileSystemError> {
+        self.ensure_writable()?;
+        let full_path = self.full_path(path);
+        if !full_path.is_dir() {
+            return Err(FileSystemError::from("Path is not a directory"));
+        }
+        let base = self.base_path.canonicalize().map_err(FileSystemError::from)?;
+        let target = full_path.canonicalize().map_err(FileSystemError::from)?;
+        if !target.starts_with(&base) {
+            return Err(FileSystemError::from("Path is outside the base path"));
+        }
+        let is_base = target == base;
+        Ok((target, is_base))
+    }
+
+    fn write_atomic(full_path: &Path, content: &[u8]) -> Result<(), FileSystemError> {
+        let (temp_path, mut file) = create_temp_file(full_path, "tmp")?;
+        let result = file.write_all(content)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| std::fs::rename(&temp_path, full_path));
+        if result.is_err() {
+            std::fs::remove_file(&temp_path).ok();
+        }
+        result.map_err(FileSystemError::from)
+    }
 }
 
 impl FileSystem for LocalFileSystem {
     fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        let mut content = Vec::new();
+        self.read_into(path, &mut content)?;
+        Ok(content)
+    }
+
+    /// A missing file is reported by the open itself, as `FileSystemErrorKind::NotFound`
+    /// carrying the IO error, rather than checked for beforehand.
+    fn read_into(&self, path: &str, buf: &mut Vec<u8>) -> Result<usize, FileSystemError> {
         let full_path = self.full_path(path);
-        if !full_path.exists() {
-            return Err(FileSystemError::from("File does not exist"));
+        if full_path.is_dir() {
+            return Err(FileSystemError::from("Path is not a file"));
         }
-        if !full_path.is_file() {
+        let mut file = File::open(full_path).map_err(FileSystemError::from)?;
+        buf.clear();
+        let read = file.read_to_end(buf).map_err(FileSystemError::from)?;
+        self.notify(FsEvent::Read { path, bytes: read as u64 });
+        Ok(read)
+    }
+
+    /// Reads only the first `n` bytes from disk.
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        let full_path = self.full_path(path);
+        if full_path.is_dir() {
             return Err(FileSystemError::from("Path is not a file"));
         }
-        std::fs::read(full_path).map_err(|e| FileSystemError::from(e.to_string()))
+        let file = File::open(full_path).map_err(FileSystemError::from)?;
+        let mut content = Vec::new();
+        file.take(n as u64).read_to_end(&mut content).map_err(FileSystemError::from)?;
+        self.notify(FsEvent::Read { path, bytes: content.len() as u64 });
+        Ok(content)
     }
 
-    fn write_file(&self, path: &str, content: FileContent) -> Result<(), FileSystemError> {
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
         self.ensure_writable()?;
         let full_path = self.full_path(path);
         if let Some(parent) = full_path.parent() {
-            std::fs::create_dir_all(parent).map_err(|e| FileSystemError::from(e.to_string()))?;
+            std::fs::create_dir_all(parent).map_err(FileSystemError::from)?;
         }
-        std::fs::write(full_path, content).map_err(|e| FileSystemError::from(e.to_string()))
+        if self.atomic_writes {
+            Self::write_atomic(&full_path, &content)?;
+        } else {
+            std::fs::write(full_path, &content).map_err(FileSystemError::from)?;
+        }
+        self.notify(FsEvent::Write { path, bytes: content.len() as u64 });
+        Ok(content.len() as u64)
     }
 
     fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
         self.ensure_writable()?;
         let full_path = self.full_path(path);
         if !full_path.exists() {
-            return Err(FileSystemError::from("File does not exist"));
+            return Err(FileSystemError::not_found(path));
         }
         if !full_path.is_file() {
             return Err(FileSystemError::from("Path is not a file"));
         }
-        std::fs::remove_file(full_path).map_err(|e| FileSystemError::from(e.to_string()))
+        std::fs::remove_file(full_path).map_err(FileSystemError::from)?;
+        self.notify(FsEvent::Delete { path });
+        Ok(())
     }
 
     fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
         let full_path = self.full_path(directory);
         if !full_path.exists() {
-            return Err(FileSystemError::from("Directory does not exist"));
+            return Err(FileSystemError::not_found(directory));
         }
         if !full_path.is_dir() {
             return Err(FileSystemError::from("Path is not a directory"));
         }
         let entries = std::fs::read_dir(full_path)
-            .map_err(|e| FileSystemError::from(e.to_string()))?;
+            .map_err(FileSystemError::from)?;
 
         let mut files = Vec::new();
         for entry in entries {
-            let entry = entry.map_err(|e| FileSystemError::from(e.to_string()))?;
-            files.push(FileInfo::from(entry));
+            let entry = entry.map_err(FileSystemError::from)?;
+            let entry_path = entry.path();
+            match entry_info(entry, self.follow_symlinks) {
+                Ok(info) => files.push(info),
+                // The entry was removed between `read_dir` and the metadata lookup
+                Err(_) if !entry_path.exists() => continue,
+                Err(e) => return Err(e),
+            }
         }
+        self.notify(FsEvent::List { directory, entries: files.len() });
         Ok(files)
     }
+
+    /// Creates an empty file, along with any missing parent directories, or updates the
+    /// modification time of an existing file.
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        self.ensure_writable()?;
+        let full_path = self.full_path(path);
+        if full_path.is_dir() {
+            return Err(FileSystemError::from("Path is not a file"));
+        }
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent).map_err(FileSystemError::from)?;
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(full_path)
+            .map_err(FileSystemError::from)?;
+        file.set_modified(std::time::SystemTime::now()).map_err(FileSystemError::from)?;
+        self.notify(FsEvent::Touch { path });
+        Ok(())
+    }
+
+    fn truncate_file(&self, path: &str, len: u64) -> Result<(), FileSystemError> {
+        self.ensure_writable()?;
+        let full_path = self.full_path(path);
+        if !full_path.is_file() {
+            return Err(FileSystemError::not_found(path));
+        }
+        let file = std::fs::OpenOptions::new().write(true).open(full_path)
+            .map_err(FileSystemError::from)?;
+        file.set_len(len).map_err(FileSystemError::from)?;
+        self.notify(FsEvent::Truncate { path, len });
+        Ok(())
+    }
+
+    /// Opens the file with `OpenOptions::append`, creating it and any missing parent
+    /// directories. Atomic writes do not apply to appends.
+    fn open_append(&self, path: &str) -> Result<Box<dyn Write + Send>, FileSystemError> {
+        self.ensure_writable()?;
+        let full_path = self.full_path(path);
+        if full_path.is_dir() {
+            return Err(FileSystemError::from("Path is not a file"));
+        }
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent).map_err(FileSystemError::from)?;
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(full_path)
+            .map_err(FileSystemError::from)?;
+        self.notify(FsEvent::Append { path });
+        Ok(Box::new(file))
+    }
+
+    /// Checks the path on disk, following symlinks.
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        Ok(self.full_path(path).is_file())
+    }
+
+    /// Checks the path on disk, following symlinks.
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        Ok(self.full_path(path).is_dir())
+    }
+
+    /// Removes the directory and everything in it with `std::fs::remove_dir_all`.
+    ///
+    /// Deleting the base path itself (an empty path or `.`) empties it but keeps the
+    /// directory. Paths that resolve outside the base path are rejected.
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        let (target, is_base) = self.directory_to_delete(path)?;
+        if is_base {
+            remove_contents(&target)?;
+        } else {
+            std::fs::remove_dir_all(target).map_err(FileSystemError::from)?;
+        }
+        self.notify(FsEvent::DeleteDir { path });
+        Ok(())
+    }
+
+    /// Removes everything in the directory on disk. Symlinks are removed, not followed.
+    /// Paths that resolve outside the base path are rejected.
+    fn empty_dir(&self, directory: &str) -> Result<usize, FileSystemError> {
+        let (target, _) = self.directory_to_delete(directory)?;
+        let files = remove_contents(&target)?;
+        self.notify(FsEvent::EmptyDir { directory, files });
+        Ok(files)
+    }
+
+    /// Walks the tree with one open `read_dir` handle per directory level.
+    fn walk(&self, directory: &str) -> Box<dyn Iterator<Item = Result<FileInfo, FileSystemError>> + '_> {
+        let full_path = self.full_path(directory);
+        let walker = match std::fs::read_dir(full_path) {
+            Ok(entries) => LocalWalk { stack: vec![entries], error: None, follow_symlinks: self.follow_symlinks },
+            Err(e) => LocalWalk { stack: Vec::new(), error: Some(FileSystemError::from(e)), follow_symlinks: self.follow_symlinks },
+        };
+        Box::new(walker)
+    }
+
+    /// Moves the directory with `std::fs::rename`, creating the parents of `to` as needed.
+    ///
+    /// The base path itself cannot be renamed, `to` must not exist yet and neither path may
+    /// leave the base path through `..`.
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        self.ensure_writable()?;
+        // Checked before touching the disk, so nothing outside the base path can be probed
+        if [from, to].iter().any(|path| normalize_path(path).split('/').any(|component| component == "..")) {
+            return Err(FileSystemError::from("Path is outside the base path"));
+        }
+        if matches!(normalize_path(from).trim_matches('/'), "" | ".") {
+            return Err(FileSystemError::from("Cannot rename the base path"));
+        }
+        let from_path = self.full_path(from);
+        let to_path = self.full_path(to);
+        if !from_path.exists() {
+            return Err(FileSystemError::not_found(from));
+        }
+        if !from_path.is_dir() {
+            return Err(FileSystemError::from("Path is not a directory"));
+        }
+        if to_path.exists() {
+            return Err(FileSystemError::from(format!("Destination already exists: {}", to)));
+        }
+        if let Some(parent) = to_path.parent() {
+            std::fs::create_dir_all(parent).map_err(FileSystemError::from)?;
+        }
+        std::fs::rename(from_path, to_path).map_err(FileSystemError::from)?;
+        self.notify(FsEvent::RenameDir { from, to });
+        Ok(())
+    }
+
+    /// Swaps the files with three renames: `a` to a temporary name next to it, `b` to
+    /// `a`, then the temporary file to `b`. No content is copied and each rename is atomic,
+    /// so either path always holds one of the two complete files, never a partial one. The
+    /// swap as a whole is not atomic: a crash between the renames leaves `a` under its
+    /// temporary name, `.<name>.<pid>.<n>.swap`. If a rename fails, the earlier ones are undone.
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.ensure_writable()?;
+        if [a, b].iter().any(|path| normalize_path(path).split('/').any(|component| component == "..")) {
+            return Err(FileSystemError::from("Path is outside the base path"));
+        }
+        let (a_path, b_path) = (self.full_path(a), self.full_path(b));
+        for (path, full_path) in [(a, &a_path), (b, &b_path)] {
+            if !full_path.is_file() {
+                return Err(FileSystemError::not_found(path));
+            }
+        }
+        if a_path == b_path {
+            return Ok(());
+        }
+        // The reserved file is replaced by `a`, so no other swap can pick the same name
+        let (temp_path, _) = create_temp_file(&a_path, "swap")?;
+        if let Err(e) = std::fs::rename(&a_path, &temp_path) {
+            std::fs::remove_file(&temp_path).ok();
+            return Err(FileSystemError::from(e));
+        }
+        if let Err(e) = std::fs::rename(&b_path, &a_path) {
+            std::fs::rename(&temp_path, &a_path).ok();
+            return Err(FileSystemError::from(e));
+        }
+        if let Err(e) = std::fs::rename(&temp_path, &b_path) {
+            std::fs::rename(&a_path, &b_path).ok();
+            std::fs::rename(&temp_path, &a_path).ok();
+            return Err(FileSystemError::from(e));
+        }
+        self.notify(FsEvent::Swap { a, b });
+        Ok(())
+    }
+
+    /// Streams the file through the hasher instead of buffering it whole.
+    fn hash_file(&self, path: &str) -> Result<[u8; 32], FileSystemError> {
+        let full_path = self.full_path(path);
+        if !full_path.is_file() {
+            return Err(FileSystemError::not_found(path));
+        }
+        let mut file = File::open(full_path).map_err(FileSystemError::from)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher).map_err(FileSystemError::from)?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Combines the size and modification time, without reading the file.
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        let full_path = self.full_path(path);
+        if !full_path.is_file() {
+            return Err(FileSystemError::not_found(path));
+        }
+        let metadata = std::fs::metadata(full_path).map_err(FileSystemError::from)?;
+        let modified = metadata.modified().map_err(FileSystemError::from)?
+            .duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        Ok(format!("{}-{}", metadata.len(), modified.as_nanos()))
+    }
+
+    /// Calls `File::sync_all` on the file, then syncs its parent directory so that a
+    /// newly created or renamed file is durable too. Directory syncing is skipped on
+    /// platforms that cannot open directories as files.
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        let full_path = self.full_path(path);
+        if !full_path.is_file() {
+            return Err(FileSystemError::not_found(path));
+        }
+        File::open(&full_path).and_then(|file| file.sync_all())
+            .map_err(FileSystemError::from)?;
+        if cfg!(unix) && let Some(parent) = full_path.parent() {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            File::open(parent).and_then(|directory| directory.sync_all())
+                .map_err(FileSystemError::from)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            writable: self.writable,
+            supports_append: self.writable,
+            ..Capabilities::default()
+        }
+    }
+
+    /// Returns the base path, or `None` if it is not valid UTF-8.
+    fn root(&self) -> Option<&str> {
+        self.base_path.to_str()
+    }
+
+    /// Returns the path below the base path made absolute, without resolving symlinks.
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        std::path::absolute(self.full_path(path)).ok()
+    }
+
+    /// Returns the space available to this process on the disk holding the base path, or
+    /// its nearest existing parent if the base path has not been created yet.
+    ///
+    /// Supported on Unix platforms and Windows; other platforms return `None`.
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        let mut path = std::path::absolute(&self.base_path).map_err(FileSystemError::from)?;
+        while !path.exists() && path.pop() {}
+        available_disk_space(&path)
+    }
 }
 
+/// Queries `statvfs` for the blocks available to unprivileged users.
+#[cfg(unix)]
+fn available_disk_space(path: &Path) -> Result<Option<u64>, FileSystemError> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| FileSystemError::from("Path contains a NUL byte"))?;
+    // SAFETY: `statvfs` is plain data, for which all zeros is a valid value
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a `struct statvfs` to fill in
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(FileSystemError::from(std::io::Error::last_os_error()));
+    }
+    // The field types vary by platform
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64)))
+}
+
+/// Queries `GetDiskFreeSpaceExW`, which accounts for per-user disk quotas.
+#[cfg(windows)]
+fn available_disk_space(path: &Path) -> Result<Option<u64>, FileSystemError> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetDiskFreeSpaceExW(directory: *const u16, available: *mut u64, total: *mut u64, total_free: *mut u64) -> i32;
+    }
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: `path` is NUL-terminated and the totals may be null
+    if unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(FileSystemError::from(std::io::Error::last_os_error()));
+    }
+    Ok(Some(available))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available_disk_space(_path: &Path) -> Result<Option<u64>, FileSystemError> {
+    Ok(None)
+}
+
+/// Creates a new, empty file next to `full_path` to write a temporary copy into, named
+/// `.<name>.<pid>.<n>.<extension>` with `n` unique within the process, so threads and
+/// processes writing the same path never share one.
+fn create_temp_file(full_path: &Path, extension: &str) -> Result<(PathBuf, File), FileSystemError> {
+    let file_name = full_path.file_name()
+        .ok_or(FileSystemError::from("Path does not name a file"))?
+        .to_string_lossy();
+    loop {
+        let temp_path = full_path.with_file_name(format!(
+            ".{}.{}.{}.{}",
+            file_name,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            // Left behind by an earlier process with the same id
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(FileSystemError::from(e)),
+        }
+    }
+}
+
+/// Removes everything in a directory, recursively, but not the directory itself.
+///
+/// # Returns
+/// The number of files removed, symlinks included.
+fn remove_contents(directory: &Path) -> Result<usize, FileSystemError> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(directory).map_err(FileSystemError::from)? {
+        let entry = entry.map_err(FileSystemError::from)?;
+        let entry_path = entry.path();
+        if entry.file_type().map_err(FileSystemError::from)?.is_dir() {
+            removed += remove_contents(&entry_path)?;
+            std::fs::remove_dir(entry_path).map_err(FileSystemError::from)?;
+        } else {
+            std::fs::remove_file(entry_path).map_err(FileSystemError::from)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Describes a directory entry, as what it points to if it is a symlink that should be
+/// followed. Dangling links are described as links either way.
+fn entry_info(entry: std::fs::DirEntry, follow_symlinks: bool) -> Result<FileInfo, FileSystemError> {
+    let entry_path = entry.path();
+    let mut info = FileInfo::try_from(entry)?;
+    if follow_symlinks && info.is_symlink && let Ok(metadata) = std::fs::metadata(&entry_path) {
+        info.is_directory = metadata.is_dir();
+        info.size = metadata.len();
+        info.modified = metadata.modified().ok();
+        info.created = metadata.created().ok();
+        info.is_symlink = false;
+    }
+    Ok(info)
+}
+
+/// Depth-first iterator over a local directory tree backing `LocalFileSystem::walk`.
+struct LocalWalk {
+    stack: Vec<std::fs::ReadDir>,
+    error: Option<FileSystemError>,
+    follow_symlinks: bool,
+}
+
+impl Iterator for LocalWalk {
+    type Item = Result<FileInfo, FileSystemError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+        loop {
+            let entries = self.stack.last_mut()?;
+            let entry = match entries.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => return Some(Err(FileSystemError::from(e))),
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let entry_path = entry.path();
+            let info = match entry_info(entry, self.follow_symlinks) {
+                Ok(info) => info,
+                // The entry was removed while walking
+                Err(_) if !entry_path.exists() => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if info.is_directory {
+                match std::fs::read_dir(&entry_path) {
+                    Ok(entries) => self.stack.push(entries),
+                    Err(e) => self.error = Some(FileSystemError::from(e)),
+                }
+            }
+            return Some(Ok(info));
+        }
+    }
+}
 
 // Tests for the LocalFileSystem
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::FileSystemErrorKind;
+    use std::time::{Duration, SystemTime};
+
     #[test]
     fn test_local_filesystem_creation() {
         let fs = LocalFileSystem::new("test_dir", true);
@@ -127,6 +669,20 @@ mod tests {
         std::fs::remove_dir_all("test_dir").ok();
     }
 
+    #[test]
+    fn test_local_filesystem_properties() {
+        let fs = LocalFileSystem::new("test_dir_properties", true).unwrap();
+        let real_path = fs.real_path("assets\\hero.png");
+        std::fs::remove_dir_all("test_dir_properties").ok();
+
+        assert_eq!(fs.root(), Some("test_dir_properties"));
+        assert!(fs.capabilities().writable);
+        assert!(!fs.capabilities().encrypted);
+        let real_path = real_path.unwrap();
+        assert!(real_path.is_absolute());
+        assert!(real_path.ends_with(Path::new("test_dir_properties").join("assets").join("hero.png")));
+    }
+
     #[test]
     fn test_local_filesystem_read_write() {
         let fs = LocalFileSystem::new("test_dir_rw", true).unwrap();
@@ -147,4 +703,446 @@ mod tests {
         let read_result = fs.read_file(path);
         assert!(read_result.is_err());
     }
+
+    #[test]
+    fn test_local_filesystem_not_found() {
+        let fs = LocalFileSystem::new("test_dir_not_found", true).unwrap();
+        // Reported by the IO layer
+        let read = fs.read_file("missing.txt").unwrap_err();
+        // Reported by the existence checks
+        let delete = fs.delete_file("missing.txt").unwrap_err();
+        let hash = fs.hash_file("missing.txt").unwrap_err();
+        let list = fs.list_files("missing").unwrap_err();
+        std::fs::remove_dir_all("test_dir_not_found").ok();
+
+        for error in [&read, &delete, &hash, &list] {
+            assert_eq!(error.kind, FileSystemErrorKind::NotFound, "Unexpected error: {}", error);
+        }
+        assert_eq!(read.io_error().map(|e| e.kind()), Some(std::io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_local_filesystem_read_into() {
+        let fs = LocalFileSystem::new("test_dir_read_into", true).unwrap();
+        fs.write_file("long.txt", b"a longer file".to_vec()).unwrap();
+        fs.write_file("short.txt", b"short".to_vec()).unwrap();
+        let mut buf = Vec::new();
+        let long = fs.read_into("long.txt", &mut buf);
+        let capacity = buf.capacity();
+        let short = fs.read_into("short.txt", &mut buf);
+        let missing = fs.read_into("missing.txt", &mut buf);
+        std::fs::remove_dir_all("test_dir_read_into").ok();
+        assert_eq!(long.unwrap(), 13);
+        assert_eq!(short.unwrap(), 5);
+        assert_eq!(buf, b"short", "Earlier content should be replaced");
+        assert_eq!(buf.capacity(), capacity, "The buffer should be reused");
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_local_filesystem_set_writable() {
+        std::fs::create_dir_all("test_dir_set_writable").unwrap();
+        let mut fs = LocalFileSystem::new("test_dir_set_writable", false).unwrap();
+        assert!(!fs.is_writable());
+        let denied = fs.write_file("file.txt", b"denied".to_vec());
+        fs.set_writable(true).unwrap();
+        let allowed = fs.write_file("file.txt", b"allowed".to_vec());
+        fs.set_writable(false).unwrap();
+        let denied_again = fs.delete_file("file.txt");
+        std::fs::remove_dir_all("test_dir_set_writable").ok();
+        assert!(denied.is_err());
+        assert!(allowed.is_ok());
+        assert!(denied_again.is_err());
+    }
+
+    #[test]
+    fn test_local_filesystem_delete_dir_recursive() {
+        let fs = LocalFileSystem::new("test_dir_delete_recursive", true).unwrap();
+        fs.touch("saves/slot1/data.bin").unwrap();
+        fs.touch("saves/slot2.bin").unwrap();
+        fs.touch("keep.txt").unwrap();
+        let outside = fs.delete_dir_recursive("..");
+        fs.delete_dir_recursive("saves").unwrap();
+        let saves_gone = !std::path::Path::new("test_dir_delete_recursive/saves").exists();
+        let kept = fs.read_file("keep.txt").is_ok();
+        fs.delete_dir_recursive("").unwrap();
+        let emptied = fs.list_files("").unwrap();
+        std::fs::remove_dir_all("test_dir_delete_recursive").ok();
+        assert!(outside.is_err(), "Deleting outside the base path should fail");
+        assert!(saves_gone);
+        assert!(kept);
+        assert!(emptied.is_empty());
+    }
+
+    #[test]
+    fn test_local_filesystem_rename_dir() {
+        let fs = LocalFileSystem::new("test_dir_rename", true).unwrap();
+        fs.write_file("mods/old/textures/a.png", b"a".to_vec()).unwrap();
+        fs.touch("mods/taken/b.png").unwrap();
+        let renamed = fs.rename_dir("mods/old", "mods/new/v2");
+        let moved = fs.read_file("mods/new/v2/textures/a.png");
+        let old_gone = !std::path::Path::new("test_dir_rename/mods/old").exists();
+        let collision = fs.rename_dir("mods/new", "mods/taken");
+        let outside = fs.rename_dir("mods/new", "../escaped");
+        let missing = fs.rename_dir("mods/missing", "mods/other");
+        let probed = fs.rename_dir("../missing", "mods/other");
+        std::fs::remove_dir_all("test_dir_rename").ok();
+        renamed.expect("Failed to rename directory");
+        assert_eq!(moved.unwrap(), b"a");
+        assert!(old_gone);
+        assert!(collision.is_err(), "Renaming onto an existing directory should fail");
+        assert!(outside.is_err(), "Renaming outside the base path should fail");
+        assert_eq!(missing.unwrap_err().kind, FileSystemErrorKind::NotFound);
+        assert_ne!(probed.unwrap_err().kind, FileSystemErrorKind::NotFound, "Paths outside the base path should not be looked up");
+    }
+
+    #[test]
+    fn test_local_filesystem_copy_dir() {
+        let source = LocalFileSystem::new("test_dir_copy_source", true).unwrap();
+        source.write_file("saves/slot1/data.bin", b"slot1".to_vec()).unwrap();
+        source.write_file("saves/slot2.bin", b"slot2".to_vec()).unwrap();
+        source.write_file("other.txt", b"other".to_vec()).unwrap();
+        let backup = LocalFileSystem::new("test_dir_copy_backup", true).unwrap();
+        let copied = source.copy_dir("saves", &backup, "backup/today");
+        let slot1 = backup.read_file("backup/today/slot1/data.bin");
+        let slot2 = backup.read_file("backup/today/slot2.bin");
+        let other = backup.read_file("backup/today/other.txt");
+        std::fs::remove_dir_all("test_dir_copy_source").ok();
+        std::fs::remove_dir_all("test_dir_copy_backup").ok();
+        assert_eq!(copied.unwrap(), 2);
+        assert_eq!(slot1.unwrap(), b"slot1");
+        assert_eq!(slot2.unwrap(), b"slot2");
+        assert!(other.is_err(), "Only files below the source directory should be copied");
+    }
+
+    #[test]
+    fn test_local_filesystem_sync() {
+        let fs = LocalFileSystem::new("test_dir_sync", true).unwrap();
+        fs.write_file("save.dat", b"progress".to_vec()).unwrap();
+        let synced = fs.sync("save.dat");
+        let missing = fs.sync("missing.dat");
+        std::fs::remove_dir_all("test_dir_sync").ok();
+        assert!(synced.is_ok());
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_local_filesystem_path_separators() {
+        std::fs::create_dir_all("test_dir_separators/sub").unwrap();
+        let fs = LocalFileSystem::new("test_dir_separators", true).unwrap();
+        fs.write_file("sub\\file.txt", b"sub".to_vec()).unwrap();
+        let content = fs.read_file("sub/file.txt");
+        std::fs::remove_dir_all("test_dir_separators").ok();
+        assert_eq!(content.unwrap(), b"sub");
+    }
+
+    #[test]
+    fn test_local_filesystem_read_string() {
+        let fs = LocalFileSystem::new("test_dir_string", true).unwrap();
+        fs.write_file("bad.txt", vec![b'o', b'k', 0xff]).unwrap();
+        let strict = fs.read_file_as_string("bad.txt");
+        let lossy = fs.read_file_as_string_lossy("bad.txt");
+        std::fs::remove_dir_all("test_dir_string").ok();
+        assert!(strict.unwrap_err().message.contains("bad.txt"));
+        assert_eq!(lossy.unwrap(), "ok\u{fffd}");
+    }
+
+    #[test]
+    fn test_local_filesystem_open_append() {
+        let mut fs = LocalFileSystem::new("test_dir_append", true).unwrap();
+        let supported = fs.capabilities().supports_append;
+        let mut writer = fs.open_append("logs/game.log").unwrap();
+        writer.write_all(b"first\n").unwrap();
+        drop(writer);
+        fs.open_append("logs/game.log").unwrap().write_all(b"second\n").unwrap();
+        let content = fs.read_file("logs/game.log");
+        fs.set_writable(false).unwrap();
+        let read_only = fs.open_append("logs/game.log");
+        std::fs::remove_dir_all("test_dir_append").ok();
+        assert!(supported);
+        assert_eq!(content.unwrap(), b"first\nsecond\n");
+        assert!(read_only.is_err(), "Read-only file systems should not append");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_local_filesystem_available_space() {
+        let fs = LocalFileSystem::new("test_dir_space", true).unwrap();
+        let space = fs.available_space();
+        std::fs::remove_dir_all("test_dir_space").ok();
+        let removed_space = fs.available_space();
+        assert!(space.unwrap().is_some_and(|bytes| bytes > 0));
+        assert!(removed_space.unwrap().is_some(), "A missing base path should use its parent");
+    }
+
+    #[test]
+    fn test_local_filesystem_peek() {
+        let fs = LocalFileSystem::new("test_dir_peek", true).unwrap();
+        fs.write_file("image.png", b"\x89PNG\r\n\x1a\nrest of the image".to_vec()).unwrap();
+        let header = fs.peek("image.png", 8);
+        let short = fs.peek("image.png", 1000);
+        let missing = fs.peek("missing.png", 8);
+        std::fs::remove_dir_all("test_dir_peek").ok();
+        assert_eq!(header.unwrap(), b"\x89PNG\r\n\x1a\n");
+        assert_eq!(short.unwrap().len(), 25, "Peeking past the end should return the whole file");
+        assert_eq!(missing.unwrap_err().kind, FileSystemErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_local_filesystem_observer() {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut fs = LocalFileSystem::new("test_dir_observer", true).unwrap();
+        fs.set_observer(Some(std::sync::Arc::new(move |event: FsEvent| recorded.lock().unwrap().push(format!("{:?}", event)))));
+        fs.write_file("save.dat", vec![0u8; 10]).unwrap();
+        fs.read_file("save.dat").unwrap();
+        fs.list_files("").unwrap();
+        let missing = fs.read_file("missing.dat");
+        fs.delete_file("save.dat").unwrap();
+        fs.set_observer(None);
+        fs.list_files("").unwrap();
+        std::fs::remove_dir_all("test_dir_observer").ok();
+        assert!(missing.is_err());
+        assert_eq!(*events.lock().unwrap(), [
+            format!("{:?}", FsEvent::Write { path: "save.dat", bytes: 10 }),
+            format!("{:?}", FsEvent::Read { path: "save.dat", bytes: 10 }),
+            format!("{:?}", FsEvent::List { directory: "", entries: 1 }),
+            format!("{:?}", FsEvent::Delete { path: "save.dat" }),
+        ], "Failed operations and those after removing the observer should not be reported");
+    }
+
+    #[test]
+    fn test_local_filesystem_observer_changes() {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut fs = LocalFileSystem::new("test_dir_observer_changes", true).unwrap();
+        fs.write_file("saves/slot.dat", vec![0u8; 10]).unwrap();
+        fs.set_observer(Some(std::sync::Arc::new(move |event: FsEvent| recorded.lock().unwrap().push(format!("{:?}", event)))));
+        fs.truncate_file("saves/slot.dat", 4).unwrap();
+        fs.rename_dir("saves", "backup").unwrap();
+        let failed = fs.rename_dir("saves", "other");
+        fs.empty_dir("backup").unwrap();
+        std::fs::remove_dir_all("test_dir_observer_changes").ok();
+
+        assert!(failed.is_err());
+        assert_eq!(*events.lock().unwrap(), [
+            format!("{:?}", FsEvent::Truncate { path: "saves/slot.dat", len: 4 }),
+            format!("{:?}", FsEvent::RenameDir { from: "saves", to: "backup" }),
+            format!("{:?}", FsEvent::EmptyDir { directory: "backup", files: 1 }),
+        ], "Every successful change should be reported");
+    }
+
+    #[test]
+    fn test_local_filesystem_is_file_is_dir() {
+        let fs = LocalFileSystem::new("test_dir_kinds", true).unwrap();
+        fs.write_file("maps/level1.map", b"map".to_vec()).unwrap();
+        let kinds = [fs.is_file("maps/level1.map"), fs.is_dir("maps/level1.map"), fs.is_dir("maps"), fs.is_file("maps"), fs.is_dir(""), fs.is_file("missing")]
+            .map(Result::unwrap);
+        std::fs::remove_dir_all("test_dir_kinds").ok();
+        assert_eq!(kinds, [true, false, true, false, true, false]);
+    }
+
+    #[test]
+    fn test_local_filesystem_touch() {
+        let fs = LocalFileSystem::new("test_dir_touch", true).unwrap();
+        fs.touch("markers/done").unwrap();
+        let created = fs.read_file("markers/done").unwrap();
+        fs.write_file("kept.txt", b"data".to_vec()).unwrap();
+        fs.touch("kept.txt").unwrap();
+        let files = fs.list_files(".").unwrap();
+        let kept = fs.read_file("kept.txt").unwrap();
+        std::fs::remove_dir_all("test_dir_touch").ok();
+        assert!(created.is_empty());
+        assert_eq!(kept, b"data");
+        assert!(files.iter().all(|f| f.modified.is_some()));
+    }
+
+    #[test]
+    fn test_local_filesystem_truncate() {
+        let fs = LocalFileSystem::new("test_dir_truncate", true).unwrap();
+        fs.write_file("slot.dat", b"abcdef".to_vec()).unwrap();
+        fs.truncate_file("slot.dat", 3).unwrap();
+        let shrunk = fs.read_file("slot.dat").unwrap();
+        fs.truncate_file("slot.dat", 5).unwrap();
+        let grown = fs.read_file("slot.dat").unwrap();
+        std::fs::remove_dir_all("test_dir_truncate").ok();
+        assert_eq!(shrunk, b"abc");
+        assert_eq!(grown, b"abc\0\0");
+    }
+
+    #[test]
+    fn test_local_filesystem_hash() {
+        let fs = LocalFileSystem::new("test_dir_hash", true).unwrap();
+        let stored = fs.write_file("hello.txt", b"hello".to_vec()).unwrap();
+        let hash = fs.hash_file_hex("hello.txt").unwrap();
+        let missing = fs.hash_file("missing.txt");
+        std::fs::remove_dir_all("test_dir_hash").ok();
+        assert_eq!(stored, 5);
+        assert_eq!(hash, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_local_filesystem_swap_files() {
+        let fs = LocalFileSystem::new("test_dir_swap", true).unwrap();
+        fs.write_file("slot_a.sav", b"current".to_vec()).unwrap();
+        fs.write_file("slot_b.sav", b"fresh".to_vec()).unwrap();
+        fs.swap_files("slot_a.sav", "slot_b.sav").unwrap();
+        let swapped = (fs.read_file("slot_a.sav").unwrap(), fs.read_file("slot_b.sav").unwrap());
+        let missing = fs.swap_files("slot_a.sav", "slot_c.sav");
+        let files = fs.list_files("").unwrap().len();
+        std::fs::remove_dir_all("test_dir_swap").ok();
+
+        assert_eq!(swapped, (b"fresh".to_vec(), b"current".to_vec()));
+        assert_eq!(missing.unwrap_err().kind, crate::FileSystemErrorKind::NotFound);
+        assert_eq!(files, 2, "No temporary file should be left behind");
+    }
+
+    #[test]
+    fn test_local_filesystem_swap_files_threads() {
+        let fs = LocalFileSystem::new("test_dir_swap_threads", true).unwrap();
+        fs.write_file("slot_a.sav", b"current".to_vec()).unwrap();
+        fs.write_file("slot_b.sav", b"fresh".to_vec()).unwrap();
+        // Swaps racing on the same files may fail, but must never lose one
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        fs.swap_files("slot_a.sav", "slot_b.sav").ok();
+                    }
+                });
+            }
+        });
+        let mut contents: Vec<Vec<u8>> = fs.list_files("").unwrap().iter()
+            .map(|file| fs.read_file(&file.name).unwrap())
+            .collect();
+        contents.sort();
+        std::fs::remove_dir_all("test_dir_swap_threads").ok();
+
+        assert_eq!(contents, [b"current".to_vec(), b"fresh".to_vec()]);
+    }
+
+    #[test]
+    fn test_local_filesystem_empty_dir() {
+        let fs = LocalFileSystem::new("test_dir_empty", true).unwrap();
+        fs.write_file("cache/a.bin", b"a".to_vec()).unwrap();
+        fs.write_file("cache/sub/b.bin", b"b".to_vec()).unwrap();
+        fs.write_file("cache/sub/deeper/c.bin", b"c".to_vec()).unwrap();
+        fs.write_file("kept.txt", b"kept".to_vec()).unwrap();
+        let removed = fs.empty_dir("cache/");
+        let cache_kept = fs.is_dir("cache").unwrap();
+        let left = fs.list_files("cache").unwrap().len();
+        let outside = fs.empty_dir("cache/../..");
+        let read_only = LocalFileSystem::new("test_dir_empty", false).unwrap().empty_dir("");
+        let other_kept = fs.is_file("kept.txt").unwrap();
+        std::fs::remove_dir_all("test_dir_empty").ok();
+
+        assert_eq!(removed.unwrap(), 3);
+        assert!(cache_kept, "The directory itself should be kept");
+        assert_eq!(left, 0);
+        assert!(outside.is_err(), "Paths outside the base path should be rejected");
+        assert!(read_only.is_err());
+        assert!(other_kept);
+    }
+
+    #[test]
+    fn test_local_filesystem_content_id() {
+        let fs = LocalFileSystem::new("test_dir_content_id", true).unwrap();
+        fs.write_file("asset.txt", b"first".to_vec()).unwrap();
+        let first = fs.content_id("asset.txt").unwrap();
+        let unchanged = fs.content_id("asset.txt").unwrap();
+        fs.write_file("asset.txt", b"second version".to_vec()).unwrap();
+        let changed = fs.content_id("asset.txt").unwrap();
+        let missing = fs.content_id("missing.txt");
+        std::fs::remove_dir_all("test_dir_content_id").ok();
+
+        assert_eq!(first, unchanged);
+        assert_ne!(first, changed, "A rewrite should change the identifier");
+        assert_eq!(missing.unwrap_err().kind, crate::FileSystemErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_local_filesystem_list_modified_since() {
+        let fs = LocalFileSystem::new("test_dir_modified_since", true).unwrap();
+        let since = SystemTime::now() - Duration::from_secs(60);
+        fs.write_file("old.txt", b"old".to_vec()).unwrap();
+        File::options().write(true).open("test_dir_modified_since/old.txt").unwrap()
+            .set_modified(since - Duration::from_secs(60)).unwrap();
+        fs.write_file("sub/new.txt", b"new".to_vec()).unwrap();
+        let changed: Vec<String> = fs.list_modified_since("", since).unwrap().into_iter().map(|f| f.name).collect();
+        std::fs::remove_dir_all("test_dir_modified_since").ok();
+
+        assert_eq!(changed, ["new.txt"]);
+    }
+
+    #[test]
+    fn test_local_filesystem_walk() {
+        let fs = LocalFileSystem::new("test_dir_walk", true).unwrap();
+        fs.write_file("a.txt", b"a".to_vec()).unwrap();
+        fs.write_file("sub/b.txt", b"b".to_vec()).unwrap();
+        fs.write_file("sub/deeper/c.txt", b"c".to_vec()).unwrap();
+        let mut names: Vec<String> = fs.walk("").map(|f| f.unwrap().name).collect();
+        let first = fs.walk("").next();
+        std::fs::remove_dir_all("test_dir_walk").ok();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "b.txt", "c.txt", "deeper", "sub"]);
+        assert!(first.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_filesystem_symlinks() {
+        let mut fs = LocalFileSystem::new("test_dir_symlinks", true).unwrap();
+        fs.write_file("real/a.txt", b"aaa".to_vec()).unwrap();
+        std::os::unix::fs::symlink("real", "test_dir_symlinks/linked").unwrap();
+        let listed = fs.list_files("").unwrap();
+        let walked = fs.walk("").count();
+        fs.set_follow_symlinks(true);
+        let followed = fs.list_files("").unwrap();
+        let walked_followed = fs.walk("").count();
+        std::fs::remove_dir_all("test_dir_symlinks").ok();
+        let link = listed.iter().find(|f| f.name == "linked").unwrap();
+        assert!(link.is_symlink && !link.is_directory, "Links should be reported as links by default");
+        assert_eq!(walked, 3, "Walking should not descend through links by default");
+        let link = followed.iter().find(|f| f.name == "linked").unwrap();
+        assert!(!link.is_symlink && link.is_directory);
+        assert_eq!(walked_followed, 4);
+    }
+
+    #[test]
+    fn test_local_filesystem_atomic_write() {
+        let mut fs = LocalFileSystem::new("test_dir_atomic", true).unwrap();
+        fs.set_atomic_writes(true);
+        fs.write_file("save.dat", b"first".to_vec()).unwrap();
+        fs.write_file("save.dat", b"second".to_vec()).unwrap();
+        let read_content = fs.read_file("save.dat").unwrap();
+        let files = fs.list_files(".").unwrap();
+        std::fs::remove_dir_all("test_dir_atomic").ok();
+        assert_eq!(read_content, b"second");
+        // The temporary file must have been renamed into place
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_local_filesystem_atomic_write_threads() {
+        let mut fs = LocalFileSystem::new("test_dir_atomic_threads", true).unwrap();
+        fs.set_atomic_writes(true);
+        let contents: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 256 * 1024]).collect();
+        std::thread::scope(|scope| {
+            for content in &contents {
+                let fs = &fs;
+                scope.spawn(move || {
+                    for _ in 0..4 {
+                        fs.write_file("save.dat", content.clone()).unwrap();
+                    }
+                });
+            }
+        });
+        let read_content = fs.read_file("save.dat").unwrap();
+        let files = fs.list_files(".").unwrap();
+        std::fs::remove_dir_all("test_dir_atomic_threads").ok();
+
+        assert!(contents.contains(&read_content), "Concurrent writes should never interleave");
+        assert_eq!(files.len(), 1, "No temporary file should be left behind");
+    }
 }
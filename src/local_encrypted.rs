@@ -1,4 +1,5 @@
 
+use std::fs::File;
 use crate::core::*;
 use crate::local::*;
 use crate::enc_utils::*;
@@ -6,9 +7,22 @@ use crate::enc_utils::*;
 /// A local file system implementation that reads and writes encrypted files to the local disk.
 /// It uses the `EncUtils` for encryption and decryption of file contents.
 /// It can be configured to be writable or read-only.
+///
+/// Files are encrypted whole by default. With `set_chunk_size`, they are stored as chunked
+/// encrypted streams instead, which `read_range` can read parts of without decrypting the
+/// rest. Both formats cannot be mixed in one directory.
+///
+/// Clones use the same key and share its encryption limit, since they encrypt under the
+/// same nonce space.
+#[derive(Clone)]
 pub struct LocalEncryptedFileSystem {
     internal: LocalFileSystem,
     enc_util: EncUtils,
+    /// Whether files are stored as chunked encrypted streams
+    chunked: bool,
+    /// Whether `write_file` reads every file back to check it decrypts to what was written
+    verify_writes: bool,
+    observer: Option<Observer>,
 }
 
 impl LocalEncryptedFileSystem {
@@ -21,27 +35,264 @@ impl LocalEncryptedFileSystem {
     pub fn new(base_path: &str, writable: bool, key: EncKey) -> Result<Self, FileSystemError> {
         let internal = LocalFileSystem::new(base_path, writable)?;
         let enc_util = EncUtils::new(key)?;
-        Ok(LocalEncryptedFileSystem { internal, enc_util })
+        Ok(LocalEncryptedFileSystem { internal, enc_util, chunked: false, verify_writes: false, observer: None })
+    }
+
+    /// Makes the file system writable or read-only. See `LocalFileSystem::set_writable`.
+    pub fn set_writable(&mut self, writable: bool) -> Result<(), FileSystemError> {
+        self.internal.set_writable(writable)
+    }
+
+    /// Returns whether the file system allows writing files.
+    pub fn is_writable(&self) -> bool {
+        self.internal.is_writable()
+    }
+
+    /// Enables or disables atomic writes on the underlying local file system.
+    ///
+    /// A partially written ciphertext cannot be decrypted at all, so this is
+    /// recommended for save data. See `LocalFileSystem::set_atomic_writes`.
+    pub fn set_atomic_writes(&mut self, atomic: bool) {
+        self.internal.set_atomic_writes(atomic);
+    }
+
+    /// Makes `write_file` read every file back and decrypt it before returning, to catch
+    /// disk corruption while the content is still at hand, e.g. for irreplaceable save data.
+    ///
+    /// Every write then costs a read and a decryption as well. A file that does not read
+    /// back as written is deleted and the write fails. Off by default.
+    pub fn set_verify_writes(&mut self, verify: bool) {
+        self.verify_writes = verify;
+    }
+
+    /// Calls `observer` after every successful operation, like
+    /// `LocalFileSystem::set_observer`. Reads and writes report plaintext sizes.
+    ///
+    /// # Arguments
+    /// - _observer:_ The callback, or `None` to stop reporting.
+    pub fn set_observer(&mut self, observer: Option<Observer>) {
+        self.observer = observer;
+    }
+
+    fn notify(&self, event: FsEvent) {
+        if let Some(observer) = &self.observer {
+            observer(event);
+        }
+    }
+
+    /// Stores files as chunked encrypted streams, or encrypted whole with `None`.
+    ///
+    /// Chunked files can be read in part with `read_range`. Files already on disk are not
+    /// converted, and are only readable while the setting matches how they were written.
+    ///
+    /// # Arguments
+    /// - _chunk_size:_ The plaintext size of each chunk, see `EncUtils::set_chunk_size`,
+    ///   or `None` to encrypt files whole.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the chunk size is invalid.
+    pub fn set_chunk_size(&mut self, chunk_size: Option<usize>) -> Result<(), FileSystemError> {
+        if let Some(chunk_size) = chunk_size {
+            self.enc_util.set_chunk_size(chunk_size)?;
+        }
+        self.chunked = chunk_size.is_some();
+        Ok(())
+    }
+
+    /// Reads part of a file, decrypting only the chunks that overlap it.
+    ///
+    /// Files encrypted whole have no chunks, so without `set_chunk_size` the whole file is
+    /// decrypted and the range sliced out of it.
+    ///
+    /// # Arguments
+    /// - _path:_ The file to read.
+    /// - _offset:_ Where the range starts in the plaintext.
+    /// - _len:_ How many bytes to read at most.
+    ///
+    /// # Returns
+    /// The plaintext in `[offset, offset + len)`, shorter if the file ends first and empty
+    /// if `offset` is past its end.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the file cannot be read or fails to decrypt.
+    pub fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<FileContent, FileSystemError> {
+        let range = if self.chunked {
+            let file = File::open(self.internal.full_path(path)).map_err(FileSystemError::from)?;
+            self.enc_util.decrypt_stream_range(file, offset, len)?
+        } else {
+            let content = self.decrypt_file(path)?;
+            let start = offset.min(content.len() as u64) as usize;
+            let end = offset.saturating_add(len).min(content.len() as u64) as usize;
+            content[start..end].to_vec()
+        };
+        self.notify(FsEvent::Read { path, bytes: range.len() as u64 });
+        Ok(range)
+    }
+
+    /// Reads and decrypts a whole file, without reporting it to the observer.
+    fn decrypt_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        let content = self.internal.read_file(path)?;
+        if self.chunked {
+            let mut decrypted = Vec::with_capacity(content.len());
+            self.enc_util.decrypt_stream(content.as_slice(), &mut decrypted)?;
+            return Ok(decrypted);
+        }
+        self.enc_util.decrypt(content)
+    }
+
+    /// Encrypts and writes a file, verifying it with `set_verify_writes`, without reporting
+    /// it to the observer.
+    fn encrypt_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        let expected = self.verify_writes.then(|| content.clone());
+        let written = if self.chunked {
+            let mut encrypted = Vec::with_capacity(content.len() + STREAM_HEADER_SIZE + TAG_SIZE);
+            self.enc_util.encrypt_stream(content.as_slice(), &mut encrypted)?;
+            self.internal.write_file(path, encrypted)?
+        } else {
+            let encrypted_content = self.enc_util.encrypt(content)?;
+            self.internal.write_file(path, encrypted_content)?
+        };
+        if let Some(expected) = expected {
+            self.verify_write(path, &expected)?;
+        }
+        Ok(written)
+    }
+
+    /// Reads a just written file back, deleting it unless it decrypts to `expected`.
+    fn verify_write(&self, path: &str, expected: &[u8]) -> Result<(), FileSystemError> {
+        let reason = match self.decrypt_file(path) {
+            Ok(content) if content == expected => return Ok(()),
+            Ok(_) => "it decrypts to different content".to_string(),
+            Err(e) => e.message,
+        };
+        self.internal.delete_file(path).ok();
+        Err(FileSystemError::from(format!("{} did not read back as written and was deleted: {}", path, reason)))
     }
 }
 
 impl FileSystem for LocalEncryptedFileSystem {
     fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
-        let content = self.internal.read_file(path)?;
-        self.enc_util.decrypt(content)
+        let content = self.decrypt_file(path)?;
+        self.notify(FsEvent::Read { path, bytes: content.len() as u64 });
+        Ok(content)
+    }
+
+    /// Decrypts only the first chunks with `set_chunk_size`, and the whole file otherwise.
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        self.read_range(path, 0, n as u64)
     }
 
-    fn write_file(&self, path: &str, content: FileContent) -> Result<(), FileSystemError> {
-        let encrypted_content = self.enc_util.encrypt(content)?;
-        self.internal.write_file(path, encrypted_content)
+    /// With `set_verify_writes`, fails and deletes the file if it does not read back as written.
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        let bytes = content.len() as u64;
+        let written = self.encrypt_file(path, content)?;
+        self.notify(FsEvent::Write { path, bytes });
+        Ok(written)
     }
 
     fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
-        self.internal.delete_file(path)
+        self.internal.delete_file(path)?;
+        self.notify(FsEvent::Delete { path });
+        Ok(())
+    }
+
+    /// Swaps the encrypted files as they are, with the guarantees of
+    /// `LocalFileSystem::swap_files`.
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.internal.swap_files(a, b)?;
+        self.notify(FsEvent::Swap { a, b });
+        Ok(())
+    }
+
+    /// Creates an encrypted empty file if it does not exist, or updates the modification time
+    /// of an existing one. Note that a touched file is not zero bytes on disk: it still holds
+    /// the nonce and tag of an encrypted empty payload.
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        if self.internal.is_file(path)? {
+            self.internal.touch(path)?;
+        } else {
+            self.encrypt_file(path, Vec::new())?;
+        }
+        self.notify(FsEvent::Touch { path });
+        Ok(())
     }
 
+    /// Always fails: an arbitrary byte length does not map onto the encrypted framing.
+    fn truncate_file(&self, _path: &str, _len: u64) -> Result<(), FileSystemError> {
+        Err(FileSystemError::from("Encrypted files cannot be truncated"))
+    }
+
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.internal.is_file(path)
+    }
+
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.internal.is_dir(path)
+    }
+
+    /// Lists files with their plaintext sizes, i.e. without the encryption overhead.
     fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
-        self.internal.list_files(directory)
+        let mut files = self.internal.list_files(directory)?;
+        for file in files.iter_mut().filter(|f| !f.is_directory) {
+            file.size = match self.chunked {
+                true => stream_plaintext_len(file.size, self.enc_util.chunk_size()).unwrap_or(0),
+                false => file.size.saturating_sub(ENCRYPTION_OVERHEAD as u64),
+            };
+        }
+        self.notify(FsEvent::List { directory, entries: files.len() });
+        Ok(files)
+    }
+
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        self.internal.delete_dir_recursive(path)?;
+        self.notify(FsEvent::DeleteDir { path });
+        Ok(())
+    }
+
+    fn empty_dir(&self, directory: &str) -> Result<usize, FileSystemError> {
+        let files = self.internal.empty_dir(directory)?;
+        self.notify(FsEvent::EmptyDir { directory, files });
+        Ok(files)
+    }
+
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        self.internal.rename_dir(from, to)?;
+        self.notify(FsEvent::RenameDir { from, to });
+        Ok(())
+    }
+
+    /// Always fails: each file is sealed with authentication tags over its whole content,
+    /// so data cannot be added without rewriting the file.
+    fn open_append(&self, _path: &str) -> Result<Box<dyn std::io::Write + Send>, FileSystemError> {
+        Err(FileSystemError::from("Encrypted files cannot be appended to, write the whole file instead"))
+    }
+
+    /// The identifier of the encrypted file on disk, so no decryption is needed.
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        self.internal.content_id(path)
+    }
+
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        self.internal.sync(path)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            encrypted: true,
+            supports_random_access: self.chunked,
+            supports_append: false,
+            ..self.internal.capabilities()
+        }
+    }
+
+    fn root(&self) -> Option<&str> {
+        self.internal.root()
+    }
+
+    /// Returns the space on the underlying disk. Each file takes some more than its
+    /// plaintext size once encrypted.
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        self.internal.available_space()
     }
 }
 
@@ -66,4 +317,206 @@ mod tests {
         // remove test directory
         std::fs::remove_dir_all("test_dir").unwrap_or(());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_local_encrypted_touch_unreadable() {
+        let fs = LocalEncryptedFileSystem::new("test_dir_enc_touch", true, EncUtils::generate_random_key()).unwrap();
+        fs.write_file("test.txt", b"Hello".to_vec()).unwrap();
+        let before = std::fs::read("test_dir_enc_touch/test.txt").unwrap();
+        let other_key = LocalEncryptedFileSystem::new("test_dir_enc_touch", true, EncUtils::generate_random_key()).unwrap();
+        let touched = other_key.touch("test.txt");
+        let after = std::fs::read("test_dir_enc_touch/test.txt").unwrap();
+        std::fs::remove_dir_all("test_dir_enc_touch").ok();
+
+        assert!(touched.is_ok());
+        assert_eq!(after, before, "A file encrypted with another key should not be overwritten");
+    }
+
+    #[test]
+    fn test_local_encrypted_open_append() {
+        let key = EncUtils::generate_random_key();
+        let fs = LocalEncryptedFileSystem::new("test_dir_enc_append", true, key).unwrap();
+        fs.write_file("test.txt", b"Hello".to_vec()).unwrap();
+        let appended = fs.open_append("test.txt");
+        let content = fs.read_file("test.txt");
+        std::fs::remove_dir_all("test_dir_enc_append").ok();
+
+        assert!(fs.capabilities().encrypted && fs.capabilities().writable);
+        assert!(!fs.capabilities().supports_append);
+        assert!(appended.is_err(), "Encrypted files cannot be appended to in place");
+        assert_eq!(content.unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn test_local_encrypted_observer() {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut fs = LocalEncryptedFileSystem::new("test_dir_enc_observer", true, EncUtils::generate_random_key()).unwrap();
+        fs.set_observer(Some(std::sync::Arc::new(move |event: FsEvent| recorded.lock().unwrap().push(format!("{:?}", event)))));
+        fs.set_verify_writes(true);
+        fs.write_file("a.dat", b"first".to_vec()).unwrap();
+        fs.write_file("b.dat", b"second".to_vec()).unwrap();
+        fs.swap_files("a.dat", "b.dat").unwrap();
+        fs.touch("c.dat").unwrap();
+        let read = fs.read_file("a.dat");
+        std::fs::remove_dir_all("test_dir_enc_observer").ok();
+
+        assert_eq!(read.unwrap(), b"second");
+        assert_eq!(*events.lock().unwrap(), [
+            format!("{:?}", FsEvent::Write { path: "a.dat", bytes: 5 }),
+            format!("{:?}", FsEvent::Write { path: "b.dat", bytes: 6 }),
+            format!("{:?}", FsEvent::Swap { a: "a.dat", b: "b.dat" }),
+            format!("{:?}", FsEvent::Touch { path: "c.dat" }),
+            format!("{:?}", FsEvent::Read { path: "a.dat", bytes: 6 }),
+        ], "Plaintext sizes should be reported, and verifying a write is not a read");
+    }
+
+    #[test]
+    fn test_local_encrypted_hash_file() {
+        let key = EncUtils::generate_random_key();
+        let fs = LocalEncryptedFileSystem::new("test_dir_enc_hash", true, key).unwrap();
+        let local = LocalFileSystem::new("test_dir_enc_hash_plain", true).unwrap();
+        fs.write_file("test.txt", b"Hello, World!".to_vec()).unwrap();
+        local.write_file("test.txt", b"Hello, World!".to_vec()).unwrap();
+        let encrypted = fs.hash_file("test.txt");
+        let plain = local.hash_file("test.txt");
+        std::fs::remove_dir_all("test_dir_enc_hash").ok();
+        std::fs::remove_dir_all("test_dir_enc_hash_plain").ok();
+
+        assert_eq!(encrypted.unwrap(), plain.unwrap(), "Hashes should be computed over the plaintext, not the stored ciphertext");
+    }
+
+    #[test]
+    fn test_local_encrypted_list_files_sizes() {
+        let key = EncUtils::generate_random_key();
+        let fs = LocalEncryptedFileSystem::new("test_dir_enc_sizes", true, key).unwrap();
+        fs.write_file("test.txt", b"Hello, World!".to_vec()).unwrap();
+        fs.write_file("empty.txt", Vec::new()).unwrap();
+        let files = fs.list_files(".");
+        std::fs::remove_dir_all("test_dir_enc_sizes").ok();
+
+        let files = files.unwrap();
+        let size = |name: &str| files.iter().find(|f| f.name == name).map(|f| f.size);
+        assert_eq!(size("test.txt"), Some(13), "Sizes should not include the encryption overhead");
+        assert_eq!(size("empty.txt"), Some(0));
+    }
+
+    #[test]
+    fn test_local_encrypted_total_size() {
+        let key = EncUtils::generate_random_key();
+        let fs = LocalEncryptedFileSystem::new("test_dir_enc_total", true, key).unwrap();
+        fs.write_file("test.txt", b"Hello, World!".to_vec()).unwrap();
+        fs.write_file("saves/slot.dat", b"progress".to_vec()).unwrap();
+        let total = fs.total_size("");
+        let saves = fs.total_size("saves");
+        std::fs::remove_dir_all("test_dir_enc_total").ok();
+
+        assert_eq!(total.unwrap(), 21, "Sizes should not include the encryption overhead");
+        assert_eq!(saves.unwrap(), 8);
+    }
+
+    #[test]
+    fn test_move_file_to_encrypted() {
+        let staging = LocalFileSystem::new("test_dir_move_staging", true).unwrap();
+        let store = LocalEncryptedFileSystem::new("test_dir_move_store", true, EncUtils::generate_random_key()).unwrap();
+        staging.write_file("save.dat", b"progress".to_vec()).unwrap();
+        staging.write_file("blocked.dat", b"kept".to_vec()).unwrap();
+        store.write_file("taken/inner.dat", b"inner".to_vec()).unwrap();
+        staging.move_file_to("save.dat", &store, "saves/save.dat").unwrap();
+        let moved = store.read_file("saves/save.dat");
+        let source_left = staging.is_file("save.dat").unwrap();
+        // A directory is in the way, so the write fails
+        let failed = staging.move_file_to("blocked.dat", &store, "taken");
+        let kept = staging.read_file("blocked.dat");
+        std::fs::remove_dir_all("test_dir_move_staging").ok();
+        std::fs::remove_dir_all("test_dir_move_store").ok();
+
+        assert_eq!(moved.unwrap(), b"progress");
+        assert!(!source_left, "The source should be deleted once the copy is verified");
+        assert!(failed.is_err());
+        assert_eq!(kept.unwrap(), b"kept", "The source should be kept when the write fails");
+    }
+
+    #[test]
+    fn test_local_encrypted_clone() {
+        let key = EncUtils::generate_random_key();
+        let fs = LocalEncryptedFileSystem::new("test_dir_enc_clone", true, key).unwrap();
+        let clone = fs.clone();
+        clone.write_file("shared.txt", b"shared".to_vec()).unwrap();
+        let read = fs.read_file("shared.txt");
+        std::fs::remove_dir_all("test_dir_enc_clone").ok();
+
+        assert_eq!(read.unwrap(), b"shared", "A clone should use the same key");
+    }
+
+    #[test]
+    fn test_local_encrypted_verify_writes() {
+        let key = EncUtils::generate_random_key();
+        let mut fs = LocalEncryptedFileSystem::new("test_dir_enc_verify", true, key).unwrap();
+        fs.set_verify_writes(true);
+        let whole = fs.write_file("save.dat", b"progress".to_vec());
+        let read_whole = fs.read_file("save.dat");
+        fs.set_chunk_size(Some(1024)).unwrap();
+        let chunked = fs.write_file("chunked.dat", vec![7; 3000]);
+        let read_chunked = fs.read_file("chunked.dat");
+        std::fs::remove_dir_all("test_dir_enc_verify").ok();
+
+        assert!(whole.is_ok() && chunked.is_ok(), "Files that round-trip should be written");
+        assert_eq!(read_whole.unwrap(), b"progress");
+        assert_eq!(read_chunked.unwrap(), vec![7; 3000]);
+    }
+
+    #[test]
+    fn test_local_encrypted_verify_writes_corrupted() {
+        let key = EncUtils::generate_random_key();
+        let mut fs = LocalEncryptedFileSystem::new("test_dir_enc_verify_corrupt", true, key).unwrap();
+        fs.set_verify_writes(true);
+        // Corrupt the files between the write and the read back
+        fs.write_file("empty.dat", Vec::new()).unwrap();
+        fs.write_file("save.dat", b"progress".to_vec()).unwrap();
+        for file in ["empty.dat", "save.dat"] {
+            let full_path = format!("test_dir_enc_verify_corrupt/{}", file);
+            let mut stored = std::fs::read(&full_path).unwrap();
+            *stored.last_mut().unwrap() ^= 1;
+            std::fs::write(&full_path, stored).unwrap();
+        }
+        let empty = fs.verify_write("empty.dat", b"");
+        let save = fs.verify_write("save.dat", b"progress");
+        let left = (fs.is_file("empty.dat").unwrap(), fs.is_file("save.dat").unwrap());
+        std::fs::remove_dir_all("test_dir_enc_verify_corrupt").ok();
+
+        assert!(empty.is_err(), "A corrupted empty file should not verify");
+        assert!(save.is_err());
+        assert_eq!(left, (false, false), "Files that fail verification should be deleted");
+    }
+
+    #[test]
+    fn test_local_encrypted_read_range() {
+        let key = EncUtils::generate_random_key();
+        let mut fs = LocalEncryptedFileSystem::new("test_dir_enc_range", true, key).unwrap();
+        let content: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        let stored = fs.write_file("whole.bin", content.clone()).unwrap();
+        let whole = fs.read_range("whole.bin", 10, 20).unwrap();
+        fs.set_chunk_size(Some(1024)).unwrap();
+        fs.write_file("chunked.bin", content.clone()).unwrap();
+        let read = fs.read_file("chunked.bin").unwrap();
+        let listed = fs.list_files("").unwrap().into_iter().find(|f| f.name == "chunked.bin").map(|f| f.size);
+        let within = fs.read_range("chunked.bin", 100, 50).unwrap();
+        let across = fs.read_range("chunked.bin", 1000, 2100).unwrap();
+        let tail = fs.read_range("chunked.bin", 4990, 100).unwrap();
+        let past_end = fs.read_range("chunked.bin", 6000, 10).unwrap();
+        let invalid = fs.set_chunk_size(Some(1000));
+        std::fs::remove_dir_all("test_dir_enc_range").ok();
+
+        assert_eq!(stored, (content.len() + ENCRYPTION_OVERHEAD) as u64, "The ciphertext length should be reported");
+        assert_eq!(whole, &content[10..30]);
+        assert_eq!(read, content);
+        assert_eq!(listed, Some(content.len() as u64));
+        assert_eq!(within, &content[100..150]);
+        assert_eq!(across, &content[1000..3100]);
+        assert_eq!(tail, &content[4990..]);
+        assert!(past_end.is_empty());
+        assert!(invalid.is_err());
+        assert!(fs.capabilities().supports_random_access);
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs::File;
+use crate::core::FileSystemError;
+
+/// A read-only, private memory mapping of a whole file, unmapped on drop.
+///
+/// Only 64-bit Unix platforms are supported; `map` fails elsewhere. The mapping reflects
+/// the file as it is on disk, so `as_slice` is only sound while nobody truncates or
+/// rewrites the file, see `map`.
+pub(crate) struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// SAFETY: the mapping belongs to the process, not to the thread that made it, and this
+// value owns it until it is dropped, so it can be moved to and unmapped from any thread
+unsafe impl Send for Mmap {}
+// SAFETY: the mapping is `PROT_READ` and nothing writes through `ptr`, so any number of
+// threads can read the bytes behind `as_slice` at once
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps all of `file` for reading.
+    ///
+    /// # Safety
+    /// The file must not be truncated or modified, by this or any other process, while the
+    /// mapping lives. The bytes behind `as_slice` would change under a shared reference,
+    /// and reading a page past a new end of the file kills the process with `SIGBUS`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the platform is not supported or the mapping fails.
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    pub(crate) unsafe fn map(file: &File) -> Result<Self, FileSystemError> {
+        use std::ffi::{c_int, c_void};
+        use std::os::unix::io::AsRawFd;
+
+        const PROT_READ: c_int = 1;
+        const MAP_PRIVATE: c_int = 2;
+
+        unsafe extern "C" {
+            fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        }
+
+        let len = usize::try_from(file.metadata().map_err(FileSystemError::from)?.len())
+            .map_err(|_| FileSystemError::from("File is too large to map"))?;
+        // Empty mappings are rejected by mmap, and there is nothing to read anyway
+        if len == 0 {
+            return Ok(Mmap { ptr: std::ptr::NonNull::dangling().as_ptr(), len: 0 });
+        }
+        // SAFETY: a fresh read-only mapping of an open descriptor, which may be closed after
+        let ptr = unsafe { mmap(std::ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr as isize == -1 {
+            return Err(FileSystemError::from(std::io::Error::last_os_error()));
+        }
+        Ok(Mmap { ptr: ptr as *const u8, len })
+    }
+
+    /// # Safety
+    /// Nothing is mapped, so always safe; `unsafe` to match the supported platforms.
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    pub(crate) unsafe fn map(_file: &File) -> Result<Self, FileSystemError> {
+        Err(FileSystemError::from("Memory-mapped files are not supported on this platform"))
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` mapped bytes that live as long as `self`
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(all(unix, target_pointer_width = "64"))]
+        if self.len > 0 {
+            unsafe extern "C" {
+                fn munmap(addr: *mut std::ffi::c_void, len: usize) -> std::ffi::c_int;
+            }
+            // SAFETY: `ptr` and `len` describe a mapping made by `map` and not yet unmapped
+            unsafe { munmap(self.ptr as *mut std::ffi::c_void, self.len) };
+        }
+    }
+}
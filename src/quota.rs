@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use crate::{join_path, normalize_path, Capabilities, FileContent, FileInfo, FileSystem, FileSystemError, FileSystemErrorKind};
+
+/// Wraps a writable file system and caps the total size of the files in it.
+///
+/// The size of every file is recorded when the quota is created and kept up to date on
+/// every write and delete made through the wrapper. Writes that would take the total over
+/// `max_bytes` fail with `FileSystemErrorKind::QuotaExceeded` and leave the inner file
+/// system untouched. Overwriting a file only counts the difference in size. Changes made
+/// to the inner file system directly are not seen by the quota.
+pub struct QuotaFileSystem<T: FileSystem> {
+    inner: T,
+    max_bytes: u64,
+    /// Size of every file by normalized path
+    sizes: Mutex<HashMap<String, u64>>,
+}
+
+impl<T: FileSystem> QuotaFileSystem<T> {
+    /// Wraps `inner`, counting the files already in it against the quota.
+    ///
+    /// # Arguments
+    /// - _inner:_ The file system to limit.
+    /// - _max_bytes:_ The maximum total size of all files.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the existing files cannot be listed.
+    pub fn new(inner: T, max_bytes: u64) -> Result<Self, FileSystemError> {
+        let mut sizes = HashMap::new();
+        Self::collect_sizes(&inner, "", &mut sizes)?;
+        Ok(QuotaFileSystem { inner, max_bytes, sizes: Mutex::new(sizes) })
+    }
+
+    fn collect_sizes(inner: &T, directory: &str, sizes: &mut HashMap<String, u64>) -> Result<(), FileSystemError> {
+        for info in inner.list_files(directory)? {
+            let path = join_path(directory, &info.name);
+            if info.is_directory {
+                Self::collect_sizes(inner, &path, sizes)?;
+            } else {
+                sizes.insert(path, info.size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the total size of all files, in bytes.
+    pub fn usage(&self) -> u64 {
+        self.sizes.lock().unwrap_or_else(|e| e.into_inner()).values().sum()
+    }
+
+    /// Returns how many more bytes can be written before the quota is reached.
+    pub fn remaining(&self) -> u64 {
+        self.max_bytes.saturating_sub(self.usage())
+    }
+
+    /// Returns the configured quota, in bytes.
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Returns the wrapped file system.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn key(path: &str) -> String {
+        normalize_path(path).trim_start_matches("./").to_string()
+    }
+
+    /// Runs `operation` if giving `path` the size `new_size` stays within the quota, and
+    /// records the new size once it succeeds.
+    fn resize<R>(&self, path: &str, new_size: u64, operation: impl FnOnce() -> Result<R, FileSystemError>) -> Result<R, FileSystemError> {
+        let key = Self::key(path);
+        let mut sizes = self.sizes.lock().unwrap_or_else(|e| e.into_inner());
+        let current = sizes.get(&key).copied().unwrap_or(0);
+        let total: u64 = sizes.values().sum::<u64>() - current + new_size;
+        if total > self.max_bytes {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::QuotaExceeded,
+                &format!("Writing {} would use {} of {} bytes allowed", path, total, self.max_bytes),
+            ));
+        }
+        let result = operation()?;
+        sizes.insert(key, new_size);
+        Ok(result)
+    }
+}
+
+impl<T: FileSystem> FileSystem for QuotaFileSystem<T> {
+    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        self.inner.read_file(path)
+    }
+
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        self.inner.peek(path, n)
+    }
+
+    /// Fails with `FileSystemErrorKind::QuotaExceeded` if the write would exceed the quota.
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        self.resize(path, content.len() as u64, || self.inner.write_file(path, content))
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_file(path)?;
+        self.sizes.lock().unwrap_or_else(|e| e.into_inner()).remove(&Self::key(path));
+        Ok(())
+    }
+
+    /// Swapping leaves the total size unchanged, so it never exceeds the quota.
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.inner.swap_files(a, b)?;
+        let mut sizes = self.sizes.lock().unwrap_or_else(|e| e.into_inner());
+        let (a, b) = (Self::key(a), Self::key(b));
+        let (size_a, size_b) = (sizes.remove(&a), sizes.remove(&b));
+        if let Some(size) = size_b {
+            sizes.insert(a, size);
+        }
+        if let Some(size) = size_a {
+            sizes.insert(b, size);
+        }
+        Ok(())
+    }
+
+    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        self.inner.list_files(directory)
+    }
+
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_file(path)
+    }
+
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_dir(path)
+    }
+
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        let key = Self::key(path);
+        if self.sizes.lock().unwrap_or_else(|e| e.into_inner()).contains_key(&key) {
+            return self.inner.touch(path);
+        }
+        self.resize(path, 0, || self.inner.touch(path))
+    }
+
+    /// Fails with `FileSystemErrorKind::QuotaExceeded` if growing the file would exceed the quota.
+    fn truncate_file(&self, path: &str, len: u64) -> Result<(), FileSystemError> {
+        self.resize(path, len, || self.inner.truncate_file(path, len))
+    }
+
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_dir_recursive(path)?;
+        let prefix = Self::key(path).trim_end_matches('/').to_string();
+        let mut sizes = self.sizes.lock().unwrap_or_else(|e| e.into_inner());
+        if prefix.is_empty() || prefix == "." {
+            sizes.clear();
+        } else {
+            let prefix = format!("{}/", prefix);
+            sizes.retain(|key, _| !key.starts_with(&prefix));
+        }
+        Ok(())
+    }
+
+    /// Always fails: what is written through the returned writer could not be checked
+    /// against the quota.
+    fn open_append(&self, _path: &str) -> Result<Box<dyn std::io::Write + Send>, FileSystemError> {
+        Err(FileSystemError::from("Appending is not supported with a quota, write the whole file instead"))
+    }
+
+    /// Sizes do not change, the recorded ones just move to the new paths.
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        self.inner.rename_dir(from, to)?;
+        let from = format!("{}/", Self::key(from).trim_end_matches('/'));
+        let to = format!("{}/", Self::key(to).trim_end_matches('/'));
+        let mut sizes = self.sizes.lock().unwrap_or_else(|e| e.into_inner());
+        let moved: Vec<String> = sizes.keys().filter(|key| key.starts_with(&from)).cloned().collect();
+        for key in moved {
+            if let Some(size) = sizes.remove(&key) {
+                sizes.insert(format!("{}{}", to, &key[from.len()..]), size);
+            }
+        }
+        Ok(())
+    }
+
+    fn hash_file(&self, path: &str) -> Result<[u8; 32], FileSystemError> {
+        self.inner.hash_file(path)
+    }
+
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        self.inner.content_id(path)
+    }
+
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.sync(path)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            supports_append: false,
+            ..self.inner.capabilities()
+        }
+    }
+
+    fn root(&self) -> Option<&str> {
+        self.inner.root()
+    }
+
+    /// Returns the inner path. Writing to it directly bypasses the quota.
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        self.inner.real_path(path)
+    }
+
+    /// Returns what is left of the quota, or the space of the inner file system if that
+    /// is smaller.
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        let remaining = self.remaining();
+        Ok(Some(self.inner.available_space()?.map_or(remaining, |inner| inner.min(remaining))))
+    }
+}
+
+#[cfg(all(test, feature = "local"))]
+mod tests {
+    use super::*;
+    use crate::LocalFileSystem;
+
+    #[test]
+    fn test_quota_file_system() {
+        let local = LocalFileSystem::new("test_dir_quota", true).unwrap();
+        local.touch("existing/old.dat").unwrap();
+        local.write_file("existing/old.dat", vec![0u8; 40]).unwrap();
+        let quota = QuotaFileSystem::new(local, 100).unwrap();
+        let initial = quota.usage();
+        quota.write_file("a.dat", vec![1u8; 50]).unwrap();
+        let too_big = quota.write_file("b.dat", vec![2u8; 20]);
+        let b_exists = quota.read_file("b.dat").is_ok();
+        // Overwriting only counts the difference
+        quota.write_file("a.dat", vec![1u8; 60]).unwrap();
+        let full = quota.remaining();
+        let available = quota.available_space();
+        quota.delete_file("a.dat").unwrap();
+        let after_delete = quota.usage();
+        quota.delete_dir_recursive("existing").unwrap();
+        let after_clear = quota.usage();
+        std::fs::remove_dir_all("test_dir_quota").ok();
+
+        assert_eq!(initial, 40, "Existing files should count against the quota");
+        assert_eq!(too_big.unwrap_err().kind, FileSystemErrorKind::QuotaExceeded);
+        assert!(!b_exists, "A rejected write should not reach the inner file system");
+        assert_eq!(full, 0);
+        assert_eq!(available.unwrap(), Some(0));
+        assert_eq!(after_delete, 40);
+        assert_eq!(after_clear, 0);
+    }
+}
@@ -0,0 +1,246 @@
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::{Capabilities, FileContent, FileInfo, FileSystem, FileSystemError};
+
+/// When `RetryingFileSystem` tries a failed read or write again.
+///
+/// Only errors caused by an `std::io::Error` whose kind is in `retry_on` are retried;
+/// anything else, e.g. a missing file or a denied permission, is returned at once. The
+/// wait before each retry starts at `initial_backoff` and doubles every time, up to
+/// `max_backoff`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times an operation is tried in total, including the first try.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry.
+    pub initial_backoff: Duration,
+    /// The longest wait between two tries.
+    pub max_backoff: Duration,
+    /// The kinds of IO error that are worth trying again.
+    pub retry_on: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, waiting 50 ms and then 100 ms, on `Interrupted`, `WouldBlock` and
+    /// `TimedOut` errors.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            retry_on: vec![ErrorKind::Interrupted, ErrorKind::WouldBlock, ErrorKind::TimedOut],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns `true` if `error` is transient according to this policy.
+    pub fn should_retry(&self, error: &FileSystemError) -> bool {
+        error.io_error().is_some_and(|error| self.retry_on.contains(&error.kind()))
+    }
+}
+
+/// A file system that retries `read_file` and `write_file` on transient errors, e.g. for
+/// a backend on a network share or a flaky removable drive.
+///
+/// Which errors are retried, how often and how long to wait in between is set by a
+/// `RetryPolicy`. Every other method is passed to the inner file system as it is, so a
+/// failed `delete_file` or `list_files` is not tried again.
+pub struct RetryingFileSystem<T: FileSystem> {
+    inner: T,
+    policy: RetryPolicy,
+}
+
+impl<T: FileSystem> RetryingFileSystem<T> {
+    /// Wraps `inner`, retrying with the default `RetryPolicy`.
+    ///
+    /// # Arguments
+    /// - _inner:_ The file system to retry operations on.
+    pub fn new(inner: T) -> Self {
+        Self::with_policy(inner, RetryPolicy::default())
+    }
+
+    /// Wraps `inner`, retrying as `policy` says.
+    ///
+    /// # Arguments
+    /// - _inner:_ The file system to retry operations on.
+    /// - _policy:_ Which errors to retry and how.
+    pub fn with_policy(inner: T, policy: RetryPolicy) -> Self {
+        RetryingFileSystem { inner, policy }
+    }
+
+    /// Returns the retry policy.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Returns the wrapped file system.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error that is not retried, or
+    /// has been tried `max_attempts` times, returning the last result.
+    fn retry<R>(&self, mut operation: impl FnMut() -> Result<R, FileSystemError>) -> Result<R, FileSystemError> {
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Err(error) if attempt < self.policy.max_attempts && self.policy.should_retry(&error) => {
+                    std::thread::sleep(backoff.min(self.policy.max_backoff));
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<T: FileSystem> FileSystem for RetryingFileSystem<T> {
+    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        self.retry(|| self.inner.read_file(path))
+    }
+
+    /// The content is copied for every attempt, since the inner file system takes it.
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        self.retry(|| self.inner.write_file(path, content.clone()))
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_file(path)
+    }
+
+    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        self.inner.list_files(directory)
+    }
+
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        self.inner.peek(path, n)
+    }
+
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_file(path)
+    }
+
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_dir(path)
+    }
+
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.touch(path)
+    }
+
+    fn truncate_file(&self, path: &str, len: u64) -> Result<(), FileSystemError> {
+        self.inner.truncate_file(path, len)
+    }
+
+    fn open_append(&self, path: &str) -> Result<Box<dyn Write + Send>, FileSystemError> {
+        self.inner.open_append(path)
+    }
+
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_dir_recursive(path)
+    }
+
+    fn empty_dir(&self, directory: &str) -> Result<usize, FileSystemError> {
+        self.inner.empty_dir(directory)
+    }
+
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        self.inner.rename_dir(from, to)
+    }
+
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.inner.swap_files(a, b)
+    }
+
+    fn hash_file(&self, path: &str) -> Result<[u8; 32], FileSystemError> {
+        self.inner.hash_file(path)
+    }
+
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        self.inner.content_id(path)
+    }
+
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.sync(path)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root(&self) -> Option<&str> {
+        self.inner.root()
+    }
+
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        self.inner.real_path(path)
+    }
+
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        self.inner.available_space()
+    }
+}
+
+#[cfg(all(test, feature = "local"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::LocalFileSystem;
+
+    /// Fails the first `failures` reads with `ErrorKind::Interrupted`.
+    struct Flaky {
+        inner: LocalFileSystem,
+        failures: usize,
+        reads: AtomicUsize,
+    }
+
+    impl FileSystem for Flaky {
+        fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+            if self.reads.fetch_add(1, Ordering::Relaxed) < self.failures {
+                return Err(FileSystemError::from(std::io::Error::from(ErrorKind::Interrupted)));
+            }
+            self.inner.read_file(path)
+        }
+
+        fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+            self.inner.write_file(path, content)
+        }
+
+        fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
+            self.inner.delete_file(path)
+        }
+
+        fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+            self.inner.list_files(directory)
+        }
+    }
+
+    #[test]
+    fn test_retrying_file_system() {
+        let policy = RetryPolicy { initial_backoff: Duration::ZERO, ..RetryPolicy::default() };
+        let flaky = |failures| Flaky {
+            inner: LocalFileSystem::new("test_dir_retrying", true).unwrap(),
+            failures,
+            reads: AtomicUsize::new(0),
+        };
+        let recovers = RetryingFileSystem::with_policy(flaky(2), policy.clone());
+        recovers.write_file("a.txt", b"data".to_vec()).unwrap();
+        let recovered = recovers.read_file("a.txt");
+        let gives_up = RetryingFileSystem::with_policy(flaky(3), policy.clone());
+        let exhausted = gives_up.read_file("a.txt");
+        let missing = RetryingFileSystem::with_policy(flaky(0), policy);
+        let not_found = missing.read_file("missing.txt");
+        std::fs::remove_dir_all("test_dir_retrying").ok();
+
+        assert_eq!(recovered.unwrap(), b"data");
+        assert_eq!(recovers.inner().reads.load(Ordering::Relaxed), 3);
+        assert!(exhausted.is_err(), "Should give up after max_attempts");
+        assert_eq!(gives_up.inner().reads.load(Ordering::Relaxed), 3);
+        assert!(not_found.is_err());
+        assert_eq!(missing.inner().reads.load(Ordering::Relaxed), 1, "NotFound should not be retried");
+    }
+}
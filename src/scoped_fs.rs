@@ -0,0 +1,171 @@
+use std::io::Write;
+use std::path::PathBuf;
+use crate::{join_path, normalize_path, Capabilities, FileContent, FileInfo, FileSystem, FileSystemError};
+
+/// A view of one directory of another file system, like a lightweight bind mount.
+///
+/// Every path is resolved below the directory, so `textures/a.png` in a view scoped to
+/// `assets` is `assets/textures/a.png` in the underlying file system, and listings report
+/// paths relative to the view. Paths with a `..` component are rejected, so code handed a
+/// view cannot reach anything outside it. Create one with `FileSystem::scoped` or `new`.
+pub struct ScopedFileSystem<'a> {
+    inner: &'a dyn FileSystem,
+    prefix: String,
+}
+
+impl<'a> ScopedFileSystem<'a> {
+    /// Creates a view of `prefix` in `inner`.
+    ///
+    /// # Arguments
+    /// - _inner:_ The file system to view.
+    /// - _prefix:_ The directory the view is rooted at, empty for the root of `inner`.
+    ///
+    /// # Errors
+    /// `FileSystemError` if `prefix` has a `..` component.
+    pub fn new(inner: &'a dyn FileSystem, prefix: &str) -> Result<Self, FileSystemError> {
+        let prefix = Self::clean(prefix)?;
+        Ok(ScopedFileSystem { inner, prefix })
+    }
+
+    /// Returns the directory of the underlying file system the view is rooted at.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Normalizes a path to its components joined by `/`, dropping empty and `.` ones.
+    fn clean(path: &str) -> Result<String, FileSystemError> {
+        let path = normalize_path(path);
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+        if components.contains(&"..") {
+            return Err(FileSystemError::from(format!("Path is outside the scoped directory: {}", path)));
+        }
+        Ok(components.join("/"))
+    }
+
+    /// Resolves a path of the view to the path in the underlying file system.
+    fn inner_path(&self, path: &str) -> Result<String, FileSystemError> {
+        let path = Self::clean(path)?;
+        if path.is_empty() {
+            return Ok(self.prefix.clone());
+        }
+        Ok(join_path(&self.prefix, &path))
+    }
+}
+
+impl FileSystem for ScopedFileSystem<'_> {
+    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        self.inner.read_file(&self.inner_path(path)?)
+    }
+
+    fn read_into(&self, path: &str, buf: &mut Vec<u8>) -> Result<usize, FileSystemError> {
+        self.inner.read_into(&self.inner_path(path)?, buf)
+    }
+
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        self.inner.peek(&self.inner_path(path)?, n)
+    }
+
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        self.inner.write_file(&self.inner_path(path)?, content)
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_file(&self.inner_path(path)?)
+    }
+
+    /// Lists `directory` in the underlying file system, with paths relative to the view.
+    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let directory = Self::clean(directory)?;
+        // Backends differ in what they put in `path`, so rebuild it from the name
+        let mut files = self.inner.list_files(&self.inner_path(&directory)?)?;
+        for file in files.iter_mut() {
+            file.path = join_path(&directory, &file.name);
+        }
+        Ok(files)
+    }
+
+    fn is_file(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_file(&self.inner_path(path)?)
+    }
+
+    fn is_dir(&self, path: &str) -> Result<bool, FileSystemError> {
+        self.inner.is_dir(&self.inner_path(path)?)
+    }
+
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.touch(&self.inner_path(path)?)
+    }
+
+    fn truncate_file(&self, path: &str, len: u64) -> Result<(), FileSystemError> {
+        self.inner.truncate_file(&self.inner_path(path)?, len)
+    }
+
+    fn open_append(&self, path: &str) -> Result<Box<dyn Write + Send>, FileSystemError> {
+        self.inner.open_append(&self.inner_path(path)?)
+    }
+
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.delete_dir_recursive(&self.inner_path(path)?)
+    }
+
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        self.inner.rename_dir(&self.inner_path(from)?, &self.inner_path(to)?)
+    }
+
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        self.inner.swap_files(&self.inner_path(a)?, &self.inner_path(b)?)
+    }
+
+    fn hash_file(&self, path: &str) -> Result<[u8; 32], FileSystemError> {
+        self.inner.hash_file(&self.inner_path(path)?)
+    }
+
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        self.inner.content_id(&self.inner_path(path)?)
+    }
+
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        self.inner.sync(&self.inner_path(path)?)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    /// Returns `None` for paths with a `..` component, like every other method rejects them.
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        self.inner.real_path(&self.inner_path(path).ok()?)
+    }
+
+    fn available_space(&self) -> Result<Option<u64>, FileSystemError> {
+        self.inner.available_space()
+    }
+}
+
+#[cfg(all(test, feature = "local"))]
+mod tests {
+    use super::*;
+    use crate::LocalFileSystem;
+
+    #[test]
+    fn test_scoped_file_system() {
+        let project = LocalFileSystem::new("test_dir_scoped", true).unwrap();
+        project.write_file("secrets.txt", b"secret".to_vec()).unwrap();
+        let assets = project.scoped("/assets/").unwrap();
+        assets.write_file("textures/a.png", b"png".to_vec()).unwrap();
+        let on_disk = std::fs::read("test_dir_scoped/assets/textures/a.png");
+        let listed: Vec<String> = assets.list_files("textures").unwrap().into_iter().map(|f| f.path).collect();
+        let root: Vec<String> = assets.list_files("").unwrap().into_iter().map(|f| f.path).collect();
+        let escaped = assets.read_file("../secrets.txt");
+        let nested_escape = assets.read_file("textures/../../secrets.txt");
+        let invalid_scope = project.scoped("assets/../..");
+        std::fs::remove_dir_all("test_dir_scoped").ok();
+
+        assert_eq!(on_disk.unwrap(), b"png");
+        assert_eq!(listed, ["textures/a.png"]);
+        assert_eq!(root, ["textures"]);
+        assert!(escaped.is_err(), "A view should not reach outside its directory");
+        assert!(nested_escape.is_err());
+        assert!(invalid_scope.is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use crate::{ArchiveCreator, ArchiveFileSystem, EncKey, FileSystem, FileSystemError};
+use crate::archive::safe_relative_path;
+
+/// Tar files are made of 512-byte blocks, headers and content alike
+const BLOCK_SIZE: usize = 512;
+const NAME_SIZE: usize = 100;
+const PREFIX_SIZE: usize = 155;
+const TYPE_REGULAR: u8 = b'0';
+const TYPE_DIRECTORY: u8 = b'5';
+const TYPE_PAX: u8 = b'x';
+const TYPE_PAX_GLOBAL: u8 = b'g';
+const TYPE_GNU_LONG_NAME: u8 = b'L';
+
+/// Summary of a tar import.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TarImportReport {
+    /// Files packed into the archive
+    pub imported: usize,
+    /// Tar entries that were left out, each with the reason, e.g. links, devices and fifos
+    pub skipped: Vec<String>,
+}
+
+impl ArchiveCreator {
+    /// Packs the regular files of a tar file into a new encrypted archive.
+    ///
+    /// Paths inside the tar are kept. Entries that are not regular files, such as links,
+    /// devices and fifos, and entries whose path would leave the archive root are skipped
+    /// and listed in the report instead of failing the import. Directories need no entry of
+    /// their own in an archive. The tar is unpacked to a staging directory next to `output`,
+    /// which is removed afterwards.
+    ///
+    /// # Arguments
+    /// - _tar_path:_ Path of the ustar, pax or GNU tar file to read.
+    /// - _output:_ Path of the archive to create. It must not exist yet.
+    /// - _key:_ The key to encrypt the archive with.
+    ///
+    /// # Returns
+    /// How many files were imported and which entries were skipped.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the tar file is malformed or the archive cannot be created,
+    /// e.g. because the tar contains no regular files.
+    pub fn from_tar(tar_path: &str, output: &str, key: EncKey) -> Result<TarImportReport, FileSystemError> {
+        ArchiveCreator::from_unpacked(output, key, |staging| extract_tar(Path::new(tar_path), staging))
+    }
+}
+
+impl ArchiveFileSystem {
+    /// Writes the decrypted contents of the archive to a tar file.
+    ///
+    /// The output is a plain ustar file that standard tools can extract, with every file at
+    /// its archive path and its stored modification time. Paths too long for a ustar header
+    /// are written with a pax extended header.
+    ///
+    /// # Arguments
+    /// - _output:_ Path of the tar file to create. An existing file is overwritten.
+    ///
+    /// # Returns
+    /// The number of files written.
+    ///
+    /// # Errors
+    /// `FileSystemError` if a file cannot be read from the archive or the tar cannot be written.
+    pub fn export_tar(&self, output: &str) -> Result<usize, FileSystemError> {
+        let mut writer = BufWriter::new(File::create(output).map_err(FileSystemError::from)?);
+        let mut count = 0;
+        for info in self.walk("") {
+            let info = info?;
+            let content = self.read_file(&info.path)?;
+            let modified = info.modified
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            write_entry(&mut writer, &info.path, TYPE_REGULAR, &content, modified)?;
+            count += 1;
+        }
+        // Two zero blocks mark the end of the tar
+        writer.write_all(&[0u8; 2 * BLOCK_SIZE]).map_err(FileSystemError::from)?;
+        writer.flush().map_err(FileSystemError::from)?;
+        Ok(count)
+    }
+}
+
+/// Unpacks the regular files of a tar into `directory`.
+fn extract_tar(tar_path: &Path, directory: &Path) -> Result<TarImportReport, FileSystemError> {
+    let mut reader = BufReader::new(File::open(tar_path).map_err(FileSystemError::from)?);
+    let mut report = TarImportReport::default();
+    // Path from a pax or GNU long name entry, which applies to the entry after it
+    let mut long_name: Option<String> = None;
+    loop {
+        let mut header = [0u8; BLOCK_SIZE];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            // Some writers leave out the end-of-archive blocks
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(FileSystemError::from(e)),
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if parse_number(&header[148..156])? != checksum(&header) {
+            return Err(FileSystemError::from("Invalid tar header checksum"));
+        }
+        let size = parse_number(&header[124..136])?;
+        let typeflag = header[156];
+        let padding = size.next_multiple_of(BLOCK_SIZE as u64) - size;
+        let mut content = (&mut reader).take(size);
+        match typeflag {
+            TYPE_REGULAR | 0 | b'7' => {
+                let name = long_name.take().unwrap_or_else(|| header_path(&header));
+                match safe_relative_path(&name) {
+                    Some(relative) => {
+                        let path = directory.join(relative);
+                        if let Some(parent) = path.parent() {
+                            std::fs::create_dir_all(parent).map_err(FileSystemError::from)?;
+                        }
+                        let mut file = File::create(&path).map_err(FileSystemError::from)?;
+                        std::io::copy(&mut content, &mut file).map_err(FileSystemError::from)?;
+                        let modified = parse_number(&header[136..148])?;
+                        // A time past what `SystemTime` can hold is left unset
+                        if let Some(modified) = UNIX_EPOCH.checked_add(Duration::from_secs(modified)) {
+                            file.set_modified(modified).ok();
+                        }
+                        report.imported += 1;
+                    }
+                    None => report.skipped.push(format!("{}: path leaves the archive root", name)),
+                }
+            }
+            TYPE_PAX | TYPE_GNU_LONG_NAME => {
+                let mut data = Vec::new();
+                content.read_to_end(&mut data).map_err(FileSystemError::from)?;
+                long_name = if typeflag == TYPE_PAX { pax_path(&data) } else { Some(c_string(&data)) };
+            }
+            TYPE_DIRECTORY | TYPE_PAX_GLOBAL => {
+                long_name = None;
+            }
+            other => {
+                let name = long_name.take().unwrap_or_else(|| header_path(&header));
+                report.skipped.push(format!("{}: unsupported tar entry type '{}'", name, other as char));
+            }
+        }
+        // Skip whatever was not consumed, plus the padding up to the next block
+        std::io::copy(&mut content, &mut std::io::sink()).map_err(FileSystemError::from)?;
+        std::io::copy(&mut (&mut reader).take(padding), &mut std::io::sink()).map_err(FileSystemError::from)?;
+    }
+    Ok(report)
+}
+
+/// Writes one tar entry, preceded by a pax header if the path does not fit in ustar.
+fn write_entry(writer: &mut impl Write, path: &str, typeflag: u8, content: &[u8], modified: u64) -> Result<(), FileSystemError> {
+    if split_path(path).is_none() {
+        let record = format!(" path={}\n", path);
+        // The record length includes its own digits
+        let mut length = record.len() + 1;
+        while length != record.len() + length.to_string().len() {
+            length += 1;
+        }
+        let record = format!("{}{}", length, record);
+        write_entry(writer, "PaxHeader", TYPE_PAX, record.as_bytes(), modified)?;
+    }
+    let header = build_header(path, typeflag, content.len() as u64, modified);
+    writer.write_all(&header).map_err(FileSystemError::from)?;
+    writer.write_all(content).map_err(FileSystemError::from)?;
+    let padding = (content.len() as u64).next_multiple_of(BLOCK_SIZE as u64) - content.len() as u64;
+    writer.write_all(&vec![0u8; padding as usize]).map_err(FileSystemError::from)
+}
+
+/// Builds a ustar header; a path that does not fit is truncated, see `write_entry`.
+fn build_header(path: &str, typeflag: u8, size: u64, modified: u64) -> [u8; BLOCK_SIZE] {
+    let mut header = [0u8; BLOCK_SIZE];
+    let (prefix, name) = split_path(path).unwrap_or(("", path));
+    let name = &name.as_bytes()[..name.len().min(NAME_SIZE)];
+    header[..name.len()].copy_from_slice(name);
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_number(&mut header[124..136], size);
+    write_number(&mut header[136..148], modified);
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let sum = checksum(&header);
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+/// Splits a path into the ustar prefix and name fields, if it fits.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= NAME_SIZE {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= PREFIX_SIZE && !name.is_empty() && name.len() <= NAME_SIZE)
+}
+
+/// Sums the header bytes with the checksum field counted as spaces.
+fn checksum(header: &[u8; BLOCK_SIZE]) -> u64 {
+    header.iter().enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum()
+}
+
+/// Writes a zero-padded octal number followed by a NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+/// Writes a number as octal, or in the base-256 extension if it is too large.
+fn write_number(field: &mut [u8], value: u64) {
+    if value < 1 << (3 * (field.len() - 1)) {
+        write_octal(field, value);
+    } else {
+        field.fill(0);
+        let bytes = value.to_be_bytes();
+        let start = field.len() - bytes.len();
+        field[start..].copy_from_slice(&bytes);
+        field[0] |= 0x80;
+    }
+}
+
+/// Parses an octal or base-256 numeric header field.
+fn parse_number(field: &[u8]) -> Result<u64, FileSystemError> {
+    if field[0] & 0x80 != 0 {
+        let mut value = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            value = value.checked_mul(256).and_then(|value| value.checked_add(b as u64)).ok_or_else(|| {
+                FileSystemError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, "Tar header number is too large"))
+            })?;
+        }
+        return Ok(value);
+    }
+    let digits = c_string(field);
+    let digits = digits.trim_matches(' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| FileSystemError::from(format!("Invalid number in tar header: {}", digits)))
+}
+
+/// Returns the path of a ustar header, joining the prefix and name fields.
+fn header_path(header: &[u8; BLOCK_SIZE]) -> String {
+    let name = c_string(&header[..NAME_SIZE]);
+    let prefix = c_string(&header[345..345 + PREFIX_SIZE]);
+    if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+}
+
+/// Returns the `path` record of pax extended header data, if there is one.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let data = String::from_utf8_lossy(data);
+    data.lines()
+        .filter_map(|record| record.split_once(' ')?.1.split_once('='))
+        .find(|(key, _)| *key == "path")
+        .map(|(_, value)| value.to_string())
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::EncUtils;
+
+    #[test]
+    fn test_tar_round_trip() {
+        let source = "test_tar_source";
+        std::fs::create_dir_all(format!("{}/levels", source)).unwrap();
+        std::fs::write(format!("{}/hero.txt", source), b"hero").unwrap();
+        std::fs::write(format!("{}/levels/one.dat", source), b"level one").unwrap();
+        let key = EncUtils::generate_random_key();
+        ArchiveCreator::new(source, "test_tar.arc", key.clone(), true).unwrap().create().unwrap();
+        let archive = ArchiveFileSystem::open(PathBuf::from("test_tar.arc"), key.clone()).unwrap();
+        let exported = archive.export_tar("test_tar.tar");
+
+        // Append a symlink and an entry escaping the root, in place of the end blocks
+        let mut tar = std::fs::read("test_tar.tar").unwrap();
+        tar.truncate(tar.len() - 2 * BLOCK_SIZE);
+        write_entry(&mut tar, "link", b'2', b"", 0).unwrap();
+        write_entry(&mut tar, "../escape.txt", TYPE_REGULAR, b"escape", 0).unwrap();
+        let long_path = format!("{}/long.txt", "deep/".repeat(30).trim_end_matches('/'));
+        write_entry(&mut tar, &long_path, TYPE_REGULAR, b"long", 0).unwrap();
+        std::fs::write("test_tar_extra.tar", &tar).unwrap();
+        std::fs::remove_file("test_tar_imported.arc").ok();
+        let report = ArchiveCreator::from_tar("test_tar_extra.tar", "test_tar_imported.arc", key.clone());
+        let imported = ArchiveFileSystem::open(PathBuf::from("test_tar_imported.arc"), key);
+        let contents = imported.as_ref().map(|fs| fs.read_files(&["hero.txt", "levels/one.dat", &long_path]));
+        std::fs::remove_dir_all(source).ok();
+        for file in ["test_tar.arc", "test_tar.tar", "test_tar_extra.tar", "test_tar_imported.arc"] {
+            std::fs::remove_file(file).ok();
+        }
+
+        assert_eq!(exported.unwrap(), 2);
+        let report = report.unwrap();
+        assert_eq!(report.imported, 3);
+        assert_eq!(report.skipped.len(), 2, "Unexpected skipped entries: {:?}", report.skipped);
+        let contents = contents.unwrap().unwrap();
+        assert_eq!(contents["hero.txt"], b"hero");
+        assert_eq!(contents["levels/one.dat"], b"level one");
+        assert_eq!(contents[long_path.as_str()], b"long");
+    }
+
+    #[test]
+    fn test_tar_existing_staging_directory() {
+        std::fs::create_dir_all("test_tar_staging.arc.import").unwrap();
+        std::fs::write("test_tar_staging.arc.import/mine.txt", b"mine").unwrap();
+        std::fs::write("test_tar_staging.tar", [0u8; 2 * BLOCK_SIZE]).unwrap();
+        let imported = ArchiveCreator::from_tar("test_tar_staging.tar", "test_tar_staging.arc", EncUtils::generate_random_key());
+        let kept = std::fs::read("test_tar_staging.arc.import/mine.txt");
+        std::fs::remove_dir_all("test_tar_staging.arc.import").ok();
+        std::fs::remove_file("test_tar_staging.tar").ok();
+        std::fs::remove_file("test_tar_staging.arc").ok();
+
+        assert!(imported.is_err(), "An existing staging directory should not be reused");
+        assert_eq!(kept.unwrap(), b"mine", "An existing staging directory should be left alone");
+    }
+
+    #[test]
+    fn test_tar_oversized_mtime() {
+        // Base-256 mtime fields past u64, at u64::MAX, and past what `SystemTime` holds
+        let mut overflowing = [0xffu8; 12];
+        overflowing[0] = 0x80 | 0x7f;
+        let mut largest = [0u8; 12];
+        largest[0] = 0x80;
+        largest[4..].fill(0xff);
+        let mut far_future = [0u8; 12];
+        far_future[0] = 0x80;
+        far_future[4] = 0x7f;
+        far_future[5..].fill(0xff);
+        let mut results = Vec::new();
+        for (i, mtime) in [overflowing, largest, far_future].iter().enumerate() {
+            let mut header = build_header("file.txt", TYPE_REGULAR, 4, 0);
+            header[136..148].copy_from_slice(mtime);
+            let sum = checksum(&header);
+            header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+            let mut tar = header.to_vec();
+            tar.extend_from_slice(b"data");
+            tar.resize(tar.len() + BLOCK_SIZE - 4 + 2 * BLOCK_SIZE, 0);
+            let tar_path = format!("test_tar_mtime_{}.tar", i);
+            let directory = format!("test_tar_mtime_{}", i);
+            std::fs::write(&tar_path, tar).unwrap();
+            results.push(extract_tar(Path::new(&tar_path), Path::new(&directory)));
+            std::fs::remove_file(&tar_path).ok();
+            std::fs::remove_dir_all(&directory).ok();
+        }
+
+        let error = results[0].as_ref().err().and_then(|e| e.io_error()).map(|e| e.kind());
+        assert_eq!(error, Some(std::io::ErrorKind::InvalidData), "A number past u64 should be invalid data");
+        assert_eq!(results[1].as_ref().unwrap().imported, 1, "An unrepresentable mtime should be skipped");
+        assert_eq!(results[2].as_ref().unwrap().imported, 1);
+    }
+}
@@ -0,0 +1,268 @@
+use std::path::PathBuf;
+use crate::{join_path, normalize_path, swap_by_copy, FileContent, FileInfo, FileSystem, FileSystemError, FileSystemErrorKind};
+
+/// A file system that mounts other file systems under path prefixes.
+///
+/// Every operation is routed to the mount with the longest prefix matching the path, with
+/// the prefix stripped, so mounting an archive at `assets` and a local directory at `save`
+/// makes `assets/ui/button.png` read `ui/button.png` from the archive. A mount at the empty
+/// prefix catches every path no other mount matches. Mount points show up as directories
+/// when listing their parent.
+#[derive(Default)]
+pub struct VirtualFileSystem {
+    mounts: Vec<(String, Box<dyn FileSystem>)>,
+}
+
+impl VirtualFileSystem {
+    /// Creates a `VirtualFileSystem` with nothing mounted.
+    pub fn new() -> Self {
+        VirtualFileSystem::default()
+    }
+
+    /// Mounts a file system under a prefix.
+    ///
+    /// # Arguments
+    /// - _prefix:_ The directory the file system appears under, e.g. `assets` or `assets/dlc`.
+    ///   Leading and trailing slashes are ignored, and an empty prefix mounts at the root.
+    /// - _file_system:_ The file system to mount.
+    ///
+    /// # Errors
+    /// `FileSystemError` if something is already mounted at the prefix.
+    pub fn mount(&mut self, prefix: &str, file_system: Box<dyn FileSystem>) -> Result<(), FileSystemError> {
+        let prefix = Self::clean(prefix);
+        if self.mounts.iter().any(|(mounted, _)| *mounted == prefix) {
+            return Err(FileSystemError::from(format!("A file system is already mounted at '{}'", prefix)));
+        }
+        self.mounts.push((prefix, file_system));
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at `prefix`.
+    ///
+    /// # Returns
+    /// The unmounted file system, or `None` if nothing was mounted there.
+    pub fn unmount(&mut self, prefix: &str) -> Option<Box<dyn FileSystem>> {
+        let prefix = Self::clean(prefix);
+        let index = self.mounts.iter().position(|(mounted, _)| *mounted == prefix)?;
+        Some(self.mounts.remove(index).1)
+    }
+
+    /// Returns the mounted prefixes, in mount order.
+    pub fn mount_points(&self) -> Vec<&str> {
+        self.mounts.iter().map(|(prefix, _)| prefix.as_str()).collect()
+    }
+
+    fn clean(path: &str) -> String {
+        normalize_path(path).trim_matches('/').to_string()
+    }
+
+    /// Finds the mount with the longest prefix containing `path`.
+    ///
+    /// # Returns
+    /// The mount prefix, its file system and `path` relative to it.
+    fn resolve(&self, path: &str) -> Option<(&str, &dyn FileSystem, String)> {
+        let path = Self::clean(path);
+        self.mounts.iter()
+            .filter_map(|(prefix, file_system)| {
+                let relative = if prefix.is_empty() {
+                    path.as_str()
+                } else if path == *prefix {
+                    ""
+                } else {
+                    path.strip_prefix(prefix.as_str())?.strip_prefix('/')?
+                };
+                Some((prefix.as_str(), file_system.as_ref(), relative.to_string()))
+            })
+            .max_by_key(|(prefix, _, _)| prefix.len())
+    }
+
+    fn resolve_or_err(&self, path: &str) -> Result<(&dyn FileSystem, String), FileSystemError> {
+        self.resolve(path)
+            .map(|(_, file_system, relative)| (file_system, relative))
+            .ok_or_else(|| FileSystemError::new(FileSystemErrorKind::NotFound, &format!("No file system mounted for path: {}", path)))
+    }
+}
+
+impl FileSystem for VirtualFileSystem {
+    fn read_file(&self, path: &str) -> Result<FileContent, FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.read_file(&relative)
+    }
+
+    fn read_into(&self, path: &str, buf: &mut Vec<u8>) -> Result<usize, FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.read_into(&relative, buf)
+    }
+
+    fn peek(&self, path: &str, n: usize) -> Result<FileContent, FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.peek(&relative, n)
+    }
+
+    fn write_file(&self, path: &str, content: FileContent) -> Result<u64, FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.write_file(&relative, content)
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.delete_file(&relative)
+    }
+
+    /// Delegates to the mount when both files are in the same one, with its guarantees.
+    /// Files in different mounts are swapped by copying, as by the default `swap_files`.
+    fn swap_files(&self, a: &str, b: &str) -> Result<(), FileSystemError> {
+        let resolved = (self.resolve(a), self.resolve(b));
+        match resolved {
+            (Some((prefix_a, file_system, relative_a)), Some((prefix_b, _, relative_b))) if prefix_a == prefix_b => {
+                file_system.swap_files(&relative_a, &relative_b)
+            }
+            _ => swap_by_copy(self, a, b),
+        }
+    }
+
+    /// Lists `directory` in the mount it belongs to, with paths relative to this file
+    /// system, plus any mount points directly inside it.
+    fn list_files(&self, directory: &str) -> Result<Vec<FileInfo>, FileSystemError> {
+        let directory = Self::clean(directory);
+        let resolved = self.resolve(&directory);
+        let mounted = resolved.is_some();
+        let mut file_infos = match resolved {
+            Some((_, file_system, relative)) => {
+                // Backends differ in what they put in `path`, so rebuild it from the name
+                let mut files = file_system.list_files(&relative)?;
+                for file in files.iter_mut() {
+                    file.path = join_path(&directory, &file.name);
+                }
+                files
+            }
+            None => Vec::new(),
+        };
+        let mut found_mount = false;
+        for (prefix, _) in &self.mounts {
+            let child = if directory.is_empty() {
+                Some(prefix.as_str())
+            } else {
+                prefix.strip_prefix(directory.as_str()).and_then(|rest| rest.strip_prefix('/'))
+            };
+            let Some(child) = child.filter(|child| !child.is_empty()) else {
+                continue;
+            };
+            found_mount = true;
+            let name = child.split('/').next().unwrap_or(child);
+            let path = join_path(&directory, name);
+            if !file_infos.iter().any(|f| f.path == path) {
+                file_infos.push(FileInfo {
+                    name: name.to_string(),
+                    path,
+                    is_directory: true,
+                    size: 0,
+                    modified: None,
+                    created: None,
+                    is_symlink: false,
+                });
+            }
+        }
+        if !mounted && !found_mount {
+            return Err(FileSystemError::new(FileSystemErrorKind::NotFound, &format!("No file system mounted for path: {}", directory)));
+        }
+        Ok(file_infos)
+    }
+
+    fn touch(&self, path: &str) -> Result<(), FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.touch(&relative)
+    }
+
+    fn truncate_file(&self, path: &str, len: u64) -> Result<(), FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.truncate_file(&relative, len)
+    }
+
+    fn delete_dir_recursive(&self, path: &str) -> Result<(), FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.delete_dir_recursive(&relative)
+    }
+
+    fn open_append(&self, path: &str) -> Result<Box<dyn std::io::Write + Send>, FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.open_append(&relative)
+    }
+
+    /// Both paths must resolve to the same mount; moving between mounts is not a rename.
+    fn rename_dir(&self, from: &str, to: &str) -> Result<(), FileSystemError> {
+        let (from_prefix, file_system, from_relative) = self.resolve(from)
+            .ok_or_else(|| FileSystemError::new(FileSystemErrorKind::NotFound, &format!("No file system mounted for path: {}", from)))?;
+        match self.resolve(to) {
+            Some((to_prefix, _, to_relative)) if to_prefix == from_prefix => file_system.rename_dir(&from_relative, &to_relative),
+            _ => Err(FileSystemError::from(format!("Cannot rename {} to {}, they are on different mounts", from, to))),
+        }
+    }
+
+    fn sync(&self, path: &str) -> Result<(), FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.sync(&relative)
+    }
+
+    fn hash_file(&self, path: &str) -> Result<[u8; 32], FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.hash_file(&relative)
+    }
+
+    fn content_id(&self, path: &str) -> Result<String, FileSystemError> {
+        let (file_system, relative) = self.resolve_or_err(path)?;
+        file_system.content_id(&relative)
+    }
+
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        let (_, file_system, relative) = self.resolve(path)?;
+        file_system.real_path(&relative)
+    }
+}
+
+#[cfg(all(test, feature = "local"))]
+mod tests {
+    use super::*;
+    use crate::LocalFileSystem;
+
+    #[test]
+    fn test_virtual_file_system() {
+        let mut vfs = VirtualFileSystem::new();
+        vfs.mount("assets", Box::new(LocalFileSystem::new("test_vfs_assets", true).unwrap())).unwrap();
+        vfs.mount("assets/dlc", Box::new(LocalFileSystem::new("test_vfs_dlc", true).unwrap())).unwrap();
+        vfs.mount("/save/", Box::new(LocalFileSystem::new("test_vfs_save", true).unwrap())).unwrap();
+        let duplicate = vfs.mount("save", Box::new(LocalFileSystem::new("test_vfs_save", true).unwrap()));
+
+        vfs.write_file("assets/base.txt", b"base".to_vec()).unwrap();
+        vfs.write_file("assets/dlc/extra.txt", b"extra".to_vec()).unwrap();
+        vfs.write_file("save/slot1.dat", b"slot".to_vec()).unwrap();
+        let on_disk = std::fs::read("test_vfs_dlc/extra.txt");
+        let mut root: Vec<(String, bool)> = vfs.list_files("").unwrap().into_iter().map(|f| (f.path, f.is_directory)).collect();
+        root.sort();
+        let mut assets: Vec<(String, bool)> = vfs.list_files("assets").unwrap().into_iter().map(|f| (f.path, f.is_directory)).collect();
+        assets.sort();
+        let kinds = [vfs.is_dir("assets"), vfs.is_dir("assets/dlc"), vfs.is_file("assets/base.txt"), vfs.is_dir("assets/base.txt"), vfs.is_file("other/file.txt")]
+            .map(Result::unwrap);
+        let saved = vfs.read_file("save/slot1.dat");
+        vfs.swap_files("assets/base.txt", "assets/dlc/extra.txt").expect("Failed to swap across mounts");
+        let swapped = (vfs.read_file("assets/base.txt").unwrap(), vfs.read_file("assets/dlc/extra.txt").unwrap());
+        let unmounted = vfs.read_file("other/file.txt");
+        let removed = vfs.unmount("save");
+        let after_unmount = vfs.read_file("save/slot1.dat");
+        std::fs::remove_dir_all("test_vfs_assets").ok();
+        std::fs::remove_dir_all("test_vfs_dlc").ok();
+        std::fs::remove_dir_all("test_vfs_save").ok();
+
+        assert!(duplicate.is_err(), "Mounting twice at the same prefix should fail");
+        assert_eq!(on_disk.unwrap(), b"extra", "The longest matching mount should receive the file");
+        assert_eq!(root, vec![("assets".to_string(), true), ("save".to_string(), true)]);
+        assert_eq!(assets, vec![("assets/base.txt".to_string(), false), ("assets/dlc".to_string(), true)]);
+        assert_eq!(kinds, [true, true, true, false, false]);
+        assert_eq!(saved.unwrap(), b"slot");
+        assert_eq!(swapped, (b"extra".to_vec(), b"base".to_vec()));
+        assert!(unmounted.is_err());
+        assert!(removed.is_some());
+        assert!(after_unmount.is_err());
+        assert_eq!(vfs.mount_points(), vec!["assets", "assets/dlc"]);
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use crate::{join_path, normalize_path, FileSystemError, FsEvent, LocalFileSystem};
+
+/// How often a watched directory is checked for changes when it is polled
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Size and modification time of every file below a watched directory, by path
+type Snapshot = HashMap<String, (u64, Option<SystemTime>)>;
+
+/// Keeps a `LocalFileSystem::watch` running. Dropping it stops watching; the callback is
+/// not called again once the drop returns.
+pub struct WatchHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    /// Closed on drop, which wakes a thread waiting for inotify events
+    wake: Option<std::io::PipeWriter>,
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.wake.take();
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            thread.join().ok();
+        }
+    }
+}
+
+impl LocalFileSystem {
+    /// Calls `callback` whenever a file below `directory` is created, modified or deleted,
+    /// e.g. to hot-reload assets in an editor.
+    ///
+    /// Created and modified files are reported as `FsEvent::Write` with their new size,
+    /// deleted ones as `FsEvent::Delete`, with paths relative to this file system. Files in
+    /// a directory moved in or out are reported one by one. The callback runs on a
+    /// background thread, and watching stops when the returned handle is dropped.
+    ///
+    /// On Linux, changes are reported as inotify delivers them, once a written file is
+    /// closed. Elsewhere, or if inotify cannot be set up, e.g. because the limit on watched
+    /// directories is reached, the size and modification time of every file are compared
+    /// four times a second instead, so several changes to a file in between are reported
+    /// once.
+    ///
+    /// # Arguments
+    /// - _directory:_ The directory to watch, recursively, empty for the base path.
+    /// - _callback:_ Receives every change.
+    ///
+    /// # Errors
+    /// `FileSystemError` if `directory` does not exist or cannot be listed.
+    pub fn watch(&self, directory: &str, callback: Box<dyn FnMut(FsEvent) + Send>) -> Result<WatchHandle, FileSystemError> {
+        self.watch_with(directory, callback, true)
+    }
+
+    /// Starts watching with inotify where it is available and `inotify` is set, and by
+    /// polling otherwise.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn watch_with(&self, directory: &str, mut callback: Box<dyn FnMut(FsEvent) + Send>, inotify: bool) -> Result<WatchHandle, FileSystemError> {
+        let full_path = self.full_path(directory);
+        if !full_path.is_dir() {
+            return Err(FileSystemError::not_found(directory));
+        }
+        let directory = normalize_path(directory).trim_matches('/').to_string();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        #[cfg(target_os = "linux")]
+        if inotify {
+            // Watches are added before the snapshot is taken, so no change in between is missed
+            let watching = inotify::Inotify::new().and_then(|mut inotify| inotify.add_tree(&full_path, &directory).map(|_| inotify));
+            if let Ok(inotify) = watching {
+                let mut previous = Snapshot::new();
+                snapshot(&full_path, &directory, &mut previous)?;
+                let (woken, wake) = std::io::pipe().map_err(FileSystemError::from)?;
+                let thread = std::thread::spawn(move || {
+                    if let Some(previous) = watch_events(inotify, &woken, previous, &full_path, &directory, &mut callback) {
+                        poll_changes(&full_path, &directory, previous, &stopped, &mut callback);
+                    }
+                });
+                return Ok(WatchHandle { stop, thread: Some(thread), wake: Some(wake) });
+            }
+        }
+        let mut previous = Snapshot::new();
+        snapshot(&full_path, &directory, &mut previous)?;
+        let thread = std::thread::spawn(move || poll_changes(&full_path, &directory, previous, &stopped, &mut callback));
+        Ok(WatchHandle { stop, thread: Some(thread), wake: None })
+    }
+}
+
+/// Compares the files below `full_path` with `previous` every `POLL_INTERVAL`, reporting
+/// the differences, until `stop` is set.
+fn poll_changes(full_path: &Path, directory: &str, mut previous: Snapshot, stop: &AtomicBool, callback: &mut dyn FnMut(FsEvent)) {
+    while !stop.load(Ordering::Relaxed) {
+        std::thread::park_timeout(POLL_INTERVAL);
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let mut current = Snapshot::new();
+        // A directory that is briefly unreadable is checked again next time
+        if snapshot(full_path, directory, &mut current).is_err() {
+            continue;
+        }
+        report_changes(&previous, &current, callback);
+        previous = current;
+    }
+}
+
+/// Reports every file that is new or changed in `current`, then every file of `previous`
+/// that is missing from it.
+fn report_changes(previous: &Snapshot, current: &Snapshot, callback: &mut dyn FnMut(FsEvent)) {
+    for (path, state) in current {
+        if previous.get(path) != Some(state) {
+            callback(FsEvent::Write { path, bytes: state.0 });
+        }
+    }
+    for path in previous.keys().filter(|path| !current.contains_key(*path)) {
+        callback(FsEvent::Delete { path });
+    }
+}
+
+/// Reports changes as inotify delivers them, keeping `files` up to date, until `woken`
+/// is closed.
+///
+/// # Returns
+/// `None` once stopped, or the files known so far if inotify failed, to carry on polling.
+#[cfg(target_os = "linux")]
+fn watch_events(mut inotify: inotify::Inotify, woken: &std::io::PipeReader, mut files: Snapshot, full_path: &Path, directory: &str, callback: &mut dyn FnMut(FsEvent)) -> Option<Snapshot> {
+    use inotify::Event;
+
+    loop {
+        let events = match inotify.wait(woken) {
+            Ok(Some(events)) => events,
+            Ok(None) => return None,
+            Err(_) => return Some(files),
+        };
+        for event in events {
+            match event {
+                Event::Written { path, full_path: file_path, is_dir: false } => {
+                    let Ok(metadata) = std::fs::symlink_metadata(&file_path) else { continue };
+                    let state = (metadata.len(), metadata.modified().ok());
+                    if !metadata.is_dir() && files.get(&path) != Some(&state) {
+                        callback(FsEvent::Write { path: &path, bytes: state.0 });
+                        files.insert(path, state);
+                    }
+                }
+                Event::Written { path, full_path: subdirectory, is_dir: true } => {
+                    // Files can be added before the watch is, so they are looked up once
+                    if let Err(e) = inotify.add_tree(&subdirectory, &path)
+                        && e.kind() != std::io::ErrorKind::NotFound {
+                        return Some(files);
+                    }
+                    let mut added = Snapshot::new();
+                    snapshot(&subdirectory, &path, &mut added).ok();
+                    for (path, state) in added {
+                        if files.get(&path) != Some(&state) {
+                            callback(FsEvent::Write { path: &path, bytes: state.0 });
+                            files.insert(path, state);
+                        }
+                    }
+                }
+                Event::Removed { path, is_dir: false } => {
+                    if files.remove(&path).is_some() {
+                        callback(FsEvent::Delete { path: &path });
+                    }
+                }
+                Event::Removed { path, is_dir: true } => {
+                    inotify.remove_tree(&path);
+                    let prefix = format!("{}/", path);
+                    let removed: Vec<String> = files.keys().filter(|file| file.starts_with(&prefix)).cloned().collect();
+                    for path in removed {
+                        files.remove(&path);
+                        callback(FsEvent::Delete { path: &path });
+                    }
+                }
+                Event::Overflow => {
+                    // Events were lost, so the files are compared as when polling
+                    if inotify.add_tree(full_path, directory).is_err() {
+                        return Some(files);
+                    }
+                    let mut current = Snapshot::new();
+                    if snapshot(full_path, directory, &mut current).is_ok() {
+                        report_changes(&files, &current, callback);
+                        files = current;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Records every file below `full_path`, whose path relative to the file system is
+/// `relative`, into `files`.
+fn snapshot(full_path: &Path, relative: &str, files: &mut Snapshot) -> Result<(), FileSystemError> {
+    for entry in std::fs::read_dir(full_path).map_err(FileSystemError::from)? {
+        let entry = entry.map_err(FileSystemError::from)?;
+        let path = join_path(relative, &entry.file_name().to_string_lossy());
+        let metadata = entry.metadata().map_err(FileSystemError::from)?;
+        if metadata.is_dir() {
+            snapshot(&entry.path(), &path, files)?;
+        } else {
+            files.insert(path, (metadata.len(), metadata.modified().ok()));
+        }
+    }
+    Ok(())
+}
+
+/// Watches a tree of directories with the Linux inotify API.
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::ffi::{c_int, CString, OsStr};
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use crate::join_path;
+
+    /// Size of `struct inotify_event` without the name that follows it
+    const EVENT_HEADER_SIZE: usize = size_of::<libc::inotify_event>();
+    /// Room for at least one event with the longest possible name
+    const BUFFER_SIZE: usize = 4096;
+
+    /// A change below a watched directory, with its path relative to the file system.
+    pub(super) enum Event {
+        /// A file was written and closed, or a file or directory was moved in or created.
+        Written { path: String, full_path: PathBuf, is_dir: bool },
+        /// A file or directory was deleted or moved away.
+        Removed { path: String, is_dir: bool },
+        /// The kernel dropped events because they were not read fast enough.
+        Overflow,
+    }
+
+    pub(super) struct Inotify {
+        file: File,
+        /// Relative and full path of every watched directory, by watch descriptor
+        directories: HashMap<c_int, (String, PathBuf)>,
+    }
+
+    impl Inotify {
+        pub fn new() -> io::Result<Self> {
+            // Not inherited by child processes, like the descriptors std opens
+            // SAFETY: takes no pointers; the result is checked before use
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: `fd` is a new descriptor owned by nothing else, so `File` may close it
+            let file = unsafe { File::from_raw_fd(fd) };
+            Ok(Inotify { file, directories: HashMap::new() })
+        }
+
+        /// Watches `full_path`, whose path relative to the file system is `relative`, and
+        /// every directory below it. Directories that are watched already are kept.
+        pub fn add_tree(&mut self, full_path: &Path, relative: &str) -> io::Result<()> {
+            let path = CString::new(full_path.as_os_str().as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_DELETE
+                | libc::IN_ONLYDIR | libc::IN_DONT_FOLLOW;
+            // SAFETY: the descriptor is open and `path` is NUL-terminated
+            let wd = unsafe { libc::inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), mask) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.directories.insert(wd, (relative.to_string(), full_path.to_path_buf()));
+            for entry in std::fs::read_dir(full_path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    self.add_tree(&entry.path(), &join_path(relative, &entry.file_name().to_string_lossy()))?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Stops watching the directory at `relative` and every directory below it.
+        pub fn remove_tree(&mut self, relative: &str) {
+            let prefix = format!("{}/", relative);
+            let fd = self.file.as_raw_fd();
+            self.directories.retain(|wd, (path, _)| {
+                let below = path == relative || path.starts_with(&prefix);
+                if below {
+                    // SAFETY: the descriptor is open; a watch the kernel already dropped
+                    // only makes this fail
+                    unsafe { libc::inotify_rm_watch(fd, *wd) };
+                }
+                !below
+            });
+        }
+
+        /// Blocks until events arrive or the write end of `woken` is closed.
+        ///
+        /// # Returns
+        /// The events read, or `None` once `woken` is closed.
+        pub fn wait(&mut self, woken: &std::io::PipeReader) -> io::Result<Option<Vec<Event>>> {
+            let mut fds = [
+                libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: woken.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            ];
+            // SAFETY: `fds` holds `fds.len()` initialized `struct pollfd`
+            while unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            if fds[1].revents != 0 {
+                return Ok(None);
+            }
+            let mut buffer = [0u8; BUFFER_SIZE];
+            let len = self.file.read(&mut buffer)?;
+            let mut events = Vec::new();
+            let mut offset = 0;
+            while offset + EVENT_HEADER_SIZE <= len {
+                // SAFETY: the header lies within the bytes read; the buffer is not aligned for it
+                let event = unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast::<libc::inotify_event>()) };
+                let name_start = offset + EVENT_HEADER_SIZE;
+                let name = &buffer[name_start..(name_start + event.len as usize).min(len)];
+                // The name is padded with NUL bytes
+                let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                offset = name_start + event.len as usize;
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    events.push(Event::Overflow);
+                    continue;
+                }
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.directories.remove(&event.wd);
+                    continue;
+                }
+                // Events about a watched directory itself are reported by its parent
+                let Some((directory, full_path)) = self.directories.get(&event.wd).filter(|_| !name.is_empty()) else { continue };
+                let path = join_path(directory, &String::from_utf8_lossy(name));
+                let is_dir = event.mask & libc::IN_ISDIR != 0;
+                if event.mask & (libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) != 0 || (is_dir && event.mask & libc::IN_CREATE != 0) {
+                    events.push(Event::Written { path, full_path: full_path.join(OsStr::from_bytes(name)), is_dir });
+                } else if event.mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+                    events.push(Event::Removed { path, is_dir });
+                }
+            }
+            Ok(Some(events))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileSystem;
+    use std::sync::mpsc;
+
+    /// Watches `directory` and returns a function waiting up to 5 seconds for an event,
+    /// formatted as `write <path> <bytes>` or `delete <path>`.
+    fn watch_events(fs: &LocalFileSystem, directory: &str, inotify: bool) -> (WatchHandle, impl Fn(&str) -> Option<String>) {
+        let (sender, receiver) = mpsc::channel();
+        let handle = fs.watch_with(directory, Box::new(move |event| {
+            let event = match event {
+                FsEvent::Write { path, bytes } => format!("write {} {}", path, bytes),
+                FsEvent::Delete { path } => format!("delete {}", path),
+                other => format!("{:?}", other),
+            };
+            sender.send(event).ok();
+        }), inotify).unwrap();
+        // A check can run partway through a write, so wait for the final state
+        let wait_for = move |expected: &str| std::iter::from_fn(|| receiver.recv_timeout(Duration::from_secs(5)).ok())
+            .find(|event| event == expected);
+        (handle, wait_for)
+    }
+
+    #[test]
+    fn test_local_filesystem_watch() {
+        let fs = LocalFileSystem::new("test_dir_watch", true).unwrap();
+        fs.write_file("assets/existing.txt", b"existing".to_vec()).unwrap();
+        let (handle, wait_for) = watch_events(&fs, "assets", true);
+        fs.write_file("assets/sub/new.txt", b"new".to_vec()).unwrap();
+        let created = wait_for("write assets/sub/new.txt 3");
+        fs.delete_file("assets/existing.txt").unwrap();
+        let deleted = wait_for("delete assets/existing.txt");
+        drop(handle);
+        let missing = fs.watch("missing", Box::new(|_| {}));
+        std::fs::remove_dir_all("test_dir_watch").ok();
+
+        assert!(created.is_some());
+        assert!(deleted.is_some());
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_local_filesystem_watch_moved_directories() {
+        let fs = LocalFileSystem::new("test_dir_watch_moved", true).unwrap();
+        fs.write_file("assets/existing.txt", b"existing".to_vec()).unwrap();
+        fs.write_file("staging/pack/texture.png", b"texture".to_vec()).unwrap();
+        let (handle, wait_for) = watch_events(&fs, "assets", true);
+        std::fs::rename("test_dir_watch_moved/staging/pack", "test_dir_watch_moved/assets/pack").unwrap();
+        let moved_in = wait_for("write assets/pack/texture.png 7");
+        fs.write_file("assets/pack/nested/new.txt", b"new".to_vec()).unwrap();
+        let written = wait_for("write assets/pack/nested/new.txt 3");
+        std::fs::rename("test_dir_watch_moved/assets/pack", "test_dir_watch_moved/staging/pack").unwrap();
+        let moved_out = wait_for("delete assets/pack/nested/new.txt");
+        fs.write_file("staging/pack/ignored.txt", b"ignored".to_vec()).unwrap();
+        fs.write_file("assets/after.txt", b"after".to_vec()).unwrap();
+        let after = wait_for("write assets/after.txt 5");
+        drop(handle);
+        std::fs::remove_dir_all("test_dir_watch_moved").ok();
+
+        assert!(moved_in.is_some(), "Files in a directory moved in should be reported");
+        assert!(written.is_some(), "Directories moved in should be watched");
+        assert!(moved_out.is_some(), "Files in a directory moved out should be reported deleted");
+        assert!(after.is_some());
+    }
+
+    #[test]
+    fn test_local_filesystem_watch_polling() {
+        let fs = LocalFileSystem::new("test_dir_watch_polling", true).unwrap();
+        fs.write_file("assets/existing.txt", b"existing".to_vec()).unwrap();
+        let (handle, wait_for) = watch_events(&fs, "assets", false);
+        fs.write_file("assets/sub/new.txt", b"new".to_vec()).unwrap();
+        let created = wait_for("write assets/sub/new.txt 3");
+        fs.delete_file("assets/existing.txt").unwrap();
+        let deleted = wait_for("delete assets/existing.txt");
+        drop(handle);
+        std::fs::remove_dir_all("test_dir_watch_polling").ok();
+
+        assert!(created.is_some());
+        assert!(deleted.is_some());
+    }
+}
@@ -0,0 +1,412 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{ArchiveCreator, ArchiveFileSystem, EncKey, FileSystem, FileSystemError};
+use crate::archive::safe_relative_path;
+use crate::inflate::inflate;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+/// The end of central directory record is followed by a comment of at most this size
+const MAX_COMMENT_SIZE: usize = 0xffff;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+const FLAG_ENCRYPTED: u16 = 1;
+const FLAG_UTF8: u16 = 1 << 11;
+/// Zip version 2.0, the first to support directories and deflate
+const VERSION_NEEDED: u16 = 20;
+/// The earliest time a zip timestamp can hold, 1980-01-01 00:00:00
+const DOS_EPOCH_DATE: u16 = (1 << 5) | 1;
+
+/// CRC-32 lookup table for the polynomial used by zip
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// A file entry of the zip central directory.
+struct ZipEntry {
+    name: String,
+    flags: u16,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+impl ArchiveCreator {
+    /// Packs the files of a zip file into a new encrypted archive.
+    ///
+    /// Paths inside the zip are kept as they are, along with the modification times. Zip
+    /// directory entries are not needed in an archive and are left out. Stored and
+    /// deflated entries are supported, which covers what common zip tools produce. The zip
+    /// is unpacked to a staging directory next to `output`, which is removed afterwards.
+    ///
+    /// # Arguments
+    /// - _zip_path:_ Path of the zip file to read.
+    /// - _output:_ Path of the archive to create. It must not exist yet.
+    /// - _key:_ The key to encrypt the archive with.
+    ///
+    /// # Returns
+    /// The number of files imported.
+    ///
+    /// # Errors
+    /// `FileSystemError` if the zip file is malformed, uses encryption, Zip64 or an
+    /// unsupported compression method, has an entry whose path would leave the archive
+    /// root, or if the archive cannot be created.
+    pub fn from_zip(zip_path: &str, output: &str, key: EncKey) -> Result<usize, FileSystemError> {
+        ArchiveCreator::from_unpacked(output, key, |staging| extract_zip(Path::new(zip_path), staging))
+    }
+}
+
+impl ArchiveFileSystem {
+    /// Writes the decrypted contents of the archive to a zip file.
+    ///
+    /// Every file keeps its archive path and modification time, and is stored without
+    /// compression. Zip64 is not written, so the archive must hold fewer than 65535 files
+    /// and the zip must stay under 4 GiB.
+    ///
+    /// # Arguments
+    /// - _output:_ Path of the zip file to create. An existing file is overwritten.
+    ///
+    /// # Returns
+    /// The number of files written.
+    ///
+    /// # Errors
+    /// `FileSystemError` if a file cannot be read from the archive, the zip cannot be
+    /// written, or it would need Zip64.
+    pub fn export_zip(&self, output: &str) -> Result<usize, FileSystemError> {
+        let mut writer = BufWriter::new(File::create(output).map_err(FileSystemError::from)?);
+        let too_large = || FileSystemError::from("Archive is too large for a zip file without Zip64");
+        let mut entries = Vec::new();
+        let mut offset = 0u64;
+        for info in self.walk("") {
+            let info = info?;
+            let content = self.read_file(&info.path)?;
+            let (time, date) = to_dos_time(info.modified);
+            let size = u32::try_from(content.len()).map_err(|_| too_large())?;
+            let entry = ZipEntry {
+                name: info.path,
+                flags: FLAG_UTF8,
+                method: METHOD_STORED,
+                time,
+                date,
+                crc: crc32(&content),
+                compressed_size: size,
+                size,
+                offset: u32::try_from(offset).map_err(|_| too_large())?,
+            };
+            let mut header = Vec::with_capacity(LOCAL_HEADER_SIZE + entry.name.len());
+            header.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+            header.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+            entry.write_common_fields(&mut header);
+            header.extend_from_slice(&0u16.to_le_bytes()); // Extra field length
+            header.extend_from_slice(entry.name.as_bytes());
+            writer.write_all(&header).map_err(FileSystemError::from)?;
+            writer.write_all(&content).map_err(FileSystemError::from)?;
+            offset += (header.len() + content.len()) as u64;
+            entries.push(entry);
+        }
+        let count = u16::try_from(entries.len()).map_err(|_| too_large())?;
+        let mut directory = Vec::new();
+        for entry in &entries {
+            directory.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            directory.extend_from_slice(&VERSION_NEEDED.to_le_bytes()); // Version made by
+            directory.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+            entry.write_common_fields(&mut directory);
+            directory.extend_from_slice(&[0u8; 2 + 2 + 2 + 2 + 4]); // Extra, comment, disk, internal and external attributes
+            directory.extend_from_slice(&entry.offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let mut end = Vec::with_capacity(END_OF_CENTRAL_DIRECTORY_SIZE);
+        end.extend_from_slice(&END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        end.extend_from_slice(&[0u8; 4]); // Disk numbers
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&u32::try_from(directory.len()).map_err(|_| too_large())?.to_le_bytes());
+        end.extend_from_slice(&u32::try_from(offset).map_err(|_| too_large())?.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // Comment length
+        writer.write_all(&directory).map_err(FileSystemError::from)?;
+        writer.write_all(&end).map_err(FileSystemError::from)?;
+        writer.flush().map_err(FileSystemError::from)?;
+        Ok(entries.len())
+    }
+}
+
+impl ZipEntry {
+    /// Writes the fields shared by local and central headers, from the flags up to the
+    /// name length.
+    fn write_common_fields(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.flags.to_le_bytes());
+        bytes.extend_from_slice(&self.method.to_le_bytes());
+        bytes.extend_from_slice(&self.time.to_le_bytes());
+        bytes.extend_from_slice(&self.date.to_le_bytes());
+        bytes.extend_from_slice(&self.crc.to_le_bytes());
+        bytes.extend_from_slice(&self.compressed_size.to_le_bytes());
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+    }
+}
+
+/// Unpacks the files of a zip into `directory`, returning how many were unpacked.
+fn extract_zip(zip_path: &Path, directory: &Path) -> Result<usize, FileSystemError> {
+    let mut file = File::open(zip_path).map_err(FileSystemError::from)?;
+    let mut count = 0;
+    for entry in read_central_directory(&mut file)? {
+        if entry.name.ends_with('/') {
+            continue;
+        }
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(FileSystemError::from(format!("Zip entry {} is encrypted, password-protected zip files are not supported", entry.name)));
+        }
+        let relative = safe_relative_path(&entry.name)
+            .ok_or(FileSystemError::from(format!("Zip entry {} has a path outside the archive root", entry.name)))?;
+        let content = read_entry(&mut file, &entry)?;
+        let path = directory.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(FileSystemError::from)?;
+        }
+        let mut output = File::create(&path).map_err(FileSystemError::from)?;
+        output.write_all(&content).map_err(FileSystemError::from)?;
+        output.set_modified(from_dos_time(entry.time, entry.date)).ok();
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Finds the end of central directory record and parses the entries it points to.
+fn read_central_directory(file: &mut File) -> Result<Vec<ZipEntry>, FileSystemError> {
+    let file_size = file.metadata().map_err(FileSystemError::from)?.len();
+    let tail_size = file_size.min((END_OF_CENTRAL_DIRECTORY_SIZE + MAX_COMMENT_SIZE) as u64);
+    let mut tail = vec![0u8; tail_size as usize];
+    file.seek(SeekFrom::Start(file_size - tail_size)).map_err(FileSystemError::from)?;
+    file.read_exact(&mut tail).map_err(FileSystemError::from)?;
+    let end = (0..tail.len().saturating_sub(END_OF_CENTRAL_DIRECTORY_SIZE - 1)).rev()
+        .find(|&i| u32_at(&tail, i) == END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+        .ok_or(FileSystemError::from("Not a zip file, the end of central directory record is missing"))?;
+    let count = u16_at(&tail, end + 10);
+    let directory_size = u32_at(&tail, end + 12);
+    let directory_offset = u32_at(&tail, end + 16);
+    if count == u16::MAX || directory_size == u32::MAX || directory_offset == u32::MAX {
+        return Err(FileSystemError::from("Zip64 files are not supported"));
+    }
+    if directory_offset as u64 + directory_size as u64 > file_size {
+        return Err(FileSystemError::from("Zip central directory exceeds file size"));
+    }
+    let mut directory = vec![0u8; directory_size as usize];
+    file.seek(SeekFrom::Start(directory_offset as u64)).map_err(FileSystemError::from)?;
+    file.read_exact(&mut directory).map_err(FileSystemError::from)?;
+    let mut entries = Vec::with_capacity(count as usize);
+    let mut position = 0;
+    for _ in 0..count {
+        if position + CENTRAL_HEADER_SIZE > directory.len() || u32_at(&directory, position) != CENTRAL_HEADER_SIGNATURE {
+            return Err(FileSystemError::from("Invalid zip central directory entry"));
+        }
+        let name_length = u16_at(&directory, position + 28) as usize;
+        let extra_length = u16_at(&directory, position + 30) as usize;
+        let comment_length = u16_at(&directory, position + 32) as usize;
+        let name = directory.get(position + CENTRAL_HEADER_SIZE..position + CENTRAL_HEADER_SIZE + name_length)
+            .ok_or(FileSystemError::from("Invalid zip central directory entry"))?;
+        let entry = ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: u16_at(&directory, position + 8),
+            method: u16_at(&directory, position + 10),
+            time: u16_at(&directory, position + 12),
+            date: u16_at(&directory, position + 14),
+            crc: u32_at(&directory, position + 16),
+            compressed_size: u32_at(&directory, position + 20),
+            size: u32_at(&directory, position + 24),
+            offset: u32_at(&directory, position + 42),
+        };
+        if entry.compressed_size == u32::MAX || entry.size == u32::MAX || entry.offset == u32::MAX {
+            return Err(FileSystemError::from("Zip64 files are not supported"));
+        }
+        entries.push(entry);
+        position += CENTRAL_HEADER_SIZE + name_length + extra_length + comment_length;
+    }
+    Ok(entries)
+}
+
+/// Reads and decompresses an entry, checking its CRC.
+fn read_entry(file: &mut File, entry: &ZipEntry) -> Result<Vec<u8>, FileSystemError> {
+    let mut header = [0u8; LOCAL_HEADER_SIZE];
+    file.seek(SeekFrom::Start(entry.offset as u64)).map_err(FileSystemError::from)?;
+    file.read_exact(&mut header).map_err(FileSystemError::from)?;
+    if u32_at(&header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Err(FileSystemError::from(format!("Invalid zip local header for {}", entry.name)));
+    }
+    // The local name and extra field may differ in length from the central directory's
+    let skip = u16_at(&header, 26) as i64 + u16_at(&header, 28) as i64;
+    file.seek(SeekFrom::Current(skip)).map_err(FileSystemError::from)?;
+    let mut compressed = Vec::new();
+    file.take(entry.compressed_size as u64).read_to_end(&mut compressed).map_err(FileSystemError::from)?;
+    if compressed.len() != entry.compressed_size as usize {
+        return Err(FileSystemError::from(format!("Zip entry {} is truncated", entry.name)));
+    }
+    let content = match entry.method {
+        METHOD_STORED => compressed,
+        // Stops as soon as the entry grows past its declared size, so a zip bomb cannot exhaust memory
+        METHOD_DEFLATED => inflate(&compressed, entry.size as usize)
+            .map_err(|e| FileSystemError::from(format!("Cannot decompress zip entry {}: {}", entry.name, e.message)))?,
+        method => return Err(FileSystemError::from(format!("Zip entry {} uses unsupported compression method {}", entry.name, method))),
+    };
+    if content.len() != entry.size as usize || crc32(&content) != entry.crc {
+        return Err(FileSystemError::from(format!("Zip entry {} is corrupted, its checksum does not match", entry.name)));
+    }
+    Ok(content)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+fn u16_at(bytes: &[u8], position: usize) -> u16 {
+    u16::from_le_bytes([bytes[position], bytes[position + 1]])
+}
+
+fn u32_at(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+/// Converts a time to the MS-DOS time and date fields of a zip header, in UTC.
+///
+/// Times before 1980, or unknown, become 1980-01-01 00:00:00.
+fn to_dos_time(time: Option<SystemTime>) -> (u16, u16) {
+    let Some(seconds) = time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()) else {
+        return (0, DOS_EPOCH_DATE);
+    };
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    if !(1980..=2107).contains(&year) {
+        return (0, DOS_EPOCH_DATE);
+    }
+    let seconds_of_day = seconds % 86400;
+    let time = ((seconds_of_day / 3600) << 11) | (((seconds_of_day / 60) % 60) << 5) | ((seconds_of_day % 60) / 2);
+    let date = ((year - 1980) << 9) | ((month as i64) << 5) | day as i64;
+    (time as u16, date as u16)
+}
+
+/// Converts the MS-DOS time and date fields of a zip header, read as UTC.
+fn from_dos_time(time: u16, date: u16) -> SystemTime {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).clamp(1, 12) as u32;
+    let day = (date & 0x1f).max(1) as u32;
+    let seconds = (time >> 11) as u64 * 3600 + ((time >> 5) & 0x3f) as u64 * 60 + (time & 0x1f) as u64 * 2;
+    UNIX_EPOCH + Duration::from_secs(days_from_civil(year, month, day) as u64 * 86400 + seconds)
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a proleptic Gregorian date to days since 1970-01-01.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::EncUtils;
+
+    #[test]
+    fn test_zip_round_trip() {
+        let source = "test_zip_source";
+        std::fs::create_dir_all(format!("{}/levels", source)).unwrap();
+        std::fs::write(format!("{}/hero.txt", source), b"hero").unwrap();
+        std::fs::write(format!("{}/levels/one.dat", source), b"level one").unwrap();
+        let key = EncUtils::generate_random_key();
+        ArchiveCreator::new(source, "test_zip.arc", key.clone(), true).unwrap().create().unwrap();
+        let archive = ArchiveFileSystem::open(PathBuf::from("test_zip.arc"), key.clone()).unwrap();
+        let exported = archive.export_zip("test_zip.zip");
+        std::fs::remove_file("test_zip_imported.arc").ok();
+        let imported = ArchiveCreator::from_zip("test_zip.zip", "test_zip_imported.arc", key.clone());
+        let reopened = ArchiveFileSystem::open(PathBuf::from("test_zip_imported.arc"), key.clone());
+        let contents = reopened.as_ref().map(|fs| fs.read_files(&["hero.txt", "levels/one.dat"]));
+        let modified = reopened.as_ref().map(|fs| fs.list_files("").unwrap()[0].modified);
+
+        // Flag the first entry as encrypted in both headers
+        let mut zip = std::fs::read("test_zip.zip").unwrap();
+        zip[6] |= FLAG_ENCRYPTED as u8;
+        let central = zip.windows(4).position(|w| w == CENTRAL_HEADER_SIGNATURE.to_le_bytes()).unwrap();
+        zip[central + 8] |= FLAG_ENCRYPTED as u8;
+        std::fs::write("test_zip_encrypted.zip", &zip).unwrap();
+        let encrypted = ArchiveCreator::from_zip("test_zip_encrypted.zip", "test_zip_encrypted.arc", key);
+        std::fs::remove_dir_all(source).ok();
+        for file in ["test_zip.arc", "test_zip.zip", "test_zip_imported.arc", "test_zip_encrypted.zip", "test_zip_encrypted.arc"] {
+            std::fs::remove_file(file).ok();
+        }
+
+        assert_eq!(exported.unwrap(), 2);
+        assert_eq!(imported.unwrap(), 2);
+        let contents = contents.unwrap().unwrap();
+        assert_eq!(contents["hero.txt"], b"hero");
+        assert_eq!(contents["levels/one.dat"], b"level one");
+        assert!(modified.unwrap().is_some(), "Modification times should survive the round trip");
+        assert!(encrypted.unwrap_err().message.contains("password-protected"));
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let (dos_time, dos_date) = to_dos_time(Some(time));
+        assert_eq!(from_dos_time(dos_time, dos_date), time);
+    }
+
+    #[test]
+    fn test_zip_entry_larger_than_declared() {
+        // A deflated entry holding "abc" in a stored block, declared as 2 bytes
+        let compressed = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        let mut zip = LOCAL_HEADER_SIGNATURE.to_le_bytes().to_vec();
+        zip.extend_from_slice(&[0u8; LOCAL_HEADER_SIZE - 4]);
+        zip.extend_from_slice(&compressed);
+        std::fs::write("test_zip_oversized.zip", &zip).unwrap();
+        let entry = ZipEntry {
+            name: "abc.txt".to_string(),
+            flags: 0,
+            method: METHOD_DEFLATED,
+            time: 0,
+            date: DOS_EPOCH_DATE,
+            crc: crc32(b"ab"),
+            compressed_size: compressed.len() as u32,
+            size: 2,
+            offset: 0,
+        };
+        let read = read_entry(&mut File::open("test_zip_oversized.zip").unwrap(), &entry);
+        std::fs::remove_file("test_zip_oversized.zip").ok();
+
+        assert!(read.unwrap_err().message.contains("Cannot decompress zip entry abc.txt"));
+    }
+}